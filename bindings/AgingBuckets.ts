@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * Outstanding amounts split by how many days they are past due
+ */
+export type AgingBuckets = { 
+/**
+ * Not due yet, or due on the report date
+ */
+not_due: string, 
+/**
+ * 1 to 30 days past due
+ */
+days_0_30: string, days_31_60: string, days_61_90: string, days_over_90: string, total: string, };
\ No newline at end of file
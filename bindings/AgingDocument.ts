@@ -0,0 +1,18 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * An order or purchase with an unpaid amount on the report date
+ */
+export type AgingDocument = { 
+/**
+ * Id of the order or purchase
+ */
+id: number, date_time: string, due_date: string, 
+/**
+ * Negative when it isn't due yet
+ */
+days_overdue: number, total: string, 
+/**
+ * Allocated from payments made up to the report date
+ */
+paid: string, outstanding: string, };
\ No newline at end of file
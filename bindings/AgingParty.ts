@@ -0,0 +1,13 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AgingBuckets } from "./AgingBuckets";
+import type { AgingDocument } from "./AgingDocument";
+
+export type AgingParty = { 
+/**
+ * Customer or supplier id, null for retail orders
+ */
+party_id: number | null, name: string, payment_terms_days: number, buckets: AgingBuckets, 
+/**
+ * Oldest first
+ */
+documents: Array<AgingDocument>, };
\ No newline at end of file
@@ -0,0 +1,13 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AgingBuckets } from "./AgingBuckets";
+import type { AgingParty } from "./AgingParty";
+
+export type AgingReport = { as_of: string, 
+/**
+ * Per customer, retail orders are grouped together
+ */
+receivables: Array<AgingParty>, receivable_totals: AgingBuckets, 
+/**
+ * Per supplier
+ */
+payables: Array<AgingParty>, payable_totals: AgingBuckets, };
\ No newline at end of file
@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PermissionKey } from "./PermissionKey";
+
+export type ApiToken = { id: number, name: string, 
+/**
+ * The token can only use these, and only while the user still has them
+ */
+permissions: Array<PermissionKey>, created_at: string, last_used_at: string | null, 
+/**
+ * Tokens without an expiry last until they are revoked
+ */
+expires_at: string | null, };
\ No newline at end of file
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AuthEventKind } from "./AuthEventKind";
+
+export type AuthEvent = { id: number, date_time: string, event: AuthEventKind, 
+/**
+ * The username that was sent, which might not exist
+ */
+username: string, user_id: number | null, ip_address: string | null, user_agent: string | null, };
\ No newline at end of file
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * The business_* settings and logo, shown on printed documents
+ */
+export type BusinessDetails = { name: string, address: string, email: string, website: string, phone_numbers: Array<string>, bank_accounts: Array<string>, 
+/**
+ * logo_high_resolution as a data URI
+ */
+logo: string, };
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LoginFailureKind } from "./LoginFailureKind";
+
+export type ClearLockoutRequest = { kind: LoginFailureKind, key: string, };
\ No newline at end of file
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type CreatedApiToken = { id: number, 
+/**
+ * Only shown once, send it as `Authorization: Bearer <token>`
+ */
+token: string, };
\ No newline at end of file
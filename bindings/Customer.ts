@@ -1,3 +1,11 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type Customer = { id: number, name: string, phone: string, address: string, notes: string, };
\ No newline at end of file
+export type Customer = { id: number, name: string, phone: string, address: string, notes: string, 
+/**
+ * Days to pay an order, null uses the payment_terms_days setting
+ */
+payment_terms_days: number | null, 
+/**
+ * Archived customers are hidden from lists but still shown on existing records
+ */
+archived_at: string | null, };
\ No newline at end of file
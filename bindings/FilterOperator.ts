@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type FilterOperator = "=" | "!=" | ">" | "<" | ">=" | "<=" | "LIKE";
\ No newline at end of file
+export type FilterOperator = "=" | "!=" | ">" | "<" | ">=" | "<=" | "LIKE" | "ILIKE" | "IN" | "NOT IN" | "BETWEEN" | "IS NULL" | "IS NOT NULL";
\ No newline at end of file
@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ListFilter } from "./ListFilter";
+
+/**
+ * Filters combined with AND, OR and NOT, e.g.
+ * { "And": [
+ *     { "Or": [
+ *         { "Filter": { "column": "customer_id", "operator": "=", "value": { "Int": 3 } } },
+ *         { "Filter": { "column": "customer_id", "operator": "=", "value": { "Int": 7 } } }
+ *     ] },
+ *     { "Not": { "Filter": { "column": "fulfilled", "operator": "=", "value": { "Boolean": true } } } }
+ * ] }
+ */
+export type FilterTree = { "And": Array<FilterTree> } | { "Or": Array<FilterTree> } | { "Not": FilterTree } | { "Filter": ListFilter };
\ No newline at end of file
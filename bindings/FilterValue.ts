@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { SqlType } from "./SqlType";
+
+export type FilterValue = SqlType | Array<SqlType>;
\ No newline at end of file
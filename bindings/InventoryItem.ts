@@ -4,4 +4,12 @@ export type InventoryItem = { id: number, name: string, description: string,
 /**
  * A decimal number with a precision of 2 decimal places
  */
-price: string, stock: number, quantity_per_box: number, };
\ No newline at end of file
+price: string, stock: number, quantity_per_box: number, 
+/**
+ * Average cost of the units in stock, set from the purchases by the costing method
+ */
+unit_cost: string, 
+/**
+ * Archived items are hidden from lists but still shown on existing orders and purchases
+ */
+archived_at: string | null, };
\ No newline at end of file
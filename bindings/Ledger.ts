@@ -0,0 +1,16 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LedgerEntry } from "./LedgerEntry";
+
+/**
+ * Account of a party over a date range, oldest entry first.
+ * A positive balance is outstanding: owed by a customer, or owed to a supplier.
+ */
+export type Ledger<P> = { party: P, from: string | null, to: string | null, 
+/**
+ * Balance of everything before `from`
+ */
+opening_balance: string, total_debit: string, total_credit: string, closing_balance: string, 
+/**
+ * Unpaid part of the orders or purchases that are past their payment terms today
+ */
+overdue: string, entries: Array<LedgerEntry>, };
\ No newline at end of file
@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LedgerEntryKind } from "./LedgerEntryKind";
+
+export type LedgerEntry = { date_time: string, kind: LedgerEntryKind, 
+/**
+ * Id of the order, purchase or payment
+ */
+reference_id: number, description: string, debit: string, credit: string, 
+/**
+ * Running balance after this entry
+ */
+balance: string, };
\ No newline at end of file
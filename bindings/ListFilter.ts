@@ -1,5 +1,9 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { FilterOperator } from "./FilterOperator";
-import type { SqlType } from "./SqlType";
+import type { FilterValue } from "./FilterValue";
 
-export type ListFilter = { column: string, operator: FilterOperator, value: SqlType, };
\ No newline at end of file
+export type ListFilter = { column: string, operator: FilterOperator, 
+/**
+ * A list for IN, NOT IN and BETWEEN, nothing for IS NULL and IS NOT NULL
+ */
+value?: FilterValue, };
\ No newline at end of file
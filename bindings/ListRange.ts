@@ -6,6 +6,11 @@ export type ListRange = {
  */
 count: number, 
 /**
- * First item's index
+ * First item's index, ignored if a cursor is sent
  */
-offset: number, };
\ No newline at end of file
+offset: number, 
+/**
+ * `next_cursor` of the previous page. Faster than an offset on deep pages,
+ * and only valid with the same sorts.
+ */
+cursor?: string, };
\ No newline at end of file
@@ -1,6 +1,19 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { FilterTree } from "./FilterTree";
 import type { ListFilter } from "./ListFilter";
 import type { ListRange } from "./ListRange";
 import type { ListSort } from "./ListSort";
 
-export type ListRequest = { range: ListRange, sorts: Array<ListSort>, filters: Array<ListFilter>, };
\ No newline at end of file
+export type ListRequest = { range: ListRange, sorts: Array<ListSort>, 
+/**
+ * All of these have to match
+ */
+filters: Array<ListFilter>, 
+/**
+ * Conditions that need OR or NOT, combined with `filters` using AND
+ */
+filter_tree?: FilterTree, 
+/**
+ * Also return archived rows. Only used by tables that can be archived
+ */
+include_archived: boolean, };
\ No newline at end of file
@@ -0,0 +1,14 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+/**
+ * A page of a list route
+ */
+export type ListResponse<T> = { items: Array<T>, 
+/**
+ * Number of rows matching the filters, on all pages
+ */
+total: bigint, 
+/**
+ * Send as `range.cursor` to get the next page, null on the last page
+ */
+next_cursor: string | null, };
\ No newline at end of file
@@ -1,4 +1,9 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
-import type { UserPermissionsVec } from "./UserPermissionsVec";
+import type { PermissionKey } from "./PermissionKey";
+import type { UserRole } from "./UserRole";
 
-export type ListUserData = { username: string, permissions: UserPermissionsVec, };
\ No newline at end of file
+export type ListUserData = { id: number, username: string, roles: Array<UserRole>, 
+/**
+ * Keys granted by all roles of the user
+ */
+permissions: Array<PermissionKey>, archived_at: string | null, };
\ No newline at end of file
@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { LoginFailureKind } from "./LoginFailureKind";
+
+export type LoginLockout = { kind: LoginFailureKind, 
+/**
+ * The username or IP address
+ */
+key: string, failures: number, last_failure_at: string, locked_until: string, };
\ No newline at end of file
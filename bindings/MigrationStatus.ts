@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { AppliedMigration } from "./AppliedMigration";
+
+export type MigrationStatus = { 
+/**
+ * Latest migration applied to the database
+ */
+database_version: number, 
+/**
+ * Latest migration known by the running server
+ */
+server_version: number, applied: Array<AppliedMigration>, };
\ No newline at end of file
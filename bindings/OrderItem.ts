@@ -1,4 +1,8 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { InventoryItem } from "./InventoryItem";
 
-export type OrderItem = { id: number, inventory_item: InventoryItem, quantity: number, price: string, discount: string, discount_percentage: boolean, };
\ No newline at end of file
+export type OrderItem = { id: number, inventory_item: InventoryItem, quantity: number, price: string, discount: string, discount_percentage: boolean, 
+/**
+ * Cost of goods sold of the line, set by the inventory costing method
+ */
+cost: string, };
\ No newline at end of file
@@ -6,4 +6,8 @@ export type OrderMeta = { id: number, date_time: string,
 /**
  * This will be false if the order is retail
  */
-customer: Customer | null, created_by_user: User, amount_paid: string, retail: boolean, retail_customer_name: string | null, retail_customer_phone: string | null, retail_customer_address: string | null, fulfilled: boolean, notes: string, total: string, };
\ No newline at end of file
+customer: Customer | null, created_by_user: User, 
+/**
+ * Sum of the payments allocated to this order
+ */
+amount_paid: string, retail: boolean, retail_customer_name: string | null, retail_customer_phone: string | null, retail_customer_address: string | null, fulfilled: boolean, notes: string, total: string, };
\ No newline at end of file
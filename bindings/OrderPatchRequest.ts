@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type OrderPatchRequest = { customer_id: number | null, set_customer_id_null: boolean, retail: boolean | null, retail_customer_name: string | null, retail_customer_phone: string | null, retail_customer_address: string | null, set_retail_customer_null: boolean, notes: string | null, amount_paid: string | null, fulfilled: boolean | null, date_time: string | null, };
\ No newline at end of file
+export type OrderPatchRequest = { customer_id: number | null, set_customer_id_null: boolean, retail: boolean | null, retail_customer_name: string | null, retail_customer_phone: string | null, retail_customer_address: string | null, set_retail_customer_null: boolean, notes: string | null, fulfilled: boolean | null, date_time: string | null, };
\ No newline at end of file
@@ -4,4 +4,8 @@ import type { PaymentParty } from "./PaymentParty";
 import type { TransferType } from "./TransferType";
 import type { User } from "./User";
 
-export type Payment = { id: number, date_time: string, amount: string, party: PaymentParty, transfer_type: TransferType, method: PaymentMethod, method_details: string, notes: string, created_by_user: User, };
\ No newline at end of file
+export type Payment = { id: number, date_time: string, amount: string, party: PaymentParty, transfer_type: TransferType, method: PaymentMethod, method_details: string, notes: string, 
+/**
+ * Part of the amount allocated to orders or purchases. The rest of a customer payment is credit.
+ */
+allocated: string, created_by_user: User, };
\ No newline at end of file
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type PaymentAllocation = { id: number, payment_id: number, 
+/**
+ * Set for incoming payments, purchase_id is null then
+ */
+order_id: number | null, 
+/**
+ * Set for outgoing payments to suppliers, order_id is null then
+ */
+purchase_id: number | null, amount: string, };
\ No newline at end of file
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type PaymentAllocationRequest = { 
+/**
+ * One of order_id and purchase_id
+ */
+order_id: number | null, purchase_id: number | null, amount: string, };
\ No newline at end of file
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type PaymentMethod = "Cash" | "Bank" | "Mobile" | "Other";
\ No newline at end of file
+export type PaymentMethod = "CASH" | "BANK" | "MOBILE" | "OTHER";
\ No newline at end of file
@@ -3,4 +3,4 @@ import type { Customer } from "./Customer";
 import type { OrderMeta } from "./OrderMeta";
 import type { Supplier } from "./Supplier";
 
-export type PaymentParty = { "Customer": Customer } | { "Supplier": Supplier } | { "Retail": OrderMeta };
\ No newline at end of file
+export type PaymentParty = { "CUSTOMER": Customer } | { "SUPPLIER": Supplier } | { "RETAIL": OrderMeta };
\ No newline at end of file
@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PartyType } from "./PartyType";
+import type { PaymentMethod } from "./PaymentMethod";
+import type { TransferType } from "./TransferType";
+
+export type PaymentPatchRequest = { date_time: string | null, amount: string | null, 
+/**
+ * Checked together with party_id, so changing one has to fit the other
+ */
+party_type: PartyType | null, party_id: number | null, transfer_type: TransferType | null, method: PaymentMethod | null, method_details: string | null, notes: string | null, };
\ No newline at end of file
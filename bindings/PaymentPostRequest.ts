@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PartyType } from "./PartyType";
+import type { PaymentAllocationRequest } from "./PaymentAllocationRequest";
+import type { PaymentMethod } from "./PaymentMethod";
+import type { TransferType } from "./TransferType";
+
+export type PaymentPostRequest = { 
+/**
+ * Defaults to now
+ */
+date_time: string | null, amount: string, party_type: PartyType, 
+/**
+ * Id of the customer, supplier or retail order
+ */
+party_id: number, transfer_type: TransferType, method: PaymentMethod, method_details: string, notes: string, 
+/**
+ * Orders or purchases the payment is for, the rest stays unallocated
+ */
+allocations: Array<PaymentAllocationRequest>, };
\ No newline at end of file
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type PnlFigures = { 
+/**
+ * Order totals after discounts
+ */
+revenue: string, 
+/**
+ * Cost of the goods sold, by the inventory costing method
+ */
+cost_of_goods_sold: string, gross_profit: string, operating_expenses: string, net_profit: string, };
\ No newline at end of file
@@ -0,0 +1,12 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PnlFigures } from "./PnlFigures";
+
+export type PnlPeriodFigures = { start: string, 
+/**
+ * Last day of the period
+ */
+end: string, figures: PnlFigures, 
+/**
+ * Figures of the period just before this one
+ */
+previous: PnlFigures, };
\ No newline at end of file
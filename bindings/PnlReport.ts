@@ -0,0 +1,18 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PnlFigures } from "./PnlFigures";
+import type { PnlPeriod } from "./PnlPeriod";
+import type { PnlPeriodFigures } from "./PnlPeriodFigures";
+
+export type PnlReport = { 
+/**
+ * Start of the first period
+ */
+from: string, 
+/**
+ * End of the last period
+ */
+to: string, period: PnlPeriod, periods: Array<PnlPeriodFigures>, totals: PnlFigures, 
+/**
+ * Totals of the same number of periods just before `from`
+ */
+previous_totals: PnlFigures, };
\ No newline at end of file
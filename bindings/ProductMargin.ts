@@ -0,0 +1,15 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type ProductMargin = { inventory_id: number, name: string, units_sold: bigint, 
+/**
+ * Line totals after discounts
+ */
+revenue: string, 
+/**
+ * Cost of the units sold, by the inventory costing method
+ */
+cogs: string, margin: string, 
+/**
+ * Margin as a percentage of the revenue, null without revenue
+ */
+margin_percentage: string | null, };
\ No newline at end of file
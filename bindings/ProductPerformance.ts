@@ -0,0 +1,31 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type ProductPerformance = { inventory_id: number, name: string, stock: number, 
+/**
+ * Position by units sold, null without sales in the range
+ */
+rank: bigint | null, units_sold: bigint, 
+/**
+ * Line totals after discounts
+ */
+revenue: string, 
+/**
+ * Difference between the list price of the lines and their totals
+ */
+discount: string, 
+/**
+ * Null for items that were never purchased, as their cost is unknown
+ */
+cogs: string | null, margin: string | null, margin_percentage: string | null, 
+/**
+ * Last sale up to the end of the range
+ */
+last_sold_at: string | null, 
+/**
+ * Days the stock lasts at the average daily sales of the range, null without sales
+ */
+days_of_cover: string | null, 
+/**
+ * In stock but not sold in the last `dead_stock_days` days of the range
+ */
+dead_stock: boolean, };
\ No newline at end of file
@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ProductPerformance } from "./ProductPerformance";
+
+export type ProductReport = { from: string, to: string, dead_stock_days: number, products: Array<ProductPerformance>, };
\ No newline at end of file
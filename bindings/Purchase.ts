@@ -3,4 +3,8 @@ import type { PurchaseItem } from "./PurchaseItem";
 import type { Supplier } from "./Supplier";
 import type { User } from "./User";
 
-export type Purchase = { items: Array<PurchaseItem>, id: number, created_by_user: User, supplier: Supplier, date_time: string, amount_paid: string, notes: string, };
\ No newline at end of file
+export type Purchase = { items: Array<PurchaseItem>, id: number, created_by_user: User, supplier: Supplier, date_time: string, 
+/**
+ * Sum of the payments allocated to this purchase
+ */
+amount_paid: string, notes: string, };
\ No newline at end of file
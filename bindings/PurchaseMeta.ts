@@ -2,4 +2,8 @@
 import type { Supplier } from "./Supplier";
 import type { User } from "./User";
 
-export type PurchaseMeta = { id: number, created_by_user: User, supplier: Supplier, date_time: string, amount_paid: string, notes: string, };
\ No newline at end of file
+export type PurchaseMeta = { id: number, created_by_user: User, supplier: Supplier, date_time: string, 
+/**
+ * Sum of the payments allocated to this purchase
+ */
+amount_paid: string, notes: string, };
\ No newline at end of file
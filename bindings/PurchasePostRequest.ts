@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type PurchasePostRequest = { supplier_id: number, notes: string, amount_paid: string, };
\ No newline at end of file
+export type PurchasePostRequest = { supplier_id: number, notes: string, };
\ No newline at end of file
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type RecoveryCodes = { 
+/**
+ * Only shown once, each can be used instead of a TOTP code one time
+ */
+codes: Array<string>, };
\ No newline at end of file
@@ -1,3 +1,8 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
+/**
+ * Filters of the same kind match any of their ids, different kinds all have to match.
+ * Documents that can't match a filter are left out, e.g. a customer filter leaves out every
+ * purchase and expense.
+ */
 export type ReportFilter = { "UserId": number } | { "ProductId": number } | { "CustomerId": number } | { "SupplierId": number };
\ No newline at end of file
@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type ResetPasswordResponse = { 
+/**
+ * Has to be changed by the user on their next login
+ */
+temporary_password: string, };
\ No newline at end of file
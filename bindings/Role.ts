@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PermissionKey } from "./PermissionKey";
+
+export type Role = { id: number, name: string, description: string, permissions: Array<PermissionKey>, created_at: string, 
+/**
+ * Number of users with this role, including archived ones
+ */
+user_count: bigint, };
\ No newline at end of file
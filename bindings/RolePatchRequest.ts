@@ -0,0 +1,8 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PermissionKey } from "./PermissionKey";
+
+export type RolePatchRequest = { name: string | null, description: string | null, 
+/**
+ * Replaces all permissions of the role
+ */
+permissions: Array<PermissionKey> | null, };
\ No newline at end of file
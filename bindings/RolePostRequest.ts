@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { PermissionKey } from "./PermissionKey";
+
+export type RolePostRequest = { name: string, description: string, permissions: Array<PermissionKey>, };
\ No newline at end of file
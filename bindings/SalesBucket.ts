@@ -0,0 +1,19 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type SalesBucket = { 
+/**
+ * First day of the bucket in the business timezone
+ */
+start: string, 
+/**
+ * Customer, inventory item or user id, null without grouping and for retail orders
+ */
+group_id: number | null, group_name: string | null, 
+/**
+ * Line totals after discounts
+ */
+revenue: string, 
+/**
+ * Orders with items in the bucket and group
+ */
+orders: bigint, units_sold: bigint, average_order_value: string, };
\ No newline at end of file
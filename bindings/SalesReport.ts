@@ -0,0 +1,10 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { SalesBucket } from "./SalesBucket";
+import type { SalesGroupBy } from "./SalesGroupBy";
+import type { SalesInterval } from "./SalesInterval";
+
+export type SalesReport = { 
+/**
+ * Timezone setting the orders were bucketed in
+ */
+timezone: string, interval: SalesInterval, group_by: SalesGroupBy | null, buckets: Array<SalesBucket>, };
\ No newline at end of file
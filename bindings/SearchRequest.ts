@@ -8,8 +8,11 @@ export type SearchRequest = { search: string, column: string | null, count: numb
  * Access nested columns using dot notation
  * customers.name
  * This will mean that column is disregarded
- * NOTE: Nested access only works if the tables are
- * joined in the query. This will happen if there is a foreign key
- * relationship between the tables.
+ * NOTE: Only columns the route allows can be searched, see routes/query.rs.
+ * Others are rejected with 400 Bad Request and the list of valid names.
  */
-nested_access: string | null, };
\ No newline at end of file
+nested_access: string | null, 
+/**
+ * Also return archived rows. Only used by tables that can be archived
+ */
+include_archived: boolean, };
\ No newline at end of file
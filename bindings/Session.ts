@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type Session = { id: number, created_at: string, last_seen_at: string, 
+/**
+ * Sessions without an expiry last until they are logged out
+ */
+expires_at: string | null, ip_address: string | null, user_agent: string | null, 
+/**
+ * True for the session that made the request
+ */
+current: boolean, };
\ No newline at end of file
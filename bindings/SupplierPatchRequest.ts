@@ -1,3 +1,7 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type SupplierPatchRequest = { name: string | null, phone: string | null, address: string | null, notes: string | null, };
\ No newline at end of file
+export type SupplierPatchRequest = { name: string | null, phone: string | null, address: string | null, notes: string | null, payment_terms_days?: number, 
+/**
+ * Go back to the payment_terms_days setting
+ */
+set_payment_terms_days_null: boolean, };
\ No newline at end of file
@@ -1,3 +1,7 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type SupplierPostRequest = { name: string, phone: string, address: string, notes: string, };
\ No newline at end of file
+export type SupplierPostRequest = { name: string, phone: string, address: string, notes: string, 
+/**
+ * Null uses the payment_terms_days setting
+ */
+payment_terms_days?: number, };
\ No newline at end of file
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type TransferType = "Incoming" | "Outgoing";
\ No newline at end of file
+export type TransferType = "INCOMING" | "OUTGOING";
\ No newline at end of file
@@ -0,0 +1,11 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type TwoFactorSetup = { 
+/**
+ * Base32 secret for entering manually
+ */
+secret: string, 
+/**
+ * otpauth:// URI to show as a QR code
+ */
+provisioning_uri: string, };
\ No newline at end of file
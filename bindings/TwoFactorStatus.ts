@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type TwoFactorStatus = { enabled: boolean, 
+/**
+ * The user holds a permission that requires 2FA
+ */
+required: boolean, unused_recovery_codes: bigint, };
\ No newline at end of file
@@ -1,4 +1,12 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
-import type { UserPermissionsVec } from "./UserPermissionsVec";
+import type { PermissionKey } from "./PermissionKey";
 
-export type User = { id: number, username: string, permissions: UserPermissionsVec, };
\ No newline at end of file
+export type User = { id: number, username: string, 
+/**
+ * Keys granted by all roles of the user
+ */
+permissions: Array<PermissionKey>, 
+/**
+ * The user has to change their password before doing anything else
+ */
+password_change_required: boolean, two_factor_enabled: boolean, };
\ No newline at end of file
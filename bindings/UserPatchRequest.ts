@@ -0,0 +1,7 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type UserPatchRequest = { username: string | null, 
+/**
+ * Replaces all roles of the user
+ */
+roles: Array<number> | null, };
\ No newline at end of file
@@ -1,3 +0,0 @@
-// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
-
-export type UserPermissionEnum = "INVENTORY_CREATE" | "INVENTORY_READ" | "INVENTORY_UPDATE" | "INVENTORY_DELETE" | "ORDER_CREATE" | "ORDER_READ" | "ORDER_UPDATE" | "ORDER_DELETE" | "CUSTOMERS_CREATE" | "CUSTOMERS_READ" | "CUSTOMERS_UPDATE" | "CUSTOMERS_DELETE" | "SUPPLIERS_CREATE" | "SUPPLIERS_READ" | "SUPPLIERS_UPDATE" | "SUPPLIERS_DELETE" | "EXPENSES_CREATE" | "EXPENSES_READ" | "EXPENSES_UPDATE" | "EXPENSES_DELETE" | "PURCHASE_CREATE" | "PURCHASE_READ" | "PURCHASE_UPDATE" | "PURCHASE_DELETE" | "PAYMENT_CREATE" | "PAYMENT_READ" | "PAYMENT_UPDATE" | "PAYMENT_DELETE" | "REPORTS" | "MANAGE_DB" | "SETTINGS" | "ADMIN";
\ No newline at end of file
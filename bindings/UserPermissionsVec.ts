@@ -1,4 +0,0 @@
-// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
-import type { UserPermissionEnum } from "./UserPermissionEnum";
-
-export type UserPermissionsVec = Array<UserPermissionEnum>;
\ No newline at end of file
@@ -2,7 +2,7 @@ import { get, writable, type Writable } from 'svelte/store';
 import { api_call } from './backend';
 import { type User } from '$bindings/User';
 import { goto } from '$app/navigation';
-import type { UserPermissionEnum } from '$bindings/UserPermissionEnum';
+import type { PermissionKey } from '$bindings/PermissionKey';
 
 /// This is a store that will hold the login information
 /// of the user.
@@ -10,7 +10,7 @@ import type { UserPermissionEnum } from '$bindings/UserPermissionEnum';
 /// For type info, check Rust code for the AuthInfo struct.
 export const auth_info_store: Writable<User | null> = writable(null);
 
-export function user_has_permission(permission: UserPermissionEnum): boolean {
+export function user_has_permission(permission: PermissionKey): boolean {
 	const auth_info = get(auth_info_store);
 
 	if (auth_info === null) {
@@ -3,13 +3,12 @@ use std::future::Future;
 use pollster::FutureExt;
 use rocket::{Ignite, Rocket, Sentinel};
 use rocket_db_pools::Connection;
-use sqlx::Row;
 
 pub use rocket_db_pools::sqlx;
 
 use crate::{
-    routes::{auth::add_user_to_db, ApiError},
-    types::permissions::{UserPermissionEnum, UserPermissions},
+    migrations,
+    routes::{auth::add_user_to_db, roles::administrator_role, ApiError},
 };
 
 pub type DB = Connection<DatabaseConnection>;
@@ -25,32 +24,34 @@ impl From<sqlx::PgPool> for DatabaseConnection {
     fn from(pool: sqlx::PgPool) -> Self {
         log::info!("Creating a new database connection pool");
 
-        let schema_exists = sqlx::query("SELECT EXISTS (SELECT 1 FROM information_schema.schemata WHERE schema_name = 'public')")
-            .fetch_one(&pool)
-            .block_on();
-
-        if let Err(e) = schema_exists {
-            log::error!("Error checking if schema exists: {:?}", e);
-            std::process::exit(1);
-        }
-
-        let schema_exists: bool = schema_exists.unwrap().get(0);
+        if let Some(target) = crate::env::migrate_down_to() {
+            log::warn!("Reverting database schema to version {}", target);
 
-        let schema_str = include_str!("schema.sql");
+            if let Err(e) = migrations::revert_to(&pool, target).block_on() {
+                log::error!("Error reverting migrations: {}", e);
+                std::process::exit(1);
+            }
 
-        if !schema_exists {
-            log::warn!("Schema does not exist");
+            log::warn!(
+                "Database schema reverted to version {}, unset MIGRATE_DOWN_TO to start the server",
+                target
+            );
+            std::process::exit(0);
         }
 
-        log::info!("Running schema creation in case a table is missing");
+        log::info!("Running pending schema migrations");
 
-        let create_schema = sqlx::raw_sql(schema_str).execute(&pool).block_on();
-
-        if let Err(e) = create_schema {
-            log::error!("Error creating schema: {:?}", e);
+        // Refuses to serve if the database is ahead of this binary
+        if let Err(e) = migrations::run_pending(&pool).block_on() {
+            log::error!("Error running migrations: {}", e);
             std::process::exit(1);
         }
 
+        log::info!(
+            "Database schema is at version {}",
+            migrations::latest_version()
+        );
+
         // If no users with admin rights exist, create a default admin user
         // This will ensure that the first user can always log in
         // TODO: In the future, when implementing user deletion, make sure that there is always at
@@ -71,12 +72,19 @@ impl From<sqlx::PgPool> for DatabaseConnection {
                 "No users found, creating default admin user (username: admin, password: admin)"
             );
 
-            let create_user_res = add_user_to_db(
-                "admin",
-                "admin",
-                UserPermissions(UserPermissionEnum::ADMIN as u32),
-                &pool,
-            )
+            let create_user_res = async {
+                let role_id = administrator_role(&pool).await?;
+
+                add_user_to_db(
+                    "admin",
+                    "admin",
+                    &[role_id],
+                    // The default password is public, so force it to be changed
+                    true,
+                    &pool,
+                )
+                .await
+            }
             .block_on();
 
             if let Err(e) = create_user_res {
@@ -14,4 +14,18 @@ pub fn api_root() -> String {
 
             "/".to_string()
         })
-}
\ No newline at end of file
+}
+
+/// Schema version to revert the database to. When set, the server reverts the
+/// migrations and exits instead of starting.
+pub fn migrate_down_to() -> Option<i32> {
+    let version = std::env::var("MIGRATE_DOWN_TO").ok()?;
+
+    match version.parse() {
+        Ok(version) => Some(version),
+        Err(e) => {
+            log::error!("Invalid MIGRATE_DOWN_TO value \"{}\": {}", version, e);
+            std::process::exit(1);
+        }
+    }
+}
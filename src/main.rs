@@ -3,8 +3,10 @@ use rocket_db_pools::Database;
 
 pub mod db;
 pub mod env;
+pub mod migrations;
 pub mod settings;
 pub mod routes;
+pub mod totp;
 
 pub mod types;
 
@@ -34,7 +36,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .attach(cors_options.to_cors().unwrap())
         .attach(DatabaseConnection::init());
     
-    let rocket = if env::public_dir().len() > 0 {
+    let rocket = if !env::public_dir().is_empty() {
         log::info!("Serving static files from: {}", env::public_dir());
         rocket.mount("/", rocket::fs::FileServer::from(env::public_dir()))
     } else {
@@ -0,0 +1,607 @@
+//! Versioned schema migrations.
+//!
+//! Every migration is a pair of `NNNN_name.up.sql` / `NNNN_name.down.sql` files in
+//! `src/migrations/`, embedded into the binary through [`MIGRATIONS`]. Applied versions are
+//! recorded in the `schema_migrations` table, so each migration runs exactly once per database.
+//!
+//! NOTE: Never edit a migration that has already been released, add a new one instead.
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, PgPool};
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+/// Update this array when adding new migrations.
+/// Versions must be unique and in ascending order.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        up: include_str!("migrations/0001_initial.up.sql"),
+        down: include_str!("migrations/0001_initial.down.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "argon2_passwords",
+        up: include_str!("migrations/0002_argon2_passwords.up.sql"),
+        down: include_str!("migrations/0002_argon2_passwords.down.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "archived_at",
+        up: include_str!("migrations/0003_archived_at.up.sql"),
+        down: include_str!("migrations/0003_archived_at.down.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "sessions",
+        up: include_str!("migrations/0004_sessions.up.sql"),
+        down: include_str!("migrations/0004_sessions.down.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "login_lockout",
+        up: include_str!("migrations/0005_login_lockout.up.sql"),
+        down: include_str!("migrations/0005_login_lockout.down.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "two_factor",
+        up: include_str!("migrations/0006_two_factor.up.sql"),
+        down: include_str!("migrations/0006_two_factor.down.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "roles",
+        up: include_str!("migrations/0007_roles.up.sql"),
+        down: include_str!("migrations/0007_roles.down.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "api_tokens",
+        up: include_str!("migrations/0008_api_tokens.up.sql"),
+        down: include_str!("migrations/0008_api_tokens.down.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "payment_allocations",
+        up: include_str!("migrations/0009_payment_allocations.up.sql"),
+        down: include_str!("migrations/0009_payment_allocations.down.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "payment_terms",
+        up: include_str!("migrations/0010_payment_terms.up.sql"),
+        down: include_str!("migrations/0010_payment_terms.down.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "inventory_costing",
+        up: include_str!("migrations/0011_inventory_costing.up.sql"),
+        down: include_str!("migrations/0011_inventory_costing.down.sql"),
+    },
+];
+
+/// Arbitrary key for the advisory lock that stops two server instances
+/// from migrating the same database at the same time.
+const MIGRATION_LOCK_KEY: i64 = 0x4552_505F_4D49_4752;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Database(sqlx::Error),
+    /// The database has migrations applied that this binary does not know about.
+    /// Serving requests would mean running old queries against a newer schema.
+    DatabaseAhead { database: i32, binary: i32 },
+    UnknownVersion(i32),
+    Failed {
+        version: i32,
+        name: &'static str,
+        error: sqlx::Error,
+    },
+}
+
+impl std::fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationError::Database(e) => write!(f, "Database error: {}", e),
+            MigrationError::DatabaseAhead { database, binary } => write!(
+                f,
+                "Database schema version {} is newer than the latest version known by this binary ({})",
+                database, binary
+            ),
+            MigrationError::UnknownVersion(version) => {
+                write!(f, "Unknown migration version {}", version)
+            }
+            MigrationError::Failed {
+                version,
+                name,
+                error,
+            } => write!(f, "Migration {} ({}) failed: {}", version, name, error),
+        }
+    }
+}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(error: sqlx::Error) -> Self {
+        MigrationError::Database(error)
+    }
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub struct AppliedMigration {
+    pub version: i32,
+    pub name: String,
+    pub applied_at: chrono::DateTime<chrono::Utc>,
+}
+
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+async fn ensure_migrations_table(pool: &PgPool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE
+            IF NOT EXISTS schema_migrations (
+                version INT PRIMARY KEY,
+                name TEXT NOT NULL,
+                applied_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn current_version(pool: &PgPool) -> Result<i32, sqlx::Error> {
+    ensure_migrations_table(pool).await?;
+
+    sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_migrations")
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn applied_migrations(pool: &PgPool) -> Result<Vec<AppliedMigration>, sqlx::Error> {
+    ensure_migrations_table(pool).await?;
+
+    sqlx::query_as("SELECT version, name, applied_at FROM schema_migrations ORDER BY version")
+        .fetch_all(pool)
+        .await
+}
+
+/// Apply every migration newer than the current database version.
+/// Each migration runs in its own transaction together with its bookkeeping row.
+///
+/// Databases created before migrations existed have no `schema_migrations` table, so they start
+/// at version 0. The initial migration only uses `IF NOT EXISTS` / `CREATE OR REPLACE` statements,
+/// which makes it safe to run against those databases.
+pub async fn run_pending(pool: &PgPool) -> Result<(), MigrationError> {
+    let current = current_version(pool).await?;
+    let latest = latest_version();
+
+    if current > latest {
+        return Err(MigrationError::DatabaseAhead {
+            database: current,
+            binary: latest,
+        });
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let mut conn = pool.acquire().await?;
+        let mut transaction = conn.begin().await?;
+
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut *transaction)
+            .await?;
+
+        // Another instance may have applied it while we were waiting for the lock
+        let already_applied: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM schema_migrations WHERE version = $1)")
+                .bind(migration.version)
+                .fetch_one(&mut *transaction)
+                .await?;
+
+        if already_applied {
+            transaction.rollback().await?;
+            continue;
+        }
+
+        log::info!(
+            "Applying migration {} ({})",
+            migration.version,
+            migration.name
+        );
+
+        sqlx::raw_sql(migration.up)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|error| MigrationError::Failed {
+                version: migration.version,
+                name: migration.name,
+                error,
+            })?;
+
+        sqlx::query("INSERT INTO schema_migrations (version, name) VALUES ($1, $2)")
+            .bind(migration.version)
+            .bind(migration.name)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+    }
+
+    Ok(())
+}
+
+/// Revert migrations until the database is at `target` version.
+/// Used for rolling back a deployment, see `env::migrate_down_to`.
+pub async fn revert_to(pool: &PgPool, target: i32) -> Result<(), MigrationError> {
+    if target != 0 && !MIGRATIONS.iter().any(|m| m.version == target) {
+        return Err(MigrationError::UnknownVersion(target));
+    }
+
+    let current = current_version(pool).await?;
+
+    if current > latest_version() {
+        return Err(MigrationError::DatabaseAhead {
+            database: current,
+            binary: latest_version(),
+        });
+    }
+
+    for migration in MIGRATIONS
+        .iter()
+        .rev()
+        .filter(|m| m.version > target && m.version <= current)
+    {
+        let mut conn = pool.acquire().await?;
+        let mut transaction = conn.begin().await?;
+
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut *transaction)
+            .await?;
+
+        log::warn!(
+            "Reverting migration {} ({})",
+            migration.version,
+            migration.name
+        );
+
+        sqlx::raw_sql(migration.down)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|error| MigrationError::Failed {
+                version: migration.version,
+                name: migration.name,
+                error,
+            })?;
+
+        sqlx::query("DELETE FROM schema_migrations WHERE version = $1")
+            .bind(migration.version)
+            .execute(&mut *transaction)
+            .await?;
+
+        transaction.commit().await?;
+    }
+
+    Ok(())
+}
+
+/// The inventory costing engine is `recompute_inventory_cost` in SQL, so these run against a
+/// real database: `TEST_DATABASE_URL=postgres://... cargo test -- --ignored`.
+/// Pending migrations are applied to it, and every test rolls back what it wrote.
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use sqlx::{types::BigDecimal, Postgres, Transaction};
+
+    use super::*;
+
+    async fn test_pool() -> PgPool {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL isn't set");
+        let pool = PgPool::connect(&url).await.unwrap();
+
+        run_pending(&pool).await.unwrap();
+
+        pool
+    }
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    /// One inventory item with its purchases and sales, inside a transaction
+    struct Ledger<'a> {
+        transaction: Transaction<'a, Postgres>,
+        inventory_id: i32,
+        user_id: i32,
+        supplier_id: i32,
+    }
+
+    impl<'a> Ledger<'a> {
+        async fn new(pool: &'a PgPool, method: &str) -> Ledger<'a> {
+            let mut transaction = pool.begin().await.unwrap();
+
+            let user_id = sqlx::query_scalar(
+                "INSERT INTO users (username, password, salt) VALUES ('costing', '', '') RETURNING id",
+            )
+            .fetch_one(&mut *transaction)
+            .await
+            .unwrap();
+
+            let supplier_id =
+                sqlx::query_scalar("INSERT INTO suppliers (name) VALUES ('Costing') RETURNING id")
+                    .fetch_one(&mut *transaction)
+                    .await
+                    .unwrap();
+
+            let inventory_id = sqlx::query_scalar(
+                r#"
+                INSERT INTO inventory (name, description, price, stock, quantity_per_box)
+                VALUES ('Costing', '', 10, 0, 1)
+                RETURNING id
+                "#,
+            )
+            .fetch_one(&mut *transaction)
+            .await
+            .unwrap();
+
+            let mut ledger = Ledger {
+                transaction,
+                inventory_id,
+                user_id,
+                supplier_id,
+            };
+            ledger.set_method(method).await;
+
+            ledger
+        }
+
+        async fn set_method(&mut self, method: &str) {
+            sqlx::query("DELETE FROM settings WHERE key = 'inventory_costing_method'")
+                .execute(&mut *self.transaction)
+                .await
+                .unwrap();
+
+            sqlx::query(
+                r#"
+                INSERT INTO settings (key, long_name, value)
+                VALUES ('inventory_costing_method', 'Inventory Costing Method', $1)
+                "#,
+            )
+            .bind(serde_json::json!({ "Text": method }))
+            .execute(&mut *self.transaction)
+            .await
+            .unwrap();
+        }
+
+        /// Returns the id of the purchase
+        async fn purchase(&mut self, date: &str, quantity: i32, price: &str) -> i32 {
+            let purchase_id: i32 = sqlx::query_scalar(
+                r#"
+                INSERT INTO purchases (date_time, supplier_id, created_by_user_id)
+                VALUES ($1::DATE, $2, $3)
+                RETURNING id
+                "#,
+            )
+            .bind(date)
+            .bind(self.supplier_id)
+            .bind(self.user_id)
+            .fetch_one(&mut *self.transaction)
+            .await
+            .unwrap();
+
+            sqlx::query(
+                r#"
+                INSERT INTO purchase_items (purchase_id, inventory_id, quantity, price)
+                VALUES ($1, $2, $3, $4)
+                "#,
+            )
+            .bind(purchase_id)
+            .bind(self.inventory_id)
+            .bind(quantity)
+            .bind(decimal(price))
+            .execute(&mut *self.transaction)
+            .await
+            .unwrap();
+
+            purchase_id
+        }
+
+        /// Returns the id of the order
+        async fn sale(&mut self, date: &str, quantity: i32) -> i32 {
+            let order_id: i32 = sqlx::query_scalar(
+                "INSERT INTO orders (date_time, created_by_user_id) VALUES ($1::DATE, $2) RETURNING id",
+            )
+            .bind(date)
+            .bind(self.user_id)
+            .fetch_one(&mut *self.transaction)
+            .await
+            .unwrap();
+
+            sqlx::query(
+                r#"
+                INSERT INTO order_items (order_id, inventory_id, quantity, price)
+                VALUES ($1, $2, $3, 10)
+                "#,
+            )
+            .bind(order_id)
+            .bind(self.inventory_id)
+            .bind(quantity)
+            .execute(&mut *self.transaction)
+            .await
+            .unwrap();
+
+            order_id
+        }
+
+        async fn move_order(&mut self, order_id: i32, date: &str) {
+            sqlx::query("UPDATE orders SET date_time = $1::DATE WHERE id = $2")
+                .bind(date)
+                .bind(order_id)
+                .execute(&mut *self.transaction)
+                .await
+                .unwrap();
+        }
+
+        async fn move_purchase(&mut self, purchase_id: i32, date: &str) {
+            sqlx::query("UPDATE purchases SET date_time = $1::DATE WHERE id = $2")
+                .bind(date)
+                .bind(purchase_id)
+                .execute(&mut *self.transaction)
+                .await
+                .unwrap();
+        }
+
+        async fn cost(&mut self, order_id: i32) -> BigDecimal {
+            sqlx::query_scalar("SELECT cost FROM order_items WHERE order_id = $1")
+                .bind(order_id)
+                .fetch_one(&mut *self.transaction)
+                .await
+                .unwrap()
+        }
+
+        async fn unit_cost(&mut self) -> BigDecimal {
+            sqlx::query_scalar("SELECT unit_cost FROM inventory WHERE id = $1")
+                .bind(self.inventory_id)
+                .fetch_one(&mut *self.transaction)
+                .await
+                .unwrap()
+        }
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn fifo_uses_the_oldest_purchases_first() {
+        let pool = test_pool().await;
+        let mut ledger = Ledger::new(&pool, "FIFO").await;
+
+        ledger.purchase("2024-01-01", 10, "2").await;
+        ledger.purchase("2024-01-02", 10, "3").await;
+        let first = ledger.sale("2024-01-03", 4).await;
+        let second = ledger.sale("2024-01-04", 8).await;
+
+        assert_eq!(ledger.cost(first).await, decimal("8"));
+        // The 6 left at 2 and then 2 at 3
+        assert_eq!(ledger.cost(second).await, decimal("18"));
+        // 8 left at 3
+        assert_eq!(ledger.unit_cost().await, decimal("3"));
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn weighted_average_uses_the_average_in_stock() {
+        let pool = test_pool().await;
+        let mut ledger = Ledger::new(&pool, "WEIGHTED_AVERAGE").await;
+
+        ledger.purchase("2024-01-01", 10, "2").await;
+        ledger.purchase("2024-01-02", 30, "4").await;
+        let first = ledger.sale("2024-01-03", 20).await;
+        ledger.purchase("2024-01-04", 20, "6.5").await;
+        let second = ledger.sale("2024-01-05", 10).await;
+
+        // (10 * 2 + 30 * 4) / 40 = 3.5
+        assert_eq!(ledger.cost(first).await, decimal("70"));
+        // (20 * 3.5 + 20 * 6.5) / 40 = 5
+        assert_eq!(ledger.cost(second).await, decimal("50"));
+        assert_eq!(ledger.unit_cost().await, decimal("5"));
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn fifo_oversold_units_come_out_of_the_next_purchase() {
+        let pool = test_pool().await;
+        let mut ledger = Ledger::new(&pool, "FIFO").await;
+
+        ledger.purchase("2024-01-01", 2, "1").await;
+        // 2 at 1, then 3 without stock at the last price
+        let oversold = ledger.sale("2024-01-02", 5).await;
+        assert_eq!(ledger.cost(oversold).await, decimal("5"));
+
+        // 3 of these make up for the oversold units, 7 are left
+        ledger.purchase("2024-01-03", 10, "2").await;
+        assert_eq!(ledger.unit_cost().await, decimal("2"));
+
+        ledger.purchase("2024-01-04", 5, "5").await;
+        // 7 at 2 and 1 at 5, none of the units the oversell already used
+        let sale = ledger.sale("2024-01-05", 8).await;
+        assert_eq!(ledger.cost(sale).await, decimal("19"));
+        assert_eq!(ledger.unit_cost().await, decimal("5"));
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn selling_before_any_purchase() {
+        for method in ["FIFO", "WEIGHTED_AVERAGE"] {
+            let pool = test_pool().await;
+            let mut ledger = Ledger::new(&pool, method).await;
+
+            let oversold = ledger.sale("2024-01-01", 5).await;
+            ledger.purchase("2024-01-02", 10, "2").await;
+
+            assert_eq!(ledger.cost(oversold).await, decimal("0"), "{}", method);
+            // 5 in stock, all bought at 2
+            assert_eq!(ledger.unit_cost().await, decimal("2"), "{}", method);
+        }
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn moving_an_order_or_purchase_replays_the_history() {
+        let pool = test_pool().await;
+        let mut ledger = Ledger::new(&pool, "FIFO").await;
+
+        ledger.purchase("2024-01-01", 5, "2").await;
+        let late_purchase = ledger.purchase("2024-01-03", 5, "4").await;
+        let first = ledger.sale("2024-01-02", 5).await;
+        let second = ledger.sale("2024-01-04", 5).await;
+
+        assert_eq!(ledger.cost(first).await, decimal("10"));
+        assert_eq!(ledger.cost(second).await, decimal("20"));
+
+        // The second sale now comes first and gets the older units, which leaves the first
+        // one without stock at the last price
+        ledger.move_order(second, "2024-01-01").await;
+        assert_eq!(ledger.cost(second).await, decimal("10"));
+        assert_eq!(ledger.cost(first).await, decimal("10"));
+        assert_eq!(ledger.unit_cost().await, decimal("4"));
+
+        // Both purchases are in stock before either sale
+        ledger.move_purchase(late_purchase, "2023-12-31").await;
+        assert_eq!(ledger.cost(second).await, decimal("20"));
+        assert_eq!(ledger.cost(first).await, decimal("10"));
+    }
+
+    #[rocket::async_test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn changing_the_method_recomputes_costs() {
+        let pool = test_pool().await;
+        let mut ledger = Ledger::new(&pool, "FIFO").await;
+
+        ledger.purchase("2024-01-01", 10, "2").await;
+        ledger.purchase("2024-01-02", 10, "4").await;
+        let sale = ledger.sale("2024-01-03", 10).await;
+
+        assert_eq!(ledger.cost(sale).await, decimal("20"));
+        assert_eq!(ledger.unit_cost().await, decimal("4"));
+
+        ledger.set_method("WEIGHTED_AVERAGE").await;
+        assert_eq!(ledger.cost(sale).await, decimal("30"));
+        assert_eq!(ledger.unit_cost().await, decimal("3"));
+
+        ledger.set_method("FIFO").await;
+        assert_eq!(ledger.cost(sale).await, decimal("20"));
+        assert_eq!(ledger.unit_cost().await, decimal("4"));
+    }
+}
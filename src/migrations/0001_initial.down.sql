@@ -0,0 +1,33 @@
+DROP TABLE IF EXISTS settings;
+DROP TABLE IF EXISTS stock_updates;
+DROP TABLE IF EXISTS expenses;
+
+DROP TRIGGER IF EXISTS prevent_customer_deletion ON customers;
+DROP TRIGGER IF EXISTS prevent_supplier_deletion ON suppliers;
+DROP TRIGGER IF EXISTS prevent_order_deletion ON orders;
+DROP FUNCTION IF EXISTS prevent_deletion_if_referenced();
+
+DROP TRIGGER IF EXISTS enforce_party_fk_trigger ON payments;
+DROP FUNCTION IF EXISTS enforce_party_fk();
+
+DROP TABLE IF EXISTS payments;
+
+DROP TYPE IF EXISTS payment_method_t;
+DROP TYPE IF EXISTS party_type_t;
+DROP TYPE IF EXISTS transfer_type_t;
+
+DROP TABLE IF EXISTS purchase_items;
+
+DROP TRIGGER IF EXISTS update_order_total_insert ON order_items;
+DROP TRIGGER IF EXISTS update_order_total_update ON order_items;
+DROP TRIGGER IF EXISTS update_order_total_delete ON order_items;
+DROP FUNCTION IF EXISTS update_order_total();
+DROP FUNCTION IF EXISTS get_order_total(INT);
+
+DROP TABLE IF EXISTS order_items;
+DROP TABLE IF EXISTS purchases;
+DROP TABLE IF EXISTS orders;
+DROP TABLE IF EXISTS inventory;
+DROP TABLE IF EXISTS suppliers;
+DROP TABLE IF EXISTS customers;
+DROP TABLE IF EXISTS users;
@@ -0,0 +1,4 @@
+-- NOTE: Argon2id hashes can not be converted back to SHA-256,
+-- users that logged in since the upgrade will not be able to log in on older versions.
+ALTER TABLE users
+    DROP COLUMN IF EXISTS password_change_required;
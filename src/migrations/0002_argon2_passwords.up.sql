@@ -0,0 +1,11 @@
+-- Argon2id PHC strings embed their own salt, so the salt column is only
+-- used by legacy SHA-256 hashes until they are upgraded on login.
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS password_change_required BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- New databases get a default admin/admin account that has to change its password, flag an
+-- existing one that still has it. Every hash is a legacy SHA-256 one at this point.
+UPDATE users
+SET password_change_required = TRUE
+WHERE username = 'admin'
+    AND password = encode(sha256(convert_to('admin' || salt, 'UTF8')), 'hex');
@@ -0,0 +1,11 @@
+ALTER TABLE inventory
+    DROP COLUMN IF EXISTS archived_at;
+
+ALTER TABLE suppliers
+    DROP COLUMN IF EXISTS archived_at;
+
+ALTER TABLE customers
+    DROP COLUMN IF EXISTS archived_at;
+
+ALTER TABLE users
+    DROP COLUMN IF EXISTS archived_at;
@@ -0,0 +1,22 @@
+-- Server-side sessions replace the user data stored in the auth cookie.
+-- The cookie only holds an opaque token, its SHA-256 is stored here.
+CREATE TABLE
+    IF NOT EXISTS sessions (
+        id SERIAL PRIMARY KEY,
+        token_hash TEXT NOT NULL UNIQUE,
+        user_id INT NOT NULL,
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            last_seen_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            expires_at TIMESTAMP
+        WITH
+            TIME ZONE, -- NULL means the session lasts until logout
+            ip_address TEXT,
+            user_agent TEXT,
+            FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+    );
+
+CREATE INDEX IF NOT EXISTS sessions_user_id_idx ON sessions (user_id);
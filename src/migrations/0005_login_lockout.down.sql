@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS auth_events;
+DROP TABLE IF EXISTS login_failures;
+
+DROP TYPE IF EXISTS auth_event_t;
+DROP TYPE IF EXISTS login_failure_kind_t;
@@ -0,0 +1,41 @@
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'login_failure_kind_t') THEN
+        CREATE TYPE login_failure_kind_t AS ENUM ('USERNAME', 'IP_ADDRESS');
+    END IF;
+
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'auth_event_t') THEN
+        CREATE TYPE auth_event_t AS ENUM ('LOGIN_SUCCESS', 'LOGIN_FAILURE', 'LOGIN_LOCKED');
+    END IF;
+END $$;
+
+-- Failed login counters, see routes/lockout.rs for the backoff policy
+CREATE TABLE
+    IF NOT EXISTS login_failures (
+        kind login_failure_kind_t NOT NULL,
+        key TEXT NOT NULL, -- username or IP address
+        failures INT NOT NULL DEFAULT 1,
+        last_failure_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            locked_until TIMESTAMP
+        WITH
+            TIME ZONE,
+            PRIMARY KEY (kind, key)
+    );
+
+CREATE TABLE
+    IF NOT EXISTS auth_events (
+        id SERIAL PRIMARY KEY,
+        date_time TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            event auth_event_t NOT NULL,
+            username TEXT NOT NULL, -- as sent by the client, might not exist
+            user_id INT,
+            ip_address TEXT,
+            user_agent TEXT,
+            FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE SET NULL
+    );
+
+CREATE INDEX IF NOT EXISTS auth_events_date_time_idx ON auth_events (date_time);
@@ -0,0 +1,37 @@
+ALTER TABLE users
+    ADD COLUMN IF NOT EXISTS totp_secret TEXT, -- base32, set during setup before it's enabled
+    ADD COLUMN IF NOT EXISTS totp_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    ADD COLUMN IF NOT EXISTS totp_last_step BIGINT; -- stops a code from being used twice
+
+CREATE TABLE
+    IF NOT EXISTS recovery_codes (
+        id SERIAL PRIMARY KEY,
+        user_id INT NOT NULL,
+        code_hash TEXT NOT NULL,
+        used_at TIMESTAMP
+        WITH
+            TIME ZONE,
+            FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+    );
+
+CREATE INDEX IF NOT EXISTS recovery_codes_user_id_idx ON recovery_codes (user_id);
+
+-- Issued after the password step of a login when the user has 2FA enabled
+CREATE TABLE
+    IF NOT EXISTS two_factor_challenges (
+        id SERIAL PRIMARY KEY,
+        token_hash TEXT NOT NULL UNIQUE,
+        user_id INT NOT NULL,
+        expires_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL,
+            -- Expiry requested at login, used for the session once the challenge is solved
+            session_expires_at TIMESTAMP
+        WITH
+            TIME ZONE,
+            FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+    );
+
+-- Users with any of these permissions can't use them until they enable 2FA
+CREATE TABLE
+    IF NOT EXISTS two_factor_required_permissions (permission INT PRIMARY KEY);
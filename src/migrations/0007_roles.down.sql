@@ -0,0 +1,96 @@
+-- Bits of the old u32 permission bitmask and the keys that replace them
+CREATE TEMPORARY TABLE legacy_permission_bits (bit INT PRIMARY KEY, key TEXT NOT NULL) ON COMMIT DROP;
+
+INSERT INTO
+    legacy_permission_bits (bit, key)
+VALUES
+    (1 << 0, 'INVENTORY_CREATE'),
+    (1 << 1, 'INVENTORY_READ'),
+    (1 << 2, 'INVENTORY_UPDATE'),
+    (1 << 3, 'INVENTORY_DELETE'),
+    (1 << 4, 'ORDER_CREATE'),
+    (1 << 5, 'ORDER_READ'),
+    (1 << 6, 'ORDER_UPDATE'),
+    (1 << 7, 'ORDER_DELETE'),
+    (1 << 8, 'CUSTOMERS_CREATE'),
+    (1 << 9, 'CUSTOMERS_READ'),
+    (1 << 10, 'CUSTOMERS_UPDATE'),
+    (1 << 11, 'CUSTOMERS_DELETE'),
+    (1 << 12, 'SUPPLIERS_CREATE'),
+    (1 << 13, 'SUPPLIERS_READ'),
+    (1 << 14, 'SUPPLIERS_UPDATE'),
+    (1 << 15, 'SUPPLIERS_DELETE'),
+    (1 << 16, 'EXPENSES_CREATE'),
+    (1 << 17, 'EXPENSES_READ'),
+    (1 << 18, 'EXPENSES_UPDATE'),
+    (1 << 19, 'EXPENSES_DELETE'),
+    (1 << 20, 'PURCHASE_CREATE'),
+    (1 << 21, 'PURCHASE_READ'),
+    (1 << 22, 'PURCHASE_UPDATE'),
+    (1 << 23, 'PURCHASE_DELETE'),
+    (1 << 24, 'PAYMENT_CREATE'),
+    (1 << 25, 'PAYMENT_READ'),
+    (1 << 26, 'PAYMENT_UPDATE'),
+    (1 << 27, 'PAYMENT_DELETE'),
+    (1 << 28, 'REPORTS'),
+    (1 << 29, 'MANAGE_DB'),
+    (1 << 30, 'SETTINGS');
+
+-- Users with ADMIN get every bit, the rest get the bits of their keys
+ALTER TABLE users
+    ADD COLUMN legacy_permissions INT NOT NULL DEFAULT 0;
+
+UPDATE users
+SET
+    legacy_permissions = CASE
+        WHEN 'ADMIN' = ANY (users.permissions) THEN -1
+        ELSE COALESCE(
+            (
+                SELECT
+                    bit_or(bits.bit)
+                FROM
+                    legacy_permission_bits bits
+                WHERE
+                    bits.key = ANY (users.permissions)
+            ),
+            0
+        )
+    END;
+
+ALTER TABLE users
+    DROP COLUMN permissions;
+
+ALTER TABLE users
+    RENAME COLUMN legacy_permissions TO permissions;
+
+ALTER TABLE users
+    ALTER COLUMN permissions DROP DEFAULT;
+
+CREATE TEMPORARY TABLE legacy_two_factor_required ON COMMIT DROP AS
+SELECT DISTINCT
+    CASE
+        WHEN required.permission = 'ADMIN' THEN -1
+        ELSE bits.bit
+    END AS permission
+FROM
+    two_factor_required_permissions required
+    LEFT JOIN legacy_permission_bits bits ON bits.key = required.permission
+WHERE
+    required.permission = 'ADMIN'
+    OR bits.bit IS NOT NULL;
+
+DELETE FROM two_factor_required_permissions;
+
+ALTER TABLE two_factor_required_permissions
+    ALTER COLUMN permission TYPE INT USING 0;
+
+INSERT INTO
+    two_factor_required_permissions (permission)
+SELECT
+    permission
+FROM
+    legacy_two_factor_required;
+
+DROP TABLE IF EXISTS user_roles;
+DROP TABLE IF EXISTS role_permissions;
+DROP TABLE IF EXISTS roles;
@@ -0,0 +1,171 @@
+CREATE TABLE
+    IF NOT EXISTS roles (
+        id SERIAL PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        description TEXT NOT NULL DEFAULT '',
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+-- Keys are registered in src/types/permissions.rs
+CREATE TABLE
+    IF NOT EXISTS role_permissions (
+        role_id INT NOT NULL,
+        permission TEXT NOT NULL,
+        PRIMARY KEY (role_id, permission),
+        FOREIGN KEY (role_id) REFERENCES roles (id) ON DELETE CASCADE
+    );
+
+CREATE TABLE
+    IF NOT EXISTS user_roles (
+        user_id INT NOT NULL,
+        role_id INT NOT NULL,
+        PRIMARY KEY (user_id, role_id),
+        FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+        FOREIGN KEY (role_id) REFERENCES roles (id) ON DELETE CASCADE
+    );
+
+CREATE INDEX IF NOT EXISTS user_roles_role_id_idx ON user_roles (role_id);
+
+-- Bits of the old u32 permission bitmask and the keys that replace them
+CREATE TEMPORARY TABLE legacy_permission_bits (bit INT PRIMARY KEY, key TEXT NOT NULL) ON COMMIT DROP;
+
+INSERT INTO
+    legacy_permission_bits (bit, key)
+VALUES
+    (1 << 0, 'INVENTORY_CREATE'),
+    (1 << 1, 'INVENTORY_READ'),
+    (1 << 2, 'INVENTORY_UPDATE'),
+    (1 << 3, 'INVENTORY_DELETE'),
+    (1 << 4, 'ORDER_CREATE'),
+    (1 << 5, 'ORDER_READ'),
+    (1 << 6, 'ORDER_UPDATE'),
+    (1 << 7, 'ORDER_DELETE'),
+    (1 << 8, 'CUSTOMERS_CREATE'),
+    (1 << 9, 'CUSTOMERS_READ'),
+    (1 << 10, 'CUSTOMERS_UPDATE'),
+    (1 << 11, 'CUSTOMERS_DELETE'),
+    (1 << 12, 'SUPPLIERS_CREATE'),
+    (1 << 13, 'SUPPLIERS_READ'),
+    (1 << 14, 'SUPPLIERS_UPDATE'),
+    (1 << 15, 'SUPPLIERS_DELETE'),
+    (1 << 16, 'EXPENSES_CREATE'),
+    (1 << 17, 'EXPENSES_READ'),
+    (1 << 18, 'EXPENSES_UPDATE'),
+    (1 << 19, 'EXPENSES_DELETE'),
+    (1 << 20, 'PURCHASE_CREATE'),
+    (1 << 21, 'PURCHASE_READ'),
+    (1 << 22, 'PURCHASE_UPDATE'),
+    (1 << 23, 'PURCHASE_DELETE'),
+    (1 << 24, 'PAYMENT_CREATE'),
+    (1 << 25, 'PAYMENT_READ'),
+    (1 << 26, 'PAYMENT_UPDATE'),
+    (1 << 27, 'PAYMENT_DELETE'),
+    (1 << 28, 'REPORTS'),
+    (1 << 29, 'MANAGE_DB'),
+    (1 << 30, 'SETTINGS');
+
+-- ADMIN (all bits set) becomes the Administrator role,
+-- every other combination of permissions gets a role named after its keys
+CREATE TEMPORARY TABLE legacy_user_roles ON COMMIT DROP AS
+SELECT
+    users.id AS user_id,
+    CASE
+        WHEN users.permissions = -1 THEN 'Administrator'
+        ELSE 'Migrated: ' || string_agg(bits.key, ', ' ORDER BY bits.bit)
+    END AS role_name,
+    CASE
+        WHEN users.permissions = -1 THEN ARRAY['ADMIN']
+        ELSE array_agg(bits.key ORDER BY bits.bit)
+    END AS keys
+FROM
+    users
+    JOIN legacy_permission_bits bits ON users.permissions & bits.bit = bits.bit
+GROUP BY
+    users.id;
+
+INSERT INTO
+    roles (name, description)
+VALUES
+    ('Administrator', 'Full access')
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO
+    roles (name, description)
+SELECT DISTINCT
+    role_name,
+    'Created from the permissions of existing users'
+FROM
+    legacy_user_roles
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO
+    role_permissions (role_id, permission)
+SELECT
+    id,
+    'ADMIN'
+FROM
+    roles
+WHERE
+    name = 'Administrator'
+UNION
+SELECT DISTINCT
+    roles.id,
+    UNNEST(legacy_user_roles.keys)
+FROM
+    legacy_user_roles
+    JOIN roles ON roles.name = legacy_user_roles.role_name
+ON CONFLICT DO NOTHING;
+
+INSERT INTO
+    user_roles (user_id, role_id)
+SELECT
+    legacy_user_roles.user_id,
+    roles.id
+FROM
+    legacy_user_roles
+    JOIN roles ON roles.name = legacy_user_roles.role_name
+ON CONFLICT DO NOTHING;
+
+-- users.permissions now caches the keys of all roles of the user, so AuthGuard and
+-- row_to_json(users) don't need to join the role tables. See roles::refresh_user_permissions.
+ALTER TABLE users
+    ALTER COLUMN permissions TYPE TEXT[] USING '{}',
+    ALTER COLUMN permissions SET DEFAULT '{}';
+
+UPDATE users
+SET
+    permissions = ARRAY(
+        SELECT DISTINCT
+            role_permissions.permission
+        FROM
+            user_roles
+            JOIN role_permissions ON role_permissions.role_id = user_roles.role_id
+        WHERE
+            user_roles.user_id = users.id
+        ORDER BY
+            1
+    );
+
+CREATE TEMPORARY TABLE legacy_two_factor_required ON COMMIT DROP AS
+SELECT DISTINCT
+    CASE
+        WHEN required.permission = -1 THEN 'ADMIN'
+        ELSE bits.key
+    END AS key
+FROM
+    two_factor_required_permissions required
+    JOIN legacy_permission_bits bits ON required.permission & bits.bit = bits.bit;
+
+DELETE FROM two_factor_required_permissions;
+
+ALTER TABLE two_factor_required_permissions
+    ALTER COLUMN permission TYPE TEXT;
+
+INSERT INTO
+    two_factor_required_permissions (permission)
+SELECT
+    key
+FROM
+    legacy_two_factor_required;
@@ -0,0 +1,23 @@
+-- Personal access tokens for scripts and devices, sent as `Authorization: Bearer <token>`.
+-- Like sessions, only the SHA-256 of the token is stored.
+CREATE TABLE
+    IF NOT EXISTS api_tokens (
+        id SERIAL PRIMARY KEY,
+        token_hash TEXT NOT NULL UNIQUE,
+        user_id INT NOT NULL,
+        name TEXT NOT NULL,
+        -- Subset of the user's permission keys, checked again on every request
+        permissions TEXT[] NOT NULL DEFAULT '{}',
+        created_at TIMESTAMP
+        WITH
+            TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            last_used_at TIMESTAMP
+        WITH
+            TIME ZONE,
+            expires_at TIMESTAMP
+        WITH
+            TIME ZONE, -- NULL means the token lasts until it's revoked
+            FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE
+    );
+
+CREATE INDEX IF NOT EXISTS api_tokens_user_id_idx ON api_tokens (user_id);
@@ -0,0 +1,10 @@
+-- amount_paid keeps its current values and can be edited by hand again
+DROP TRIGGER IF EXISTS update_amount_paid ON payment_allocations;
+DROP FUNCTION IF EXISTS update_amount_paid();
+
+-- Payments created by the up migration
+DELETE FROM payments
+WHERE notes = 'Recorded from amount_paid'
+    AND id IN (SELECT payment_id FROM payment_allocations);
+
+DROP TABLE IF EXISTS payment_allocations;
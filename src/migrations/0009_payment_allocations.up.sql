@@ -0,0 +1,88 @@
+-- Links payments to the orders and purchases they pay for. One payment can be split
+-- over several orders, and the part of a customer payment that isn't allocated is credit.
+CREATE TABLE
+    IF NOT EXISTS payment_allocations (
+        id SERIAL PRIMARY KEY,
+        payment_id INT NOT NULL,
+        order_id INT,
+        purchase_id INT,
+        amount NUMERIC(32, 4) NOT NULL CHECK (amount > 0),
+        FOREIGN KEY (payment_id) REFERENCES payments (id) ON DELETE CASCADE,
+        -- Deleting an order or purchase frees the money for other allocations
+        FOREIGN KEY (order_id) REFERENCES orders (id) ON DELETE CASCADE,
+        FOREIGN KEY (purchase_id) REFERENCES purchases (id) ON DELETE CASCADE,
+        CHECK ((order_id IS NULL) <> (purchase_id IS NULL))
+    );
+
+CREATE INDEX IF NOT EXISTS payment_allocations_payment_id_idx ON payment_allocations (payment_id);
+CREATE INDEX IF NOT EXISTS payment_allocations_order_id_idx ON payment_allocations (order_id);
+CREATE INDEX IF NOT EXISTS payment_allocations_purchase_id_idx ON payment_allocations (purchase_id);
+
+-- amount_paid of orders and purchases is the sum of their allocations,
+-- kept up to date the same way as orders.total
+CREATE OR REPLACE FUNCTION update_amount_paid() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP <> 'INSERT' THEN
+        UPDATE orders
+        SET amount_paid = (SELECT COALESCE(SUM(amount), 0) FROM payment_allocations WHERE order_id = OLD.order_id)
+        WHERE id = OLD.order_id;
+
+        UPDATE purchases
+        SET amount_paid = (SELECT COALESCE(SUM(amount), 0) FROM payment_allocations WHERE purchase_id = OLD.purchase_id)
+        WHERE id = OLD.purchase_id;
+    END IF;
+
+    IF TG_OP <> 'DELETE' THEN
+        UPDATE orders
+        SET amount_paid = (SELECT COALESCE(SUM(amount), 0) FROM payment_allocations WHERE order_id = NEW.order_id)
+        WHERE id = NEW.order_id;
+
+        UPDATE purchases
+        SET amount_paid = (SELECT COALESCE(SUM(amount), 0) FROM payment_allocations WHERE purchase_id = NEW.purchase_id)
+        WHERE id = NEW.purchase_id;
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS update_amount_paid ON payment_allocations;
+CREATE TRIGGER update_amount_paid
+AFTER INSERT OR UPDATE OR DELETE ON payment_allocations
+FOR EACH ROW
+EXECUTE FUNCTION update_amount_paid();
+
+-- Amounts typed in by hand become a payment each, so amount_paid stays the same
+DO $$
+DECLARE
+    o RECORD;
+    p RECORD;
+    new_payment_id INT;
+BEGIN
+    FOR o IN SELECT * FROM orders WHERE amount_paid > 0 LOOP
+        INSERT INTO payments (date_time, amount, party_id, party_type, transfer_type, method, notes, created_by_user_id)
+        VALUES (
+            o.date_time,
+            o.amount_paid,
+            COALESCE(o.customer_id, o.id),
+            CASE WHEN o.customer_id IS NULL THEN 'RETAIL' ELSE 'CUSTOMER' END::party_type_t,
+            'INCOMING',
+            'OTHER',
+            'Recorded from amount_paid',
+            o.created_by_user_id
+        )
+        RETURNING id INTO new_payment_id;
+
+        INSERT INTO payment_allocations (payment_id, order_id, amount)
+        VALUES (new_payment_id, o.id, o.amount_paid);
+    END LOOP;
+
+    FOR p IN SELECT * FROM purchases WHERE amount_paid > 0 LOOP
+        INSERT INTO payments (date_time, amount, party_id, party_type, transfer_type, method, notes, created_by_user_id)
+        VALUES (p.date_time, p.amount_paid, p.supplier_id, 'SUPPLIER', 'OUTGOING', 'OTHER', 'Recorded from amount_paid', p.created_by_user_id)
+        RETURNING id INTO new_payment_id;
+
+        INSERT INTO payment_allocations (payment_id, purchase_id, amount)
+        VALUES (new_payment_id, p.id, p.amount_paid);
+    END LOOP;
+END $$;
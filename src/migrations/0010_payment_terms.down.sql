@@ -0,0 +1,5 @@
+ALTER TABLE suppliers
+    DROP COLUMN IF EXISTS payment_terms_days;
+
+ALTER TABLE customers
+    DROP COLUMN IF EXISTS payment_terms_days;
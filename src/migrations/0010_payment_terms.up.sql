@@ -0,0 +1,6 @@
+-- Days a party has to pay, NULL uses the payment_terms_days setting
+ALTER TABLE customers
+    ADD COLUMN IF NOT EXISTS payment_terms_days INT CHECK (payment_terms_days >= 0);
+
+ALTER TABLE suppliers
+    ADD COLUMN IF NOT EXISTS payment_terms_days INT CHECK (payment_terms_days >= 0);
@@ -0,0 +1,18 @@
+DROP TRIGGER IF EXISTS recompute_all_costs ON settings;
+DROP FUNCTION IF EXISTS recompute_all_costs();
+
+DROP TRIGGER IF EXISTS recompute_purchase_cost ON purchases;
+DROP TRIGGER IF EXISTS recompute_order_cost ON orders;
+DROP FUNCTION IF EXISTS recompute_moved_cost();
+
+DROP TRIGGER IF EXISTS recompute_purchase_item_cost ON purchase_items;
+DROP TRIGGER IF EXISTS recompute_order_item_cost ON order_items;
+DROP FUNCTION IF EXISTS recompute_item_cost();
+
+DROP FUNCTION IF EXISTS recompute_inventory_cost(INT);
+
+ALTER TABLE inventory
+    DROP COLUMN IF EXISTS unit_cost;
+
+ALTER TABLE order_items
+    DROP COLUMN IF EXISTS cost;
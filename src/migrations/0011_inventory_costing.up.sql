@@ -0,0 +1,194 @@
+-- Cost of goods sold of the line, recomputed by recompute_inventory_cost
+ALTER TABLE order_items
+    ADD COLUMN IF NOT EXISTS cost NUMERIC(32, 4) NOT NULL DEFAULT 0.00;
+
+-- Average cost of the units in stock, recomputed by recompute_inventory_cost
+ALTER TABLE inventory
+    ADD COLUMN IF NOT EXISTS unit_cost NUMERIC(32, 4) NOT NULL DEFAULT 0.00;
+
+-- Replays the purchases and sales of an inventory item in date order and sets the cost of
+-- every order item, using the inventory_costing_method setting:
+--  - WEIGHTED_AVERAGE: sales cost the average cost of the units in stock
+--  - FIFO: sales use up the oldest purchases first
+-- Units sold without stock in hand cost the last purchase price, or 0 before the first one,
+-- and the next purchases make up for them before adding to the stock.
+-- Every write to an item's order or purchase lines replays its whole history.
+CREATE OR REPLACE FUNCTION recompute_inventory_cost(inv_id INT) RETURNS VOID AS $$
+DECLARE
+    method TEXT;
+    movement RECORD;
+    -- FIFO layers still in stock, from layer_head on
+    layer_quantity NUMERIC[] := '{}';
+    layer_cost NUMERIC[] := '{}';
+    layer_head INT := 1;
+    -- FIFO units sold without stock, not yet made up for by a purchase
+    backorder NUMERIC := 0;
+    filled NUMERIC;
+    on_hand NUMERIC := 0;
+    average NUMERIC := 0;
+    last_cost NUMERIC := 0;
+    needed NUMERIC;
+    taken NUMERIC;
+    line_cost NUMERIC;
+    stock_value NUMERIC;
+BEGIN
+    -- Replays of one item run one at a time. Every statement after the lock sees the rows
+    -- committed by the replay that held it, so a concurrent purchase isn't missed.
+    PERFORM 1 FROM inventory WHERE id = inv_id FOR UPDATE;
+
+    SELECT value->>'Text' INTO method FROM settings WHERE key = 'inventory_costing_method';
+    method := COALESCE(method, 'WEIGHTED_AVERAGE');
+
+    FOR movement IN
+        SELECT purchases.date_time, 0 AS sort, purchase_items.id, purchase_items.quantity,
+            purchase_items.price AS unit_cost, FALSE AS sale
+        FROM purchase_items
+            INNER JOIN purchases ON purchase_items.purchase_id = purchases.id
+        WHERE purchase_items.inventory_id = inv_id
+        UNION ALL
+        SELECT orders.date_time, 1, order_items.id, order_items.quantity, NULL, TRUE
+        FROM order_items
+            INNER JOIN orders ON order_items.order_id = orders.id
+        WHERE order_items.inventory_id = inv_id
+        -- Purchases made at the same time as a sale are received first
+        ORDER BY date_time, sort, id
+    LOOP
+        IF NOT movement.sale THEN
+            IF on_hand > 0 THEN
+                average := (on_hand * average + movement.quantity * movement.unit_cost)
+                    / (on_hand + movement.quantity);
+            ELSE
+                average := movement.unit_cost;
+            END IF;
+
+            filled := LEAST(backorder, movement.quantity);
+            backorder := backorder - filled;
+
+            IF movement.quantity > filled THEN
+                layer_quantity := array_append(layer_quantity, movement.quantity - filled);
+                layer_cost := array_append(layer_cost, movement.unit_cost);
+            END IF;
+
+            on_hand := on_hand + movement.quantity;
+            last_cost := movement.unit_cost;
+        ELSE
+            IF method = 'FIFO' THEN
+                needed := movement.quantity;
+                line_cost := 0;
+
+                WHILE needed > 0 AND layer_head <= COALESCE(array_length(layer_quantity, 1), 0) LOOP
+                    taken := LEAST(needed, layer_quantity[layer_head]);
+                    line_cost := line_cost + taken * layer_cost[layer_head];
+                    layer_quantity[layer_head] := layer_quantity[layer_head] - taken;
+                    needed := needed - taken;
+
+                    IF layer_quantity[layer_head] <= 0 THEN
+                        layer_head := layer_head + 1;
+                    END IF;
+                END LOOP;
+
+                line_cost := line_cost + needed * last_cost;
+                backorder := backorder + needed;
+            ELSIF on_hand > 0 THEN
+                line_cost := movement.quantity * average;
+            ELSE
+                line_cost := movement.quantity * last_cost;
+            END IF;
+
+            on_hand := on_hand - movement.quantity;
+
+            UPDATE order_items
+            SET cost = ROUND(line_cost, 4)
+            WHERE id = movement.id AND cost IS DISTINCT FROM ROUND(line_cost, 4);
+        END IF;
+    END LOOP;
+
+    IF method = 'FIFO' THEN
+        stock_value := 0;
+
+        FOR i IN layer_head..COALESCE(array_length(layer_quantity, 1), 0) LOOP
+            stock_value := stock_value + layer_quantity[i] * layer_cost[i];
+        END LOOP;
+    ELSE
+        stock_value := on_hand * average;
+    END IF;
+
+    UPDATE inventory
+    SET unit_cost = CASE WHEN on_hand > 0 THEN ROUND(stock_value / on_hand, 4) ELSE last_cost END
+    WHERE id = inv_id;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION recompute_item_cost() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP <> 'INSERT' THEN
+        PERFORM recompute_inventory_cost(OLD.inventory_id);
+    END IF;
+
+    IF TG_OP <> 'DELETE' AND (TG_OP = 'INSERT' OR NEW.inventory_id <> OLD.inventory_id) THEN
+        PERFORM recompute_inventory_cost(NEW.inventory_id);
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Only the columns that change the replay, so setting order_items.cost doesn't recurse
+DROP TRIGGER IF EXISTS recompute_order_item_cost ON order_items;
+CREATE TRIGGER recompute_order_item_cost
+AFTER INSERT OR DELETE OR UPDATE OF inventory_id, quantity, order_id ON order_items
+FOR EACH ROW
+EXECUTE FUNCTION recompute_item_cost();
+
+DROP TRIGGER IF EXISTS recompute_purchase_item_cost ON purchase_items;
+CREATE TRIGGER recompute_purchase_item_cost
+AFTER INSERT OR DELETE OR UPDATE OF inventory_id, quantity, price, purchase_id ON purchase_items
+FOR EACH ROW
+EXECUTE FUNCTION recompute_item_cost();
+
+-- Moving an order or purchase in time changes which units it used
+CREATE OR REPLACE FUNCTION recompute_moved_cost() RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_TABLE_NAME = 'orders' THEN
+        PERFORM recompute_inventory_cost(inventory_id)
+        FROM (SELECT DISTINCT inventory_id FROM order_items WHERE order_id = NEW.id) items;
+    ELSE
+        PERFORM recompute_inventory_cost(inventory_id)
+        FROM (SELECT DISTINCT inventory_id FROM purchase_items WHERE purchase_id = NEW.id) items;
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS recompute_order_cost ON orders;
+CREATE TRIGGER recompute_order_cost
+AFTER UPDATE OF date_time ON orders
+FOR EACH ROW
+WHEN (OLD.date_time IS DISTINCT FROM NEW.date_time)
+EXECUTE FUNCTION recompute_moved_cost();
+
+DROP TRIGGER IF EXISTS recompute_purchase_cost ON purchases;
+CREATE TRIGGER recompute_purchase_cost
+AFTER UPDATE OF date_time ON purchases
+FOR EACH ROW
+WHEN (OLD.date_time IS DISTINCT FROM NEW.date_time)
+EXECUTE FUNCTION recompute_moved_cost();
+
+-- Changing the costing method recomputes everything
+CREATE OR REPLACE FUNCTION recompute_all_costs() RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM recompute_inventory_cost(id) FROM inventory;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS recompute_all_costs ON settings;
+CREATE TRIGGER recompute_all_costs
+AFTER INSERT OR UPDATE OF value ON settings
+FOR EACH ROW
+WHEN (NEW.key = 'inventory_costing_method')
+EXECUTE FUNCTION recompute_all_costs();
+
+SELECT recompute_inventory_cost(id) FROM inventory;
@@ -0,0 +1,244 @@
+use rocket::{http::Status, serde::json::Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgConnection, Postgres};
+
+use crate::{
+    db::DB,
+    types::permissions::{perm, PermissionKey},
+};
+
+use super::{
+    auth::AuthGuard,
+    roles, sessions, ApiError, ApiReturn,
+};
+
+/// Makes tokens recognizable in scripts and secret scanners
+const TOKEN_PREFIX: &str = "erp_";
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    /// The token can only use these, and only while the user still has them
+    pub permissions: Vec<PermissionKey>,
+    pub created_at: sqlx::types::chrono::DateTime<chrono::Utc>,
+    pub last_used_at: Option<sqlx::types::chrono::DateTime<chrono::Utc>>,
+    /// Tokens without an expiry last until they are revoked
+    pub expires_at: Option<sqlx::types::chrono::DateTime<chrono::Utc>>,
+}
+
+/// Token id, user id and permissions of a valid token. Also records that it was used.
+pub(super) async fn authenticate(
+    token: &str,
+    conn: &mut PgConnection,
+) -> Result<Option<(i32, i32, Vec<PermissionKey>)>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = CURRENT_TIMESTAMP
+        WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+        RETURNING id, user_id, permissions
+        "#,
+    )
+    .bind(sessions::hash_token(token))
+    .fetch_optional(conn)
+    .await
+}
+
+/// Revoke every token of a user
+pub(super) async fn revoke_user_tokens<'a, E>(user_id: i32, conn: E) -> Result<u64, ApiError>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let res = sqlx::query("DELETE FROM api_tokens WHERE user_id = $1")
+        .bind(user_id)
+        .execute(conn)
+        .await?;
+
+    Ok(res.rows_affected())
+}
+
+async fn list_for_user(user_id: i32, conn: &mut PgConnection) -> Result<Vec<ApiToken>, ApiError> {
+    let tokens = sqlx::query_as(
+        r#"
+        SELECT id, name, permissions, created_at, last_used_at, expires_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(conn)
+    .await?;
+
+    Ok(tokens)
+}
+
+// GET /auth/tokens
+// -> 200 OK
+//     [
+//         {
+//             "id": 1,
+//             "name": "Warehouse scanner",
+//             "permissions": ["INVENTORY_READ", "INVENTORY_UPDATE"],
+//             "created_at": "...",
+//             "last_used_at": "...",
+//             "expires_at": null
+//         }
+//     ]
+#[rocket::get("/auth/tokens")]
+pub(super) async fn list(
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+) -> Result<Json<Vec<ApiToken>>, ApiError> {
+    Ok(Json(list_for_user(auth.auth_info.user.id, &mut db).await?))
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct ApiTokenPostRequest {
+    name: String,
+    permissions: Vec<PermissionKey>,
+    #[serde(default)]
+    expires_at: Option<sqlx::types::chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct CreatedApiToken {
+    id: i32,
+    /// Only shown once, send it as `Authorization: Bearer <token>`
+    token: String,
+}
+
+// POST /auth/tokens
+// {
+//     "name": "Warehouse scanner",
+//     "permissions": ["INVENTORY_READ", "INVENTORY_UPDATE"],
+//     "expires_at": "2025-01-01T00:00:00Z" (optional)
+// }
+// -> 201 Created
+//     {
+//         "id": 1,
+//         "token": "erp_..."
+//     }
+// -> 400 Bad Request
+// -> 403 Forbidden (permission the user doesn't have, or sent with an API token)
+#[rocket::post("/auth/tokens", data = "<req>")]
+pub(super) async fn post(
+    req: Json<ApiTokenPostRequest>,
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+) -> Result<ApiReturn<CreatedApiToken>, ApiError> {
+    let req = req.into_inner();
+    let user = &auth.auth_info.user;
+
+    // Otherwise a leaked token could be used to mint a broader one
+    auth.auth_info.require_session()?;
+
+    if req.name.trim().is_empty() {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Token name cannot be empty".to_string(),
+        ));
+    }
+
+    if req.permissions.is_empty() {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Token needs at least one permission".to_string(),
+        ));
+    }
+
+    roles::validate_permissions(&req.permissions)?;
+
+    let missing: Vec<&str> = req
+        .permissions
+        .iter()
+        .filter(|p| !user.has_permission(&p.0))
+        .map(|p| p.0.as_str())
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(ApiError(
+            Status::Forbidden,
+            format!("You don't have these permissions: {}", missing.join(", ")),
+        ));
+    }
+
+    if req.expires_at.is_some_and(|t| t <= chrono::Utc::now()) {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Expiry must be in the future".to_string(),
+        ));
+    }
+
+    let token = format!("{}{}", TOKEN_PREFIX, sessions::generate_token());
+
+    let id: i32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO api_tokens (token_hash, user_id, name, permissions, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#,
+    )
+    .bind(sessions::hash_token(&token))
+    .bind(user.id)
+    .bind(&req.name)
+    .bind(&req.permissions)
+    .bind(req.expires_at)
+    .fetch_one(&mut **db)
+    .await?;
+
+    Ok(ApiReturn(Status::Created, CreatedApiToken { id, token }))
+}
+
+// DELETE /auth/tokens/<id>
+// -> 200 OK
+// -> 404 Not Found
+// -> 403 Forbidden (sent with an API token)
+// Only tokens of the logged in user can be revoked.
+#[rocket::delete("/auth/tokens/<id>")]
+pub(super) async fn revoke(
+    id: i32,
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+) -> Result<Status, ApiError> {
+    auth.auth_info.require_session()?;
+
+    let res = sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(auth.auth_info.user.id)
+        .execute(&mut **db)
+        .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ApiError(Status::NotFound, "Token not found".to_string()));
+    }
+
+    Ok(Status::Ok)
+}
+
+// GET /auth/users/<id>/tokens [Permissions: ADMIN]
+// Response: Vec<ApiToken>
+#[rocket::get("/auth/users/<id>/tokens")]
+pub(super) async fn list_for(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Json<Vec<ApiToken>>, ApiError> {
+    Ok(Json(list_for_user(id, &mut db).await?))
+}
+
+// DELETE /auth/users/<id>/tokens [Permissions: ADMIN]
+// -> 200 OK
+//     2 (number of tokens revoked)
+#[rocket::delete("/auth/users/<id>/tokens")]
+pub(super) async fn revoke_all_for(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Json<u64>, ApiError> {
+    Ok(Json(revoke_user_tokens(id, &mut **db).await?))
+}
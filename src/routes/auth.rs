@@ -1,25 +1,36 @@
 use rocket::{
-    http::{Cookie, CookieJar, SameSite, Status},
+    http::{Cookie, CookieJar, Status},
     outcome::{try_outcome, Outcome},
     serde::json::Json,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::{Executor, Postgres};
+use sqlx::{Acquire, Executor, Postgres};
 use ts_rs::TS;
 
 use crate::{
     db::{FromDB, DB},
-    types::permissions::{UserPermissionEnum, UserPermissions, UserPermissionsVec},
+    types::permissions::{grants, perm, Permission, PermissionInfo, PermissionKey, ADMIN_KEY, PERMISSIONS},
 };
 
-use super::ApiError;
+use super::{
+    api_tokens,
+    lockout::{self, AuthEventKind},
+    roles,
+    sessions::{self, ClientInfo, SESSION_COOKIE},
+    two_factor, ApiError, ApiReturn, SqlType,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone, TS)]
 #[ts(export)]
 pub(super) struct User {
     pub id: i32,
     pub username: String,
-    pub permissions: UserPermissionsVec,
+    /// Keys granted by all roles of the user
+    pub permissions: Vec<PermissionKey>,
+    /// The user has to change their password before doing anything else
+    pub password_change_required: bool,
+    #[serde(default)]
+    pub two_factor_enabled: bool,
 }
 
 #[derive(sqlx::FromRow, Debug, Clone, PartialEq, Deserialize, Serialize)]
@@ -27,8 +38,24 @@ pub(super) struct UserRow {
     pub id: i32,
     pub username: String,
     pub password: String,
+    /// Only used by legacy SHA-256 hashes, Argon2 hashes store their salt in `password`
     pub salt: String,
-    pub permissions: i32,
+    /// Cache of the keys granted by the user's roles, see roles::refresh_user_permissions
+    #[serde(default)]
+    pub permissions: Vec<PermissionKey>,
+    #[serde(default)]
+    pub password_change_required: bool,
+    /// Archived users can't log in, but stay attached to the records they created
+    #[serde(default)]
+    pub archived_at: Option<sqlx::types::chrono::DateTime<chrono::Utc>>,
+    /// Base32, set during setup but only used once `totp_enabled` is true
+    #[serde(default)]
+    pub totp_secret: Option<String>,
+    #[serde(default)]
+    pub totp_enabled: bool,
+    /// Last TOTP step that was used, so a code can't be used twice
+    #[serde(default)]
+    pub totp_last_step: Option<i64>,
 }
 
 impl From<UserRow> for User {
@@ -36,13 +63,19 @@ impl From<UserRow> for User {
         User {
             id: row.id,
             username: row.username,
-            permissions: UserPermissionsVec::split_from(UserPermissions::from(
-                row.permissions as u32,
-            )),
+            permissions: row.permissions,
+            password_change_required: row.password_change_required,
+            two_factor_enabled: row.totp_enabled,
         }
     }
 }
 
+impl User {
+    pub fn has_permission(&self, key: &str) -> bool {
+        grants(&self.permissions, key)
+    }
+}
+
 impl FromDB for User {
     async fn from_db(id: i32, db: &mut crate::db::DB) -> Result<Self, ApiError> {
         let row: UserRow = sqlx::query_as(
@@ -61,20 +94,45 @@ impl FromDB for User {
             _ => e.into(),
         })?;
 
-        Ok(User {
-            id: row.id,
-            username: row.username,
-            permissions: UserPermissionsVec::split_from(UserPermissions::from(
-                row.permissions as u32,
-            )),
-        })
+        Ok(User::from(row))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub(super) struct AuthCookie {
+/// How a request was authenticated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Credential {
+    /// Id of the session from the cookie
+    Session(i32),
+    /// Id of the token from the `Authorization: Bearer` header
+    ApiToken(i32),
+}
+
+/// The user and credential a request was authenticated with
+#[derive(Debug)]
+pub(super) struct AuthInfo {
+    /// For API tokens, `permissions` only holds the keys the token is limited to
     pub user: User,
-    pub expiry_time: Option<i64>,
+    pub credential: Credential,
+}
+
+impl AuthInfo {
+    pub fn session_id(&self) -> Option<i32> {
+        match self.credential {
+            Credential::Session(id) => Some(id),
+            Credential::ApiToken(_) => None,
+        }
+    }
+
+    /// For routes that manage the account itself, like sessions, tokens, 2FA and the
+    /// password. A token of any scope could otherwise lock its user out.
+    pub fn require_session(&self) -> Result<i32, ApiError> {
+        self.session_id().ok_or_else(|| {
+            ApiError(
+                Status::Forbidden,
+                "Only allowed from a logged in session, not with an API token".to_string(),
+            )
+        })
+    }
 }
 
 // POST /auth/login
@@ -85,7 +143,15 @@ pub(super) struct AuthCookie {
 //     "expires_in": 0
 // }
 // -> 200 OK
+//     {
+//         "two_factor_challenge": null
+//     }
+// -> 202 Accepted (2FA enabled, send the challenge and a code to /auth/login/two_factor)
+//     {
+//         "two_factor_challenge": "..."
+//     }
 // -> 401 Unauthorized
+// -> 429 Too Many Requests (too many failed attempts, see routes/lockout.rs)
 #[derive(serde::Deserialize)]
 pub(super) struct LoginData {
     username: String,
@@ -94,12 +160,19 @@ pub(super) struct LoginData {
     expires_in: Option<i64>,
 }
 
+#[derive(serde::Serialize, TS)]
+#[ts(export)]
+pub(super) struct LoginResponse {
+    two_factor_challenge: Option<String>,
+}
+
 #[rocket::post("/auth/login", data = "<login_data>")]
 pub(super) async fn login(
     mut db: DB,
+    client: ClientInfo,
     login_data: Json<LoginData>,
     cookies: &CookieJar<'_>,
-) -> Result<Status, Status> {
+) -> Result<ApiReturn<LoginResponse>, ApiError> {
     // No need to refresh cookie, so no AuthGuard needed
     let LoginData {
         username,
@@ -107,75 +180,127 @@ pub(super) async fn login(
         expires_in,
     } = login_data.into_inner();
 
+    if let Some(retry_after) = lockout::locked_for(&username, &client, &mut db).await? {
+        lockout::log_auth_event(
+            AuthEventKind::LoginLocked,
+            &username,
+            None,
+            &client,
+            &mut db,
+        )
+        .await?;
+
+        return Err(ApiError(
+            Status::TooManyRequests,
+            format!(
+                "Too many failed login attempts, try again in {} seconds",
+                retry_after
+            ),
+        ));
+    }
+
     // NOTE: Assumes that usernames are unique
-    let row: Result<UserRow, sqlx::Error> =
-        sqlx::query_as("SELECT * FROM users WHERE username = $1 LIMIT 1")
-            .bind(&username)
-            .fetch_one(&mut **db)
-            .await;
+    let row: Option<UserRow> = sqlx::query_as("SELECT * FROM users WHERE username = $1 LIMIT 1")
+        .bind(&username)
+        .fetch_optional(&mut **db)
+        .await?;
 
-    if let Err(e) = row {
-        match e {
-            sqlx::Error::RowNotFound => return Ok(Status::Unauthorized),
-            _ => {
-                log::error!("DB error while fetching user: {:?}", e);
-                return Err(Status::InternalServerError);
+    // Archived users fail the same way as unknown ones
+    let Some(mut user_row) = row.filter(|row| row.archived_at.is_none()) else {
+        lockout::record_failure(&username, None, &client, &mut db).await?;
+        return Err(invalid_login());
+    };
+
+    let password_check = verify_password(&password, &user_row.password, &user_row.salt);
+
+    if password_check == PasswordCheck::Invalid {
+        lockout::record_failure(&username, Some(user_row.id), &client, &mut db).await?;
+        return Err(invalid_login());
+    }
+
+    if password_check == PasswordCheck::ValidNeedsRehash {
+        // Upgrade legacy hashes now that we know the plain text password
+        let rehash_res = match hash_password(&password) {
+            Ok(new_hash) => sqlx::query("UPDATE users SET password = $1, salt = '' WHERE id = $2")
+                .bind(&new_hash)
+                .bind(user_row.id)
+                .execute(&mut **db)
+                .await
+                .map(|_| new_hash)
+                .map_err(ApiError::from),
+            Err(e) => Err(e),
+        };
+
+        match rehash_res {
+            Ok(new_hash) => {
+                log::info!("Upgraded password hash for user {}", user_row.id);
+                user_row.password = new_hash;
+                user_row.salt = String::new();
             }
+            // Not fatal, the old hash still works and will be upgraded on the next login
+            Err(e) => log::error!("Failed to upgrade password hash: {:?}", e),
         }
     }
 
-    let user_row = row.unwrap();
+    let expiry_timestamp = expires_in.map(|expires_in| {
+        rocket::time::OffsetDateTime::now_utc() + rocket::time::Duration::seconds(expires_in)
+    });
 
-    let salted_hashed_password = salt_hash_password(&password, &user_row.salt);
+    if user_row.totp_enabled {
+        // The login only counts as successful once the code is verified
+        let challenge =
+            two_factor::create_challenge(user_row.id, expiry_timestamp, &mut db).await?;
 
-    if user_row.password == salted_hashed_password {
-        let expiry_timestamp = expires_in.map(|expires_in| {
-            rocket::time::OffsetDateTime::now_utc() + rocket::time::Duration::seconds(expires_in)
-        });
+        return Ok(ApiReturn(
+            Status::Accepted,
+            LoginResponse {
+                two_factor_challenge: Some(challenge),
+            },
+        ));
+    }
 
-        let mut cookie = Cookie::new(
-            "auth_info",
-            serde_json::json!(AuthCookie {
-                user: User::from(user_row),
-                expiry_time: expiry_timestamp.map(|t| t.unix_timestamp()),
-            })
-            .to_string(),
-        );
+    lockout::record_success(&username, user_row.id, &client, &mut db).await?;
 
-        // Set expiry if provided
-        if let Some(expiry_timestamp) = expiry_timestamp {
-            cookie.set_expires(expiry_timestamp);
-        } else {
-            cookie.set_expires(None);
-        }
+    let token = sessions::create_session(user_row.id, expiry_timestamp, &client, &mut **db).await?;
 
-        cookie.set_same_site(SameSite::Lax);
-        cookie.set_secure(true);
+    log::info!("Sending token cookie");
 
-        log::info!("Sending token cookie");
+    cookies.add_private(sessions::session_cookie(token, expiry_timestamp));
 
-        cookies.add_private(cookie);
+    Ok(ApiReturn(
+        Status::Ok,
+        LoginResponse {
+            two_factor_challenge: None,
+        },
+    ))
+}
 
-        return Ok(Status::Ok);
-    } else {
-        return Ok(Status::Unauthorized);
-    }
+fn invalid_login() -> ApiError {
+    ApiError(
+        Status::Unauthorized,
+        "Invalid username or password".to_string(),
+    )
 }
 
 // POST /auth/logout
 // -> 200 OK
 // -> 401 Unauthorized
 #[rocket::post("/auth/logout")]
-pub(super) async fn logout(cookies: &CookieJar<'_>) -> Status {
-    // No need to refresh cookie, so no AuthGuard needed
-    if cookies.get_private("auth_info").is_some() {
+pub(super) async fn logout(mut db: DB, cookies: &CookieJar<'_>) -> Result<Status, ApiError> {
+    // Logging out has to work even for expired sessions, so no AuthGuard needed
+    if let Some(cookie) = cookies.get_private(SESSION_COOKIE) {
+        sqlx::query("DELETE FROM sessions WHERE token_hash = $1")
+            .bind(sessions::hash_token(cookie.value()))
+            .execute(&mut **db)
+            .await?;
+
         log::info!("Removing token cookie");
 
-        cookies.remove_private(Cookie::from("auth_info"));
+        cookies.remove_private(Cookie::from(SESSION_COOKIE));
 
-        Status::Ok
+        Ok(Status::Ok)
     } else {
-        Status::Unauthorized
+        Ok(Status::Unauthorized)
     }
 }
 
@@ -183,90 +308,116 @@ pub(super) async fn logout(cookies: &CookieJar<'_>) -> Status {
 pub(super) struct CreateUserData {
     username: String,
     password: String,
-    permissions: UserPermissionsVec,
+    /// Role ids, see GET /auth/roles
+    roles: Vec<i32>,
 }
 
 // POST /auth/create_user [Permissions: ADMIN]
 // {
 //     "username": "user",
 //     "password": "pass",
-//     "permissions": ["ORDER_WRITE", "INVENTORY_READ"]
+//     "roles": [2, 3]
 // }
 // -> 200 OK
 // -> 400 Bad Request
 // -> 500 Internal Server Error
-// TODO: Verify password, check existing user, make sure theres at least one admin, handle token expiry
+// TODO: Verify password, handle token expiry
 #[rocket::post("/auth/create_user", data = "<create_user_data>")]
 pub(super) async fn create_user(
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::ADMIN as u32 }>,
+    _auth: AuthGuard<perm::ADMIN>,
     create_user_data: Json<CreateUserData>,
 ) -> Result<Status, ApiError> {
     let CreateUserData {
         username,
         password,
-        permissions,
+        roles,
     } = create_user_data.into_inner();
 
-    add_user_to_db(&username, &password, permissions.flatten(), &mut **db)
-        .await
-        .map_err(|e| {
-            if e.to_string()
-                .contains("duplicate key value violates unique constraint")
-            {
-                ApiError(Status::BadRequest, "Username already exists".to_string())
-            } else {
-                e.into()
-            }
-        })?;
-    // .map_err(|e| match e {
-    //     e => e.into(),
-    // })?;
+    add_user_to_db(&username, &password, &roles, false, &mut **db).await?;
 
     Ok(Status::Created)
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, TS)]
+#[ts(export)]
+pub(super) struct UserRole {
+    id: i32,
+    name: String,
+}
+
 #[derive(serde::Serialize, TS)]
 #[ts(export)]
 pub(super) struct ListUserData {
+    id: i32,
     username: String,
-    permissions: UserPermissionsVec,
+    roles: Vec<UserRole>,
+    /// Keys granted by all roles of the user
+    permissions: Vec<PermissionKey>,
+    archived_at: Option<sqlx::types::chrono::DateTime<chrono::Utc>>,
 }
 
-// GET /auth/list_users [Permissions: ADMIN]
+// GET /auth/list_users?include_archived=false [Permissions: ADMIN]
 // -> 200 OK
 //     [
 //         {
+//             "id": 1,
 //             "username": "user",
-//             "permissions": ["ORDER_WRITE", "INVENTORYREAD"]
+//             "roles": [{ "id": 2, "name": "Cashier" }],
+//             "permissions": ["ORDER_CREATE", "ORDER_READ"],
+//             "archived_at": null
 //         }
 //     ]
 // -> 500 Internal Server Error
-#[rocket::get("/auth/list_users")]
+#[rocket::get("/auth/list_users?<include_archived>")]
 pub(super) async fn list_users(
+    include_archived: Option<bool>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::ADMIN as u32 }>,
+    _auth: AuthGuard<perm::ADMIN>,
 ) -> Result<Json<Vec<ListUserData>>, Status> {
     #[derive(sqlx::FromRow, Debug, Clone, PartialEq)]
     pub struct ListUserRow {
+        id: i32,
         username: String,
-        permissions: i32,
+        roles: sqlx::types::Json<Vec<UserRole>>,
+        permissions: Vec<PermissionKey>,
+        archived_at: Option<sqlx::types::chrono::DateTime<chrono::Utc>>,
     }
 
-    let rows: Result<Vec<ListUserRow>, sqlx::Error> =
-        sqlx::query_as("SELECT username, permissions FROM users")
-            .fetch_all(&mut **db)
-            .await;
+    let rows: Result<Vec<ListUserRow>, sqlx::Error> = sqlx::query_as(
+        r#"
+        SELECT
+            users.id,
+            users.username,
+            COALESCE(
+                json_agg(json_build_object('id', roles.id, 'name', roles.name) ORDER BY roles.name)
+                    FILTER (WHERE roles.id IS NOT NULL),
+                '[]'
+            ) AS roles,
+            users.permissions,
+            users.archived_at
+        FROM users
+        LEFT JOIN user_roles ON user_roles.user_id = users.id
+        LEFT JOIN roles ON roles.id = user_roles.role_id
+        WHERE $1 OR users.archived_at IS NULL
+        GROUP BY users.id
+        ORDER BY users.id
+        "#,
+    )
+    .bind(include_archived.unwrap_or(false))
+    .fetch_all(&mut **db)
+    .await;
 
     match rows {
         Ok(rows) => {
             let users: Vec<ListUserData> = rows
-                .iter()
+                .into_iter()
                 .map(|row| ListUserData {
-                    username: row.username.clone(),
-                    permissions: UserPermissionsVec::split_from(UserPermissions::from(
-                        row.permissions as u32,
-                    )),
+                    id: row.id,
+                    username: row.username,
+                    roles: row.roles.0,
+                    permissions: row.permissions,
+                    archived_at: row.archived_at,
                 })
                 .collect();
 
@@ -281,18 +432,42 @@ pub(super) async fn list_users(
 
 // GET /auth/permissions [Permissions: ADMIN]
 // -> 200 OK
-// -> 500 Internal Server Error
+//     [
+//         {
+//             "key": "INVENTORY_CREATE",
+//             "description": "Add inventory items"
+//         }
+//     ]
 #[rocket::get("/auth/permissions")]
 pub(super) async fn permissions(
-    _auth: AuthGuard<{ UserPermissionEnum::ADMIN as u32 }>,
-) -> Result<Json<Vec<UserPermissionEnum>>, Status> {
-    Ok(Json(UserPermissionEnum::variants().to_vec()))
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Json<Vec<PermissionInfo>>, Status> {
+    Ok(Json(PERMISSIONS.to_vec()))
 }
 
-// TODO: Think of another way to delete because things like orders
-// have a foreign key to users. Maybe have a deleted column instead.
-// Deleting inventory also messes up orders, so maybe have a deleted column
-// on everything.
+/// Fails if no unarchived user holds ADMIN anymore.
+/// Call at the end of a transaction that changes users or roles, after
+/// `LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE` so two admins can't demote each other.
+pub(super) async fn ensure_admin_exists(conn: &mut sqlx::PgConnection) -> Result<(), ApiError> {
+    let admin_count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM users WHERE $1 = ANY (permissions) AND archived_at IS NULL",
+    )
+    .bind(ADMIN_KEY)
+    .fetch_one(conn)
+    .await?;
+
+    if admin_count < 1 {
+        return Err(ApiError(
+            Status::BadRequest,
+            "At least 1 admin must exist".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// NOTE: Users that created orders, purchases or stock updates can't be deleted
+// because of foreign keys, archive them instead.
 
 // DELETE /auth/delete_user [Permissions: ADMIN]
 // {
@@ -300,6 +475,7 @@ pub(super) async fn permissions(
 // }
 // -> 200 OK
 // -> 400 Bad Request
+// -> 404 Not Found
 // -> 500 Internal Server Error
 #[derive(serde::Deserialize)]
 pub(super) struct DeleteUserData {
@@ -309,48 +485,183 @@ pub(super) struct DeleteUserData {
 #[rocket::delete("/auth/delete_user", data = "<delete_user_data>")]
 pub(super) async fn delete_user(
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::ADMIN as u32 }>,
+    _auth: AuthGuard<perm::ADMIN>,
     delete_user_data: Json<DeleteUserData>,
 ) -> Result<Status, ApiError> {
     let DeleteUserData { username } = delete_user_data.into_inner();
 
-    // Check if at least 2 admins exist
-    let admin_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE permissions = $1")
-        .bind(UserPermissionEnum::ADMIN as i32)
-        .fetch_one(&mut **db)
+    let mut transaction = db.begin().await?;
+
+    sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
         .await?;
 
-    let deleting_user: UserRow = sqlx::query_as("SELECT * FROM users WHERE username = $1")
+    let res = sqlx::query("DELETE FROM users WHERE username = $1")
         .bind(&username)
-        .fetch_one(&mut **db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => ApiError(Status::NotFound, "User not found".to_string()),
-            e => e.into(),
-        })?;
+        .execute(&mut *transaction)
+        .await?;
 
-    if deleting_user.permissions == UserPermissionEnum::ADMIN as i32 && admin_count < 2 {
-        return Err(ApiError(
-            Status::BadRequest,
-            "At least 1 admin must exist to delete an admin".to_string(),
-        ));
+    if res.rows_affected() == 0 {
+        return Err(ApiError(Status::NotFound, "User not found".to_string()));
     }
 
-    if admin_count < 2 {
-        return Err(ApiError(
-            Status::BadRequest,
-            "At least 1 admin must exist".to_string(),
-        ));
+    ensure_admin_exists(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Status::Ok)
+}
+
+#[derive(serde::Deserialize, TS)]
+#[ts(export)]
+pub(super) struct UserPatchRequest {
+    username: Option<String>,
+    /// Replaces all roles of the user
+    roles: Option<Vec<i32>>,
+}
+
+// PATCH /auth/users/<id> [Permissions: ADMIN]
+// {
+//     "username": "new_name",
+//     "roles": [2]
+// }
+// -> 200 OK
+// -> 400 Bad Request
+// -> 404 Not Found
+#[rocket::patch("/auth/users/<id>", data = "<req>")]
+pub(super) async fn patch_user(
+    req: Json<UserPatchRequest>,
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Status, ApiError> {
+    let req = req.into_inner();
+
+    if let Some(username) = &req.username {
+        if username.trim().is_empty() {
+            return Err(ApiError(
+                Status::BadRequest,
+                "Username cannot be empty".to_string(),
+            ));
+        }
     }
 
-    sqlx::query("DELETE FROM users WHERE username = $1")
-        .bind(&username)
-        .execute(&mut **db)
+    let mut current_param = 1;
+
+    let columns = vec![req.username.as_ref().map(|_| "username")]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<&str>>();
+
+    let sets_string = super::generate_sets_string(&columns, &mut current_param);
+
+    let set_binds = vec![req.username.as_ref().map(|v| SqlType::String(v.clone()))]
+        .into_iter()
+        .flatten();
+
+    let mut transaction = db.begin().await?;
+
+    // Serialize concurrent role changes, otherwise two admins could demote each other
+    sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await?;
+
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE id = $1)")
+        .bind(id)
+        .fetch_one(&mut *transaction)
         .await?;
 
+    if !exists {
+        return Err(ApiError(Status::NotFound, "User not found".to_string()));
+    }
+
+    if !sets_string.is_empty() {
+        let query_str = format!(
+            r#"
+            UPDATE users
+            SET {}
+            WHERE id = ${}
+            "#,
+            sets_string, current_param
+        );
+
+        let query = sqlx::query(&query_str);
+
+        let query = set_binds.fold(query, |query, value| value.bind_to_query(query));
+
+        query
+            .bind(id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::Database(e) if e.is_unique_violation() => {
+                    ApiError(Status::BadRequest, "Username already exists".to_string())
+                }
+                e => e.into(),
+            })?;
+    }
+
+    if let Some(role_ids) = &req.roles {
+        roles::set_user_roles(id, role_ids, &mut transaction).await?;
+        roles::refresh_user_permissions(&mut transaction).await?;
+        ensure_admin_exists(&mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+
     Ok(Status::Ok)
 }
 
+// POST /auth/users/<id>/archive [Permissions: ADMIN]
+// -> 200 OK
+// -> 400 Bad Request
+// -> 404 Not Found
+// Archived users are logged out and can't log in until restored.
+#[rocket::post("/auth/users/<id>/archive")]
+pub(super) async fn archive_user(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Status, ApiError> {
+    let mut transaction = db.begin().await?;
+
+    // Same as patch_user, stops two admins from archiving each other
+    sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await?;
+
+    let res = sqlx::query(
+        "UPDATE users SET archived_at = COALESCE(archived_at, CURRENT_TIMESTAMP) WHERE id = $1",
+    )
+    .bind(id)
+    .execute(&mut *transaction)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ApiError(Status::NotFound, "User not found".to_string()));
+    }
+
+    ensure_admin_exists(&mut transaction).await?;
+
+    sessions::revoke_user_sessions(id, None, &mut *transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Status::Ok)
+}
+
+// POST /auth/users/<id>/restore [Permissions: ADMIN]
+// -> 200 OK
+// -> 404 Not Found
+#[rocket::post("/auth/users/<id>/restore")]
+pub(super) async fn restore_user(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Status, ApiError> {
+    super::set_archived("users", id, false, &mut db).await
+}
+
 // GET /auth/status
 // -> 200 OK
 // {
@@ -366,29 +677,238 @@ pub(super) async fn delete_user(
 // An easier way is to instead of returning old_info, retrieve the user from the DB again. This is the same
 // number of DB calls.
 #[rocket::get("/auth/status")]
-pub(super) async fn status(
-    // Will refresh the token cookie
-    auth: AuthGuard<0>,
-) -> Result<Json<User>, Status> {
-    let cookie_info: AuthCookie = auth.auth_info;
+pub(super) async fn status(auth: AuthGuard<perm::NONE>) -> Result<Json<User>, Status> {
+    Ok(Json(auth.auth_info.user))
+}
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+fn validate_new_password(password: &str) -> Result<(), ApiError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(ApiError(
+            Status::BadRequest,
+            format!(
+                "Password must be at least {} characters long",
+                MIN_PASSWORD_LENGTH
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub(super) struct ChangePasswordData {
+    old_password: String,
+    new_password: String,
+}
+
+// POST /auth/change_password
+// {
+//     "old_password": "pass",
+//     "new_password": "new_pass"
+// }
+// -> 200 OK
+// -> 400 Bad Request
+// -> 401 Unauthorized
+// -> 403 Forbidden (sent with an API token)
+// Logs out every other session of the user.
+#[rocket::post("/auth/change_password", data = "<change_password_data>")]
+pub(super) async fn change_password(
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+    change_password_data: Json<ChangePasswordData>,
+) -> Result<Status, ApiError> {
+    auth.auth_info.require_session()?;
+
+    let ChangePasswordData {
+        old_password,
+        new_password,
+    } = change_password_data.into_inner();
+
+    let user_row: UserRow = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        .bind(auth.auth_info.user.id)
+        .fetch_one(&mut **db)
+        .await?;
+
+    if verify_password(&old_password, &user_row.password, &user_row.salt) == PasswordCheck::Invalid
+    {
+        return Err(ApiError(
+            Status::Unauthorized,
+            "Old password is incorrect".to_string(),
+        ));
+    }
+
+    if old_password == new_password {
+        return Err(ApiError(
+            Status::BadRequest,
+            "New password must be different from the old password".to_string(),
+        ));
+    }
+
+    validate_new_password(&new_password)?;
+
+    let hashed_password = hash_password(&new_password)?;
+
+    let mut transaction = db.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET password = $1, salt = '', password_change_required = FALSE
+        WHERE id = $2
+        "#,
+    )
+    .bind(&hashed_password)
+    .bind(user_row.id)
+    .execute(&mut *transaction)
+    .await?;
+
+    sessions::revoke_user_sessions(
+        user_row.id,
+        auth.auth_info.session_id(),
+        &mut *transaction,
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Status::Ok)
+}
+
+#[derive(serde::Serialize, TS)]
+#[ts(export)]
+pub(super) struct ResetPasswordResponse {
+    /// Has to be changed by the user on their next login
+    temporary_password: String,
+}
+
+// POST /auth/reset_password/<id> [Permissions: ADMIN]
+// -> 200 OK
+//     {
+//         "temporary_password": "..."
+//     }
+// -> 404 Not Found
+// Logs out every session of the user and revokes their API tokens.
+#[rocket::post("/auth/reset_password/<id>")]
+pub(super) async fn reset_password(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Json<ResetPasswordResponse>, ApiError> {
+    let temporary_password = generate_temporary_password();
+
+    let hashed_password = hash_password(&temporary_password)?;
+
+    let mut transaction = db.begin().await?;
+
+    let res = sqlx::query(
+        r#"
+        UPDATE users
+        SET password = $1, salt = '', password_change_required = TRUE
+        WHERE id = $2
+        "#,
+    )
+    .bind(&hashed_password)
+    .bind(id)
+    .execute(&mut *transaction)
+    .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ApiError(Status::NotFound, "User not found".to_string()));
+    }
+
+    sessions::revoke_user_sessions(id, None, &mut *transaction).await?;
+
+    // The password might have leaked together with tokens made from it
+    api_tokens::revoke_user_tokens(id, &mut *transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(ResetPasswordResponse { temporary_password }))
+}
+
+fn generate_temporary_password() -> String {
+    use rand::{distributions::Alphanumeric, Rng};
+
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
 
-    Ok(Json(cookie_info.user))
+/// Result of checking a password against the hash stored in `users.password`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PasswordCheck {
+    Invalid,
+    Valid,
+    /// The password is correct, but the stored hash uses an outdated scheme or parameters
+    /// and should be replaced with a new one from `hash_password`.
+    ValidNeedsRehash,
 }
 
-pub fn generate_salt() -> String {
-    use rand::Rng;
+/// Hash a password with Argon2id.
+/// The returned PHC string contains the salt and parameters, so no separate salt is stored.
+pub fn hash_password(password: &str) -> Result<String, ApiError> {
+    use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
+    use rand::RngCore;
 
-    let mut rng = rand::thread_rng();
+    let mut salt_bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt_bytes);
 
-    let salt: String = (0..32)
-        .map(|_| rng.gen_range(0..16))
-        .map(|n| format!("{:x}", n))
-        .collect();
+    let salt = SaltString::encode_b64(&salt_bytes)
+        .map_err(|e| ApiError(Status::InternalServerError, e.to_string()))?;
 
-    salt
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| ApiError(Status::InternalServerError, e.to_string()))
 }
 
-pub fn salt_hash_password(password: &str, salt: &str) -> String {
+/// Verify a password against either an Argon2 PHC string or a legacy salted SHA-256 hash.
+pub fn verify_password(password: &str, hash: &str, salt: &str) -> PasswordCheck {
+    use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordVerifier};
+
+    // PHC strings always start with $<algorithm>$
+    if hash.starts_with('$') {
+        let parsed = match PasswordHash::new(hash) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                log::error!("Stored password hash is not a valid PHC string: {}", e);
+                return PasswordCheck::Invalid;
+            }
+        };
+
+        if Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_err()
+        {
+            return PasswordCheck::Invalid;
+        }
+
+        let up_to_date = parsed.algorithm == Algorithm::Argon2id.ident()
+            && Params::try_from(&parsed).is_ok_and(|params| params == Params::default());
+
+        if up_to_date {
+            PasswordCheck::Valid
+        } else {
+            PasswordCheck::ValidNeedsRehash
+        }
+    } else {
+        let legacy_hash = legacy_salt_hash_password(password, salt);
+
+        if constant_time_eq(legacy_hash.as_bytes(), hash.as_bytes()) {
+            PasswordCheck::ValidNeedsRehash
+        } else {
+            PasswordCheck::Invalid
+        }
+    }
+}
+
+/// Single round of SHA-256 over `password + salt`.
+/// Only kept to verify rows that haven't been upgraded to Argon2id yet.
+fn legacy_salt_hash_password(password: &str, salt: &str) -> String {
     use ring::digest::{digest, SHA256};
 
     let salted_password = format!("{}{}", password, salt);
@@ -398,43 +918,78 @@ pub fn salt_hash_password(password: &str, salt: &str) -> String {
     hash.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
 }
 
+/// Compares without returning early, so the time taken doesn't reveal how much matched
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Create a user with the given role ids
 pub async fn add_user_to_db<'a, E>(
     username: &str,
     password: &str,
-    permissions: UserPermissions,
+    role_ids: &[i32],
+    password_change_required: bool,
     conn: E,
-) -> Result<(), sqlx::Error>
+) -> Result<(), ApiError>
 where
     E: Executor<'a, Database = Postgres>,
 {
-    let salt = generate_salt();
-
-    let salted_hashed_password = salt_hash_password(&password, &salt);
+    let hashed_password = hash_password(password)?;
 
+    // The permissions cache is filled directly, the new user_roles rows
+    // aren't visible to other parts of the same statement
     crate::db::sqlx::query(
-        "INSERT INTO users (username, password, salt, permissions) VALUES ($1, $2, $3, $4)",
+        r#"
+        WITH new_user AS (
+            INSERT INTO users (username, password, salt, permissions, password_change_required)
+            VALUES (
+                $1, $2, '',
+                ARRAY(
+                    SELECT DISTINCT permission FROM role_permissions
+                    WHERE role_id = ANY ($3)
+                    ORDER BY 1
+                ),
+                $4
+            )
+            RETURNING id
+        )
+        INSERT INTO user_roles (user_id, role_id)
+        SELECT new_user.id, role_id
+        FROM new_user, UNNEST($3::INT[]) AS role_id
+        ON CONFLICT DO NOTHING
+        "#,
     )
-    .bind(&username)
-    .bind(&salted_hashed_password)
-    .bind(&salt)
-    .bind(permissions.0 as i32)
+    .bind(username)
+    .bind(&hashed_password)
+    .bind(role_ids)
+    .bind(password_change_required)
     .execute(conn)
-    .await?;
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => {
+            ApiError(Status::BadRequest, "Username already exists".to_string())
+        }
+        sqlx::Error::Database(e) if e.is_foreign_key_violation() => {
+            ApiError(Status::BadRequest, "Role not found".to_string())
+        }
+        e => e.into(),
+    })?;
 
     Ok(())
 }
 
-/// Mandatory guard that refreshes the token cookie on every request and checks permissions.
+/// Mandatory guard that validates the session and checks permissions.
 /// USE ON EVERY ROUTE TO KEEP PERMISSIONS UP TO DATE.
-pub(super) struct AuthGuard<const PERMISSIONS: u32> {
-    pub auth_info: AuthCookie,
+pub(super) struct AuthGuard<P: Permission> {
+    pub auth_info: AuthInfo,
+    _permission: std::marker::PhantomData<fn() -> P>,
 }
 
-/// Guard that checks if the user has the required permissions.
-/// Pass in the required permissions as a const generic after converting them to a u32.
-/// Input 0 for no permissions required.
+/// Guard that checks if the user has the required permission.
+/// Pass in one of the marker types from `perm`, e.g. `AuthGuard<perm::ORDER_READ>`.
+/// Use `perm::NONE` for routes that only need a logged in user.
 #[rocket::async_trait]
-impl<'r, const PERMISSIONS: u32> rocket::request::FromRequest<'r> for AuthGuard<PERMISSIONS> {
+impl<'r, P: Permission> rocket::request::FromRequest<'r> for AuthGuard<P> {
     type Error = ();
 
     async fn from_request(
@@ -442,79 +997,121 @@ impl<'r, const PERMISSIONS: u32> rocket::request::FromRequest<'r> for AuthGuard<
     ) -> rocket::request::Outcome<Self, Self::Error> {
         let cookies = request.cookies();
 
-        if let Some(cookie) = cookies.get_private("auth_info") {
-            let auth_cookie: AuthCookie =
-                serde_json::from_str(cookie.value()).expect("Failed to parse auth_info cookie");
-
-            let mut db = try_outcome!(request
-                .guard::<DB>()
-                .await
-                .map_error(|_| (Status::InternalServerError, ())));
-
-            let user_row: Result<UserRow, sqlx::Error> =
-                sqlx::query_as("SELECT * FROM users WHERE id = $1 LIMIT 1")
-                    .bind(&auth_cookie.user.id)
-                    .fetch_one(&mut **db)
-                    .await;
-
-            if let Err(e) = user_row {
-                match e {
-                    sqlx::Error::RowNotFound => {
-                        // User not found (maybe deleted), remove cookie.
-                        log::info!("Username not found, removing token cookie");
-                        cookies.remove_private(Cookie::from("auth_info"));
+        let mut db = try_outcome!(request
+            .guard::<DB>()
+            .await
+            .map_error(|_| (Status::InternalServerError, ())));
+
+        // Scripts and devices send an API token instead of the cookie.
+        // A bad token is rejected even if a cookie was sent too.
+        let (credential, user_id, token_permissions) =
+            if let Some(authorization) = request.headers().get_one("Authorization") {
+                let Some(token) = authorization.strip_prefix("Bearer ") else {
+                    return Outcome::Error((Status::Unauthorized, ()));
+                };
+
+                match api_tokens::authenticate(token.trim(), &mut db).await {
+                    Ok(Some((token_id, user_id, permissions))) => {
+                        (Credential::ApiToken(token_id), user_id, Some(permissions))
+                    }
+                    // Revoked, expired or made up
+                    Ok(None) => return Outcome::Error((Status::Unauthorized, ())),
+                    Err(e) => {
+                        log::error!("DB error while fetching API token: {:?}", e);
+                        return Outcome::Error((Status::InternalServerError, ()));
+                    }
+                }
+            } else {
+                let Some(cookie) = cookies.get_private(SESSION_COOKIE) else {
+                    return Outcome::Error((Status::Unauthorized, ()));
+                };
+
+                // Expired sessions are left for the next login to clean up
+                let session: Result<(i32, i32), sqlx::Error> = sqlx::query_as(
+                    r#"
+                    UPDATE sessions
+                    SET last_seen_at = CURRENT_TIMESTAMP
+                    WHERE token_hash = $1 AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                    RETURNING id, user_id
+                    "#,
+                )
+                .bind(sessions::hash_token(cookie.value()))
+                .fetch_one(&mut **db)
+                .await;
+
+                match session {
+                    Ok((session_id, user_id)) => (Credential::Session(session_id), user_id, None),
+                    Err(sqlx::Error::RowNotFound) => {
+                        // Logged out, expired or revoked
+                        log::info!("Session not found, removing token cookie");
+                        cookies.remove_private(Cookie::from(SESSION_COOKIE));
                         return Outcome::Error((Status::Unauthorized, ()));
                     }
-                    _ => {
-                        log::error!("DB error while fetching user: {:?}", e);
+                    Err(e) => {
+                        log::error!("DB error while fetching session: {:?}", e);
                         return Outcome::Error((Status::InternalServerError, ()));
                     }
                 }
+            };
+
+        // Sessions and tokens are deleted together with their user, so the user must exist
+        let user_row: UserRow = match sqlx::query_as("SELECT * FROM users WHERE id = $1 LIMIT 1")
+            .bind(user_id)
+            .fetch_one(&mut **db)
+            .await
+        {
+            Ok(user_row) => user_row,
+            Err(e) => {
+                log::error!("DB error while fetching user: {:?}", e);
+                return Outcome::Error((Status::InternalServerError, ()));
             }
+        };
+
+        if user_row.archived_at.is_some() {
+            log::info!("User is archived, removing token cookie");
+            cookies.remove_private(Cookie::from(SESSION_COOKIE));
+            return Outcome::Error((Status::Unauthorized, ()));
+        }
 
-            let user_row = user_row.unwrap();
+        let mut user = User::from(user_row);
 
-            let new_auth_cookie = AuthCookie {
-                user: User::from(user_row),
-                expiry_time: auth_cookie.expiry_time,
-            };
+        // Tokens get the keys they were created with, minus any the user has lost since
+        if let Some(token_permissions) = token_permissions {
+            let permissions = token_permissions
+                .into_iter()
+                .filter(|p| user.has_permission(&p.0))
+                .collect();
 
-            // User exists, new permissions received.
-            let mut refresh_cookie = Cookie::new(
-                "auth_info",
-                serde_json::to_string(&new_auth_cookie).unwrap(),
-            );
-
-            // Configure cookie
-            refresh_cookie.set_expires(
-                auth_cookie
-                    .expiry_time
-                    .map(|t| rocket::time::OffsetDateTime::from_unix_timestamp(t).unwrap()),
-            );
-
-            refresh_cookie.set_same_site(SameSite::Lax);
-            refresh_cookie.set_secure(true);
-
-            log::info!("Refreshing token cookie");
-
-            cookies.remove_private(Cookie::from("auth_info"));
-            cookies.add_private(refresh_cookie);
-
-            // Check for permissions
-            if new_auth_cookie
-                .user
-                .permissions
-                .flatten()
-                .has_permissions(UserPermissions::from(PERMISSIONS))
-            {
-                return Outcome::Success(AuthGuard {
-                    auth_info: new_auth_cookie,
-                });
-            } else {
+            user.permissions = permissions;
+        }
+
+        let auth_info = AuthInfo { user, credential };
+
+        if let Some(key) = P::KEY {
+            // Only allow routes without permissions (status, change_password)
+            // until the password has been changed
+            if auth_info.user.password_change_required {
+                return Outcome::Error((Status::Forbidden, ()));
+            }
+
+            // Same for 2FA, the setup routes don't need permissions
+            match two_factor::is_missing_two_factor(&auth_info.user, &mut db).await {
+                Ok(false) => {}
+                Ok(true) => return Outcome::Error((Status::Forbidden, ())),
+                Err(e) => {
+                    log::error!("DB error while checking 2FA requirement: {:?}", e);
+                    return Outcome::Error((Status::InternalServerError, ()));
+                }
+            }
+
+            if !auth_info.user.has_permission(key) {
                 return Outcome::Error((Status::Forbidden, ()));
             }
-        } else {
-            return Outcome::Error((Status::Unauthorized, ()));
         }
+
+        Outcome::Success(AuthGuard {
+            auth_info,
+            _permission: std::marker::PhantomData,
+        })
     }
 }
@@ -1,10 +1,10 @@
-use crate::types::permissions::UserPermissionEnum;
+use crate::types::permissions::perm;
 
 use super::{auth::AuthGuard, ApiError};
 
 #[rocket::get("/db/backup")]
 pub(super) async fn backup(
-    _auth: AuthGuard<{ UserPermissionEnum::MANAGE_DB as u32 }>,
+    _auth: AuthGuard<perm::MANAGE_DB>,
 ) -> Result<String, ApiError> {
     let db_url = std::env::var("ROCKET_DATABASES");
 
@@ -65,7 +65,7 @@ pub(super) async fn backup(
 
 // #[rocket::post("/db/restore", data = "<sql_data>")]
 // pub async fn restore(
-//     _auth: AuthGuard<{ UserPermissionEnum::MANAGE_DB as u32 }>,
+//     _auth: AuthGuard<perm::MANAGE_DB>,
 //     sql_data: rocket::Data<'_>,
 //     mut db: DB,
 // ) -> Result<Status, ApiError> {
@@ -5,19 +5,51 @@ use sqlx::prelude::FromRow;
 use crate::{
     db::{FromDB, DB},
     routes::{auth::AuthGuard, search::SearchRequest, ListRequest},
-    types::permissions::UserPermissionEnum,
+    types::permissions::perm,
 };
 
-use super::{ApiError, ApiReturn, SqlType};
+use super::{
+    export::{Export, ExportColumn, ExportFormat, ExportKind},
+    query::{Column, Columns, ListQuery},
+    ApiError, ApiReturn, ListResponse, SqlType,
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS, FromRow)]
 #[ts(export)]
 pub(super) struct Customer {
-    id: i32,
-    name: String,
-    phone: String,
-    address: String,
-    notes: String,
+    pub id: i32,
+    pub name: String,
+    pub phone: String,
+    pub address: String,
+    pub notes: String,
+    /// Days to pay an order, null uses the payment_terms_days setting
+    #[serde(default)]
+    pub payment_terms_days: Option<i32>,
+    /// Archived customers are hidden from lists but still shown on existing records
+    #[serde(default)]
+    pub archived_at: Option<sqlx::types::chrono::DateTime<chrono::Utc>>,
+}
+
+/// Columns that can be used in list and search requests
+const COLUMNS: Columns = Columns(&[
+    Column::new("id"),
+    Column::new("name"),
+    Column::new("phone"),
+    Column::new("address"),
+    Column::new("notes"),
+    Column::new("payment_terms_days"),
+    Column::new("archived_at"),
+]);
+
+fn check_payment_terms(days: Option<i32>) -> Result<(), ApiError> {
+    if days.is_some_and(|days| days < 0) {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Payment terms can't be negative".to_string(),
+        ));
+    }
+
+    Ok(())
 }
 
 impl FromDB for Customer {
@@ -42,7 +74,7 @@ impl FromDB for Customer {
 
 // #[rocket::get("/customers/count")]
 // pub(super) async fn count(mut db: crate::db::DB,
-// _auth: crate::routes::auth::AuthGuard<{ crate::types::permissions::UserPermissionEnum::CUSTOMERS_READ as u32 }>,
+// _auth: crate::routes::auth::AuthGuard<crate::types::permissions::perm::CUSTOMERS_READ>,
 // ) -> Result<rocket::serde::json::Json<i32>, rocket::http::Status> {
 //     let count = sqlx::query_scalar("SELECT COUNT(*) FROM customers")
 //         .fetch_one(&mut **db)
@@ -55,9 +87,9 @@ impl FromDB for Customer {
 #[rocket::get("/customers/count")]
 pub(super) async fn count(
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::CUSTOMERS_READ as u32 }>,
+    _auth: AuthGuard<perm::CUSTOMERS_READ>,
 ) -> Result<Json<i64>, ApiError> {
-    let count = sqlx::query_scalar("SELECT COUNT(*) FROM customers")
+    let count = sqlx::query_scalar("SELECT COUNT(*) FROM customers WHERE archived_at IS NULL")
         .fetch_one(&mut **db)
         .await?;
 
@@ -68,57 +100,72 @@ pub(super) async fn count(
 pub(super) async fn list(
     req: Json<ListRequest>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::CUSTOMERS_READ as u32 }>,
-) -> Result<Json<Vec<Customer>>, ApiError> {
+    _auth: AuthGuard<perm::CUSTOMERS_READ>,
+) -> Result<Json<ListResponse<Customer>>, ApiError> {
     let req = req.into_inner();
 
-    let mut current_param = 1;
-
-    let sorts_string = super::generate_sorts_string(&req.sorts);
-
-    let (filters_string, filter_binds) =
-        super::generate_filters_string(&req.filters, &mut current_param);
-
-    let query_str = format!(
-        r#"
-        SELECT * FROM customers
-        {}
-        {}
-        LIMIT ${}
-        OFFSET ${}
-        "#,
-        filters_string,
-        sorts_string,
-        current_param,
-        current_param + 1
-    );
-
-    let query = sqlx::query_as(&query_str);
+    let page = COLUMNS
+        .page::<Customer, _>(
+            ListQuery {
+                select: "*",
+                from: "customers",
+                key: "id",
+                condition: (!req.include_archived).then_some("archived_at IS NULL"),
+            },
+            &req,
+            &mut db,
+        )
+        .await?;
 
-    let query = filter_binds
-        .into_iter()
-        .fold(query, |query, value| value.bind_to_query_as(query));
+    Ok(Json(page))
+}
 
-    let data = query
-        .bind(req.range.count)
-        .bind(req.range.offset)
-        .fetch_all(&mut **db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::ColumnNotFound(column) => {
-                ApiError(Status::BadRequest, format!("Column not found: {}", column))
-            }
-            _ => e.into(),
-        })?;
+/// Columns of customer exports
+const EXPORT_COLUMNS: &[ExportColumn] = &[
+    ExportColumn::new("ID", "id", ExportKind::Integer),
+    ExportColumn::new("Name", "name", ExportKind::Text),
+    ExportColumn::new("Phone", "phone", ExportKind::Text),
+    ExportColumn::new("Address", "address", ExportKind::Text),
+    ExportColumn::new("Notes", "notes", ExportKind::Text),
+    ExportColumn::new("Payment Terms (Days)", "payment_terms_days", ExportKind::Integer),
+    ExportColumn::new("Archived At", "archived_at", ExportKind::DateTime),
+];
+
+// POST /customers/export?format=xlsx [Permissions: CUSTOMERS_READ]
+// Request: ListRequest, the range is ignored and every matching row is exported
+// format is csv (default) or xlsx
+// -> 200 OK
+//     customers.csv or customers.xlsx
+// -> 400 Bad Request (invalid format, columns or filters)
+#[rocket::post("/customers/export?<format>", data = "<req>")]
+pub(super) async fn export(
+    format: Option<&str>,
+    req: Json<ListRequest>,
+    db: DB,
+    _auth: AuthGuard<perm::CUSTOMERS_READ>,
+) -> Result<Export, ApiError> {
+    let format = ExportFormat::parse(format)?;
+    let req = req.into_inner();
 
-    Ok(Json(data))
+    let (rest, binds) = COLUMNS.export_string(
+        &ListQuery {
+            select: "*",
+            from: "customers",
+            key: "id",
+            condition: (!req.include_archived).then_some("archived_at IS NULL"),
+        },
+        &req,
+        &mut 2,
+    )?;
+
+    Export::new(format, "customers", EXPORT_COLUMNS, rest, binds, db).await
 }
 
 #[rocket::get("/customers/<id>")]
 pub(super) async fn get(
     id: i32,
     mut db: crate::db::DB,
-    _auth: AuthGuard<{ UserPermissionEnum::CUSTOMERS_READ as u32 }>,
+    _auth: AuthGuard<perm::CUSTOMERS_READ>,
 ) -> Result<Json<Customer>, ApiError> {
     Ok(Json(Customer::from_db(id, &mut db).await?))
 }
@@ -130,20 +177,26 @@ pub(super) struct CustomerPostRequest {
     pub phone: String,
     pub address: String,
     pub notes: String,
+    /// Null uses the payment_terms_days setting
+    #[serde(default)]
+    #[ts(optional)]
+    pub payment_terms_days: Option<i32>,
 }
 
 #[rocket::post("/customers", data = "<item>")]
 pub(super) async fn post(
     item: Json<CustomerPostRequest>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::CUSTOMERS_CREATE as u32 }>,
+    _auth: AuthGuard<perm::CUSTOMERS_CREATE>,
 ) -> Result<ApiReturn<i32>, ApiError> {
     let item = item.into_inner();
 
+    check_payment_terms(item.payment_terms_days)?;
+
     let id: (i32,) = sqlx::query_as(
         r#"
-        INSERT INTO customers (name, phone, address, notes)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO customers (name, phone, address, notes, payment_terms_days)
+        VALUES ($1, $2, $3, $4, $5)
         RETURNING id
         "#,
     )
@@ -151,6 +204,7 @@ pub(super) async fn post(
     .bind(&item.phone)
     .bind(&item.address)
     .bind(&item.notes)
+    .bind(item.payment_terms_days)
     .fetch_one(&mut **db)
     .await?;
 
@@ -164,6 +218,12 @@ pub(super) struct CustomerPatchRequest {
     pub phone: Option<String>,
     pub address: Option<String>,
     pub notes: Option<String>,
+    #[serde(default)]
+    #[ts(optional)]
+    pub payment_terms_days: Option<i32>,
+    /// Go back to the payment_terms_days setting
+    #[serde(default)]
+    pub set_payment_terms_days_null: bool,
 }
 
 #[rocket::patch("/customers/<id>", data = "<req>")]
@@ -171,10 +231,14 @@ pub(super) async fn patch(
     req: Json<CustomerPatchRequest>,
     id: i32,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::CUSTOMERS_UPDATE as u32 }>,
+    _auth: AuthGuard<perm::CUSTOMERS_UPDATE>,
 ) -> Result<Status, ApiError> {
     let req = req.into_inner();
 
+    check_payment_terms(req.payment_terms_days)?;
+
+    let set_terms_null = req.set_payment_terms_days_null;
+
     let mut current_param = 1;
 
     let columns = vec![
@@ -182,6 +246,7 @@ pub(super) async fn patch(
         req.phone.as_ref().map(|_| "phone"),
         req.address.as_ref().map(|_| "address"),
         req.notes.as_ref().map(|_| "notes"),
+        (req.payment_terms_days.is_some() || set_terms_null).then_some("payment_terms_days"),
     ]
     .into_iter()
     .flatten()
@@ -198,6 +263,11 @@ pub(super) async fn patch(
         req.phone.as_ref().map(|v| SqlType::String(v.clone())),
         req.address.as_ref().map(|v| SqlType::String(v.clone())),
         req.notes.as_ref().map(|v| SqlType::String(v.clone())),
+        if set_terms_null {
+            Some(SqlType::Null)
+        } else {
+            req.payment_terms_days.map(SqlType::Int)
+        },
     ]
     .into_iter()
     .flatten();
@@ -233,19 +303,17 @@ pub(super) async fn patch(
 pub(super) async fn search(
     req: Json<SearchRequest>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::CUSTOMERS_READ as u32 }>,
+    _auth: AuthGuard<perm::CUSTOMERS_READ>,
 ) -> Result<Json<Vec<Customer>>, ApiError> {
     let req = req.into_inner();
 
-    let x = req
-        .column
-        .unwrap_or(req.nested_access.unwrap_or("id".to_string()));
+    let x = COLUMNS.search_column(&req)?;
 
     let query_str = format!(
         r#"
         SELECT *, word_similarity($1, {}::text) AS sml
         FROM customers
-        WHERE $1 <% {}::text
+        WHERE $1 <% {}::text AND ($3 OR archived_at IS NULL)
         ORDER BY sml DESC, {}::text
         LIMIT $2
         "#,
@@ -257,6 +325,7 @@ pub(super) async fn search(
     let data = query
         .bind(req.search)
         .bind(req.count)
+        .bind(req.include_archived)
         .fetch_all(&mut **db)
         .await
         .map_err(|e| match e {
@@ -268,3 +337,27 @@ pub(super) async fn search(
 
     Ok(Json(data))
 }
+
+// POST /customers/<id>/archive [Permissions: CUSTOMERS_DELETE]
+// -> 200 OK
+// -> 404 Not Found
+#[rocket::post("/customers/<id>/archive")]
+pub(super) async fn archive(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::CUSTOMERS_DELETE>,
+) -> Result<Status, ApiError> {
+    super::set_archived("customers", id, true, &mut db).await
+}
+
+// POST /customers/<id>/restore [Permissions: CUSTOMERS_DELETE]
+// -> 200 OK
+// -> 404 Not Found
+#[rocket::post("/customers/<id>/restore")]
+pub(super) async fn restore(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::CUSTOMERS_DELETE>,
+) -> Result<Status, ApiError> {
+    super::set_archived("customers", id, false, &mut db).await
+}
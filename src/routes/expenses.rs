@@ -8,10 +8,15 @@ use crate::{
         auth::{AuthGuard, UserRow},
         ListRequest,
     },
-    types::permissions::UserPermissionEnum,
+    types::permissions::perm,
 };
 
-use super::{auth::User, ApiError, ApiReturn, SqlType};
+use super::{
+    auth::User,
+    export::{Export, ExportColumn, ExportFormat, ExportKind},
+    query::{Column, Columns, ListQuery},
+    ApiError, ApiReturn, ListResponse, SqlType,
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS, FromRow)]
 #[ts(export)]
@@ -23,6 +28,29 @@ pub(super) struct Expense {
     pub amount: sqlx::types::BigDecimal,
 }
 
+/// Columns that can be used in list requests, users only exposes the username
+const COLUMNS: Columns = Columns(&[
+    Column::aliased("id", "expenses.id"),
+    Column::aliased("date_time", "expenses.date_time"),
+    Column::aliased("description", "expenses.description"),
+    Column::aliased("amount", "expenses.amount"),
+    Column::aliased("created_by_user_id", "expenses.created_by_user_id"),
+    Column::new("users.username"),
+]);
+
+/// Expenses with their user, for list and export queries
+pub(super) const EXPENSES_FROM: &str =
+    "expenses INNER JOIN users ON expenses.created_by_user_id = users.id";
+
+/// Columns of expense exports, from [`EXPENSES_FROM`]
+pub(super) const EXPENSE_EXPORT_COLUMNS: &[ExportColumn] = &[
+    ExportColumn::new("ID", "expenses.id", ExportKind::Integer),
+    ExportColumn::new("Date", "expenses.date_time", ExportKind::DateTime),
+    ExportColumn::new("Description", "expenses.description", ExportKind::Text),
+    ExportColumn::new("Amount", "expenses.amount", ExportKind::Currency),
+    ExportColumn::new("Created By", "users.username", ExportKind::Text),
+];
+
 #[derive(FromRow, Debug)]
 pub(super) struct ExpenseRow {
     pub id: i32,
@@ -47,7 +75,7 @@ impl From<ExpenseRow> for Expense {
 #[rocket::get("/expenses/<id>")]
 pub(super) async fn get(
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::EXPENSES_READ as u32 }>,
+    _auth: AuthGuard<perm::EXPENSES_READ>,
     id: i32,
 ) -> Result<rocket::serde::json::Json<Expense>, ApiError> {
     let expense_row: ExpenseRow = sqlx::query_as(
@@ -73,7 +101,7 @@ pub(super) async fn get(
 #[rocket::get("/expenses/count")]
 pub(super) async fn count(
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::EXPENSES_READ as u32 }>,
+    _auth: AuthGuard<perm::EXPENSES_READ>,
 ) -> Result<rocket::serde::json::Json<i64>, ApiError> {
     let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM expenses")
         .fetch_one(&mut **db)
@@ -85,58 +113,60 @@ pub(super) async fn count(
 #[rocket::post("/expenses/list", data = "<list_request>")]
 pub(super) async fn list(
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::EXPENSES_READ as u32 }>,
+    _auth: AuthGuard<perm::EXPENSES_READ>,
     list_request: rocket::serde::json::Json<ListRequest>,
-) -> Result<rocket::serde::json::Json<Vec<Expense>>, ApiError> {
+) -> Result<rocket::serde::json::Json<ListResponse<Expense>>, ApiError> {
     let list_request = list_request.into_inner();
 
-    let mut current_param = 1;
-
-    let sorts_sql = super::generate_sorts_string(&list_request.sorts);
-
-    let (filters_sql, filter_binds) =
-        super::generate_filters_string(&list_request.filters, &mut current_param);
-
-    let query = format!(
-        r#"SELECT 
-            expenses.id, 
-            date_time, 
-            description, 
-            row_to_json(users) AS created_by_user,
-            amount
-        FROM expenses
-            INNER JOIN users ON expenses.created_by_user_id = users.id
-        {}
-        {}
-        LIMIT ${}
-        OFFSET ${}
-        "#,
-        filters_sql,
-        sorts_sql,
-        current_param,
-        current_param + 1
-    );
-
-    let query = sqlx::query_as(&query);
-
-    let query = filter_binds
-        .into_iter()
-        .fold(query, |query, value| value.bind_to_query_as(query));
-
-    let query = query
-        .bind(list_request.range.count)
-        .bind(list_request.range.offset);
+    let page = COLUMNS
+        .page::<ExpenseRow, _>(
+            ListQuery {
+                select: r#"
+                    expenses.id,
+                    expenses.date_time,
+                    expenses.description,
+                    row_to_json(users) AS created_by_user,
+                    expenses.amount
+                "#,
+                from: EXPENSES_FROM,
+                key: "expenses.id",
+                condition: None,
+            },
+            &list_request,
+            &mut db,
+        )
+        .await?;
 
-    let expenses: Vec<Expense> = query
-        .fetch_all(&mut **db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => ApiError(Status::NotFound, "No expenses found".to_string()),
-            _ => ApiError(Status::InternalServerError, e.to_string()),
-        })
-        .map(|rows: Vec<ExpenseRow>| rows.into_iter().map(Expense::from).collect())?;
+    Ok(rocket::serde::json::Json(page))
+}
 
-    Ok(rocket::serde::json::Json(expenses))
+// POST /expenses/export?format=xlsx [Permissions: EXPENSES_READ]
+// Request: ListRequest, the range is ignored and every matching row is exported
+// format is csv (default) or xlsx
+// -> 200 OK
+//     expenses.csv or expenses.xlsx
+// -> 400 Bad Request (invalid format, columns or filters)
+#[rocket::post("/expenses/export?<format>", data = "<list_request>")]
+pub(super) async fn export(
+    format: Option<&str>,
+    db: DB,
+    _auth: AuthGuard<perm::EXPENSES_READ>,
+    list_request: rocket::serde::json::Json<ListRequest>,
+) -> Result<Export, ApiError> {
+    let format = ExportFormat::parse(format)?;
+
+    let (rest, binds) = COLUMNS.export_string(
+        &ListQuery {
+            select: "*",
+            from: EXPENSES_FROM,
+            key: "expenses.id",
+            condition: None,
+        },
+        &list_request,
+        &mut 2,
+    )?;
+
+    Export::new(format, "expenses", EXPENSE_EXPORT_COLUMNS, rest, binds, db).await
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
@@ -150,7 +180,7 @@ pub(super) struct ExpensePostRequest {
 pub(super) async fn post(
     mut db: DB,
     #[allow(unused)]
-    auth: AuthGuard<{ UserPermissionEnum::EXPENSES_CREATE as u32 }>,
+    auth: AuthGuard<perm::EXPENSES_CREATE>,
     expense: rocket::serde::json::Json<ExpensePostRequest>,
 ) -> Result<ApiReturn<i32>, ApiError> {
     let req = expense.into_inner();
@@ -182,7 +212,7 @@ pub(super) struct ExpensePatchRequest {
 pub(super) async fn patch(
     mut db: DB,
     #[allow(unused)]
-    auth: AuthGuard<{ UserPermissionEnum::EXPENSES_UPDATE as u32 }>,
+    auth: AuthGuard<perm::EXPENSES_UPDATE>,
     id: i32,
     expense: rocket::serde::json::Json<ExpensePatchRequest>,
 ) -> Result<Status, ApiError> {
@@ -245,7 +275,7 @@ pub(super) async fn patch(
 pub(super) async fn delete(
     mut db: DB,
     #[allow(unused)]
-    auth: AuthGuard<{ UserPermissionEnum::EXPENSES_DELETE as u32 }>,
+    auth: AuthGuard<perm::EXPENSES_DELETE>,
     id: i32,
 ) -> Result<Status, ApiError> {
     sqlx::query("DELETE FROM expenses WHERE id = $1")
@@ -0,0 +1,830 @@
+//! Streams list and report rows as CSV or XLSX spreadsheets.
+//!
+//! Rows are written while they are fetched, so a large export is never held in memory.
+//! An XLSX file is a zip archive of XML files. It is written here with uncompressed entries
+//! whose checksum and size follow their data, as they aren't known before the last row.
+
+use std::pin::Pin;
+
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDate, NaiveDateTime};
+use rocket::{
+    futures::{Stream, StreamExt},
+    http::{ContentType, Status},
+    response::{self, stream::ByteStream, Responder},
+    tokio::sync::mpsc,
+    Request,
+};
+use sqlx::{postgres::PgRow, Row};
+
+use crate::{
+    db::DB,
+    settings::{timezone, CurrencyFormat},
+};
+
+use super::{ApiError, SqlType};
+
+/// Bytes are sent once this many are written
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Rows read ahead of the response
+const ROW_BUFFER: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum ExportFormat {
+    Csv,
+    Xlsx,
+}
+
+impl ExportFormat {
+    /// The `format` query parameter, csv if it's missing
+    pub fn parse(value: Option<&str>) -> Result<Self, ApiError> {
+        match value {
+            None | Some("csv") => Ok(Self::Csv),
+            Some("xlsx") => Ok(Self::Xlsx),
+            Some(value) => Err(ApiError(
+                Status::BadRequest,
+                format!("Invalid format: {}, expected csv or xlsx", value),
+            )),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Xlsx => "xlsx",
+        }
+    }
+
+    fn content_type(self) -> ContentType {
+        match self {
+            Self::Csv => ContentType::CSV,
+            Self::Xlsx => ContentType::new(
+                "application",
+                "vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum ExportKind {
+    Text,
+    Integer,
+    /// Formatted with the currency_* settings
+    Currency,
+    /// Shown in the timezone setting
+    DateTime,
+    Boolean,
+}
+
+/// A column of an export
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct ExportColumn {
+    pub header: &'static str,
+    /// SQL expression of the value, e.g. `customers.name`
+    pub sql: &'static str,
+    pub kind: ExportKind,
+}
+
+impl ExportColumn {
+    pub const fn new(header: &'static str, sql: &'static str, kind: ExportKind) -> Self {
+        ExportColumn { header, sql, kind }
+    }
+
+    /// The value cast to the type it is read as, `$1` is the timezone
+    fn select_sql(&self) -> String {
+        match self.kind {
+            ExportKind::Text => format!("({})::TEXT", self.sql),
+            ExportKind::Integer => format!("({})::BIGINT", self.sql),
+            ExportKind::Currency => format!("({})::NUMERIC", self.sql),
+            ExportKind::DateTime => format!("({})::TIMESTAMPTZ AT TIME ZONE $1", self.sql),
+            ExportKind::Boolean => format!("({})::BOOLEAN", self.sql),
+        }
+    }
+}
+
+enum Cell {
+    Empty,
+    Text(String),
+    Integer(i64),
+    Currency(BigDecimal),
+    DateTime(NaiveDateTime),
+    Boolean(bool),
+}
+
+impl Cell {
+    fn from_row(row: &PgRow, index: usize, kind: ExportKind) -> Result<Self, sqlx::Error> {
+        let cell = match kind {
+            ExportKind::Text => row.try_get::<Option<String>, _>(index)?.map(Cell::Text),
+            ExportKind::Integer => row.try_get::<Option<i64>, _>(index)?.map(Cell::Integer),
+            ExportKind::Currency => row
+                .try_get::<Option<BigDecimal>, _>(index)?
+                .map(Cell::Currency),
+            ExportKind::DateTime => row
+                .try_get::<Option<NaiveDateTime>, _>(index)?
+                .map(Cell::DateTime),
+            ExportKind::Boolean => row.try_get::<Option<bool>, _>(index)?.map(Cell::Boolean),
+        };
+
+        Ok(cell.unwrap_or(Cell::Empty))
+    }
+
+    fn csv(&self, currency: &CurrencyFormat) -> String {
+        match self {
+            Cell::Empty => String::new(),
+            // Spreadsheets would run text starting like a formula
+            Cell::Text(text) if text.starts_with(['=', '+', '-', '@', '\t', '\r']) => {
+                format!("'{}", text)
+            }
+            Cell::Text(text) => text.clone(),
+            Cell::Integer(number) => number.to_string(),
+            Cell::Currency(amount) => currency.format(amount),
+            Cell::DateTime(date_time) => date_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            Cell::Boolean(value) => value.to_string(),
+        }
+    }
+
+    fn xlsx(&self, reference: &str) -> String {
+        match self {
+            Cell::Empty => String::new(),
+            Cell::Text(text) => format!(
+                r#"<c r="{}" t="inlineStr"><is><t xml:space="preserve">{}</t></is></c>"#,
+                reference,
+                escape_xml(text)
+            ),
+            Cell::Integer(number) => format!(r#"<c r="{}"><v>{}</v></c>"#, reference, number),
+            Cell::Currency(amount) => format!(
+                r#"<c r="{}" s="{}"><v>{}</v></c>"#,
+                reference, CURRENCY_STYLE, amount
+            ),
+            Cell::DateTime(date_time) => format!(
+                r#"<c r="{}" s="{}"><v>{}</v></c>"#,
+                reference,
+                DATE_TIME_STYLE,
+                excel_serial(date_time)
+            ),
+            Cell::Boolean(value) => format!(
+                r#"<c r="{}" t="b"><v>{}</v></c>"#,
+                reference,
+                u8::from(*value)
+            ),
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_line(fields: impl Iterator<Item = String>) -> String {
+    let mut line = fields.map(|f| csv_field(&f)).collect::<Vec<_>>().join(",");
+    line.push_str("\r\n");
+    line
+}
+
+fn escape_xml(text: &str) -> String {
+    text.chars()
+        .filter(|c| matches!(c, '\t' | '\n' | '\r') || *c >= ' ')
+        .fold(String::with_capacity(text.len()), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                c => escaped.push(c),
+            }
+            escaped
+        })
+}
+
+/// Days since 1899-12-30, how spreadsheets store dates
+fn excel_serial(date_time: &NaiveDateTime) -> f64 {
+    let epoch = NaiveDate::from_ymd_opt(1899, 12, 30)
+        .expect("1899-12-30 is a valid date")
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is a valid time");
+
+    (*date_time - epoch).num_milliseconds() as f64 / 86_400_000.0
+}
+
+/// Column letters of a zero based index, e.g. 27 is AB
+fn column_name(mut index: usize) -> String {
+    let mut name = vec![];
+
+    loop {
+        name.push(b'A' + (index % 26) as u8);
+
+        if index < 26 {
+            break;
+        }
+
+        index = index / 26 - 1;
+    }
+
+    name.reverse();
+    String::from_utf8(name).expect("column names are ASCII")
+}
+
+/// Indices in the cellXfs of styles.xml
+const CURRENCY_STYLE: usize = 1;
+const DATE_TIME_STYLE: usize = 2;
+const HEADER_STYLE: usize = 3;
+
+fn styles_xml(currency: &CurrencyFormat) -> String {
+    // Quotes would end the literal text, the group and decimal separators are the reader's
+    let literal = |text: &str| {
+        let text = text.replace('"', "");
+
+        if text.is_empty() {
+            text
+        } else {
+            format!("\"{}\"", text)
+        }
+    };
+
+    let decimals = if currency.decimal_places > 0 {
+        format!(".{}", "0".repeat(currency.decimal_places as usize))
+    } else {
+        String::new()
+    };
+
+    let currency_format = format!(
+        "{}#,##0{}{}",
+        literal(&currency.prefix),
+        decimals,
+        literal(&currency.suffix)
+    );
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">
+<numFmts count="2"><numFmt numFmtId="164" formatCode="{}"/><numFmt numFmtId="165" formatCode="yyyy-mm-dd hh:mm:ss"/></numFmts>
+<fonts count="2"><font><sz val="11"/><name val="Calibri"/></font><font><b/><sz val="11"/><name val="Calibri"/></font></fonts>
+<fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills>
+<borders count="1"><border><left/><right/><top/><bottom/><diagonal/></border></borders>
+<cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs>
+<cellXfs count="4"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/><xf numFmtId="164" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/><xf numFmtId="165" fontId="0" fillId="0" borderId="0" xfId="0" applyNumberFormat="1"/><xf numFmtId="0" fontId="1" fillId="0" borderId="0" xfId="0" applyFont="1"/></cellXfs>
+<cellStyles count="1"><cellStyle name="Normal" xfId="0" builtinId="0"/></cellStyles>
+</styleSheet>"#,
+        escape_xml(&currency_format)
+    )
+}
+
+const CONTENT_TYPES_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types">
+<Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/>
+<Default Extension="xml" ContentType="application/xml"/>
+<Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>
+<Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>
+<Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/>
+</Types>"#;
+
+const RELS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/>
+</Relationships>"#;
+
+const WORKBOOK_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships">
+<sheets><sheet name="Export" sheetId="1" r:id="rId1"/></sheets>
+</workbook>"#;
+
+const WORKBOOK_RELS_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+<Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/>
+<Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>
+</Relationships>"#;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+struct ZipEntry {
+    name: &'static str,
+    offset: u32,
+    crc: u32,
+    size: u32,
+}
+
+/// Writes a zip archive front to back. Entries are stored uncompressed and archives over
+/// 4 GiB aren't supported.
+#[derive(Default)]
+struct ZipWriter {
+    offset: u32,
+    entries: Vec<ZipEntry>,
+}
+
+impl ZipWriter {
+    // Version 2.0, sizes in a data descriptor after the data, stored, 1980-01-01 00:00
+    const VERSION: u16 = 20;
+    const FLAGS: u16 = 0x0008;
+    const METHOD: u16 = 0;
+    const TIME: u16 = 0;
+    const DATE: u16 = 0x0021;
+
+    fn start_file(&mut self, name: &'static str, out: &mut Vec<u8>) {
+        self.entries.push(ZipEntry {
+            name,
+            offset: self.offset,
+            crc: !0,
+            size: 0,
+        });
+
+        let start = out.len();
+        out.extend(0x0403_4b50u32.to_le_bytes());
+        out.extend(Self::VERSION.to_le_bytes());
+        out.extend(Self::FLAGS.to_le_bytes());
+        out.extend(Self::METHOD.to_le_bytes());
+        out.extend(Self::TIME.to_le_bytes());
+        out.extend(Self::DATE.to_le_bytes());
+        // Checksum and sizes are in the data descriptor
+        out.extend([0; 12]);
+        out.extend((name.len() as u16).to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        out.extend(name.as_bytes());
+        self.offset += (out.len() - start) as u32;
+    }
+
+    fn write(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        let entry = self.entries.last_mut().expect("write is called after start_file");
+
+        for byte in data {
+            entry.crc = CRC_TABLE[((entry.crc ^ *byte as u32) & 0xFF) as usize] ^ (entry.crc >> 8);
+        }
+
+        entry.size += data.len() as u32;
+        self.offset += data.len() as u32;
+        out.extend(data);
+    }
+
+    fn end_file(&mut self, out: &mut Vec<u8>) {
+        let entry = self.entries.last_mut().expect("end_file is called after start_file");
+        entry.crc = !entry.crc;
+
+        out.extend(0x0807_4b50u32.to_le_bytes());
+        out.extend(entry.crc.to_le_bytes());
+        out.extend(entry.size.to_le_bytes());
+        out.extend(entry.size.to_le_bytes());
+        self.offset += 16;
+    }
+
+    fn add_file(&mut self, name: &'static str, data: &[u8], out: &mut Vec<u8>) {
+        self.start_file(name, out);
+        self.write(data, out);
+        self.end_file(out);
+    }
+
+    /// The central directory, which readers use to find the entries
+    fn finish(self, out: &mut Vec<u8>) {
+        let start = out.len();
+
+        for entry in &self.entries {
+            out.extend(0x0201_4b50u32.to_le_bytes());
+            out.extend(Self::VERSION.to_le_bytes());
+            out.extend(Self::VERSION.to_le_bytes());
+            out.extend(Self::FLAGS.to_le_bytes());
+            out.extend(Self::METHOD.to_le_bytes());
+            out.extend(Self::TIME.to_le_bytes());
+            out.extend(Self::DATE.to_le_bytes());
+            out.extend(entry.crc.to_le_bytes());
+            out.extend(entry.size.to_le_bytes());
+            out.extend(entry.size.to_le_bytes());
+            out.extend((entry.name.len() as u16).to_le_bytes());
+            // Extra field, comment, disk, internal and external attributes
+            out.extend([0; 12]);
+            out.extend(entry.offset.to_le_bytes());
+            out.extend(entry.name.as_bytes());
+        }
+
+        let size = (out.len() - start) as u32;
+
+        out.extend(0x0605_4b50u32.to_le_bytes());
+        out.extend([0; 4]);
+        out.extend((self.entries.len() as u16).to_le_bytes());
+        out.extend((self.entries.len() as u16).to_le_bytes());
+        out.extend(size.to_le_bytes());
+        out.extend(self.offset.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+    }
+}
+
+/// Writes the rows of an export in one of the formats
+enum SheetWriter {
+    Csv,
+    Xlsx { zip: ZipWriter, row: usize },
+}
+
+impl SheetWriter {
+    fn start(
+        format: ExportFormat,
+        columns: &[ExportColumn],
+        currency: &CurrencyFormat,
+        out: &mut Vec<u8>,
+    ) -> Self {
+        match format {
+            ExportFormat::Csv => {
+                // Byte order mark, so spreadsheets read the file as UTF-8
+                out.extend("\u{feff}".as_bytes());
+                out.extend(csv_line(columns.iter().map(|c| c.header.to_string())).as_bytes());
+
+                SheetWriter::Csv
+            }
+            ExportFormat::Xlsx => {
+                let mut zip = ZipWriter::default();
+
+                zip.add_file("[Content_Types].xml", CONTENT_TYPES_XML.as_bytes(), out);
+                zip.add_file("_rels/.rels", RELS_XML.as_bytes(), out);
+                zip.add_file("xl/workbook.xml", WORKBOOK_XML.as_bytes(), out);
+                zip.add_file("xl/_rels/workbook.xml.rels", WORKBOOK_RELS_XML.as_bytes(), out);
+                zip.add_file("xl/styles.xml", styles_xml(currency).as_bytes(), out);
+
+                zip.start_file("xl/worksheets/sheet1.xml", out);
+
+                let mut sheet = String::from(
+                    r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+                );
+                sheet.push_str(&format!(
+                    r#"<cols><col min="1" max="{}" width="20" customWidth="1"/></cols><sheetData><row r="1">"#,
+                    columns.len()
+                ));
+
+                for (i, column) in columns.iter().enumerate() {
+                    sheet.push_str(&format!(
+                        r#"<c r="{}1" t="inlineStr" s="{}"><is><t>{}</t></is></c>"#,
+                        column_name(i),
+                        HEADER_STYLE,
+                        escape_xml(column.header)
+                    ));
+                }
+
+                sheet.push_str("</row>");
+                zip.write(sheet.as_bytes(), out);
+
+                SheetWriter::Xlsx { zip, row: 1 }
+            }
+        }
+    }
+
+    fn row(&mut self, cells: &[Cell], currency: &CurrencyFormat, out: &mut Vec<u8>) {
+        match self {
+            SheetWriter::Csv => {
+                out.extend(csv_line(cells.iter().map(|c| c.csv(currency))).as_bytes());
+            }
+            SheetWriter::Xlsx { zip, row } => {
+                *row += 1;
+
+                let mut xml = format!(r#"<row r="{}">"#, row);
+
+                for (i, cell) in cells.iter().enumerate() {
+                    xml.push_str(&cell.xlsx(&format!("{}{}", column_name(i), row)));
+                }
+
+                xml.push_str("</row>");
+                zip.write(xml.as_bytes(), out);
+            }
+        }
+    }
+
+    fn finish(self, out: &mut Vec<u8>) {
+        if let SheetWriter::Xlsx { mut zip, .. } = self {
+            zip.write(b"</sheetData></worksheet>", out);
+            zip.end_file(out);
+            zip.finish(out);
+        }
+    }
+}
+
+type ExportBody = Pin<Box<dyn Stream<Item = Vec<u8>> + Send>>;
+
+/// A spreadsheet download, streamed while the rows are read
+pub(super) struct Export {
+    format: ExportFormat,
+    filename: String,
+    body: ExportBody,
+}
+
+impl Export {
+    /// Streams the rows of `SELECT <columns> <rest>`, where `rest` is the FROM clause and
+    /// everything after it. Its placeholders start at `$2`, as `$1` is the timezone.
+    pub async fn new(
+        format: ExportFormat,
+        filename: &str,
+        columns: &'static [ExportColumn],
+        rest: String,
+        binds: Vec<SqlType>,
+        mut db: DB,
+    ) -> Result<Self, ApiError> {
+        let currency = CurrencyFormat::load(&mut db).await?;
+        let timezone = timezone(&mut db).await?;
+
+        let sql = format!(
+            "SELECT {} {}",
+            columns
+                .iter()
+                .map(ExportColumn::select_sql)
+                .collect::<Vec<_>>()
+                .join(", "),
+            rest
+        );
+
+        // Rows are read by a task that owns the connection, so the first one can be awaited
+        // here. A bad filter or cast usually fails on it, while an error response can still
+        // be sent.
+        let (sender, mut receiver) = mpsc::channel(ROW_BUFFER);
+
+        rocket::tokio::spawn(async move {
+            let mut rows = binds
+                .into_iter()
+                .fold(sqlx::query(&sql).bind(&timezone), |q, value| value.bind_to_query(q))
+                .fetch(&mut **db);
+
+            while let Some(row) = rows.next().await {
+                let cells = row.and_then(|row| {
+                    columns
+                        .iter()
+                        .enumerate()
+                        .map(|(i, column)| Cell::from_row(&row, i, column.kind))
+                        .collect::<Result<Vec<_>, _>>()
+                });
+                if let Err(e) = &cells {
+                    log::error!("Export of {} failed: {}", sql, e);
+                }
+                let failed = cells.is_err();
+
+                // The receiver is gone when the download was cancelled
+                if sender.send(cells).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let mut next = match receiver.recv().await {
+            Some(Err(e)) => return Err(e.into()),
+            next => next,
+        };
+
+        let body = ByteStream! {
+            let mut out = vec![];
+            let mut writer = SheetWriter::start(format, columns, &currency, &mut out);
+
+            while let Some(cells) = next {
+                match cells {
+                    Ok(cells) => writer.row(&cells, &currency, &mut out),
+                    Err(_) => {
+                        // The response has started, so the file is left incomplete
+                        yield out;
+                        return;
+                    }
+                }
+
+                if out.len() >= CHUNK_SIZE {
+                    yield std::mem::take(&mut out);
+                }
+
+                next = receiver.recv().await;
+            }
+
+            writer.finish(&mut out);
+            yield out;
+        };
+
+        Ok(Export {
+            format,
+            filename: filename.to_string(),
+            body: Box::pin(body.0),
+        })
+    }
+}
+
+impl<'r> Responder<'r, 'r> for Export {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'r> {
+        let mut response = ByteStream(self.body).respond_to(req)?;
+
+        response.set_header(self.format.content_type());
+        response.set_raw_header(
+            "Content-Disposition",
+            format!(
+                "attachment; filename=\"{}.{}\"",
+                self.filename,
+                self.format.extension()
+            ),
+        );
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn currency() -> CurrencyFormat {
+        CurrencyFormat {
+            prefix: "$".to_string(),
+            suffix: String::new(),
+            decimal_places: 2,
+            decimal_separator: ".".to_string(),
+            thousand_separator: ",".to_string(),
+        }
+    }
+
+    fn date_time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn crc_matches_check_value() {
+        let mut zip = ZipWriter::default();
+        let mut out = vec![];
+
+        zip.add_file("check", b"123456789", &mut out);
+        zip.add_file("empty", b"", &mut out);
+
+        assert_eq!(zip.entries[0].crc, 0xCBF4_3926);
+        assert_eq!(zip.entries[1].crc, 0);
+    }
+
+    #[test]
+    fn column_names() {
+        assert_eq!(column_name(0), "A");
+        assert_eq!(column_name(25), "Z");
+        assert_eq!(column_name(26), "AA");
+        assert_eq!(column_name(27), "AB");
+        assert_eq!(column_name(701), "ZZ");
+        assert_eq!(column_name(702), "AAA");
+    }
+
+    #[test]
+    fn csv_quotes_fields_when_needed() {
+        assert_eq!(csv_field("plain text"), "plain text");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("cr\r"), "\"cr\r\"");
+        assert_eq!(
+            csv_line(["a".to_string(), "b,c".to_string(), String::new()].into_iter()),
+            "a,\"b,c\",\r\n"
+        );
+    }
+
+    #[test]
+    fn csv_guards_text_that_looks_like_a_formula() {
+        let currency = currency();
+        let text = |s: &str| Cell::Text(s.to_string()).csv(&currency);
+
+        assert_eq!(text("=SUM(A1:A9)"), "'=SUM(A1:A9)");
+        assert_eq!(text("+1"), "'+1");
+        assert_eq!(text("-1"), "'-1");
+        assert_eq!(text("@cmd"), "'@cmd");
+        assert_eq!(text("\tx"), "'\tx");
+        assert_eq!(text("\rx"), "'\rx");
+        assert_eq!(text("a=b"), "a=b");
+        // Only text is entered as typed, numbers are written by the export itself
+        assert_eq!(Cell::Integer(-1).csv(&currency), "-1");
+        assert_eq!(Cell::Empty.csv(&currency), "");
+        assert_eq!(Cell::Boolean(true).csv(&currency), "true");
+        assert_eq!(
+            Cell::DateTime(date_time("2024-03-01 09:05:00")).csv(&currency),
+            "2024-03-01 09:05:00"
+        );
+    }
+
+    #[test]
+    fn xml_is_escaped() {
+        assert_eq!(
+            escape_xml(r#"a & <b> "c" 'd'"#),
+            "a &amp; &lt;b&gt; &quot;c&quot; 'd'"
+        );
+        // Control characters other than whitespace aren't allowed in XML 1.0
+        assert_eq!(escape_xml("\u{1}x\ty\n\u{1f}z\r"), "x\ty\nz\r");
+        assert_eq!(escape_xml("ünïcödé €"), "ünïcödé €");
+    }
+
+    #[test]
+    fn excel_serials() {
+        assert_eq!(excel_serial(&date_time("1899-12-30 00:00:00")), 0.0);
+        assert_eq!(excel_serial(&date_time("1900-01-01 00:00:00")), 2.0);
+        assert_eq!(excel_serial(&date_time("2024-01-01 00:00:00")), 45292.0);
+        assert_eq!(excel_serial(&date_time("2024-01-01 18:00:00")), 45292.75);
+    }
+
+    #[test]
+    fn csv_export_starts_with_bom_and_header() {
+        const COLUMNS: [ExportColumn; 2] = [
+            ExportColumn::new("Name", "name", ExportKind::Text),
+            ExportColumn::new("Total, incl. tax", "total", ExportKind::Currency),
+        ];
+        let currency = currency();
+        let mut out = vec![];
+
+        let mut writer = SheetWriter::start(ExportFormat::Csv, &COLUMNS, &currency, &mut out);
+        writer.row(
+            &[
+                Cell::Text("Widget".to_string()),
+                Cell::Currency(BigDecimal::from_str("1234.5").unwrap()),
+            ],
+            &currency,
+            &mut out,
+        );
+        writer.finish(&mut out);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\u{feff}Name,\"Total, incl. tax\"\r\nWidget,\"$1,234.50\"\r\n"
+        );
+    }
+
+    #[test]
+    fn xlsx_export_is_a_valid_zip() {
+        const COLUMNS: [ExportColumn; 6] = [
+            ExportColumn::new("Name", "name", ExportKind::Text),
+            ExportColumn::new("Quantity", "quantity", ExportKind::Integer),
+            ExportColumn::new("Total", "total", ExportKind::Currency),
+            ExportColumn::new("Date", "date_time", ExportKind::DateTime),
+            ExportColumn::new("Paid", "paid", ExportKind::Boolean),
+            ExportColumn::new("Notes", "notes", ExportKind::Text),
+        ];
+        let currency = currency();
+        let mut out = vec![];
+
+        let mut writer = SheetWriter::start(ExportFormat::Xlsx, &COLUMNS, &currency, &mut out);
+        for _ in 0..2 {
+            writer.row(
+                &[
+                    Cell::Text("Nuts & <bolts>".to_string()),
+                    Cell::Integer(5),
+                    Cell::Currency(BigDecimal::from_str("12.50").unwrap()),
+                    Cell::DateTime(date_time("2024-01-01 18:00:00")),
+                    Cell::Boolean(true),
+                    Cell::Empty,
+                ],
+                &currency,
+                &mut out,
+            );
+        }
+        writer.finish(&mut out);
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(out)).unwrap();
+        let mut names = archive.file_names().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "[Content_Types].xml",
+                "_rels/.rels",
+                "xl/_rels/workbook.xml.rels",
+                "xl/styles.xml",
+                "xl/workbook.xml",
+                "xl/worksheets/sheet1.xml",
+            ]
+        );
+
+        let mut sheet = String::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).unwrap();
+            let mut data = String::new();
+            // Fails if the checksum in the central directory doesn't match the data
+            file.read_to_string(&mut data).unwrap();
+            assert_eq!(file.size(), data.len() as u64);
+
+            if file.name() == "xl/worksheets/sheet1.xml" {
+                sheet = data;
+            }
+        }
+
+        assert!(sheet.contains(r#"<c r="F1" t="inlineStr" s="3"><is><t>Notes</t></is></c>"#));
+        assert!(sheet.contains(r#"<row r="3"><c r="A3" t="inlineStr"><is><t xml:space="preserve">Nuts &amp; &lt;bolts&gt;</t></is></c><c r="B3"><v>5</v></c><c r="C3" s="1"><v>12.50</v></c><c r="D3" s="2"><v>45292.75</v></c><c r="E3" t="b"><v>1</v></c></row>"#));
+        assert!(sheet.ends_with("</sheetData></worksheet>"));
+    }
+}
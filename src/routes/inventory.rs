@@ -2,13 +2,17 @@ use crate::db::FromDB;
 use crate::routes::auth::AuthGuard;
 use crate::routes::search::SearchRequest;
 use crate::routes::{ListRequest, SqlType};
-use crate::{db::DB, types::permissions::UserPermissionEnum};
+use crate::{db::DB, types::permissions::perm};
 use bigdecimal::BigDecimal;
 use rocket::{http::Status, serde::json::Json};
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 
-use super::{ApiError, ApiReturn};
+use super::{
+    export::{Export, ExportColumn, ExportFormat, ExportKind},
+    query::{Column, Columns, ListQuery},
+    ApiError, ApiReturn, ListResponse,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ts_rs::TS, FromRow)]
 #[ts(export)]
@@ -20,8 +24,26 @@ pub(super) struct InventoryItem {
     pub price: BigDecimal,
     pub stock: i32,
     pub quantity_per_box: i32,
+    /// Average cost of the units in stock, set from the purchases by the costing method
+    #[serde(default)]
+    pub unit_cost: BigDecimal,
+    /// Archived items are hidden from lists but still shown on existing orders and purchases
+    #[serde(default)]
+    pub archived_at: Option<sqlx::types::chrono::DateTime<chrono::Utc>>,
 }
 
+/// Columns that can be used in list and search requests
+const COLUMNS: Columns = Columns(&[
+    Column::new("id"),
+    Column::new("name"),
+    Column::new("description"),
+    Column::new("price"),
+    Column::new("stock"),
+    Column::new("quantity_per_box"),
+    Column::new("unit_cost"),
+    Column::new("archived_at"),
+]);
+
 impl FromDB for InventoryItem {
     async fn from_db(id: i32, db: &mut crate::db::DB) -> Result<Self, ApiError> {
         sqlx::query_as(
@@ -65,7 +87,7 @@ pub(super) struct InventoryItemPostRequest {
 }
 
 pub(super) async fn count_impl(mut db: DB) -> Result<Json<i64>, ApiError> {
-    let count = sqlx::query_scalar("SELECT COUNT(*) FROM inventory")
+    let count = sqlx::query_scalar("SELECT COUNT(*) FROM inventory WHERE archived_at IS NULL")
         .fetch_one(&mut **db)
         .await?;
 
@@ -77,7 +99,7 @@ pub(super) async fn count_impl(mut db: DB) -> Result<Json<i64>, ApiError> {
 #[rocket::get("/inventory/count")]
 pub(super) async fn count(
     db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::INVENTORY_READ as u32 }>,
+    _auth: AuthGuard<perm::INVENTORY_READ>,
 ) -> Result<Json<i64>, ApiError> {
     count_impl(db).await
 }
@@ -100,59 +122,79 @@ pub(super) async fn count(
 ///     }]
 /// }
 /// ```
-/// Response: Vec<InventoryItem>
+/// Response: ListResponse<InventoryItem>
 #[rocket::post("/inventory/list", data = "<req>")]
 pub(super) async fn list(
     mut db: DB,
-    #[allow(unused)] _auth: AuthGuard<{ UserPermissionEnum::INVENTORY_READ as u32 }>,
+    #[allow(unused)] _auth: AuthGuard<perm::INVENTORY_READ>,
     req: Json<ListRequest>,
-) -> Result<Json<Vec<InventoryItem>>, ApiError> {
+) -> Result<Json<ListResponse<InventoryItem>>, ApiError> {
     let req = req.into_inner();
 
-    let mut current_param = 1;
-
-    let sorts_string = super::generate_sorts_string(&req.sorts);
-
-    let (filters_string, filter_binds) =
-        super::generate_filters_string(&req.filters, &mut current_param);
-
-    let query_str = format!(
-        r#"
-        SELECT * FROM inventory
-        {}
-        {}
-        LIMIT ${}
-        OFFSET ${}
-        "#,
-        filters_string,
-        sorts_string,
-        current_param,
-        current_param + 1
-    );
-
-    let query = sqlx::query_as(&query_str);
-
-    let query = filter_binds
-        .into_iter()
-        .fold(query, |query, value| value.bind_to_query_as(query));
+    let page = COLUMNS
+        .page::<InventoryItem, _>(
+            ListQuery {
+                select: "*",
+                from: "inventory",
+                key: "id",
+                condition: (!req.include_archived).then_some("archived_at IS NULL"),
+            },
+            &req,
+            &mut db,
+        )
+        .await?;
 
-    let query = query.bind(req.range.count).bind(req.range.offset);
+    Ok(Json(page))
+}
 
-    let data: Vec<InventoryItem> = query.fetch_all(&mut **db).await.map_err(|e| match e {
-        sqlx::Error::ColumnNotFound(column) => {
-            ApiError(Status::BadRequest, format!("Column not found: {}", column))
-        }
-        _ => e.into(),
-    })?;
+/// Columns of inventory exports
+const EXPORT_COLUMNS: &[ExportColumn] = &[
+    ExportColumn::new("ID", "id", ExportKind::Integer),
+    ExportColumn::new("Name", "name", ExportKind::Text),
+    ExportColumn::new("Description", "description", ExportKind::Text),
+    ExportColumn::new("Price", "price", ExportKind::Currency),
+    ExportColumn::new("Stock", "stock", ExportKind::Integer),
+    ExportColumn::new("Quantity Per Box", "quantity_per_box", ExportKind::Integer),
+    ExportColumn::new("Unit Cost", "unit_cost", ExportKind::Currency),
+    ExportColumn::new("Stock Value", "stock * unit_cost", ExportKind::Currency),
+    ExportColumn::new("Archived At", "archived_at", ExportKind::DateTime),
+];
+
+// POST /inventory/export?format=xlsx [Permissions: INVENTORY_READ]
+// Request: ListRequest, the range is ignored and every matching row is exported
+// format is csv (default) or xlsx
+// -> 200 OK
+//     inventory.csv or inventory.xlsx
+// -> 400 Bad Request (invalid format, columns or filters)
+#[rocket::post("/inventory/export?<format>", data = "<req>")]
+pub(super) async fn export(
+    format: Option<&str>,
+    req: Json<ListRequest>,
+    db: DB,
+    _auth: AuthGuard<perm::INVENTORY_READ>,
+) -> Result<Export, ApiError> {
+    let format = ExportFormat::parse(format)?;
+    let req = req.into_inner();
 
-    Ok(Json(data))
+    let (rest, binds) = COLUMNS.export_string(
+        &ListQuery {
+            select: "*",
+            from: "inventory",
+            key: "id",
+            condition: (!req.include_archived).then_some("archived_at IS NULL"),
+        },
+        &req,
+        &mut 2,
+    )?;
+
+    Export::new(format, "inventory", EXPORT_COLUMNS, rest, binds, db).await
 }
 
 #[rocket::get("/inventory/<id>")]
 pub(super) async fn get(
     id: i32,
     mut db: DB,
-    #[allow(unused)] auth: AuthGuard<{ UserPermissionEnum::INVENTORY_READ as u32 }>,
+    #[allow(unused)] auth: AuthGuard<perm::INVENTORY_READ>,
 ) -> Result<Json<InventoryItem>, ApiError> {
     let item = sqlx::query_as(
         r#"
@@ -181,7 +223,7 @@ pub(super) async fn get(
 pub(super) async fn post(
     item: Json<InventoryItemPostRequest>,
     mut db: DB,
-    #[allow(unused)] auth: AuthGuard<{ UserPermissionEnum::INVENTORY_CREATE as u32 }>,
+    #[allow(unused)] auth: AuthGuard<perm::INVENTORY_CREATE>,
 ) -> Result<ApiReturn<i32>, ApiError> {
     let item = item.into_inner();
 
@@ -211,7 +253,7 @@ pub(super) async fn patch(
     item: Json<InventoryItemPatchRequest>,
     id: i32,
     mut db: DB,
-    #[allow(unused)] auth: AuthGuard<{ UserPermissionEnum::INVENTORY_UPDATE as u32 }>,
+    #[allow(unused)] auth: AuthGuard<perm::INVENTORY_UPDATE>,
 ) -> Result<Status, ApiError> {
     let req = item.into_inner();
 
@@ -238,10 +280,10 @@ pub(super) async fn patch(
         req.name.as_ref().map(|v| SqlType::String(v.clone())),
         req.description.as_ref().map(|v| SqlType::String(v.clone())),
         req.price.as_ref().map(|v| SqlType::BigDecimal(v.clone())),
-        req.stock.as_ref().map(|v| SqlType::Int(v.clone())),
+        req.stock.as_ref().map(|v| SqlType::Int(*v)),
         req.quantity_per_box
             .as_ref()
-            .map(|v| SqlType::Int(v.clone())),
+            .map(|v| SqlType::Int(*v)),
     ]
     .into_iter()
     .flatten();
@@ -280,19 +322,17 @@ pub(super) async fn patch(
 pub(super) async fn search(
     req: Json<SearchRequest>,
     mut db: DB,
-    #[allow(unused)] auth: AuthGuard<{ UserPermissionEnum::CUSTOMERS_READ as u32 }>,
+    #[allow(unused)] auth: AuthGuard<perm::CUSTOMERS_READ>,
 ) -> Result<Json<Vec<InventoryItem>>, ApiError> {
     let req = req.into_inner();
 
-    let x = req
-        .column
-        .unwrap_or(req.nested_access.unwrap_or("id".to_string()));
+    let x = COLUMNS.search_column(&req)?;
 
     let query_str = format!(
         r#"
         SELECT *, word_similarity($1, {}::text) AS sml
         FROM inventory
-        WHERE $1 <% {}::text
+        WHERE $1 <% {}::text AND ($3 OR archived_at IS NULL)
         ORDER BY sml DESC, {}::text
         LIMIT $2
         "#,
@@ -304,6 +344,7 @@ pub(super) async fn search(
     let data: Vec<InventoryItem> = query
         .bind(&req.search)
         .bind(req.count)
+        .bind(req.include_archived)
         .fetch_all(&mut **db)
         .await
         .map_err(|e| match e {
@@ -315,3 +356,25 @@ pub(super) async fn search(
 
     Ok(Json(data))
 }
+
+/// POST /inventory/<id>/archive
+/// Response: ApiError or Status
+#[rocket::post("/inventory/<id>/archive")]
+pub(super) async fn archive(
+    id: i32,
+    mut db: DB,
+    #[allow(unused)] auth: AuthGuard<perm::INVENTORY_DELETE>,
+) -> Result<Status, ApiError> {
+    super::set_archived("inventory", id, true, &mut db).await
+}
+
+/// POST /inventory/<id>/restore
+/// Response: ApiError or Status
+#[rocket::post("/inventory/<id>/restore")]
+pub(super) async fn restore(
+    id: i32,
+    mut db: DB,
+    #[allow(unused)] auth: AuthGuard<perm::INVENTORY_DELETE>,
+) -> Result<Status, ApiError> {
+    super::set_archived("inventory", id, false, &mut db).await
+}
@@ -0,0 +1,728 @@
+//! Order invoices as PDF, laid out like the invoice page of the frontend.
+//!
+//! Rendering only reads an `Invoice`, which holds everything loaded from the database, so
+//! the same order and settings always give the same file.
+
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+
+use crate::{
+    db::{FromDB, DB},
+    routes::auth::AuthGuard,
+    settings::{invoice_signature_fields, timezone, BusinessDetails, CurrencyFormat},
+    types::permissions::perm,
+};
+
+use super::{
+    orders::{get_order_items, OrderItem, OrderMeta},
+    pdf::{Document, Font, Image, ImageId, Page, PdfFile, PAGE_HEIGHT, PAGE_WIDTH},
+    ApiError,
+};
+
+const LEFT: f32 = 40.0;
+const RIGHT: f32 = PAGE_WIDTH - 40.0;
+const TOP: f32 = PAGE_HEIGHT - 40.0;
+/// Content stops here, the page number goes below
+const BOTTOM: f32 = 56.0;
+
+const LOGO_SIZE: f32 = 72.0;
+
+// Grays of the frontend's zinc text colors and borders
+const TEXT: f32 = 0.1;
+const LABEL: f32 = 0.16;
+const MUTED: f32 = 0.25;
+const RULE: f32 = 0.73;
+
+/// Everything printed on an invoice
+pub(super) struct Invoice {
+    pub order: OrderMeta,
+    pub items: Vec<OrderItem>,
+    /// Date of the order in the business timezone
+    pub date_time: NaiveDateTime,
+    pub business: BusinessDetails,
+    pub currency: CurrencyFormat,
+    pub signature_fields: bool,
+}
+
+impl Invoice {
+    pub async fn load(id: i32, db: &mut DB) -> Result<Self, ApiError> {
+        let order = OrderMeta::from_db(id, db).await?;
+        let items = get_order_items(id, db).await?;
+
+        let timezone = timezone(db).await?;
+        let date_time: NaiveDateTime = sqlx::query_scalar("SELECT $1::TIMESTAMPTZ AT TIME ZONE $2")
+            .bind(order.date_time)
+            .bind(&timezone)
+            .fetch_one(&mut ***db)
+            .await?;
+
+        Ok(Self {
+            order,
+            items,
+            date_time,
+            business: BusinessDetails::load(db).await?,
+            currency: CurrencyFormat::load(db).await?,
+            signature_fields: invoice_signature_fields(db).await?,
+        })
+    }
+
+    /// Sum of the item totals, rounded to the 4 places get_order_total returns
+    pub fn total(&self) -> BigDecimal {
+        self.items
+            .iter()
+            .map(OrderItem::total)
+            .sum::<BigDecimal>()
+            .round(4)
+    }
+
+    pub fn title(&self) -> String {
+        format!("Invoice #{}", self.order.id)
+    }
+
+    pub fn render(&self) -> Vec<u8> {
+        let mut layout = Layout::new();
+
+        let logo = Image::from_data_uri(&self.business.logo).map(|image| {
+            let size = (image.width as f32, image.height as f32);
+            (layout.document.add_image(image), size)
+        });
+
+        self.render_header(&mut layout, logo);
+        self.render_customer(&mut layout);
+        self.render_items(&mut layout);
+        self.render_footer(&mut layout);
+
+        let title = self.title();
+        let count = layout.document.pages.len();
+
+        for (i, page) in layout.document.pages.iter_mut().enumerate() {
+            page.text_center(
+                PAGE_WIDTH / 2.0,
+                32.0,
+                Font::Regular,
+                8.0,
+                MUTED,
+                &format!("{} \u{B7} Page {} of {}", title, i + 1, count),
+            );
+        }
+
+        layout.document.finish(&title)
+    }
+
+    fn render_header(&self, layout: &mut Layout, logo: Option<(ImageId, (f32, f32))>) {
+        let top = layout.y;
+        let page = layout.page();
+
+        // Logo and invoice number on the left
+        let mut left = top;
+
+        if let Some((id, (width, height))) = logo {
+            // Fit in the square, keeping the aspect ratio
+            let scale = LOGO_SIZE / width.max(height);
+            let (width, height) = (width * scale, height * scale);
+
+            page.image(id, LEFT, top - height, width, height);
+            left -= height + 12.0;
+        }
+
+        left -= 22.0;
+        page.text(LEFT, left, Font::Regular, 22.0, 0.0, "Invoice");
+        page.text(
+            LEFT + Font::Regular.width("Invoice ", 22.0),
+            left,
+            Font::Regular,
+            16.0,
+            LABEL,
+            &format!("#{}", self.order.id),
+        );
+
+        // Business details on the right
+        let mut right = top;
+
+        for line in Font::Regular.wrap(&self.business.name, 18.0, 280.0) {
+            right -= 20.0;
+            page.text_right(RIGHT, right, Font::Regular, 18.0, TEXT, &line);
+        }
+
+        right -= 4.0;
+
+        let details = [&self.business.address]
+            .into_iter()
+            .chain(self.business.phone_numbers.iter())
+            .chain([&self.business.email, &self.business.website])
+            .filter(|line| !line.trim().is_empty());
+
+        for detail in details {
+            for line in Font::Regular.wrap(detail, 9.0, 260.0) {
+                right -= 12.0;
+                page.text_right(RIGHT, right, Font::Regular, 9.0, LABEL, &line);
+            }
+        }
+
+        layout.y = left.min(right) - 14.0;
+        layout.rule();
+    }
+
+    fn render_customer(&self, layout: &mut Layout) {
+        let order = &self.order;
+        let customer = order.customer.as_ref();
+
+        let name = if order.retail {
+            order.retail_customer_name.clone().unwrap_or_default()
+        } else {
+            customer.map(|c| c.name.clone()).unwrap_or_default()
+        };
+
+        let mut details = vec![];
+
+        if order.retail {
+            let address = order.retail_customer_address.clone().unwrap_or_default();
+            if !address.trim().is_empty() {
+                details.push(("Address", address));
+            }
+            details.push(("Phone", order.retail_customer_phone.clone().unwrap_or_default()));
+        } else if let Some(customer) = customer {
+            details.push(("Address", customer.address.clone()));
+            details.push(("Phone", customer.phone.clone()));
+            if !customer.notes.trim().is_empty() {
+                details.push(("Notes", customer.notes.clone()));
+            }
+        }
+
+        let top = layout.y - 4.0;
+        let page = layout.page();
+
+        let mut left = top - 10.0;
+        page.text(LEFT, left, Font::Bold, 9.0, LABEL, "Customer");
+
+        for line in Font::Regular.wrap(&name, 14.0, 340.0) {
+            left -= 17.0;
+            page.text(LEFT, left, Font::Regular, 14.0, MUTED, &line);
+        }
+
+        for (label, value) in details {
+            left -= 16.0;
+            page.text(LEFT, left, Font::Bold, 8.0, LABEL, label);
+
+            for line in Font::Regular.wrap(&value, 9.0, 340.0) {
+                left -= 11.0;
+                page.text(LEFT, left, Font::Regular, 9.0, MUTED, &line);
+            }
+        }
+
+        let mut right = top;
+        let date = self.date_time.format("%Y-%m-%d %H:%M").to_string();
+        let kind = if order.retail { "Retail" } else { "Wholesale" };
+
+        for (label, value) in [("Date", date.as_str()), ("Type", kind)] {
+            right -= 10.0;
+            page.text_right(RIGHT, right, Font::Bold, 8.0, LABEL, label);
+            right -= 11.0;
+            page.text_right(RIGHT, right, Font::Regular, 9.0, MUTED, value);
+            right -= 6.0;
+        }
+
+        layout.y = left.min(right) - 14.0;
+        layout.rule();
+    }
+
+    fn render_items(&self, layout: &mut Layout) {
+        let zero = BigDecimal::from(0);
+        let columns = ItemColumns::new(self.items.iter().any(|item| item.discount > zero));
+
+        columns.header(layout);
+
+        for (i, item) in self.items.iter().enumerate() {
+            let name = Font::Regular.wrap(&item.inventory_item.name, 9.0, columns.item_width());
+            let description = if item.inventory_item.description.trim().is_empty() {
+                vec![]
+            } else {
+                Font::Regular.wrap(&item.inventory_item.description, 7.5, columns.item_width())
+            };
+
+            let height = 10.0 + name.len() as f32 * 12.0 + description.len() as f32 * 9.5;
+
+            if layout.reserve(height) {
+                columns.header(layout);
+            }
+
+            let top = layout.y;
+            let baseline = top - 14.0;
+            let page = layout.page();
+
+            if i > 0 {
+                page.dashed_line((LEFT, top), (RIGHT, top), 0.5, RULE);
+            }
+
+            let mut cells = vec![
+                (i + 1).to_string(),
+                String::new(),
+                item.inventory_item.quantity_per_box.to_string(),
+                item.quantity.to_string(),
+                self.currency.format(&item.price),
+            ];
+            if columns.discount {
+                cells.push(if item.discount <= zero {
+                    "-".to_string()
+                } else if item.discount_percentage {
+                    format!("{}%", trim_decimal(&item.discount))
+                } else {
+                    self.currency.format(&item.discount)
+                });
+            }
+            cells.push(self.currency.format(&item.total()));
+
+            for (column, cell) in columns.columns.iter().zip(&cells) {
+                column.draw(page, baseline, Font::Regular, cell);
+            }
+
+            let mut y = baseline;
+            for (j, line) in name.iter().enumerate() {
+                if j > 0 {
+                    y -= 12.0;
+                }
+                page.text(columns.item_x(), y, Font::Regular, 9.0, TEXT, line);
+            }
+            for line in &description {
+                y -= 9.5;
+                page.text(columns.item_x(), y, Font::Regular, 7.5, LABEL, line);
+            }
+
+            layout.y = top - height;
+        }
+    }
+
+    fn render_footer(&self, layout: &mut Layout) {
+        let notes = self.order.notes.trim();
+        let accounts = &self.business.bank_accounts;
+
+        // Notes and bank accounts share the space left of the total
+        let total_width = 130.0;
+        let space = RIGHT - LEFT - total_width - 16.0;
+        let (notes_width, accounts_width) = match (notes.is_empty(), accounts.is_empty()) {
+            (false, false) => (space * 0.45, space * 0.55 - 16.0),
+            _ => (space, space),
+        };
+
+        let notes = if notes.is_empty() {
+            vec![]
+        } else {
+            Font::Regular.wrap(notes, 8.0, notes_width)
+        };
+        let accounts = accounts
+            .iter()
+            .flat_map(|account| Font::Regular.wrap(account, 8.0, accounts_width))
+            .collect::<Vec<_>>();
+
+        let column_height = |lines: &[String]| 14.0 + lines.len() as f32 * 11.0;
+        let height = 16.0
+            + column_height(&notes)
+                .max(column_height(&accounts))
+                .max(40.0)
+            + if self.signature_fields { 70.0 } else { 0.0 };
+
+        layout.reserve(height);
+        layout.y -= 4.0;
+        layout.rule();
+
+        let top = layout.y - 6.0;
+        let page = layout.page();
+        let mut bottom = top - 40.0;
+        let mut x = LEFT;
+
+        for (label, lines, width) in [
+            ("Notes", &notes, notes_width),
+            ("Bank Accounts", &accounts, accounts_width),
+        ] {
+            if lines.is_empty() {
+                continue;
+            }
+
+            let mut y = top - 10.0;
+            page.text(x, y, Font::Bold, 9.0, LABEL, label);
+            y -= 4.0;
+
+            for line in lines.iter() {
+                y -= 11.0;
+                page.text(x, y, Font::Regular, 8.0, MUTED, line);
+            }
+
+            bottom = bottom.min(y);
+            x += width + 16.0;
+        }
+
+        page.text_right(RIGHT, top - 10.0, Font::Bold, 9.0, LABEL, "Total");
+        page.text_right(
+            RIGHT,
+            top - 32.0,
+            Font::Regular,
+            18.0,
+            LABEL,
+            &self.currency.format(&self.total()),
+        );
+
+        let mut y = bottom - 8.0;
+
+        if self.signature_fields {
+            let line_y = y - 40.0;
+            let third = (RIGHT - LEFT) / 3.0;
+
+            for (i, caption) in ["Checked by", "Received by", "Authorised by"]
+                .into_iter()
+                .enumerate()
+            {
+                let center = LEFT + third * (i as f32 + 0.5);
+
+                page.line((center - 65.0, line_y), (center + 65.0, line_y), 0.75, RULE);
+                page.text_center(center, line_y - 13.0, Font::Regular, 9.0, MUTED, caption);
+            }
+
+            y = line_y - 20.0;
+        }
+
+        layout.y = y;
+    }
+}
+
+/// The page being drawn and how far down it is
+struct Layout {
+    document: Document,
+    y: f32,
+}
+
+impl Layout {
+    fn new() -> Self {
+        let mut document = Document::default();
+        document.pages.push(Page::default());
+
+        Self { document, y: TOP }
+    }
+
+    fn page(&mut self) -> &mut Page {
+        let last = self.document.pages.len() - 1;
+        &mut self.document.pages[last]
+    }
+
+    /// Starts a new page if `height` doesn't fit on this one, returning whether it did
+    fn reserve(&mut self, height: f32) -> bool {
+        if self.y - height >= BOTTOM {
+            return false;
+        }
+
+        self.document.pages.push(Page::default());
+        self.y = TOP;
+        true
+    }
+
+    /// Full width line at the current height
+    fn rule(&mut self) {
+        let y = self.y;
+        self.page().line((LEFT, y), (RIGHT, y), 0.75, RULE);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ItemColumn {
+    title: &'static str,
+    left: f32,
+    right: f32,
+    align_right: bool,
+}
+
+impl ItemColumn {
+    fn draw(&self, page: &mut Page, baseline: f32, font: Font, text: &str) {
+        if self.align_right {
+            page.text_right(self.right - 4.0, baseline, font, 9.0, TEXT, text);
+        } else {
+            page.text(self.left + 4.0, baseline, font, 9.0, TEXT, text);
+        }
+    }
+}
+
+/// No., Item, Qty/Box, Qty, Price, Disc. if any item has a discount, and Amount
+struct ItemColumns {
+    columns: Vec<ItemColumn>,
+    discount: bool,
+}
+
+impl ItemColumns {
+    fn new(discount: bool) -> Self {
+        let numbers = [
+            ("Qty/Box", 52.0),
+            ("Qty", 44.0),
+            ("Price", 76.0),
+            ("Disc.", 60.0),
+            ("Amount", 86.0),
+        ];
+
+        // Laid out from the right, the item name gets what's left
+        let mut right = RIGHT;
+        let mut columns = vec![];
+
+        for (title, width) in numbers.into_iter().rev() {
+            if title == "Disc." && !discount {
+                continue;
+            }
+
+            columns.push(ItemColumn {
+                title,
+                left: right - width,
+                right,
+                align_right: true,
+            });
+            right -= width;
+        }
+
+        columns.push(ItemColumn {
+            title: "Item",
+            left: LEFT + 28.0,
+            right,
+            align_right: false,
+        });
+        columns.push(ItemColumn {
+            title: "No.",
+            left: LEFT,
+            right: LEFT + 28.0,
+            align_right: false,
+        });
+        columns.reverse();
+
+        Self { columns, discount }
+    }
+
+    fn item_x(&self) -> f32 {
+        self.columns[1].left + 4.0
+    }
+
+    fn item_width(&self) -> f32 {
+        self.columns[1].right - self.columns[1].left - 8.0
+    }
+
+    fn header(&self, layout: &mut Layout) {
+        let top = layout.y;
+        let page = layout.page();
+
+        for column in &self.columns {
+            column.draw(page, top - 13.0, Font::Bold, column.title);
+        }
+
+        page.line((LEFT, top - 19.0), (RIGHT, top - 19.0), 0.75, RULE);
+        layout.y = top - 19.0;
+    }
+}
+
+/// `10.5000` as `10.5`
+fn trim_decimal(value: &BigDecimal) -> String {
+    let text = value.to_string();
+
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        text
+    }
+}
+
+// GET /orders/<id>/invoice.pdf [Permissions: ORDER_READ]
+// -> 200 OK
+//     A4 invoice with the logo, business details and bank accounts from the settings, and
+//     signature lines if invoice_signature_fields is on. The total is computed like
+//     get_order_total. The same order and settings always give the same bytes.
+// -> 404 Not Found
+#[rocket::get("/orders/<id>/invoice.pdf")]
+pub(super) async fn invoice_pdf(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ORDER_READ>,
+) -> Result<PdfFile, ApiError> {
+    let invoice = Invoice::load(id, &mut db).await?;
+
+    Ok(PdfFile {
+        filename: format!("invoice-{}.pdf", id),
+        bytes: invoice.render(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    use crate::routes::{auth::User, customers::Customer, inventory::InventoryItem};
+
+    use super::*;
+
+    /// Rendered by `renders_fixture`, set UPDATE_FIXTURES=1 to write it again after a layout
+    /// change and check the new file by eye
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/invoice.pdf");
+
+    /// A 4 by 4 RGBA PNG with some transparent pixels
+    const LOGO: &str = "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAAQAAAAECAYAAACp8Z5+AAAANElEQVR42hWLQQ0AQAzCEDZhPCcKEbja9R4NpAFJ3VHPELo0vQFPN6RkhLsm4y/CJSwg9AdvyyS31nz7IwAAAABJRU5ErkJggg==";
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    fn item(
+        id: i32,
+        name: &str,
+        price: &str,
+        quantity: i32,
+        discount: &str,
+        percentage: bool,
+    ) -> OrderItem {
+        OrderItem {
+            id,
+            inventory_item: InventoryItem {
+                id,
+                name: name.to_string(),
+                description: String::new(),
+                price: decimal(price),
+                stock: 0,
+                quantity_per_box: 12,
+                unit_cost: decimal("0"),
+                archived_at: None,
+            },
+            quantity,
+            price: decimal(price),
+            discount: decimal(discount),
+            discount_percentage: percentage,
+            cost: decimal("0"),
+        }
+    }
+
+    fn invoice(items: Vec<OrderItem>) -> Invoice {
+        let date_time = Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap();
+
+        Invoice {
+            order: OrderMeta {
+                id: 42,
+                date_time,
+                customer: Some(Customer {
+                    id: 7,
+                    name: "Müller & Söhne (Wholesale)".to_string(),
+                    phone: "+49 30 1234567".to_string(),
+                    address: "Friedrichstraße 123\n10117 Berlin".to_string(),
+                    notes: "Deliveries to the back door, ring twice. Closed on Sundays and public \
+                        holidays."
+                        .to_string(),
+                    payment_terms_days: Some(30),
+                    archived_at: None,
+                }),
+                created_by_user: User {
+                    id: 1,
+                    username: "admin".to_string(),
+                    permissions: vec![],
+                    password_change_required: false,
+                    two_factor_enabled: false,
+                },
+                amount_paid: decimal("0"),
+                retail: false,
+                retail_customer_name: None,
+                retail_customer_phone: None,
+                retail_customer_address: None,
+                fulfilled: false,
+                notes: "Prices include 19% VAT \\ returns within 14 days (unopened).".to_string(),
+                total: decimal("0"),
+            },
+            items,
+            date_time: NaiveDate::from_ymd_opt(2024, 3, 1)
+                .unwrap()
+                .and_hms_opt(10, 30, 0)
+                .unwrap(),
+            business: BusinessDetails {
+                name: "Café Ünïcode — 漢字 Trading Company".to_string(),
+                address: "1 Market Street, Springfield".to_string(),
+                email: "sales@example.com".to_string(),
+                website: "https://example.com".to_string(),
+                phone_numbers: vec!["+1 555 0100".to_string(), "+1 555 0101".to_string()],
+                bank_accounts: vec![
+                    "Example Bank, IBAN DE89 3704 0044 0532 0130 00".to_string(),
+                    "Other Bank 123-456-789".to_string(),
+                ],
+                logo: LOGO.to_string(),
+            },
+            currency: CurrencyFormat {
+                prefix: "€".to_string(),
+                suffix: String::new(),
+                decimal_places: 2,
+                decimal_separator: ",".to_string(),
+                thousand_separator: ".".to_string(),
+            },
+            signature_fields: true,
+        }
+    }
+
+    /// The expected totals are what `SELECT get_order_total(id)` returns for orders with the
+    /// same items, which sums the exact line totals and rounds half away from zero when the
+    /// sum is stored as NUMERIC(32, 4)
+    #[test]
+    fn total_matches_get_order_total() {
+        let cases = [
+            // 3.7035 * 0.9 = 3.33315
+            (vec![item(1, "a", "1.2345", 3, "10", true)], "3.3332"),
+            // 0.00025 rounds up, not to the even 0.0002
+            (vec![item(1, "a", "0.0005", 1, "50", true)], "0.0003"),
+            // Lines aren't rounded before they are summed
+            (
+                vec![
+                    item(1, "a", "0.0005", 1, "50", true),
+                    item(2, "b", "0.0005", 1, "50", true),
+                ],
+                "0.0005",
+            ),
+            // 59.97 - 5.5 plus 70.0007 * 0.666667
+            (
+                vec![
+                    item(1, "a", "19.99", 3, "5.5", false),
+                    item(2, "b", "10.0001", 7, "33.3333", true),
+                ],
+                "101.1372",
+            ),
+            // An absolute discount over the line's price
+            (vec![item(1, "a", "2.50", 2, "7.25", false)], "-2.25"),
+            (vec![], "0"),
+        ];
+
+        for (items, expected) in cases {
+            assert_eq!(
+                invoice(items).total(),
+                decimal(expected),
+                "expected {}",
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn renders_fixture() {
+        let long_name =
+            "Gadget with a rather long name that wraps onto more lines in the item column";
+        let long_word = "Supercalifragilisticexpialidociousandthensomemoretomakeitlonger";
+
+        let mut items = vec![
+            item(1, "Widget", "19.99", 3, "5.5", false),
+            item(2, long_name, "10.0001", 7, "33.3333", true),
+            item(3, long_word, "0.5", 1000, "0", false),
+        ];
+        items[0].inventory_item.description = "Blue, 10 cm (boxed)".to_string();
+
+        // Enough lines for a second page
+        for id in 4..=40 {
+            items.push(item(id, &format!("Item {}", id), "1.25", id, "0", false));
+        }
+
+        let bytes = invoice(items).render();
+
+        if std::env::var_os("UPDATE_FIXTURES").is_some() {
+            std::fs::write(FIXTURE, &bytes).unwrap();
+        }
+
+        let fixture = std::fs::read(FIXTURE).unwrap();
+        assert!(
+            bytes == fixture,
+            "the rendered invoice differs from {}, run with UPDATE_FIXTURES=1 if that's intended",
+            FIXTURE
+        );
+    }
+}
@@ -0,0 +1,573 @@
+use bigdecimal::BigDecimal;
+use chrono::NaiveDate;
+use rocket::{http::Status, response::content::RawHtml, serde::json::Json};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{
+    db::DB,
+    settings::{payment_terms_days, BusinessDetails, CurrencyFormat},
+    types::permissions::perm,
+};
+
+use super::{auth::AuthGuard, customers::Customer, suppliers::Supplier, ApiError, DateRange};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ts_rs::TS)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[ts(export)]
+pub(super) enum LedgerEntryKind {
+    Order,
+    Purchase,
+    /// Payment from a customer or to a supplier
+    Payment,
+    /// Payment the other way around
+    Refund,
+}
+
+impl LedgerEntryKind {
+    fn from_sql(kind: &str) -> Result<Self, ApiError> {
+        match kind {
+            "ORDER" => Ok(Self::Order),
+            "PURCHASE" => Ok(Self::Purchase),
+            "PAYMENT" => Ok(Self::Payment),
+            "REFUND" => Ok(Self::Refund),
+            _ => Err(ApiError(
+                Status::InternalServerError,
+                format!("Unknown ledger entry kind {}", kind),
+            )),
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Order => "Order",
+            Self::Purchase => "Purchase",
+            Self::Payment => "Payment",
+            Self::Refund => "Refund",
+        }
+    }
+}
+
+#[derive(FromRow, Debug)]
+struct LedgerEntryRow {
+    date_time: chrono::DateTime<chrono::Utc>,
+    kind: String,
+    reference_id: i32,
+    description: String,
+    debit: BigDecimal,
+    credit: BigDecimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct LedgerEntry {
+    pub date_time: chrono::DateTime<chrono::Utc>,
+    pub kind: LedgerEntryKind,
+    /// Id of the order, purchase or payment
+    pub reference_id: i32,
+    pub description: String,
+    pub debit: BigDecimal,
+    pub credit: BigDecimal,
+    /// Running balance after this entry
+    pub balance: BigDecimal,
+}
+
+/// Account of a party over a date range, oldest entry first.
+/// A positive balance is outstanding: owed by a customer, or owed to a supplier.
+#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct Ledger<P> {
+    pub party: P,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Balance of everything before `from`
+    pub opening_balance: BigDecimal,
+    pub total_debit: BigDecimal,
+    pub total_credit: BigDecimal,
+    pub closing_balance: BigDecimal,
+    /// Unpaid part of the orders or purchases that are past their payment terms today
+    pub overdue: BigDecimal,
+    pub entries: Vec<LedgerEntry>,
+}
+
+/// Queries for the ledger of one kind of party, `$1` being its id
+struct LedgerSource {
+    /// Rows of date_time, kind, reference_id, description, debit and credit
+    entries: &'static str,
+    /// Unpaid amount of the orders or purchases past the party's payment terms,
+    /// `$2` being the payment_terms_days setting
+    overdue: &'static str,
+    /// Whether credits increase the balance, as for what is owed to suppliers
+    credit_balance: bool,
+}
+
+/// Orders are debits, payments from the customer credits and refunds debits again
+const CUSTOMER_LEDGER: LedgerSource = LedgerSource {
+    entries: r#"
+        SELECT
+            date_time,
+            'ORDER' AS kind,
+            id AS reference_id,
+            COALESCE(notes, '') AS description,
+            total AS debit,
+            0::NUMERIC AS credit
+        FROM orders
+        WHERE customer_id = $1
+        UNION ALL
+        SELECT
+            date_time,
+            CASE transfer_type WHEN 'INCOMING' THEN 'PAYMENT' ELSE 'REFUND' END,
+            id,
+            concat_ws(' - ', method::text, NULLIF(method_details, ''), NULLIF(notes, '')),
+            CASE transfer_type WHEN 'OUTGOING' THEN amount ELSE 0 END,
+            CASE transfer_type WHEN 'INCOMING' THEN amount ELSE 0 END
+        FROM payments
+        WHERE party_type = 'CUSTOMER' AND party_id = $1
+    "#,
+    overdue: r#"
+        SELECT COALESCE(SUM(GREATEST(total - amount_paid, 0)), 0)
+        FROM orders
+            INNER JOIN customers ON orders.customer_id = customers.id
+        WHERE customer_id = $1
+            AND date_time + make_interval(days => COALESCE(customers.payment_terms_days, $2))
+                < CURRENT_TIMESTAMP
+    "#,
+    credit_balance: false,
+};
+
+/// Purchases are credits, payments to the supplier debits and refunds credits again
+const SUPPLIER_LEDGER: LedgerSource = LedgerSource {
+    entries: r#"
+        SELECT
+            date_time,
+            'PURCHASE' AS kind,
+            id AS reference_id,
+            COALESCE(notes, '') AS description,
+            0::NUMERIC AS debit,
+            (
+                SELECT COALESCE(SUM(price * quantity), 0) FROM purchase_items
+                WHERE purchase_id = purchases.id
+            ) AS credit
+        FROM purchases
+        WHERE supplier_id = $1
+        UNION ALL
+        SELECT
+            date_time,
+            CASE transfer_type WHEN 'OUTGOING' THEN 'PAYMENT' ELSE 'REFUND' END,
+            id,
+            concat_ws(' - ', method::text, NULLIF(method_details, ''), NULLIF(notes, '')),
+            CASE transfer_type WHEN 'OUTGOING' THEN amount ELSE 0 END,
+            CASE transfer_type WHEN 'INCOMING' THEN amount ELSE 0 END
+        FROM payments
+        WHERE party_type = 'SUPPLIER' AND party_id = $1
+    "#,
+    overdue: r#"
+        SELECT COALESCE(SUM(GREATEST(
+            (
+                SELECT COALESCE(SUM(price * quantity), 0) FROM purchase_items
+                WHERE purchase_id = purchases.id
+            ) - amount_paid,
+            0
+        )), 0)
+        FROM purchases
+            INNER JOIN suppliers ON purchases.supplier_id = suppliers.id
+        WHERE supplier_id = $1
+            AND date_time + make_interval(days => COALESCE(suppliers.payment_terms_days, $2))
+                < CURRENT_TIMESTAMP
+    "#,
+    credit_balance: true,
+};
+
+async fn ledger<P>(
+    party: P,
+    party_id: i32,
+    source: &LedgerSource,
+    range: DateRange,
+    db: &mut DB,
+) -> Result<Ledger<P>, ApiError> {
+    let terms = payment_terms_days(db).await? as i32;
+    let (start, end) = range.bounds(db).await?;
+
+    let balance_sql = if source.credit_balance {
+        "credit - debit"
+    } else {
+        "debit - credit"
+    };
+
+    let opening_balance: BigDecimal = sqlx::query_scalar(&format!(
+        r#"
+        SELECT COALESCE(SUM({}), 0)
+        FROM ({}) entries
+        WHERE date_time < $2
+        "#,
+        balance_sql, source.entries
+    ))
+    .bind(party_id)
+    .bind(start)
+    .fetch_one(&mut ***db)
+    .await?;
+
+    let rows: Vec<LedgerEntryRow> = sqlx::query_as(&format!(
+        r#"
+        SELECT * FROM ({}) entries
+        WHERE ($2::TIMESTAMPTZ IS NULL OR date_time >= $2)
+            AND ($3::TIMESTAMPTZ IS NULL OR date_time < $3)
+        ORDER BY date_time, kind, reference_id
+        "#,
+        source.entries
+    ))
+    .bind(party_id)
+    .bind(start)
+    .bind(end)
+    .fetch_all(&mut ***db)
+    .await?;
+
+    let overdue = sqlx::query_scalar(source.overdue)
+        .bind(party_id)
+        .bind(terms)
+        .fetch_one(&mut ***db)
+        .await?;
+
+    let mut balance = opening_balance.clone();
+    let mut total_debit = BigDecimal::from(0);
+    let mut total_credit = BigDecimal::from(0);
+    let mut entries = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        balance = if source.credit_balance {
+            balance + &row.credit - &row.debit
+        } else {
+            balance + &row.debit - &row.credit
+        };
+        total_debit += &row.debit;
+        total_credit += &row.credit;
+
+        entries.push(LedgerEntry {
+            date_time: row.date_time,
+            kind: LedgerEntryKind::from_sql(&row.kind)?,
+            reference_id: row.reference_id,
+            description: row.description,
+            debit: row.debit,
+            credit: row.credit,
+            balance: balance.clone(),
+        });
+    }
+
+    Ok(Ledger {
+        party,
+        from: range.from,
+        to: range.to,
+        opening_balance,
+        total_debit,
+        total_credit,
+        closing_balance: balance,
+        overdue,
+        entries,
+    })
+}
+
+async fn customer_ledger_for(
+    id: i32,
+    range: DateRange,
+    db: &mut DB,
+) -> Result<Ledger<Customer>, ApiError> {
+    let customer: Option<Customer> = sqlx::query_as("SELECT * FROM customers WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&mut ***db)
+        .await?;
+
+    let Some(customer) = customer else {
+        return Err(ApiError(
+            Status::NotFound,
+            format!("No customer with id {}", id),
+        ));
+    };
+
+    ledger(customer, id, &CUSTOMER_LEDGER, range, db).await
+}
+
+async fn supplier_ledger_for(
+    id: i32,
+    range: DateRange,
+    db: &mut DB,
+) -> Result<Ledger<Supplier>, ApiError> {
+    let supplier: Option<Supplier> = sqlx::query_as("SELECT * FROM suppliers WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&mut ***db)
+        .await?;
+
+    let Some(supplier) = supplier else {
+        return Err(ApiError(
+            Status::NotFound,
+            format!("No supplier with id {}", id),
+        ));
+    };
+
+    ledger(supplier, id, &SUPPLIER_LEDGER, range, db).await
+}
+
+// GET /customers/<id>/ledger?from=2024-01-01&to=2024-03-31 [Permissions: PAYMENT_READ]
+// from and to are days in the timezone setting, optional and both included
+// -> 200 OK
+// {
+//     "party": { "id": 3, "name": "John", ... },
+//     "from": "2024-01-01",
+//     "to": "2024-03-31",
+//     "opening_balance": "40.00",
+//     "total_debit": "120.00",
+//     "total_credit": "150.00",
+//     "closing_balance": "10.00",
+//     "overdue": "0.00",
+//     "entries": [
+//         {
+//             "date_time": "2024-01-05T10:00:00Z",
+//             "kind": "ORDER",
+//             "reference_id": 12,
+//             "description": "",
+//             "debit": "120.00",
+//             "credit": "0",
+//             "balance": "160.00"
+//         },
+//         ...
+//     ]
+// }
+// -> 400 Bad Request (invalid dates)
+// -> 404 Not Found
+#[rocket::get("/customers/<id>/ledger?<from>&<to>")]
+pub(super) async fn customer_ledger(
+    id: i32,
+    from: Option<&str>,
+    to: Option<&str>,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_READ>,
+) -> Result<Json<Ledger<Customer>>, ApiError> {
+    let range = DateRange::parse(from, to)?;
+
+    Ok(Json(customer_ledger_for(id, range, &mut db).await?))
+}
+
+// GET /customers/<id>/statement?from=2024-01-01&to=2024-03-31 [Permissions: PAYMENT_READ]
+// -> 200 OK
+//     Printable HTML page of the ledger with the business details from the settings
+// -> 400 Bad Request
+// -> 404 Not Found
+#[rocket::get("/customers/<id>/statement?<from>&<to>")]
+pub(super) async fn customer_statement(
+    id: i32,
+    from: Option<&str>,
+    to: Option<&str>,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_READ>,
+) -> Result<RawHtml<String>, ApiError> {
+    let range = DateRange::parse(from, to)?;
+
+    let ledger = customer_ledger_for(id, range, &mut db).await?;
+    let business = BusinessDetails::load(&mut db).await?;
+    let currency = CurrencyFormat::load(&mut db).await?;
+
+    let party = StatementParty {
+        title: "Statement of account",
+        name: &ledger.party.name,
+        phone: &ledger.party.phone,
+        address: &ledger.party.address,
+    };
+
+    Ok(RawHtml(render_statement(
+        &party, &ledger, &business, &currency,
+    )))
+}
+
+// GET /suppliers/<id>/ledger?from=2024-01-01&to=2024-03-31 [Permissions: PAYMENT_READ]
+// Same as the customer ledger, with purchases as credits and payments to the supplier as
+// debits. The balance is what is owed to the supplier.
+// -> 200 OK
+// -> 400 Bad Request (invalid dates)
+// -> 404 Not Found
+#[rocket::get("/suppliers/<id>/ledger?<from>&<to>")]
+pub(super) async fn supplier_ledger(
+    id: i32,
+    from: Option<&str>,
+    to: Option<&str>,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_READ>,
+) -> Result<Json<Ledger<Supplier>>, ApiError> {
+    let range = DateRange::parse(from, to)?;
+
+    Ok(Json(supplier_ledger_for(id, range, &mut db).await?))
+}
+
+// GET /suppliers/<id>/statement?from=2024-01-01&to=2024-03-31 [Permissions: PAYMENT_READ]
+// -> 200 OK
+//     Printable HTML accounts payable statement
+// -> 400 Bad Request
+// -> 404 Not Found
+#[rocket::get("/suppliers/<id>/statement?<from>&<to>")]
+pub(super) async fn supplier_statement(
+    id: i32,
+    from: Option<&str>,
+    to: Option<&str>,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_READ>,
+) -> Result<RawHtml<String>, ApiError> {
+    let range = DateRange::parse(from, to)?;
+
+    let ledger = supplier_ledger_for(id, range, &mut db).await?;
+    let business = BusinessDetails::load(&mut db).await?;
+    let currency = CurrencyFormat::load(&mut db).await?;
+
+    let party = StatementParty {
+        title: "Accounts payable statement",
+        name: &ledger.party.name,
+        phone: &ledger.party.phone,
+        address: &ledger.party.address,
+    };
+
+    Ok(RawHtml(render_statement(
+        &party, &ledger, &business, &currency,
+    )))
+}
+
+struct StatementParty<'a> {
+    title: &'a str,
+    name: &'a str,
+    phone: &'a str,
+    address: &'a str,
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn render_statement<P>(
+    party: &StatementParty,
+    ledger: &Ledger<P>,
+    business: &BusinessDetails,
+    currency: &CurrencyFormat,
+) -> String {
+    let period = match (ledger.from, ledger.to) {
+        (Some(from), Some(to)) => format!("{} to {}", from, to),
+        (Some(from), None) => format!("Since {}", from),
+        (None, Some(to)) => format!("Until {}", to),
+        (None, None) => "All time".to_string(),
+    };
+
+    let business_lines = [&business.address, &business.email, &business.website]
+        .into_iter()
+        .chain(business.phone_numbers.iter())
+        .filter(|line| !line.is_empty())
+        .map(|line| format!("<div>{}</div>", escape_html(line)))
+        .collect::<String>();
+
+    let party_lines = [party.phone, party.address]
+        .into_iter()
+        .filter(|line| !line.is_empty())
+        .map(|line| format!("<div>{}</div>", escape_html(line)))
+        .collect::<String>();
+
+    let mut rows = format!(
+        r#"<tr><td></td><td>Opening balance</td><td></td><td></td><td></td><td class="amount">{}</td></tr>"#,
+        currency.format(&ledger.opening_balance)
+    );
+
+    let zero = BigDecimal::from(0);
+    let amount_cell = |amount: &BigDecimal| {
+        if *amount == zero {
+            String::new()
+        } else {
+            currency.format(amount)
+        }
+    };
+
+    for entry in &ledger.entries {
+        rows.push_str(&format!(
+            r#"<tr><td>{}</td><td>{} #{}</td><td>{}</td><td class="amount">{}</td><td class="amount">{}</td><td class="amount">{}</td></tr>"#,
+            entry.date_time.format("%Y-%m-%d"),
+            entry.kind.label(),
+            entry.reference_id,
+            escape_html(&entry.description),
+            amount_cell(&entry.debit),
+            amount_cell(&entry.credit),
+            currency.format(&entry.balance),
+        ));
+    }
+
+    let bank_accounts = if business.bank_accounts.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "<div class=\"footer\"><strong>Bank accounts</strong>{}</div>",
+            business
+                .bank_accounts
+                .iter()
+                .map(|account| format!("<div>{}</div>", escape_html(account)))
+                .collect::<String>()
+        )
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>Statement - {party_name}</title>
+<style>
+body {{ font-family: sans-serif; font-size: 13px; margin: 2em; color: #000; }}
+.header {{ display: flex; justify-content: space-between; align-items: flex-start; }}
+.header img {{ max-width: 96px; max-height: 96px; }}
+h1 {{ font-size: 20px; margin: 0 0 0.3em 0; }}
+h2 {{ font-size: 16px; margin: 1.5em 0 0.5em 0; }}
+table {{ width: 100%; border-collapse: collapse; margin-top: 1em; }}
+th, td {{ border-bottom: 1px solid #ccc; padding: 4px 6px; text-align: left; }}
+.amount {{ text-align: right; white-space: nowrap; }}
+tfoot td {{ font-weight: bold; border-top: 2px solid #000; }}
+.footer {{ margin-top: 2em; }}
+@media print {{ body {{ margin: 0; }} }}
+</style>
+</head>
+<body>
+<div class="header">
+<div><h1>{business_name}</h1>{business_lines}</div>
+<img src="{logo}" alt="">
+</div>
+<h2>{title}</h2>
+<div><strong>{party_name}</strong></div>
+{party_lines}
+<div>{period}</div>
+<table>
+<thead><tr><th>Date</th><th>Reference</th><th>Description</th><th class="amount">Debit</th><th class="amount">Credit</th><th class="amount">Balance</th></tr></thead>
+<tbody>{rows}</tbody>
+<tfoot><tr><td></td><td>Closing balance</td><td></td><td class="amount">{total_debit}</td><td class="amount">{total_credit}</td><td class="amount">{closing_balance}</td></tr></tfoot>
+</table>
+<div class="footer">Overdue today: <strong>{overdue}</strong></div>
+{bank_accounts}
+</body>
+</html>
+"#,
+        title = escape_html(party.title),
+        party_name = escape_html(party.name),
+        business_name = escape_html(&business.name),
+        business_lines = business_lines,
+        logo = escape_html(&business.logo),
+        party_lines = party_lines,
+        period = period,
+        rows = rows,
+        total_debit = currency.format(&ledger.total_debit),
+        total_credit = currency.format(&ledger.total_credit),
+        closing_balance = currency.format(&ledger.closing_balance),
+        overdue = currency.format(&ledger.overdue),
+        bank_accounts = bank_accounts,
+    )
+}
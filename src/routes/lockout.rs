@@ -0,0 +1,344 @@
+use rocket::{http::Status, serde::json::Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::{db::DB, types::permissions::perm};
+
+use super::{
+    auth::AuthGuard,
+    query::{Column, Columns, ListQuery},
+    sessions::ClientInfo,
+    ApiError, ListRequest, ListResponse, ListSort, SortOrder,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ts_rs::TS, sqlx::Type)]
+#[sqlx(rename_all = "SCREAMING_SNAKE_CASE")]
+#[sqlx(type_name = "login_failure_kind_t")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[ts(export)]
+pub enum LoginFailureKind {
+    Username,
+    IpAddress,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ts_rs::TS, sqlx::Type)]
+#[sqlx(rename_all = "SCREAMING_SNAKE_CASE")]
+#[sqlx(type_name = "auth_event_t")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[ts(export)]
+pub enum AuthEventKind {
+    LoginSuccess,
+    LoginFailure,
+    /// Rejected without checking the password because of too many failures
+    LoginLocked,
+}
+
+/// Failed logins are counted separately per username and per IP address.
+/// After `free_attempts` failures every further one doubles the wait before the next try,
+/// up to `max_delay_secs`, which acts as a temporary lockout.
+struct LockoutPolicy {
+    free_attempts: i32,
+    max_delay_secs: i64,
+}
+
+impl LockoutPolicy {
+    fn for_kind(kind: LoginFailureKind) -> Self {
+        match kind {
+            LoginFailureKind::Username => LockoutPolicy {
+                free_attempts: 5,
+                max_delay_secs: 15 * 60,
+            },
+            // Offices share one public IP, so be more lenient
+            LoginFailureKind::IpAddress => LockoutPolicy {
+                free_attempts: 20,
+                max_delay_secs: 15 * 60,
+            },
+        }
+    }
+
+    /// Seconds until the next attempt is allowed after `failures` failed attempts
+    fn delay_secs(&self, failures: i32) -> i64 {
+        if failures < self.free_attempts {
+            return 0;
+        }
+
+        let exponent = (failures - self.free_attempts).min(32) as u32;
+
+        2_i64.saturating_pow(exponent).min(self.max_delay_secs)
+    }
+}
+
+/// Failure counters restart after this many seconds without a failure
+const FAILURE_WINDOW_SECS: i64 = 60 * 60;
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct LoginLockout {
+    pub kind: LoginFailureKind,
+    /// The username or IP address
+    pub key: String,
+    pub failures: i32,
+    pub last_failure_at: sqlx::types::chrono::DateTime<chrono::Utc>,
+    pub locked_until: sqlx::types::chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct AuthEvent {
+    pub id: i32,
+    pub date_time: sqlx::types::chrono::DateTime<chrono::Utc>,
+    pub event: AuthEventKind,
+    /// The username that was sent, which might not exist
+    pub username: String,
+    pub user_id: Option<i32>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Columns of auth_events that can be used in list requests
+const COLUMNS: Columns = Columns(&[
+    Column::new("id"),
+    Column::new("date_time"),
+    // Compared as text so filters can send the name of the event
+    Column::aliased("event", "event::text"),
+    Column::new("username"),
+    Column::new("user_id"),
+    Column::new("ip_address"),
+    Column::new("user_agent"),
+]);
+
+/// Returns the number of seconds until the username or IP address may try again
+pub(super) async fn locked_for(
+    username: &str,
+    client: &ClientInfo,
+    conn: &mut PgConnection,
+) -> Result<Option<i64>, ApiError> {
+    let seconds: Option<f64> = sqlx::query_scalar(
+        r#"
+        SELECT CEIL(EXTRACT(EPOCH FROM MAX(locked_until) - CURRENT_TIMESTAMP))::FLOAT8
+        FROM login_failures
+        WHERE ((kind = 'USERNAME' AND key = $1) OR (kind = 'IP_ADDRESS' AND key = $2))
+            AND locked_until > CURRENT_TIMESTAMP
+        "#,
+    )
+    .bind(username)
+    .bind(&client.ip_address)
+    .fetch_one(conn)
+    .await?;
+
+    Ok(seconds.map(|seconds| seconds as i64))
+}
+
+async fn increment_failures(
+    kind: LoginFailureKind,
+    key: &str,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    let failures: i32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO login_failures (kind, key)
+        VALUES ($1, $2)
+        ON CONFLICT (kind, key) DO UPDATE
+        SET failures = CASE
+                WHEN login_failures.last_failure_at < CURRENT_TIMESTAMP - make_interval(secs => $3) THEN 1
+                ELSE login_failures.failures + 1
+            END,
+            last_failure_at = CURRENT_TIMESTAMP
+        RETURNING failures
+        "#,
+    )
+    .bind(kind)
+    .bind(key)
+    .bind(FAILURE_WINDOW_SECS as f64)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let delay = LockoutPolicy::for_kind(kind).delay_secs(failures);
+
+    if delay > 0 {
+        log::warn!(
+            "{:?} {} failed to log in {} times, locked for {} seconds",
+            kind,
+            key,
+            failures,
+            delay
+        );
+
+        sqlx::query(
+            r#"
+            UPDATE login_failures
+            SET locked_until = CURRENT_TIMESTAMP + make_interval(secs => $3)
+            WHERE kind = $1 AND key = $2
+            "#,
+        )
+        .bind(kind)
+        .bind(key)
+        .bind(delay as f64)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub(super) async fn log_auth_event(
+    event: AuthEventKind,
+    username: &str,
+    user_id: Option<i32>,
+    client: &ClientInfo,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    sqlx::query(
+        r#"
+        INSERT INTO auth_events (event, username, user_id, ip_address, user_agent)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(event)
+    .bind(username)
+    .bind(user_id)
+    .bind(&client.ip_address)
+    .bind(&client.user_agent)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Count a failed login against the username and IP address and log it.
+/// Unknown usernames are counted too, so lockouts don't reveal which users exist.
+pub(super) async fn record_failure(
+    username: &str,
+    user_id: Option<i32>,
+    client: &ClientInfo,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    increment_failures(LoginFailureKind::Username, username, conn).await?;
+
+    if let Some(ip_address) = &client.ip_address {
+        increment_failures(LoginFailureKind::IpAddress, ip_address, conn).await?;
+    }
+
+    log_auth_event(AuthEventKind::LoginFailure, username, user_id, client, conn).await
+}
+
+/// Reset the username counter and log the login.
+/// The IP address counter is kept, otherwise an attacker could reset it with their own account.
+pub(super) async fn record_success(
+    username: &str,
+    user_id: i32,
+    client: &ClientInfo,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    sqlx::query("DELETE FROM login_failures WHERE kind = 'USERNAME' AND key = $1")
+        .bind(username)
+        .execute(&mut *conn)
+        .await?;
+
+    log_auth_event(
+        AuthEventKind::LoginSuccess,
+        username,
+        Some(user_id),
+        client,
+        conn,
+    )
+    .await
+}
+
+// GET /auth/lockouts [Permissions: ADMIN]
+// -> 200 OK
+//     [
+//         {
+//             "kind": "USERNAME",
+//             "key": "admin",
+//             "failures": 12,
+//             "last_failure_at": "...",
+//             "locked_until": "..."
+//         }
+//     ]
+#[rocket::get("/auth/lockouts")]
+pub(super) async fn list(
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Json<Vec<LoginLockout>>, ApiError> {
+    let lockouts = sqlx::query_as(
+        r#"
+        SELECT kind, key, failures, last_failure_at, locked_until
+        FROM login_failures
+        WHERE locked_until > CURRENT_TIMESTAMP
+        ORDER BY locked_until DESC
+        "#,
+    )
+    .fetch_all(&mut **db)
+    .await?;
+
+    Ok(Json(lockouts))
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct ClearLockoutRequest {
+    kind: LoginFailureKind,
+    key: String,
+}
+
+// DELETE /auth/lockouts [Permissions: ADMIN]
+// {
+//     "kind": "IP_ADDRESS",
+//     "key": "127.0.0.1"
+// }
+// -> 200 OK
+// -> 404 Not Found
+// Also resets the failure counter.
+#[rocket::delete("/auth/lockouts", data = "<req>")]
+pub(super) async fn clear(
+    req: Json<ClearLockoutRequest>,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Status, ApiError> {
+    let res = sqlx::query("DELETE FROM login_failures WHERE kind = $1 AND key = $2")
+        .bind(req.kind)
+        .bind(&req.key)
+        .execute(&mut **db)
+        .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ApiError(Status::NotFound, "Lockout not found".to_string()));
+    }
+
+    Ok(Status::Ok)
+}
+
+// POST /auth/events/list [Permissions: ADMIN]
+// Request: ListRequest, newest first if it has no sorts
+// Response: ListResponse<AuthEvent>
+#[rocket::post("/auth/events/list", data = "<req>")]
+pub(super) async fn list_events(
+    req: Json<ListRequest>,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Json<ListResponse<AuthEvent>>, ApiError> {
+    let mut req = req.into_inner();
+
+    if req.sorts.is_empty() {
+        req.sorts.push(ListSort {
+            column: "date_time".to_string(),
+            order: SortOrder::Desc,
+        });
+    }
+
+    let page = COLUMNS
+        .page::<AuthEvent, AuthEvent>(
+            ListQuery {
+                select: "*",
+                from: "auth_events",
+                key: "id",
+                condition: None,
+            },
+            &req,
+            &mut db,
+        )
+        .await?;
+
+    Ok(Json(page))
+}
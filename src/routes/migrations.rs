@@ -0,0 +1,37 @@
+use rocket::serde::json::Json;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::DatabaseConnection,
+    migrations::{self, AppliedMigration},
+    types::permissions::perm,
+};
+
+use super::{auth::AuthGuard, ApiError};
+
+#[derive(Serialize, Deserialize, Debug, Clone, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct MigrationStatus {
+    /// Latest migration applied to the database
+    database_version: i32,
+    /// Latest migration known by the running server
+    server_version: i32,
+    applied: Vec<AppliedMigration>,
+}
+
+// GET /db/migrations [Permissions: ADMIN]
+// -> 200 OK
+// -> 500 Internal Server Error
+#[rocket::get("/db/migrations")]
+pub(super) async fn status(
+    pool: &rocket::State<DatabaseConnection>,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Json<MigrationStatus>, ApiError> {
+    let applied = migrations::applied_migrations(pool).await?;
+
+    Ok(Json(MigrationStatus {
+        database_version: applied.last().map(|m| m.version).unwrap_or(0),
+        server_version: migrations::latest_version(),
+        applied,
+    }))
+}
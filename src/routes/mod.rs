@@ -1,20 +1,31 @@
+pub mod api_tokens;
 pub mod auth;
 pub mod backup;
 pub mod customers;
 pub mod expenses;
+pub mod export;
 pub mod inventory;
+pub mod invoice;
+pub mod ledger;
+pub mod lockout;
+pub mod migrations;
 pub mod orders;
 pub mod purchases;
+pub mod query;
 pub mod reports;
+pub mod roles;
 pub mod search;
+pub mod sessions;
 pub mod settings;
 pub mod suppliers;
+pub mod two_factor;
 pub mod payments;
+pub mod pdf;
 
 pub mod public;
 
 use bigdecimal::BigDecimal;
-use chrono::Utc;
+use chrono::{DateTime, NaiveDate, Utc};
 use inventory::InventoryItem;
 use rocket::{
     http::{ContentType, Status},
@@ -39,16 +50,50 @@ pub fn routes() -> Vec<rocket::Route> {
         auth::delete_user,
         auth::list_users,
         auth::permissions,
+        auth::change_password,
+        auth::reset_password,
+        auth::patch_user,
+        auth::archive_user,
+        auth::restore_user,
+        roles::list,
+        roles::post,
+        roles::patch,
+        roles::delete,
+        api_tokens::list,
+        api_tokens::post,
+        api_tokens::revoke,
+        api_tokens::list_for,
+        api_tokens::revoke_all_for,
+        sessions::list,
+        sessions::revoke,
+        sessions::logout_everywhere,
+        sessions::force_logout,
+        lockout::list,
+        lockout::clear,
+        lockout::list_events,
+        two_factor::login,
+        two_factor::status,
+        two_factor::setup,
+        two_factor::enable,
+        two_factor::recovery_codes,
+        two_factor::disable,
+        two_factor::reset,
+        two_factor::get_required_permissions,
+        two_factor::set_required_permissions,
         inventory::count,
         inventory::list,
+        inventory::export,
         inventory::get,
         inventory::patch,
         inventory::post,
         inventory::search,
+        inventory::archive,
+        inventory::restore,
         orders::get,
         orders::get_items,
         orders::count,
         orders::list,
+        orders::export,
         orders::post,
         orders::update_items,
         orders::preview_update_items,
@@ -56,10 +101,12 @@ pub fn routes() -> Vec<rocket::Route> {
         orders::delete,
         orders::total,
         orders::search,
+        invoice::invoice_pdf,
         purchases::get,
         purchases::get_items,
         purchases::count,
         purchases::list,
+        purchases::export,
         purchases::post,
         purchases::update_items,
         purchases::preview_update_items,
@@ -70,25 +117,35 @@ pub fn routes() -> Vec<rocket::Route> {
         customers::get,
         customers::count,
         customers::list,
+        customers::export,
         customers::post,
         customers::patch,
         customers::search,
+        customers::archive,
+        customers::restore,
         suppliers::get,
         suppliers::count,
         suppliers::list,
+        suppliers::export,
         suppliers::post,
         suppliers::patch,
         suppliers::search,
+        suppliers::archive,
+        suppliers::restore,
         reports::create_report,
         reports::create_expense_report,
+        reports::export_expense_report,
         reports::create_order_report,
+        reports::export_order_report,
         expenses::get,
         expenses::count,
         expenses::list,
+        expenses::export,
         expenses::post,
         expenses::patch,
         expenses::delete,
         backup::backup,
+        migrations::status,
         settings::get_all,
         settings::get,
         settings::get_multiple,
@@ -96,6 +153,24 @@ pub fn routes() -> Vec<rocket::Route> {
         settings::reset,
         payments::get,
         payments::count,
+        payments::list,
+        payments::export,
+        payments::post,
+        payments::patch,
+        payments::delete,
+        payments::get_allocations,
+        payments::post_allocation,
+        payments::delete_allocation,
+        payments::get_customer_credit,
+        ledger::customer_ledger,
+        ledger::customer_statement,
+        ledger::supplier_ledger,
+        ledger::supplier_statement,
+        reports::aging,
+        reports::product_margins,
+        reports::pnl,
+        reports::sales,
+        reports::products,
         // backup::restore,
         // customers::delete,
     ]
@@ -132,15 +207,39 @@ struct StockUpdateFactory {
 struct ListRequest {
     range: ListRange,
     sorts: Vec<ListSort>,
+    /// All of these have to match
     filters: Vec<ListFilter>,
+    /// Conditions that need OR or NOT, combined with `filters` using AND
+    #[serde(default)]
+    #[ts(optional)]
+    filter_tree: Option<FilterTree>,
+    /// Also return archived rows. Only used by tables that can be archived
+    #[serde(default)]
+    include_archived: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
 struct ListRange {
     /// Number of items to send
     count: i32,
-    /// First item's index
+    /// First item's index, ignored if a cursor is sent
     offset: i32,
+    /// `next_cursor` of the previous page. Faster than an offset on deep pages,
+    /// and only valid with the same sorts.
+    #[serde(default)]
+    #[ts(optional)]
+    cursor: Option<String>,
+}
+
+/// A page of a list route
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+struct ListResponse<T> {
+    items: Vec<T>,
+    /// Number of rows matching the filters, on all pages
+    total: i64,
+    /// Send as `range.cursor` to get the next page, null on the last page
+    next_cursor: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
@@ -153,7 +252,37 @@ struct ListSort {
 struct ListFilter {
     column: String,
     operator: FilterOperator,
-    value: SqlType,
+    /// A list for IN, NOT IN and BETWEEN, nothing for IS NULL and IS NOT NULL
+    #[serde(default)]
+    #[ts(optional)]
+    value: Option<FilterValue>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[serde(untagged)]
+#[ts(export)]
+enum FilterValue {
+    Single(SqlType),
+    List(Vec<SqlType>),
+}
+
+/// Filters combined with AND, OR and NOT, e.g.
+/// { "And": [
+///     { "Or": [
+///         { "Filter": { "column": "customer_id", "operator": "=", "value": { "Int": 3 } } },
+///         { "Filter": { "column": "customer_id", "operator": "=", "value": { "Int": 7 } } }
+///     ] },
+///     { "Not": { "Filter": { "column": "fulfilled", "operator": "=", "value": { "Boolean": true } } } }
+/// ] }
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+enum FilterTree {
+    /// Matches everything if empty
+    And(Vec<FilterTree>),
+    /// Matches nothing if empty
+    Or(Vec<FilterTree>),
+    Not(Box<FilterTree>),
+    Filter(ListFilter),
 }
 
 // TODO: Overhaul all routes to use this error type
@@ -218,6 +347,71 @@ impl<'r, T: Serialize> Responder<'r, 'static> for ApiReturn<T> {
     }
 }
 
+/// `from` and `to` query parameters as `YYYY-MM-DD`, both days included.
+/// Days are in the timezone setting, see `bounds`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DateRange {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+/// Parses a `YYYY-MM-DD` query parameter called `name`
+fn parse_date(name: &str, value: &str) -> Result<NaiveDate, ApiError> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        ApiError(
+            Status::BadRequest,
+            format!("Invalid {} date: {}, expected YYYY-MM-DD", name, value),
+        )
+    })
+}
+
+impl DateRange {
+    fn parse(from: Option<&str>, to: Option<&str>) -> Result<Self, ApiError> {
+        let range = Self {
+            from: from.map(|from| parse_date("from", from)).transpose()?,
+            to: to.map(|to| parse_date("to", to)).transpose()?,
+        };
+
+        if let (Some(from), Some(to)) = (range.from, range.to) {
+            if from > to {
+                return Err(ApiError(
+                    Status::BadRequest,
+                    "from can't be after to".to_string(),
+                ));
+            }
+        }
+
+        Ok(range)
+    }
+
+    /// Start of `from` and of the day after `to` in the timezone setting
+    async fn bounds(
+        &self,
+        db: &mut crate::db::DB,
+    ) -> Result<(Option<DateTime<Utc>>, Option<DateTime<Utc>>), ApiError> {
+        let timezone = crate::settings::timezone(db).await?;
+
+        Ok(sqlx::query_as(
+            "SELECT $1::DATE::TIMESTAMP AT TIME ZONE $3, ($2::DATE + 1)::TIMESTAMP AT TIME ZONE $3",
+        )
+        .bind(self.from)
+        .bind(self.to)
+        .bind(&timezone)
+        .fetch_one(&mut ***db)
+        .await?)
+    }
+}
+
+/// Today in the timezone setting
+async fn today(db: &mut crate::db::DB) -> Result<NaiveDate, ApiError> {
+    let timezone = crate::settings::timezone(db).await?;
+
+    Ok(sqlx::query_scalar("SELECT (now() AT TIME ZONE $1)::DATE")
+        .bind(&timezone)
+        .fetch_one(&mut ***db)
+        .await?)
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
 #[ts(export)]
 #[serde(rename_all = "UPPERCASE")]
@@ -243,6 +437,20 @@ enum FilterOperator {
     Le,
     #[serde(rename = "LIKE")]
     Like,
+    /// Case insensitive LIKE
+    #[serde(rename = "ILIKE")]
+    ILike,
+    #[serde(rename = "IN")]
+    In,
+    #[serde(rename = "NOT IN")]
+    NotIn,
+    /// Inclusive on both ends
+    #[serde(rename = "BETWEEN")]
+    Between,
+    #[serde(rename = "IS NULL")]
+    IsNull,
+    #[serde(rename = "IS NOT NULL")]
+    IsNotNull,
 }
 
 impl FilterOperator {
@@ -255,6 +463,12 @@ impl FilterOperator {
             FilterOperator::Ge => ">=",
             FilterOperator::Le => "<=",
             FilterOperator::Like => "LIKE",
+            FilterOperator::ILike => "ILIKE",
+            FilterOperator::In => "IN",
+            FilterOperator::NotIn => "NOT IN",
+            FilterOperator::Between => "BETWEEN",
+            FilterOperator::IsNull => "IS NULL",
+            FilterOperator::IsNotNull => "IS NOT NULL",
         }
     }
 }
@@ -269,6 +483,9 @@ enum SqlType {
     Boolean(bool),
     Null,
     DateTime(sqlx::types::chrono::DateTime<chrono::Utc>),
+    /// Bound as an INT[], e.g. for `= ANY($n)`. Only built by the server, not sent by clients
+    #[serde(skip)]
+    IntArray(Option<Vec<i32>>),
 }
 
 impl SqlType {
@@ -284,6 +501,7 @@ impl SqlType {
             SqlType::Boolean(b) => query.bind(b),
             SqlType::Null => query.bind(None::<i32>),
             SqlType::DateTime(d) => query.bind(d),
+            SqlType::IntArray(ids) => query.bind(ids),
         }
     }
 
@@ -299,6 +517,7 @@ impl SqlType {
             SqlType::Boolean(b) => query.bind(b),
             SqlType::Null => query.bind(None::<i32>),
             SqlType::DateTime(d) => query.bind(d),
+            SqlType::IntArray(ids) => query.bind(ids),
         }
     }
 }
@@ -317,52 +536,35 @@ fn generate_sets_string(columns: &[&'static str], current_param: &mut i32) -> St
     sets
 }
 
-fn generate_sorts_string(sorts: &[ListSort]) -> String {
-    let mut sorts_string = String::new();
-    for (i, sort) in sorts.iter().enumerate() {
-        if i == 0 {
-            sorts_string.push_str("ORDER BY ");
-        }
-
-        sorts_string.push_str(&sort.column);
-        sorts_string.push(' ');
-        sorts_string.push_str(match &sort.order {
-            SortOrder::Asc => "ASC",
-            SortOrder::Desc => "DESC",
-        });
-        sorts_string.push_str(", ");
+/// Shared implementation of the archive and restore routes.
+/// Archiving an already archived row keeps the original timestamp.
+async fn set_archived(
+    table: &'static str,
+    id: i32,
+    archived: bool,
+    db: &mut crate::db::DB,
+) -> Result<Status, ApiError> {
+    let query_str = format!(
+        r#"
+        UPDATE {}
+        SET archived_at = CASE WHEN $1 THEN COALESCE(archived_at, CURRENT_TIMESTAMP) ELSE NULL END
+        WHERE id = $2
+        "#,
+        table
+    );
+
+    let res = sqlx::query(&query_str)
+        .bind(archived)
+        .bind(id)
+        .execute(&mut ***db)
+        .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ApiError(
+            Status::NotFound,
+            format!("Row with id {} not found", id),
+        ));
     }
-    sorts_string.pop();
-    sorts_string.pop();
-    sorts_string
-}
-
-fn generate_filters_string(
-    filters: &[ListFilter],
-    current_arg: &mut i32,
-) -> (String, Vec<SqlType>) {
-    let mut filter_binds = vec![];
-
-    let filter_string = filters
-        .iter()
-        .enumerate()
-        .map(|(i, filter)| {
-            let str = format!(
-                "{} {} {} ${}",
-                if i == 0 { "WHERE" } else { "AND" },
-                filter.column,
-                filter.operator.to_sql(),
-                current_arg
-            );
-
-            filter_binds.push(filter.value.clone());
-
-            *current_arg += 1;
-
-            str
-        })
-        .collect::<Vec<_>>()
-        .join(" ");
 
-    (filter_string, filter_binds)
+    Ok(Status::Ok)
 }
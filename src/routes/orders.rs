@@ -8,15 +8,18 @@ use crate::{
         auth::{AuthGuard, UserRow},
         ListRequest,
     },
-    types::permissions::UserPermissionEnum,
+    types::permissions::perm,
 };
 
 use super::{
-    auth::{AuthCookie, User},
+    auth::{AuthInfo, User},
     customers::Customer,
+    export::{Export, ExportColumn, ExportFormat, ExportKind},
     inventory::InventoryItem,
+    payments,
+    query::{Column, Columns, ListQuery},
     search::SearchRequest,
-    ApiError, ApiReturn, SqlType, StockUpdate, StockUpdateFactory,
+    ApiError, ApiReturn, ListResponse, SqlType, StockUpdate, StockUpdateFactory,
 };
 
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS, FromRow)]
@@ -27,6 +30,7 @@ pub(super) struct OrderMeta {
     /// This will be false if the order is retail
     pub customer: Option<Customer>,
     pub created_by_user: User,
+    /// Sum of the payments allocated to this order
     pub amount_paid: sqlx::types::BigDecimal,
     pub retail: bool,
     pub retail_customer_name: Option<String>,
@@ -37,6 +41,56 @@ pub(super) struct OrderMeta {
     pub total: sqlx::types::BigDecimal,
 }
 
+/// Columns that can be used in list and search requests.
+/// Unqualified names are the orders columns, users only exposes the username.
+const COLUMNS: Columns = Columns(&[
+    Column::aliased("id", "orders.id"),
+    Column::new("orders.id"),
+    Column::aliased("date_time", "orders.date_time"),
+    Column::aliased("customer_id", "orders.customer_id"),
+    Column::aliased("created_by_user_id", "orders.created_by_user_id"),
+    Column::aliased("amount_paid", "orders.amount_paid"),
+    Column::aliased("retail", "orders.retail"),
+    Column::aliased("retail_customer_name", "orders.retail_customer_name"),
+    Column::aliased("retail_customer_phone", "orders.retail_customer_phone"),
+    Column::aliased("retail_customer_address", "orders.retail_customer_address"),
+    Column::aliased("notes", "orders.notes"),
+    Column::aliased("fulfilled", "orders.fulfilled"),
+    Column::aliased("total", "orders.total"),
+    Column::new("customers.name"),
+    Column::new("customers.phone"),
+    Column::new("users.username"),
+]);
+
+/// Orders with their customer and user, for list and export queries
+pub(super) const ORDERS_FROM: &str = r#"
+    orders
+    LEFT JOIN customers ON orders.customer_id = customers.id
+    INNER JOIN users ON orders.created_by_user_id = users.id
+"#;
+
+/// Columns of order exports, from [`ORDERS_FROM`]
+pub(super) const ORDER_EXPORT_COLUMNS: &[ExportColumn] = &[
+    ExportColumn::new("ID", "orders.id", ExportKind::Integer),
+    ExportColumn::new("Date", "orders.date_time", ExportKind::DateTime),
+    ExportColumn::new(
+        "Customer",
+        "COALESCE(customers.name, orders.retail_customer_name)",
+        ExportKind::Text,
+    ),
+    ExportColumn::new("Retail", "orders.retail", ExportKind::Boolean),
+    ExportColumn::new("Created By", "users.username", ExportKind::Text),
+    ExportColumn::new("Total", "orders.total", ExportKind::Currency),
+    ExportColumn::new("Paid", "orders.amount_paid", ExportKind::Currency),
+    ExportColumn::new(
+        "Outstanding",
+        "GREATEST(orders.total - orders.amount_paid, 0)",
+        ExportKind::Currency,
+    ),
+    ExportColumn::new("Fulfilled", "orders.fulfilled", ExportKind::Boolean),
+    ExportColumn::new("Notes", "orders.notes", ExportKind::Text),
+];
+
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
 #[ts(export)]
 pub(super) struct Order {
@@ -108,10 +162,15 @@ impl FromDB for OrderMeta {
         .bind(id)
         .fetch_one(&mut ***db)
         .await
-        .map_err(|e| ApiError(Status::InternalServerError, e.to_string()))
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => {
+                ApiError(Status::NotFound, format!("No order with id {}", id))
+            }
+            _ => ApiError(Status::InternalServerError, e.to_string()),
+        })
         .map(|row: OrderMetaRow| row.into())?;
 
-        Ok(order_meta.into())
+        Ok(order_meta)
     }
 }
 
@@ -121,7 +180,7 @@ impl FromDB for OrderMeta {
 pub(super) async fn get(
     id: i32,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::ORDER_READ as u32 }>,
+    _auth: AuthGuard<perm::ORDER_READ>,
 ) -> Result<rocket::serde::json::Json<OrderMeta>, ApiError> {
     let order_meta: OrderMeta = sqlx::query_as(
         r#"
@@ -162,6 +221,8 @@ pub(super) struct OrderItem {
     pub price: sqlx::types::BigDecimal,
     pub discount: sqlx::types::BigDecimal,
     pub discount_percentage: bool,
+    /// Cost of goods sold of the line, set by the inventory costing method
+    pub cost: sqlx::types::BigDecimal,
 }
 
 #[derive(FromRow, Debug, Deserialize)]
@@ -172,6 +233,21 @@ pub(super) struct OrderItemRow {
     pub price: sqlx::types::BigDecimal,
     pub discount: sqlx::types::BigDecimal,
     pub discount_percentage: bool,
+    pub cost: sqlx::types::BigDecimal,
+}
+
+impl OrderItem {
+    /// Price of the line after the discount, like get_order_total in SQL
+    pub fn total(&self) -> sqlx::types::BigDecimal {
+        let gross = &self.price * sqlx::types::BigDecimal::from(self.quantity);
+
+        if self.discount_percentage {
+            let hundred = sqlx::types::BigDecimal::from(100);
+            &gross * (&hundred - &self.discount) / hundred
+        } else {
+            gross - &self.discount
+        }
+    }
 }
 
 impl From<OrderItemRow> for OrderItem {
@@ -183,6 +259,7 @@ impl From<OrderItemRow> for OrderItem {
             price: value.price,
             discount: value.discount,
             discount_percentage: value.discount_percentage,
+            cost: value.cost,
         }
     }
 }
@@ -191,9 +268,14 @@ impl From<OrderItemRow> for OrderItem {
 pub(super) async fn get_items(
     id: i32,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::ORDER_READ as u32 }>,
+    _auth: AuthGuard<perm::ORDER_READ>,
 ) -> Result<rocket::serde::json::Json<Vec<OrderItem>>, ApiError> {
-    let order_items: Vec<OrderItem> = sqlx::query_as(
+    Ok(rocket::serde::json::Json(get_order_items(id, &mut db).await?))
+}
+
+/// Items of an order in the order they were added
+pub(super) async fn get_order_items(id: i32, db: &mut DB) -> Result<Vec<OrderItem>, ApiError> {
+    sqlx::query_as(
         r#"
         SELECT 
             order_items.id as id,
@@ -201,25 +283,25 @@ pub(super) async fn get_items(
             order_items.price as price,
             order_items.quantity as quantity,
             order_items.discount as discount,
-            order_items.discount_percentage as discount_percentage
+            order_items.discount_percentage as discount_percentage,
+            order_items.cost as cost
         FROM order_items
             INNER JOIN inventory ON inventory_id = inventory.id
         WHERE order_id = $1
+        ORDER BY order_items.id
         "#,
     )
     .bind(id)
-    .fetch_all(&mut **db)
+    .fetch_all(&mut ***db)
     .await
     .map_err(|e| ApiError(Status::InternalServerError, e.to_string()))
-    .map(|rows: Vec<OrderItemRow>| rows.into_iter().map(OrderItem::from).collect())?;
-
-    Ok(rocket::serde::json::Json(order_items))
+    .map(|rows: Vec<OrderItemRow>| rows.into_iter().map(OrderItem::from).collect())
 }
 
 #[rocket::get("/orders/count")]
 pub(super) async fn count(
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::ORDER_READ as u32 }>,
+    _auth: AuthGuard<perm::ORDER_READ>,
 ) -> Result<rocket::serde::json::Json<i64>, ApiError> {
     let count: (i64,) = sqlx::query_as("SELECT count(*) FROM orders")
         .fetch_one(&mut **db)
@@ -247,84 +329,85 @@ pub(super) async fn count(
 ///     }]
 /// }
 /// ```
-/// Response: Vec<Order>
+/// Response: ListResponse<OrderMeta>
 ///
 /// Nested columns: customer, created_by_user
 #[rocket::post("/orders/list", data = "<req>")]
 pub(super) async fn list(
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::ORDER_READ as u32 }>,
+    _auth: AuthGuard<perm::ORDER_READ>,
     req: rocket::serde::json::Json<ListRequest>,
-) -> Result<rocket::serde::json::Json<Vec<OrderMeta>>, ApiError> {
+) -> Result<rocket::serde::json::Json<ListResponse<OrderMeta>>, ApiError> {
     let req = req.into_inner();
 
-    let mut current_param = 1;
-
-    let sorts_string = super::generate_sorts_string(&req.sorts);
-
-    let (filters_string, filter_binds) =
-        super::generate_filters_string(&req.filters, &mut current_param);
-
-    let query_str = format!(
-        r#"
-        SELECT 
-            orders.id,
-            orders.date_time,
-            orders.amount_paid,
-            orders.retail,
-            orders.retail_customer_name,
-            orders.retail_customer_phone,
-            orders.retail_customer_address,
-            orders.notes,
-            orders.fulfilled,
-            orders.total,
-            row_to_json(customers) AS customer,
-            row_to_json(users) AS created_by_user
-        FROM orders
-            LEFT JOIN customers ON orders.customer_id = customers.id
-            INNER JOIN users ON orders.created_by_user_id = users.id
-        {}
-        {}
-        LIMIT ${}
-        OFFSET ${}
-        "#,
-        filters_string,
-        sorts_string,
-        current_param,
-        current_param + 1
-    );
-
-    let query = sqlx::query_as(&query_str);
-
-    let query = filter_binds
-        .into_iter()
-        .fold(query, |query, value| value.bind_to_query_as(query));
+    let page = COLUMNS
+        .page::<OrderMetaRow, _>(
+            ListQuery {
+                select: r#"
+                    orders.id,
+                    orders.date_time,
+                    orders.amount_paid,
+                    orders.retail,
+                    orders.retail_customer_name,
+                    orders.retail_customer_phone,
+                    orders.retail_customer_address,
+                    orders.notes,
+                    orders.fulfilled,
+                    orders.total,
+                    row_to_json(customers) AS customer,
+                    row_to_json(users) AS created_by_user
+                "#,
+                from: ORDERS_FROM,
+                key: "orders.id",
+                condition: None,
+            },
+            &req,
+            &mut db,
+        )
+        .await?;
 
-    let query = query.bind(req.range.count).bind(req.range.offset);
+    Ok(rocket::serde::json::Json(page))
+}
 
-    let orders: Vec<OrderMeta> = query
-        .fetch_all(&mut **db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => ApiError(Status::NotFound, "No orders found".to_string()),
-            e => ApiError(Status::InternalServerError, e.to_string()),
-        })
-        .map(|rows: Vec<OrderMetaRow>| rows.into_iter().map(OrderMeta::from).collect())?;
+// POST /orders/export?format=xlsx [Permissions: ORDER_READ]
+// Request: ListRequest, the range is ignored and every matching row is exported
+// format is csv (default) or xlsx
+// -> 200 OK
+//     orders.csv or orders.xlsx
+// -> 400 Bad Request (invalid format, columns or filters)
+#[rocket::post("/orders/export?<format>", data = "<req>")]
+pub(super) async fn export(
+    format: Option<&str>,
+    db: DB,
+    _auth: AuthGuard<perm::ORDER_READ>,
+    req: rocket::serde::json::Json<ListRequest>,
+) -> Result<Export, ApiError> {
+    let format = ExportFormat::parse(format)?;
+    let req = req.into_inner();
 
-    Ok(rocket::serde::json::Json(orders))
+    let (rest, binds) = COLUMNS.export_string(
+        &ListQuery {
+            select: "*",
+            from: ORDERS_FROM,
+            key: "orders.id",
+            condition: None,
+        },
+        &req,
+        &mut 2,
+    )?;
+
+    Export::new(format, "orders", ORDER_EXPORT_COLUMNS, rest, binds, db).await
 }
 
 #[rocket::post("/orders/search", data = "<req>")]
 pub(super) async fn search(
     req: rocket::serde::json::Json<SearchRequest>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::ORDER_READ as u32 }>,
+    _auth: AuthGuard<perm::ORDER_READ>,
 ) -> Result<rocket::serde::json::Json<Vec<OrderMeta>>, ApiError> {
     let req = req.into_inner();
 
-    let x = req
-        .column
-        .unwrap_or(req.nested_access.unwrap_or("id".to_string()));
+    let x = COLUMNS.search_column(&req)?;
 
     let query_str = format!(
         r#"
@@ -391,7 +474,6 @@ pub(super) struct OrderPostRequest {
     pub retail_customer_phone: Option<String>,
     pub retail_customer_address: Option<String>,
     pub notes: String,
-    pub amount_paid: sqlx::types::BigDecimal,
     pub fulfilled: bool,
 }
 
@@ -406,7 +488,6 @@ pub(super) struct OrderPatchRequest {
     pub retail_customer_address: Option<String>,
     pub set_retail_customer_null: bool,
     pub notes: Option<String>,
-    pub amount_paid: Option<sqlx::types::BigDecimal>,
     pub fulfilled: Option<bool>,
     pub date_time: Option<chrono::DateTime<chrono::Utc>>,
 }
@@ -415,7 +496,7 @@ pub(super) struct OrderPatchRequest {
 pub(super) async fn post(
     req: rocket::serde::json::Json<OrderPostRequest>,
     mut db: DB,
-    auth: AuthGuard<{ UserPermissionEnum::ORDER_CREATE as u32 }>,
+    auth: AuthGuard<perm::ORDER_CREATE>,
 ) -> Result<ApiReturn<i32>, ApiError> {
     let req = req.into_inner();
 
@@ -423,14 +504,13 @@ pub(super) async fn post(
 
     let id: (i32,) = sqlx::query_as(
         r#"
-        INSERT INTO orders (customer_id, created_by_user_id, amount_paid, retail, retail_customer_name, retail_customer_phone, retail_customer_address, notes, fulfilled)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        INSERT INTO orders (customer_id, created_by_user_id, retail, retail_customer_name, retail_customer_phone, retail_customer_address, notes, fulfilled)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
         RETURNING id
         "#,
     )
     .bind(req.customer_id)
     .bind(user_id)
-    .bind(req.amount_paid)
     .bind(req.retail)
     .bind(req.retail_customer_name)
     .bind(req.retail_customer_phone)
@@ -447,7 +527,7 @@ pub(super) async fn post(
 pub(super) async fn total(
     id: i32,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::ORDER_READ as u32 }>,
+    _auth: AuthGuard<perm::ORDER_READ>,
 ) -> Result<rocket::serde::json::Json<OrderTotal>, ApiError> {
     Ok(rocket::serde::json::Json(
         get_order_total(id, &mut db).await?,
@@ -473,7 +553,7 @@ pub(super) async fn patch(
     id: i32,
     req: rocket::serde::json::Json<OrderPatchRequest>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::ORDER_UPDATE as u32 }>,
+    _auth: AuthGuard<perm::ORDER_UPDATE>,
 ) -> Result<Status, ApiError> {
     let req = req.into_inner();
 
@@ -484,6 +564,27 @@ pub(super) async fn patch(
         )
     })?;
 
+    let current: Option<(Option<i32>, bool)> =
+        sqlx::query_as("SELECT customer_id, retail FROM orders WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *transaction)
+            .await?;
+
+    if let Some((customer_id, retail)) = current {
+        let party_changed = (req.customer_id.is_some() && req.customer_id != customer_id)
+            || (req.set_customer_id_null && customer_id.is_some())
+            || req.retail.is_some_and(|r| r != retail);
+
+        // Payments are allocated to orders of their own customer, see payments::allocate
+        if party_changed && payments::order_has_allocations(id, &mut transaction).await? {
+            return Err(ApiError(
+                Status::BadRequest,
+                "Remove the payment allocations of the order before changing its customer"
+                    .to_string(),
+            ));
+        }
+    }
+
     let mut current_param_index = 1;
 
     let columns = vec![
@@ -499,7 +600,6 @@ pub(super) async fn patch(
             .as_ref()
             .map(|_| "retail_customer_address"),
         req.notes.as_ref().map(|_| "notes"),
-        req.amount_paid.as_ref().map(|_| "amount_paid"),
         req.fulfilled.as_ref().map(|_| "fulfilled"),
         req.date_time.as_ref().map(|_| "date_time"),
     ]
@@ -514,8 +614,8 @@ pub(super) async fn patch(
     }
 
     let set_binds = vec![
-        req.customer_id.as_ref().map(|v| SqlType::Int(v.clone())),
-        req.retail.as_ref().map(|v| SqlType::Boolean(v.clone())),
+        req.customer_id.as_ref().map(|v| SqlType::Int(*v)),
+        req.retail.as_ref().map(|v| SqlType::Boolean(*v)),
         req.retail_customer_name
             .as_ref()
             .map(|v| SqlType::String(v.clone())),
@@ -526,11 +626,8 @@ pub(super) async fn patch(
             .as_ref()
             .map(|v| SqlType::String(v.clone())),
         req.notes.as_ref().map(|v| SqlType::String(v.clone())),
-        req.amount_paid
-            .as_ref()
-            .map(|v| SqlType::BigDecimal(v.clone())),
-        req.fulfilled.as_ref().map(|v| SqlType::Boolean(v.clone())),
-        req.date_time.as_ref().map(|v| SqlType::DateTime(v.clone())),
+        req.fulfilled.as_ref().map(|v| SqlType::Boolean(*v)),
+        req.date_time.as_ref().map(|v| SqlType::DateTime(*v)),
     ]
     .into_iter()
     .flatten();
@@ -617,10 +714,10 @@ pub(super) async fn patch(
 }
 
 async fn calculate_stock_deltas(
-    requests: &Vec<OrderItemUpdateRequest>,
-    current_items: &Vec<OrderItem>,
+    requests: &[OrderItemUpdateRequest],
+    current_items: &[OrderItem],
     order_id: i32,
-    auth_info: &AuthCookie,
+    auth_info: &AuthInfo,
     db: &mut DB,
 ) -> Result<Vec<StockUpdateFactory>, ApiError> {
     let mut stock_update_factories = vec![];
@@ -641,10 +738,12 @@ async fn calculate_stock_deltas(
     // Calculate stock deltas for new items
     for req in requests.iter() {
         // Find and merge
-        stock_update_factories
+        if let Some(update) = stock_update_factories
             .iter_mut()
             .find(|update| update.inventory.id == req.inventory_item_id)
-            .map(|update| update.delta -= req.quantity);
+        {
+            update.delta -= req.quantity;
+        }
 
         // Add new items
         if !current_items
@@ -675,7 +774,7 @@ pub(super) async fn preview_update_items(
     id: i32,
     req: rocket::serde::json::Json<Vec<OrderItemUpdateRequest>>,
     mut db: DB,
-    auth: AuthGuard<{ UserPermissionEnum::ORDER_READ as u32 }>,
+    auth: AuthGuard<perm::ORDER_READ>,
 ) -> Result<ApiReturn<Vec<StockUpdateFactory>>, ApiError> {
     let requests = req.into_inner();
 
@@ -701,7 +800,8 @@ pub(super) async fn preview_update_items(
             order_items.price as price,
             order_items.quantity as quantity,
             order_items.discount as discount,
-            order_items.discount_percentage as discount_percentage
+            order_items.discount_percentage as discount_percentage,
+            order_items.cost as cost
         FROM order_items
             INNER JOIN inventory ON inventory_id = inventory.id
         WHERE order_id = $1
@@ -738,13 +838,14 @@ pub(super) async fn preview_update_items(
 /// If an item is not in the request, it will be removed
 /// If an item is in the request, it will be updated
 /// If an item is not in the current items, it will be added
+/// Fails if the new total is less than the amount paid
 /// POST /orders/<id>/items
 #[rocket::post("/orders/<id>/items/update", data = "<req>")]
 pub(super) async fn update_items(
     id: i32,
     req: rocket::serde::json::Json<Vec<OrderItemUpdateRequest>>,
     mut db: DB,
-    auth: AuthGuard<{ UserPermissionEnum::ORDER_UPDATE as u32 }>,
+    auth: AuthGuard<perm::ORDER_UPDATE>,
 ) -> Result<ApiReturn<Vec<StockUpdate>>, ApiError> {
     let requests = req.into_inner();
 
@@ -770,7 +871,8 @@ pub(super) async fn update_items(
             order_items.price as price,
             order_items.quantity as quantity,
             order_items.discount as discount,
-            order_items.discount_percentage as discount_percentage
+            order_items.discount_percentage as discount_percentage,
+            order_items.cost as cost
         FROM order_items
             INNER JOIN inventory ON inventory_id = inventory.id
         WHERE order_id = $1
@@ -1037,6 +1139,27 @@ pub(super) async fn update_items(
         stock_updates.push(stock_update);
     }
 
+    // Payments are allocated up to the total, so it can't drop below what was allocated
+    let overpaid: bool = sqlx::query_scalar(
+        r#"
+        SELECT get_order_total(id) < amount_paid
+        FROM orders
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(id)
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    if overpaid {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Order total would be less than the amount paid, remove or reduce payment allocations first"
+                .to_string(),
+        ));
+    }
+
     transaction.commit().await.map_err(|e| {
         ApiError(
             Status::InternalServerError,
@@ -1051,7 +1174,7 @@ pub(super) async fn update_items(
 pub(super) async fn delete(
     id: i32,
     mut db: DB,
-    auth: AuthGuard<{ UserPermissionEnum::ORDER_DELETE as u32 }>,
+    auth: AuthGuard<perm::ORDER_DELETE>,
 ) -> Result<ApiReturn<Vec<StockUpdate>>, ApiError> {
     // Add stock back for all items in the order
     let order_items: Vec<OrderItem> = sqlx::query_as(
@@ -1060,9 +1183,10 @@ pub(super) async fn delete(
         order_items.id as id,
         row_to_json(inventory) as inventory,
         order_items.price as price,
-        order_items.quantity as quantity
+        order_items.quantity as quantity,
         order_items.discount as discount,
-        order_items.discount_percentage as discount_percentage
+        order_items.discount_percentage as discount_percentage,
+        order_items.cost as cost
     FROM order_items
         INNER JOIN inventory ON inventory_id = inventory.id
     WHERE order_id = $1
@@ -1080,7 +1204,7 @@ pub(super) async fn delete(
     .collect();
 
     let stock_update_factories =
-        calculate_stock_deltas(&vec![], &order_items, id, &auth.auth_info, &mut db)
+        calculate_stock_deltas(&[], &order_items, id, &auth.auth_info, &mut db)
             .await
             .map_err(|e| {
                 ApiError(
@@ -1,16 +1,21 @@
 use bigdecimal::BigDecimal;
-use rocket::serde::json::Json;
+use rocket::{http::Status, serde::json::Json};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use sqlx::{Acquire, FromRow, PgConnection};
 
-use crate::{db::FromDB, types::permissions::UserPermissionEnum};
+use crate::{
+    db::{FromDB, DB},
+    types::permissions::perm,
+};
 
 use super::{
     auth::{AuthGuard, User, UserRow},
     customers::Customer,
-    orders::{Order, OrderMeta},
+    export::{Export, ExportColumn, ExportFormat, ExportKind},
+    orders::OrderMeta,
+    query::{Column, Columns, ListQuery},
     suppliers::Supplier,
-    ApiError, ListRequest,
+    ApiError, ApiReturn, ListRequest, ListResponse,
 };
 
 #[derive(FromRow, Debug)]
@@ -18,19 +23,22 @@ pub(super) struct PaymentRow {
     pub id: i32,
     pub date_time: chrono::DateTime<chrono::Utc>,
     pub amount: BigDecimal,
-    // JSON type <PaymentParty variants>
-    pub party: sqlx::types::JsonValue,
+    pub party_id: i32,
+    /// The customer or supplier, null for retail orders which are resolved separately
+    pub party: Option<sqlx::types::JsonValue>,
     pub party_type: PartyType,
     pub transfer_type: TransferType,
     pub method: PaymentMethod,
     pub method_details: String,
     pub notes: String,
+    pub allocated: BigDecimal,
     pub created_by_user: sqlx::types::Json<UserRow>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS, sqlx::Type)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ts_rs::TS, sqlx::Type)]
 #[sqlx(rename_all = "SCREAMING_SNAKE_CASE")]
 #[sqlx(type_name = "payment_method_t")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[ts(export)]
 pub enum PaymentMethod {
     Cash,
@@ -39,26 +47,29 @@ pub enum PaymentMethod {
     Other,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS, sqlx::Type)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ts_rs::TS, sqlx::Type)]
 #[sqlx(rename_all = "SCREAMING_SNAKE_CASE")]
 #[sqlx(type_name = "party_type_t")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[ts(export)]
 pub enum PartyType {
     Customer,
     Supplier,
+    /// A retail order, for buyers that aren't saved as customers
     Retail,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS, sqlx::Type)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, ts_rs::TS, sqlx::Type)]
 #[sqlx(rename_all = "SCREAMING_SNAKE_CASE")]
 #[sqlx(type_name = "transfer_type_t")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[ts(export)]
-
 pub enum TransferType {
     Incoming,
     Outgoing,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS, FromRow)]
+#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
 #[ts(export)]
 pub(super) struct Payment {
     pub id: i32,
@@ -69,80 +80,410 @@ pub(super) struct Payment {
     pub method: PaymentMethod,
     pub method_details: String,
     pub notes: String,
+    /// Part of the amount allocated to orders or purchases. The rest of a customer payment is credit.
+    pub allocated: BigDecimal,
     pub created_by_user: User,
 }
 
 impl Payment {
-    async fn from_row(row: PaymentRow, db: &mut crate::db::DB) -> Result<Self, ApiError> {
+    async fn from_row(row: PaymentRow, db: &mut DB) -> Result<Self, ApiError> {
+        let party = match (row.party_type, row.party) {
+            (PartyType::Customer, Some(party)) => {
+                PaymentParty::Customer(serde_json::from_value(party)?)
+            }
+            (PartyType::Supplier, Some(party)) => {
+                PaymentParty::Supplier(serde_json::from_value(party)?)
+            }
+            (PartyType::Retail, _) => {
+                PaymentParty::Retail(Box::new(OrderMeta::from_db(row.party_id, db).await?))
+            }
+            // enforce_party_fk makes sure the party exists
+            (_, None) => {
+                return Err(ApiError(
+                    Status::InternalServerError,
+                    format!("Party of payment {} not found", row.id),
+                ))
+            }
+        };
+
         Ok(Self {
             id: row.id,
             date_time: row.date_time,
             amount: row.amount,
-            party: match row.party_type {
-                PartyType::Customer => PaymentParty::Customer(serde_json::from_value(row.party)?),
-                PartyType::Supplier => PaymentParty::Supplier(serde_json::from_value(row.party)?),
-                PartyType::Retail => PaymentParty::Retail(serde_json::from_value(row.party)?),
-            },
+            party,
             transfer_type: row.transfer_type,
             method: row.method,
             method_details: row.method_details,
             notes: row.notes,
+            allocated: row.allocated,
             created_by_user: row.created_by_user.0.into(),
         })
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 #[ts(export)]
 pub(super) enum PaymentParty {
     Customer(Customer),
     Supplier(Supplier),
-    Retail(OrderMeta),
+    Retail(Box<OrderMeta>),
+}
+
+const PAYMENT_SELECT: &str = r#"
+    payments.id,
+    payments.date_time,
+    payments.amount,
+    payments.party_id,
+    payments.party_type,
+    CASE
+        WHEN payments.party_type = 'CUSTOMER' THEN row_to_json(customers)
+        WHEN payments.party_type = 'SUPPLIER' THEN row_to_json(suppliers)
+    END AS party,
+    payments.transfer_type,
+    payments.method,
+    COALESCE(payments.method_details, '') AS method_details,
+    COALESCE(payments.notes, '') AS notes,
+    (
+        SELECT COALESCE(SUM(amount), 0) FROM payment_allocations
+        WHERE payment_allocations.payment_id = payments.id
+    ) AS allocated,
+    row_to_json(users) AS created_by_user
+"#;
+
+const PAYMENT_FROM: &str = r#"
+    payments
+    INNER JOIN users ON payments.created_by_user_id = users.id
+    LEFT JOIN customers ON payments.party_id = customers.id AND payments.party_type = 'CUSTOMER'
+    LEFT JOIN suppliers ON payments.party_id = suppliers.id AND payments.party_type = 'SUPPLIER'
+"#;
+
+/// Columns of payment exports, from PAYMENT_FROM
+const EXPORT_COLUMNS: &[ExportColumn] = &[
+    ExportColumn::new("ID", "payments.id", ExportKind::Integer),
+    ExportColumn::new("Date", "payments.date_time", ExportKind::DateTime),
+    ExportColumn::new("Party Type", "payments.party_type", ExportKind::Text),
+    ExportColumn::new(
+        "Party",
+        "COALESCE(customers.name, suppliers.name, 'Retail order ' || payments.party_id)",
+        ExportKind::Text,
+    ),
+    ExportColumn::new("Transfer Type", "payments.transfer_type", ExportKind::Text),
+    ExportColumn::new("Method", "payments.method", ExportKind::Text),
+    ExportColumn::new("Method Details", "payments.method_details", ExportKind::Text),
+    ExportColumn::new("Amount", "payments.amount", ExportKind::Currency),
+    ExportColumn::new("Notes", "payments.notes", ExportKind::Text),
+    ExportColumn::new("Created By", "users.username", ExportKind::Text),
+];
+
+/// Columns that can be used in list requests.
+/// Enums are compared as text, e.g. `"method" = "CASH"`.
+const COLUMNS: Columns = Columns(&[
+    Column::aliased("id", "payments.id"),
+    Column::aliased("date_time", "payments.date_time"),
+    Column::aliased("amount", "payments.amount"),
+    Column::aliased("party_id", "payments.party_id"),
+    Column::aliased("party_type", "payments.party_type::text"),
+    Column::aliased("transfer_type", "payments.transfer_type::text"),
+    Column::aliased("method", "payments.method::text"),
+    Column::aliased("method_details", "payments.method_details"),
+    Column::aliased("notes", "payments.notes"),
+    Column::aliased("created_by_user_id", "payments.created_by_user_id"),
+    Column::new("customers.name"),
+    Column::new("suppliers.name"),
+    Column::new("users.username"),
+]);
+
+/// Same checks as the enforce_party_fk trigger, with errors meant for users.
+/// Retail payments also have to belong to a retail order.
+async fn check_party(
+    party_type: PartyType,
+    party_id: i32,
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    let exists: bool = sqlx::query_scalar(
+        r#"
+        SELECT CASE $1
+            WHEN 'CUSTOMER' THEN EXISTS (SELECT 1 FROM customers WHERE id = $2)
+            WHEN 'SUPPLIER' THEN EXISTS (SELECT 1 FROM suppliers WHERE id = $2)
+            WHEN 'RETAIL' THEN EXISTS (SELECT 1 FROM orders WHERE id = $2 AND retail)
+        END
+        "#,
+    )
+    .bind(party_type)
+    .bind(party_id)
+    .fetch_one(conn)
+    .await?;
+
+    if !exists {
+        return Err(ApiError(
+            Status::BadRequest,
+            match party_type {
+                PartyType::Customer => format!("Customer {} not found", party_id),
+                PartyType::Supplier => format!("Supplier {} not found", party_id),
+                PartyType::Retail => format!("Retail order {} not found", party_id),
+            },
+        ));
+    }
+
+    Ok(())
+}
+
+fn check_amount(amount: &BigDecimal) -> Result<(), ApiError> {
+    if *amount <= BigDecimal::from(0) {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Amount must be positive".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, FromRow, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct PaymentAllocation {
+    pub id: i32,
+    pub payment_id: i32,
+    /// Set for incoming payments, purchase_id is null then
+    pub order_id: Option<i32>,
+    /// Set for outgoing payments to suppliers, order_id is null then
+    pub purchase_id: Option<i32>,
+    pub amount: BigDecimal,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct PaymentAllocationRequest {
+    /// One of order_id and purchase_id
+    #[serde(default)]
+    pub order_id: Option<i32>,
+    #[serde(default)]
+    pub purchase_id: Option<i32>,
+    pub amount: BigDecimal,
+}
+
+/// Allocate part of a payment to an order or purchase, which updates its amount_paid.
+///
+/// Incoming payments from a customer go to that customer's orders, a retail payment only to
+/// its own order, and outgoing payments to a supplier go to that supplier's purchases.
+/// Neither the payment nor the order or purchase can be allocated more than its amount.
+pub(super) async fn allocate(
+    payment_id: i32,
+    req: &PaymentAllocationRequest,
+    conn: &mut PgConnection,
+) -> Result<i32, ApiError> {
+    check_amount(&req.amount)?;
+
+    let payment: Option<(BigDecimal, PartyType, i32, TransferType)> = sqlx::query_as(
+        r#"
+        SELECT amount, party_type, party_id, transfer_type
+        FROM payments
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(payment_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some((payment_amount, party_type, party_id, transfer_type)) = payment else {
+        return Err(ApiError(
+            Status::NotFound,
+            format!("No payment with id {}", payment_id),
+        ));
+    };
+
+    // Amount the order or purchase still needs, if it belongs to the payment's party
+    let owed: Option<BigDecimal> = match (req.order_id, req.purchase_id) {
+        (Some(order_id), None) => {
+            if transfer_type != TransferType::Incoming || party_type == PartyType::Supplier {
+                return Err(ApiError(
+                    Status::BadRequest,
+                    "Only incoming payments from customers can pay for orders".to_string(),
+                ));
+            }
+
+            sqlx::query_scalar(
+                r#"
+                SELECT total - amount_paid FROM orders
+                WHERE id = $1 AND CASE $2
+                    WHEN 'CUSTOMER' THEN customer_id = $3
+                    ELSE id = $3 AND retail
+                END
+                FOR UPDATE
+                "#,
+            )
+            .bind(order_id)
+            .bind(party_type)
+            .bind(party_id)
+            .fetch_optional(&mut *conn)
+            .await?
+        }
+        (None, Some(purchase_id)) => {
+            if transfer_type != TransferType::Outgoing || party_type != PartyType::Supplier {
+                return Err(ApiError(
+                    Status::BadRequest,
+                    "Only outgoing payments to suppliers can pay for purchases".to_string(),
+                ));
+            }
+
+            sqlx::query_scalar(
+                r#"
+                SELECT COALESCE((
+                    SELECT SUM(price * quantity) FROM purchase_items WHERE purchase_id = purchases.id
+                ), 0) - amount_paid
+                FROM purchases
+                WHERE id = $1 AND supplier_id = $2
+                FOR UPDATE
+                "#,
+            )
+            .bind(purchase_id)
+            .bind(party_id)
+            .fetch_optional(&mut *conn)
+            .await?
+        }
+        _ => {
+            return Err(ApiError(
+                Status::BadRequest,
+                "Allocations need either an order_id or a purchase_id".to_string(),
+            ))
+        }
+    };
+
+    let Some(owed) = owed else {
+        return Err(ApiError(
+            Status::BadRequest,
+            "The order or purchase doesn't belong to the party of the payment".to_string(),
+        ));
+    };
+
+    if req.amount > owed {
+        return Err(ApiError(
+            Status::BadRequest,
+            format!("Only {} is left to pay", owed.max(BigDecimal::from(0))),
+        ));
+    }
+
+    let allocated: BigDecimal = sqlx::query_scalar(
+        "SELECT COALESCE(SUM(amount), 0) FROM payment_allocations WHERE payment_id = $1",
+    )
+    .bind(payment_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let unallocated = payment_amount - allocated;
+
+    if req.amount > unallocated {
+        return Err(ApiError(
+            Status::BadRequest,
+            format!("Only {} of the payment is unallocated", unallocated),
+        ));
+    }
+
+    let id = sqlx::query_scalar(
+        r#"
+        INSERT INTO payment_allocations (payment_id, order_id, purchase_id, amount)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+    )
+    .bind(payment_id)
+    .bind(req.order_id)
+    .bind(req.purchase_id)
+    .bind(&req.amount)
+    .fetch_one(conn)
+    .await?;
+
+    Ok(id)
+}
+
+async fn has_allocations(column: &str, id: i32, conn: &mut PgConnection) -> Result<bool, ApiError> {
+    let exists = sqlx::query_scalar(&format!(
+        "SELECT EXISTS (SELECT 1 FROM payment_allocations WHERE {} = $1)",
+        column
+    ))
+    .bind(id)
+    .fetch_one(conn)
+    .await?;
+
+    Ok(exists)
+}
+
+pub(super) async fn order_has_allocations(
+    order_id: i32,
+    conn: &mut PgConnection,
+) -> Result<bool, ApiError> {
+    has_allocations("order_id", order_id, conn).await
+}
+
+pub(super) async fn purchase_has_allocations(
+    purchase_id: i32,
+    conn: &mut PgConnection,
+) -> Result<bool, ApiError> {
+    has_allocations("purchase_id", purchase_id, conn).await
+}
+
+/// Money a customer has paid that isn't allocated to any order, minus refunds.
+/// It can be spent by allocating the remaining amount of their payments to new orders.
+pub(super) async fn customer_credit(
+    customer_id: i32,
+    conn: &mut PgConnection,
+) -> Result<BigDecimal, ApiError> {
+    let credit = sqlx::query_scalar(
+        r#"
+        SELECT COALESCE(SUM(
+            CASE transfer_type
+                WHEN 'INCOMING' THEN amount - (
+                    SELECT COALESCE(SUM(amount), 0) FROM payment_allocations
+                    WHERE payment_id = payments.id
+                )
+                ELSE -amount
+            END
+        ), 0)
+        FROM payments
+        WHERE party_type = 'CUSTOMER' AND party_id = $1
+        "#,
+    )
+    .bind(customer_id)
+    .fetch_one(conn)
+    .await?;
+
+    Ok(credit)
+}
+
+/// Errors raised by the enforce_party_fk trigger are the client's fault
+fn map_payment_error(e: sqlx::Error) -> ApiError {
+    match e {
+        sqlx::Error::Database(e) if e.code().as_deref() == Some("P0001") => {
+            ApiError(Status::BadRequest, e.message().to_string())
+        }
+        e => e.into(),
+    }
 }
 
 #[rocket::get("/payments/<id>")]
 pub(super) async fn get(
     id: i32,
-    mut db: crate::db::DB,
-    _auth: AuthGuard<{ UserPermissionEnum::PAYMENT_READ as u32 }>,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_READ>,
 ) -> Result<Json<Payment>, ApiError> {
-    let row = sqlx::query_as(
-        r#"
-            SELECT
-                payments.id,
-                payments.date_time,
-                payments.amount,
-                payments.party_id,
-                payments.party_type,
-                case 
-                    when party_type = 'CUSTOMER' then row_to_json(customers)
-                    when party_type = 'SUPPLIER' then row_to_json(suppliers)
-                    when party_type = 'RETAIL' then row_to_json(orders)
-                end as party,
-                payments.transfer_type,
-                payments.method,
-                payments.method_details,
-                payments.notes,
-                row_to_json(users) AS created_by_user
-            FROM payments
-                INNER JOIN users ON payments.created_by_user_id = users.id
-                LEFT JOIN customers ON payments.party_id = customers.id AND payments.party_type = 'CUSTOMER'
-                LEFT JOIN suppliers ON payments.party_id = suppliers.id AND payments.party_type = 'SUPPLIER'
-                LEFT JOIN orders ON payments.party_id = orders.id AND payments.party_type = 'RETAIL'
-            WHERE payments.id = $1
-            "#,
-    )
+    let row = sqlx::query_as(&format!(
+        "SELECT {} FROM {} WHERE payments.id = $1",
+        PAYMENT_SELECT, PAYMENT_FROM
+    ))
     .bind(id)
-    .fetch_one(&mut **db)
-    .await?;
+    .fetch_optional(&mut **db)
+    .await?
+    .ok_or_else(|| ApiError(Status::NotFound, format!("No payment with id {}", id)))?;
 
     Payment::from_row(row, &mut db).await.map(Json)
 }
 
 #[rocket::get("/payments/count")]
 pub(super) async fn count(
-    mut db: crate::db::DB,
-    _auth: AuthGuard<{ UserPermissionEnum::PAYMENT_READ as u32 }>,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_READ>,
 ) -> Result<Json<i64>, ApiError> {
     let count = sqlx::query_scalar("SELECT COUNT(*) FROM payments")
         .fetch_one(&mut **db)
@@ -151,59 +492,429 @@ pub(super) async fn count(
     Ok(Json(count))
 }
 
-// #[rocket::post("/payments/list", data = "<list_request>")]
-// pub(super) async fn list(
-//     mut db: crate::db::DB,
-//     _auth: AuthGuard<{ UserPermissionEnum::EXPENSES_READ as u32 }>,
-//     list_request: rocket::serde::json::Json<ListRequest>,
-// ) -> Result<rocket::serde::json::Json<Vec<Payment>>, ApiError> {
-//     let list_request = list_request.into_inner();
-
-//     let mut current_param = 1;
-
-//     let sorts_sql = super::generate_sorts_string(&list_request.sorts);
-
-//     let (filters_sql, filter_binds) =
-//         super::generate_filters_string(&list_request.filters, &mut current_param);
-
-//     let query = format!(
-//         r#"SELECT 
-//             expenses.id, 
-//             date_time, 
-//             description, 
-//             row_to_json(users) AS created_by_user,
-//             amount
-//         FROM expenses
-//             INNER JOIN users ON expenses.created_by_user_id = users.id
-//         {}
-//         {}
-//         LIMIT ${}
-//         OFFSET ${}
-//         "#,
-//         filters_sql,
-//         sorts_sql,
-//         current_param,
-//         current_param + 1
-//     );
-
-//     let query = sqlx::query_as(&query);
-
-//     let query = filter_binds
-//         .into_iter()
-//         .fold(query, |query, value| value.bind_to_query_as(query));
-
-//     let query = query
-//         .bind(list_request.range.count)
-//         .bind(list_request.range.offset);
-
-//     let expenses: Vec<Expense> = query
-//         .fetch_all(&mut **db)
-//         .await
-//         .map_err(|e| match e {
-//             sqlx::Error::RowNotFound => ApiError(Status::NotFound, "No expenses found".to_string()),
-//             _ => ApiError(Status::InternalServerError, e.to_string()),
-//         })
-//         .map(|rows: Vec<ExpenseRow>| rows.into_iter().map(Expense::from).collect())?;
-
-//     Ok(rocket::serde::json::Json(expenses))
+// POST /payments/list [Permissions: PAYMENT_READ]
+// Request: ListRequest, e.g. payments from customer 3 by cash in March
+// {
+//     "range": { "count": 50, "offset": 0 },
+//     "sorts": [{ "column": "date_time", "order": "DESC" }],
+//     "filters": [
+//         { "column": "party_type", "operator": "=", "value": { "String": "CUSTOMER" } },
+//         { "column": "party_id", "operator": "=", "value": { "Int": 3 } },
+//         { "column": "method", "operator": "=", "value": { "String": "CASH" } },
+//         { "column": "date_time", "operator": "BETWEEN", "value": [
+//             { "DateTime": "2024-03-01T00:00:00Z" }, { "DateTime": "2024-04-01T00:00:00Z" }
+//         ] }
+//     ]
 // }
+// Response: ListResponse<Payment>
+#[rocket::post("/payments/list", data = "<req>")]
+pub(super) async fn list(
+    req: Json<ListRequest>,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_READ>,
+) -> Result<Json<ListResponse<Payment>>, ApiError> {
+    let req = req.into_inner();
+
+    let page = COLUMNS
+        .page::<PaymentRow, PaymentRow>(
+            ListQuery {
+                select: PAYMENT_SELECT,
+                from: PAYMENT_FROM,
+                key: "payments.id",
+                condition: None,
+            },
+            &req,
+            &mut db,
+        )
+        .await?;
+
+    let mut items = Vec::with_capacity(page.items.len());
+
+    for row in page.items {
+        items.push(Payment::from_row(row, &mut db).await?);
+    }
+
+    Ok(Json(ListResponse {
+        items,
+        total: page.total,
+        next_cursor: page.next_cursor,
+    }))
+}
+
+// POST /payments/export?format=xlsx [Permissions: PAYMENT_READ]
+// Request: ListRequest, the range is ignored and every matching row is exported
+// format is csv (default) or xlsx
+// -> 200 OK
+//     payments.csv or payments.xlsx
+// -> 400 Bad Request (invalid format, columns or filters)
+#[rocket::post("/payments/export?<format>", data = "<req>")]
+pub(super) async fn export(
+    format: Option<&str>,
+    req: Json<ListRequest>,
+    db: DB,
+    _auth: AuthGuard<perm::PAYMENT_READ>,
+) -> Result<Export, ApiError> {
+    let format = ExportFormat::parse(format)?;
+
+    let (rest, binds) = COLUMNS.export_string(
+        &ListQuery {
+            select: PAYMENT_SELECT,
+            from: PAYMENT_FROM,
+            key: "payments.id",
+            condition: None,
+        },
+        &req,
+        &mut 2,
+    )?;
+
+    Export::new(format, "payments", EXPORT_COLUMNS, rest, binds, db).await
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct PaymentPostRequest {
+    /// Defaults to now
+    #[serde(default)]
+    pub date_time: Option<chrono::DateTime<chrono::Utc>>,
+    pub amount: BigDecimal,
+    pub party_type: PartyType,
+    /// Id of the customer, supplier or retail order
+    pub party_id: i32,
+    pub transfer_type: TransferType,
+    pub method: PaymentMethod,
+    #[serde(default)]
+    pub method_details: String,
+    #[serde(default)]
+    pub notes: String,
+    /// Orders or purchases the payment is for, the rest stays unallocated
+    #[serde(default)]
+    pub allocations: Vec<PaymentAllocationRequest>,
+}
+
+// POST /payments [Permissions: PAYMENT_CREATE]
+// {
+//     "amount": "150.00",
+//     "party_type": "CUSTOMER",
+//     "party_id": 3,
+//     "transfer_type": "INCOMING",
+//     "method": "BANK",
+//     "method_details": "Transfer ref 1234" (optional),
+//     "notes": "" (optional),
+//     "date_time": "2024-03-01T10:00:00Z" (optional),
+//     "allocations": [{ "order_id": 12, "amount": "100.00" }] (optional)
+// }
+// -> 201 Created
+//     5 (id of the new payment)
+// -> 400 Bad Request
+#[rocket::post("/payments", data = "<req>")]
+pub(super) async fn post(
+    req: Json<PaymentPostRequest>,
+    mut db: DB,
+    auth: AuthGuard<perm::PAYMENT_CREATE>,
+) -> Result<ApiReturn<i32>, ApiError> {
+    let req = req.into_inner();
+
+    check_amount(&req.amount)?;
+
+    let mut transaction = db.begin().await?;
+
+    check_party(req.party_type, req.party_id, &mut transaction).await?;
+
+    let id: i32 = sqlx::query_scalar(
+        r#"
+        INSERT INTO payments (
+            date_time, amount, party_id, party_type, transfer_type,
+            method, method_details, notes, created_by_user_id
+        )
+        VALUES (COALESCE($1, CURRENT_TIMESTAMP), $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING id
+        "#,
+    )
+    .bind(req.date_time)
+    .bind(&req.amount)
+    .bind(req.party_id)
+    .bind(req.party_type)
+    .bind(req.transfer_type)
+    .bind(req.method)
+    .bind(&req.method_details)
+    .bind(&req.notes)
+    .bind(auth.auth_info.user.id)
+    .fetch_one(&mut *transaction)
+    .await
+    .map_err(map_payment_error)?;
+
+    for allocation in &req.allocations {
+        allocate(id, allocation, &mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(ApiReturn(Status::Created, id))
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct PaymentPatchRequest {
+    pub date_time: Option<chrono::DateTime<chrono::Utc>>,
+    pub amount: Option<BigDecimal>,
+    /// Checked together with party_id, so changing one has to fit the other
+    pub party_type: Option<PartyType>,
+    pub party_id: Option<i32>,
+    pub transfer_type: Option<TransferType>,
+    pub method: Option<PaymentMethod>,
+    pub method_details: Option<String>,
+    pub notes: Option<String>,
+}
+
+// PATCH /payments/<id> [Permissions: PAYMENT_UPDATE]
+// {
+//     "amount": "120.00",
+//     "method": "CASH"
+// }
+// -> 200 OK
+// -> 400 Bad Request (also when changing the party, transfer type or an amount below
+//    what is allocated, while the payment has allocations)
+// -> 404 Not Found
+#[rocket::patch("/payments/<id>", data = "<req>")]
+pub(super) async fn patch(
+    id: i32,
+    req: Json<PaymentPatchRequest>,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_UPDATE>,
+) -> Result<Status, ApiError> {
+    let req = req.into_inner();
+
+    if let Some(amount) = &req.amount {
+        check_amount(amount)?;
+    }
+
+    let mut transaction = db.begin().await?;
+
+    let current: Option<(PartyType, i32, TransferType, BigDecimal)> = sqlx::query_as(
+        r#"
+        SELECT party_type, party_id, transfer_type, (
+            SELECT COALESCE(SUM(amount), 0) FROM payment_allocations WHERE payment_id = payments.id
+        )
+        FROM payments
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(&mut *transaction)
+    .await?;
+
+    let Some((party_type, party_id, transfer_type, allocated)) = current else {
+        return Err(ApiError(
+            Status::NotFound,
+            format!("No payment with id {}", id),
+        ));
+    };
+
+    if allocated > BigDecimal::from(0) {
+        if req.party_type.is_some_and(|t| t != party_type)
+            || req.party_id.is_some_and(|p| p != party_id)
+            || req.transfer_type.is_some_and(|t| t != transfer_type)
+        {
+            return Err(ApiError(
+                Status::BadRequest,
+                "Remove the allocations of the payment before changing its party or transfer type"
+                    .to_string(),
+            ));
+        }
+
+        if req.amount.as_ref().is_some_and(|amount| *amount < allocated) {
+            return Err(ApiError(
+                Status::BadRequest,
+                format!("{} of the payment is already allocated", allocated),
+            ));
+        }
+    }
+
+    if req.party_type.is_some() || req.party_id.is_some() {
+        check_party(
+            req.party_type.unwrap_or(party_type),
+            req.party_id.unwrap_or(party_id),
+            &mut transaction,
+        )
+        .await?;
+    }
+
+    // Enums can't be bound as SqlType, so every column is set with a fallback to itself
+    sqlx::query(
+        r#"
+        UPDATE payments SET
+            date_time = COALESCE($1, date_time),
+            amount = COALESCE($2, amount),
+            party_type = COALESCE($3, party_type),
+            party_id = COALESCE($4, party_id),
+            transfer_type = COALESCE($5, transfer_type),
+            method = COALESCE($6, method),
+            method_details = COALESCE($7, method_details),
+            notes = COALESCE($8, notes)
+        WHERE id = $9
+        "#,
+    )
+    .bind(req.date_time)
+    .bind(&req.amount)
+    .bind(req.party_type)
+    .bind(req.party_id)
+    .bind(req.transfer_type)
+    .bind(req.method)
+    .bind(&req.method_details)
+    .bind(&req.notes)
+    .bind(id)
+    .execute(&mut *transaction)
+    .await
+    .map_err(map_payment_error)?;
+
+    transaction.commit().await?;
+
+    Ok(Status::Ok)
+}
+
+// DELETE /payments/<id> [Permissions: PAYMENT_DELETE]
+// -> 200 OK
+// -> 404 Not Found
+#[rocket::delete("/payments/<id>")]
+pub(super) async fn delete(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_DELETE>,
+) -> Result<Status, ApiError> {
+    let res = sqlx::query("DELETE FROM payments WHERE id = $1")
+        .bind(id)
+        .execute(&mut **db)
+        .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ApiError(
+            Status::NotFound,
+            format!("No payment with id {}", id),
+        ));
+    }
+
+    Ok(Status::Ok)
+}
+
+// GET /payments/<id>/allocations [Permissions: PAYMENT_READ]
+// -> 200 OK
+// [
+//     {
+//         "id": 1,
+//         "payment_id": 5,
+//         "order_id": 12,
+//         "purchase_id": null,
+//         "amount": "100.00"
+//     }
+// ]
+// -> 404 Not Found
+#[rocket::get("/payments/<id>/allocations")]
+pub(super) async fn get_allocations(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_READ>,
+) -> Result<Json<Vec<PaymentAllocation>>, ApiError> {
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM payments WHERE id = $1)")
+        .bind(id)
+        .fetch_one(&mut **db)
+        .await?;
+
+    if !exists {
+        return Err(ApiError(
+            Status::NotFound,
+            format!("No payment with id {}", id),
+        ));
+    }
+
+    let allocations = sqlx::query_as(
+        r#"
+        SELECT id, payment_id, order_id, purchase_id, amount
+        FROM payment_allocations
+        WHERE payment_id = $1
+        ORDER BY id
+        "#,
+    )
+    .bind(id)
+    .fetch_all(&mut **db)
+    .await?;
+
+    Ok(Json(allocations))
+}
+
+// POST /payments/<id>/allocations [Permissions: PAYMENT_UPDATE]
+// {
+//     "order_id": 12, (or "purchase_id")
+//     "amount": "100.00"
+// }
+// -> 201 Created
+//     1 (id of the new allocation)
+// -> 400 Bad Request
+// -> 404 Not Found
+#[rocket::post("/payments/<id>/allocations", data = "<req>")]
+pub(super) async fn post_allocation(
+    id: i32,
+    req: Json<PaymentAllocationRequest>,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_UPDATE>,
+) -> Result<ApiReturn<i32>, ApiError> {
+    let mut transaction = db.begin().await?;
+
+    let allocation_id = allocate(id, &req, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(ApiReturn(Status::Created, allocation_id))
+}
+
+// DELETE /payments/<id>/allocations/<allocation_id> [Permissions: PAYMENT_UPDATE]
+// -> 200 OK
+// -> 404 Not Found
+#[rocket::delete("/payments/<id>/allocations/<allocation_id>")]
+pub(super) async fn delete_allocation(
+    id: i32,
+    allocation_id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_UPDATE>,
+) -> Result<Status, ApiError> {
+    let res = sqlx::query("DELETE FROM payment_allocations WHERE id = $1 AND payment_id = $2")
+        .bind(allocation_id)
+        .bind(id)
+        .execute(&mut **db)
+        .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ApiError(
+            Status::NotFound,
+            format!("No allocation with id {} on payment {}", allocation_id, id),
+        ));
+    }
+
+    Ok(Status::Ok)
+}
+
+// GET /customers/<id>/credit [Permissions: PAYMENT_READ]
+// -> 200 OK
+//     "50.00" (unallocated payments minus refunds, negative if more was refunded)
+// -> 404 Not Found
+#[rocket::get("/customers/<id>/credit")]
+pub(super) async fn get_customer_credit(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::PAYMENT_READ>,
+) -> Result<Json<BigDecimal>, ApiError> {
+    let exists: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM customers WHERE id = $1)")
+            .bind(id)
+            .fetch_one(&mut **db)
+            .await?;
+
+    if !exists {
+        return Err(ApiError(
+            Status::NotFound,
+            format!("No customer with id {}", id),
+        ));
+    }
+
+    Ok(Json(customer_credit(id, &mut db).await?))
+}
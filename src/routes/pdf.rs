@@ -0,0 +1,946 @@
+//! Writes PDF documents with text in the standard Helvetica fonts, lines and images.
+//!
+//! The standard fonts are built into every PDF reader, so nothing is embedded and text
+//! widths come from the tables below. Text is encoded as Windows-1252, other characters
+//! are drawn as `?`. Nothing in the file depends on when it was written, so the same
+//! document always gives the same bytes.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rocket::{
+    http::ContentType,
+    response::{self, Responder},
+    Request,
+};
+
+/// A4 in points
+pub(super) const PAGE_WIDTH: f32 = 595.28;
+pub(super) const PAGE_HEIGHT: f32 = 841.89;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Helvetica glyph widths of the WinAnsi codes 32 to 255, in 1/1000 of the font size
+const HELVETICA_WIDTHS: [u16; 224] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584, 350,
+    556, 350, 222, 556, 333, 1000, 556, 556, 333, 1000, 667, 333, 1000, 350, 611, 350,
+    350, 222, 222, 333, 333, 350, 556, 1000, 333, 1000, 500, 333, 944, 350, 500, 667,
+    278, 333, 556, 556, 556, 556, 260, 556, 333, 737, 370, 556, 584, 333, 737, 333,
+    400, 584, 333, 333, 333, 556, 537, 278, 333, 333, 365, 556, 834, 834, 834, 611,
+    667, 667, 667, 667, 667, 667, 1000, 722, 667, 667, 667, 667, 278, 278, 278, 278,
+    722, 722, 778, 778, 778, 778, 778, 584, 778, 722, 722, 722, 722, 667, 667, 611,
+    556, 556, 556, 556, 556, 556, 889, 500, 556, 556, 556, 556, 278, 278, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 584, 611, 556, 556, 556, 556, 500, 556, 500,
+];
+
+const HELVETICA_BOLD_WIDTHS: [u16; 224] = [
+    278, 333, 474, 556, 556, 889, 722, 238, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 333, 333, 584, 584, 584, 611,
+    975, 722, 722, 722, 722, 667, 611, 778, 722, 278, 556, 722, 611, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 333, 278, 333, 584, 556,
+    333, 556, 611, 556, 611, 556, 333, 611, 611, 278, 278, 556, 278, 889, 611, 611,
+    611, 611, 389, 556, 333, 611, 556, 778, 556, 556, 500, 389, 280, 389, 584, 350,
+    556, 350, 278, 556, 500, 1000, 556, 556, 333, 1000, 667, 333, 1000, 350, 611, 350,
+    350, 278, 278, 500, 500, 350, 556, 1000, 333, 1000, 556, 333, 944, 350, 500, 667,
+    278, 333, 556, 556, 556, 556, 280, 556, 333, 737, 370, 556, 584, 333, 737, 333,
+    400, 584, 333, 333, 333, 611, 556, 278, 333, 333, 365, 556, 834, 834, 834, 611,
+    722, 722, 722, 722, 722, 722, 1000, 722, 667, 667, 667, 667, 278, 278, 278, 278,
+    722, 722, 778, 778, 778, 778, 778, 584, 778, 722, 722, 722, 722, 667, 667, 611,
+    556, 556, 556, 556, 556, 556, 889, 556, 556, 556, 556, 556, 278, 278, 278, 278,
+    611, 611, 611, 611, 611, 611, 611, 584, 611, 611, 611, 611, 611, 556, 611, 556,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn resource_name(self) -> &'static str {
+        match self {
+            Self::Regular => "F1",
+            Self::Bold => "F2",
+        }
+    }
+
+    fn widths(self) -> &'static [u16; 224] {
+        match self {
+            Self::Regular => &HELVETICA_WIDTHS,
+            Self::Bold => &HELVETICA_BOLD_WIDTHS,
+        }
+    }
+
+    /// Width of `text` in points
+    pub fn width(self, text: &str, size: f32) -> f32 {
+        let units: u32 = text
+            .chars()
+            .map(|c| self.widths()[(win_ansi(c) - 32) as usize] as u32)
+            .sum();
+
+        units as f32 * size / 1000.0
+    }
+
+    /// Splits `text` into lines no wider than `max_width`, at spaces where possible.
+    /// Newlines in the text are kept.
+    pub fn wrap(self, text: &str, size: f32, max_width: f32) -> Vec<String> {
+        let mut lines = vec![];
+
+        for paragraph in text.lines() {
+            let mut line = String::new();
+
+            for word in paragraph.split_whitespace() {
+                let candidate = if line.is_empty() {
+                    word.to_string()
+                } else {
+                    format!("{} {}", line, word)
+                };
+
+                if self.width(&candidate, size) <= max_width {
+                    line = candidate;
+                    continue;
+                }
+
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+
+                // Words longer than a line are broken anywhere
+                for c in word.chars() {
+                    line.push(c);
+
+                    if self.width(&line, size) > max_width && line.chars().count() > 1 {
+                        line.pop();
+                        lines.push(std::mem::replace(&mut line, c.to_string()));
+                    }
+                }
+            }
+
+            lines.push(line);
+        }
+
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+
+        lines
+    }
+}
+
+/// The Windows-1252 code of `c`, `?` if it has none or can't be printed
+fn win_ansi(c: char) -> u8 {
+    match c {
+        ' '..='~' => c as u8,
+        '\u{A0}'..='\u{FF}' => c as u8,
+        '\u{20AC}' => 128,
+        '\u{201A}' => 130,
+        '\u{0192}' => 131,
+        '\u{201E}' => 132,
+        '\u{2026}' => 133,
+        '\u{2020}' => 134,
+        '\u{2021}' => 135,
+        '\u{02C6}' => 136,
+        '\u{2030}' => 137,
+        '\u{0160}' => 138,
+        '\u{2039}' => 139,
+        '\u{0152}' => 140,
+        '\u{017D}' => 142,
+        '\u{2018}' => 145,
+        '\u{2019}' => 146,
+        '\u{201C}' => 147,
+        '\u{201D}' => 148,
+        '\u{2022}' => 149,
+        '\u{2013}' => 150,
+        '\u{2014}' => 151,
+        '\u{02DC}' => 152,
+        '\u{2122}' => 153,
+        '\u{0161}' => 154,
+        '\u{203A}' => 155,
+        '\u{0153}' => 156,
+        '\u{017E}' => 158,
+        '\u{0178}' => 159,
+        _ => b'?',
+    }
+}
+
+/// A PDF literal string, with the bytes outside printable ASCII escaped
+fn pdf_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('(');
+
+    for byte in text.chars().map(win_ansi) {
+        match byte {
+            b'(' | b')' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(byte as char);
+            }
+            b' '..=b'~' => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+
+    escaped.push(')');
+    escaped
+}
+
+/// Coordinates and sizes with at most two decimals, so they print the same everywhere
+fn number(value: f32) -> String {
+    let formatted = format!("{:.2}", value);
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+
+    match trimmed {
+        "-0" => "0".to_string(),
+        _ => trimmed.to_string(),
+    }
+}
+
+/// Index of an image added to a `Document`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct ImageId(usize);
+
+/// Drawing operations of one page. The origin is the bottom left corner.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Page {
+    content: String,
+}
+
+impl Page {
+    /// Draws `text` with its baseline starting at `x`, `y`. `gray` goes from 0 (black) to 1.
+    pub fn text(&mut self, x: f32, y: f32, font: Font, size: f32, gray: f32, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        self.content.push_str(&format!(
+            "BT /{} {} Tf {} g {} {} Td {} Tj ET\n",
+            font.resource_name(),
+            number(size),
+            number(gray),
+            number(x),
+            number(y),
+            pdf_string(text),
+        ));
+    }
+
+    /// Draws `text` ending at `x`
+    pub fn text_right(&mut self, x: f32, y: f32, font: Font, size: f32, gray: f32, text: &str) {
+        self.text(x - font.width(text, size), y, font, size, gray, text);
+    }
+
+    /// Draws `text` centered on `x`
+    pub fn text_center(&mut self, x: f32, y: f32, font: Font, size: f32, gray: f32, text: &str) {
+        self.text(x - font.width(text, size) / 2.0, y, font, size, gray, text);
+    }
+
+    pub fn line(&mut self, from: (f32, f32), to: (f32, f32), width: f32, gray: f32) {
+        self.content.push_str(&format!(
+            "{} G {} w {} {} m {} {} l S\n",
+            number(gray),
+            number(width),
+            number(from.0),
+            number(from.1),
+            number(to.0),
+            number(to.1),
+        ));
+    }
+
+    pub fn dashed_line(&mut self, from: (f32, f32), to: (f32, f32), width: f32, gray: f32) {
+        self.content.push_str("[2 2] 0 d\n");
+        self.line(from, to, width, gray);
+        self.content.push_str("[] 0 d\n");
+    }
+
+    /// Draws `image` stretched over the rectangle with its bottom left corner at `x`, `y`
+    pub fn image(&mut self, image: ImageId, x: f32, y: f32, width: f32, height: f32) {
+        self.content.push_str(&format!(
+            "q {} 0 0 {} {} {} cm /Im{} Do Q\n",
+            number(width),
+            number(height),
+            number(x),
+            number(y),
+            image.0,
+        ));
+    }
+}
+
+/// A JPEG or PNG image, with its samples in the form PDF expects
+#[derive(Debug, Clone)]
+pub(super) struct Image {
+    pub width: u32,
+    pub height: u32,
+    color_space: &'static str,
+    filter: &'static str,
+    data: Vec<u8>,
+    /// Adobe CMYK JPEGs are stored inverted
+    inverted: bool,
+    /// Zlib compressed 8 bit alpha samples
+    alpha: Option<Vec<u8>>,
+}
+
+impl Image {
+    /// Reads an image setting like `logo_high_resolution`. None if it isn't a base64 JPEG or
+    /// PNG, or uses something not supported here like interlacing.
+    pub fn from_data_uri(uri: &str) -> Option<Self> {
+        let (_, encoded) = uri.split_once(";base64,")?;
+        let bytes = STANDARD.decode(encoded.trim()).ok()?;
+
+        // The type in the URI isn't checked when the setting is saved, so the bytes decide
+        if bytes.starts_with(&[0xFF, 0xD8]) {
+            Self::from_jpeg(bytes)
+        } else if bytes.starts_with(PNG_SIGNATURE) {
+            Self::from_png(&bytes)
+        } else {
+            None
+        }
+    }
+
+    /// JPEGs are embedded as they are, only the frame header is read
+    fn from_jpeg(bytes: Vec<u8>) -> Option<Self> {
+        let mut position = 2;
+
+        loop {
+            if *bytes.get(position)? != 0xFF {
+                return None;
+            }
+
+            let marker = *bytes.get(position + 1)?;
+
+            // Fill bytes and markers without a length
+            if marker == 0xFF {
+                position += 1;
+                continue;
+            }
+            if marker == 0x01 || (0xD0..=0xD7).contains(&marker) {
+                position += 2;
+                continue;
+            }
+
+            let length = u16::from_be_bytes([*bytes.get(position + 2)?, *bytes.get(position + 3)?])
+                as usize;
+
+            // Start of frame, except DHT, JPG and DAC which share the range
+            if (0xC0..=0xCF).contains(&marker) && ![0xC4, 0xC8, 0xCC].contains(&marker) {
+                let header = bytes.get(position + 4..position + 10)?;
+
+                if header[0] != 8 {
+                    return None;
+                }
+
+                let height = u16::from_be_bytes([header[1], header[2]]) as u32;
+                let width = u16::from_be_bytes([header[3], header[4]]) as u32;
+                let (color_space, inverted) = match header[5] {
+                    1 => ("DeviceGray", false),
+                    3 => ("DeviceRGB", false),
+                    4 => ("DeviceCMYK", true),
+                    _ => return None,
+                };
+
+                return Some(Self {
+                    width,
+                    height,
+                    color_space,
+                    filter: "DCTDecode",
+                    data: bytes,
+                    inverted,
+                    alpha: None,
+                });
+            }
+
+            // Start of scan without a frame
+            if marker == 0xDA {
+                return None;
+            }
+
+            position += 2 + length;
+        }
+    }
+
+    /// PNGs are decoded and split into 8 bit color and alpha samples
+    fn from_png(bytes: &[u8]) -> Option<Self> {
+        let mut position = PNG_SIGNATURE.len();
+        let mut header = None;
+        let mut palette: &[u8] = &[];
+        let mut transparency: &[u8] = &[];
+        let mut compressed = vec![];
+
+        while position + 8 <= bytes.len() {
+            let length = u32::from_be_bytes(bytes[position..position + 4].try_into().ok()?) as usize;
+            let kind = &bytes[position + 4..position + 8];
+            let data = bytes.get(position + 8..position + 8 + length)?;
+
+            match kind {
+                b"IHDR" => header = Some(data),
+                b"PLTE" => palette = data,
+                b"tRNS" => transparency = data,
+                b"IDAT" => compressed.extend_from_slice(data),
+                b"IEND" => break,
+                _ => {}
+            }
+
+            // Length, type, data and CRC
+            position += 12 + length;
+        }
+
+        let header = header.filter(|header| header.len() == 13)?;
+        let width = u32::from_be_bytes(header[0..4].try_into().ok()?);
+        let height = u32::from_be_bytes(header[4..8].try_into().ok()?);
+        let depth = header[8] as usize;
+        let color_type = header[9];
+
+        if header[12] != 0 || width == 0 || height == 0 {
+            return None;
+        }
+
+        let channels = match (color_type, depth) {
+            (0, 1 | 2 | 4 | 8 | 16) => 1,
+            (2, 8 | 16) => 3,
+            (3, 1 | 2 | 4 | 8) => 1,
+            (4, 8 | 16) => 2,
+            (6, 8 | 16) => 4,
+            _ => return None,
+        };
+
+        let raw = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).ok()?;
+        let samples = unfilter_png(&raw, width as usize, height as usize, channels * depth)?;
+
+        let pixels = width as usize * height as usize;
+        let mut color = Vec::with_capacity(pixels * 3);
+        let mut alpha = Vec::with_capacity(pixels);
+        let row_bytes = (width as usize * channels * depth).div_ceil(8);
+
+        for row in samples.chunks(row_bytes) {
+            for x in 0..width as usize {
+                // Samples of the pixel reduced to 8 bits, or the palette index
+                let sample = |channel: usize| -> u8 {
+                    let index = x * channels + channel;
+
+                    match depth {
+                        16 => row[index * 2],
+                        8 => row[index],
+                        _ => {
+                            let bit = index * depth;
+                            let value = (row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1);
+
+                            if color_type == 3 {
+                                value
+                            } else {
+                                (value as u32 * 255 / ((1 << depth) - 1)) as u8
+                            }
+                        }
+                    }
+                };
+
+                match color_type {
+                    0 => {
+                        color.push(sample(0));
+                        alpha.push(255);
+                    }
+                    2 => {
+                        color.extend_from_slice(&[sample(0), sample(1), sample(2)]);
+                        alpha.push(255);
+                    }
+                    3 => {
+                        let index = sample(0) as usize;
+                        color.extend_from_slice(palette.get(index * 3..index * 3 + 3)?);
+                        alpha.push(transparency.get(index).copied().unwrap_or(255));
+                    }
+                    4 => {
+                        color.push(sample(0));
+                        alpha.push(sample(1));
+                    }
+                    _ => {
+                        color.extend_from_slice(&[sample(0), sample(1), sample(2)]);
+                        alpha.push(sample(3));
+                    }
+                }
+            }
+        }
+
+        let color_space = if matches!(color_type, 0 | 4) {
+            "DeviceGray"
+        } else {
+            "DeviceRGB"
+        };
+
+        Some(Self {
+            width,
+            height,
+            color_space,
+            filter: "FlateDecode",
+            data: miniz_oxide::deflate::compress_to_vec_zlib(&color, 6),
+            inverted: false,
+            alpha: alpha
+                .iter()
+                .any(|a| *a != 255)
+                .then(|| miniz_oxide::deflate::compress_to_vec_zlib(&alpha, 6)),
+        })
+    }
+}
+
+/// Reverses the PNG row filters, returning the rows without their filter type byte
+fn unfilter_png(raw: &[u8], width: usize, height: usize, bits_per_pixel: usize) -> Option<Vec<u8>> {
+    let row_bytes = (width * bits_per_pixel).div_ceil(8);
+    // Distance to the same byte of the previous pixel
+    let step = (bits_per_pixel / 8).max(1);
+
+    if raw.len() < height * (row_bytes + 1) {
+        return None;
+    }
+
+    let mut rows = vec![0u8; height * row_bytes];
+
+    for y in 0..height {
+        let filter = raw[y * (row_bytes + 1)];
+        let line = &raw[y * (row_bytes + 1) + 1..(y + 1) * (row_bytes + 1)];
+        let (previous, current) = rows.split_at_mut(y * row_bytes);
+        let above = if y == 0 {
+            None
+        } else {
+            Some(&previous[(y - 1) * row_bytes..])
+        };
+        let current = &mut current[..row_bytes];
+
+        for i in 0..row_bytes {
+            let a = if i >= step { current[i - step] } else { 0 };
+            let b = above.map_or(0, |above| above[i]);
+            let c = match above {
+                Some(above) if i >= step => above[i - step],
+                _ => 0,
+            };
+
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => {
+                    let p = a as i16 + b as i16 - c as i16;
+                    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+
+                    if pa <= pb && pa <= pc {
+                        a
+                    } else if pb <= pc {
+                        b
+                    } else {
+                        c
+                    }
+                }
+                _ => return None,
+            };
+
+            current[i] = line[i].wrapping_add(predicted);
+        }
+    }
+
+    Some(rows)
+}
+
+/// Pages and the images they draw, written out by `finish`
+#[derive(Debug, Clone, Default)]
+pub(super) struct Document {
+    pub pages: Vec<Page>,
+    images: Vec<Image>,
+}
+
+impl Document {
+    pub fn add_image(&mut self, image: Image) -> ImageId {
+        self.images.push(image);
+        ImageId(self.images.len() - 1)
+    }
+
+    pub fn finish(self, title: &str) -> Vec<u8> {
+        let mut writer = ObjectWriter::default();
+
+        // 1 is the catalog, 2 the page tree, 3 the document info and 4 and 5 the fonts
+        let mut next_id = 6;
+        let mut image_ids = vec![];
+
+        for image in &self.images {
+            let alpha_id = image.alpha.as_ref().map(|_| next_id + 1);
+            image_ids.push((next_id, alpha_id));
+            next_id += 1 + alpha_id.map_or(0, |_| 1);
+        }
+
+        let page_ids = (0..self.pages.len())
+            .map(|i| next_id + i * 2)
+            .collect::<Vec<_>>();
+
+        writer.object(1, "<< /Type /Catalog /Pages 2 0 R >>");
+        writer.object(
+            2,
+            &format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                page_ids
+                    .iter()
+                    .map(|id| format!("{} 0 R", id))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                page_ids.len(),
+            ),
+        );
+        writer.object(3, &format!("<< /Title {} >>", pdf_string(title)));
+        writer.object(
+            4,
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>",
+        );
+        writer.object(
+            5,
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>",
+        );
+
+        for (image, (id, alpha_id)) in self.images.iter().zip(&image_ids) {
+            let mut dictionary = format!(
+                "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /{} /BitsPerComponent 8 /Filter /{}",
+                image.width, image.height, image.color_space, image.filter,
+            );
+
+            if image.inverted {
+                dictionary.push_str(" /Decode [1 0 1 0 1 0 1 0]");
+            }
+            if let Some(alpha_id) = alpha_id {
+                dictionary.push_str(&format!(" /SMask {} 0 R", alpha_id));
+            }
+
+            writer.stream(*id, &dictionary, &image.data);
+
+            if let (Some(alpha_id), Some(alpha)) = (alpha_id, &image.alpha) {
+                writer.stream(
+                    *alpha_id,
+                    &format!(
+                        "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceGray /BitsPerComponent 8 /Filter /FlateDecode",
+                        image.width, image.height,
+                    ),
+                    alpha,
+                );
+            }
+        }
+
+        let mut resources = "<< /Font << /F1 4 0 R /F2 5 0 R >>".to_string();
+
+        if !image_ids.is_empty() {
+            resources.push_str(&format!(
+                " /XObject << {}>>",
+                image_ids
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (id, _))| format!("/Im{} {} 0 R ", i, id))
+                    .collect::<String>(),
+            ));
+        }
+
+        resources.push_str(" >>");
+
+        for (page, id) in self.pages.iter().zip(&page_ids) {
+            writer.object(
+                *id,
+                &format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources {} /Contents {} 0 R >>",
+                    number(PAGE_WIDTH),
+                    number(PAGE_HEIGHT),
+                    resources,
+                    id + 1,
+                ),
+            );
+            writer.stream(id + 1, "<<", page.content.as_bytes());
+        }
+
+        writer.finish(3)
+    }
+}
+
+/// Keeps the offset of every object for the cross-reference table
+#[derive(Debug, Default)]
+struct ObjectWriter {
+    bytes: Vec<u8>,
+    offsets: Vec<(usize, usize)>,
+}
+
+impl ObjectWriter {
+    fn object(&mut self, id: usize, body: &str) {
+        self.start(id);
+        self.bytes.extend_from_slice(body.as_bytes());
+        self.bytes.extend_from_slice(b"\nendobj\n");
+    }
+
+    /// `dictionary` is left open so the length can be added
+    fn stream(&mut self, id: usize, dictionary: &str, data: &[u8]) {
+        self.start(id);
+        self.bytes.extend_from_slice(
+            format!("{} /Length {} >>\nstream\n", dictionary, data.len()).as_bytes(),
+        );
+        self.bytes.extend_from_slice(data);
+        self.bytes.extend_from_slice(b"\nendstream\nendobj\n");
+    }
+
+    fn start(&mut self, id: usize) {
+        if self.bytes.is_empty() {
+            // The second line marks the file as binary
+            self.bytes.extend_from_slice(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n");
+        }
+
+        self.offsets.push((id, self.bytes.len()));
+        self.bytes
+            .extend_from_slice(format!("{} 0 obj\n", id).as_bytes());
+    }
+
+    fn finish(mut self, info_id: usize) -> Vec<u8> {
+        self.offsets.sort();
+
+        let xref_offset = self.bytes.len();
+        let mut xref = format!(
+            "xref\n0 {}\n0000000000 65535 f \n",
+            self.offsets.len() + 1
+        );
+
+        for (_, offset) in &self.offsets {
+            xref.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+
+        xref.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R /Info {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.offsets.len() + 1,
+            info_id,
+            xref_offset,
+        ));
+
+        self.bytes.extend_from_slice(xref.as_bytes());
+        self.bytes
+    }
+}
+
+/// A PDF shown in the browser, with a file name for when it's saved
+pub(super) struct PdfFile {
+    pub filename: String,
+    pub bytes: Vec<u8>,
+}
+
+impl<'r> Responder<'r, 'static> for PdfFile {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = self.bytes.respond_to(req)?;
+
+        response.set_header(ContentType::PDF);
+        response.set_raw_header(
+            "Content-Disposition",
+            format!("inline; filename=\"{}\"", self.filename),
+        );
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_breaks_at_spaces() {
+        // "aaa" is 16.68 points wide at 10 points, "aaaa" 22.24
+        assert_eq!(Font::Regular.wrap("aaa aaa", 10.0, 17.0), ["aaa", "aaa"]);
+        assert_eq!(Font::Regular.wrap("a a a", 10.0, 17.0), ["a a", "a"]);
+        assert_eq!(Font::Regular.wrap("  a   a  ", 10.0, 100.0), ["a a"]);
+    }
+
+    #[test]
+    fn wrap_breaks_long_words_anywhere() {
+        assert_eq!(
+            Font::Regular.wrap("aaaaaaaaaa", 10.0, 17.0),
+            ["aaa", "aaa", "aaa", "a"]
+        );
+        // The word starts on a new line and the next one follows its last piece
+        assert_eq!(
+            Font::Regular.wrap("ab aaaaaaa b", 10.0, 17.0),
+            ["ab", "aaa", "aaa", "a b"]
+        );
+        // A character wider than the line still gets one
+        assert_eq!(Font::Bold.wrap("WW", 10.0, 1.0), ["W", "W"]);
+
+        for line in Font::Bold.wrap("Supercalifragilisticexpialidocious", 9.0, 40.0) {
+            assert!(Font::Bold.width(&line, 9.0) <= 40.0, "{} is too wide", line);
+        }
+    }
+
+    #[test]
+    fn wrap_keeps_newlines() {
+        assert_eq!(Font::Regular.wrap("a\n\nb", 10.0, 100.0), ["a", "", "b"]);
+        assert_eq!(Font::Regular.wrap("", 10.0, 100.0), [""]);
+    }
+
+    #[test]
+    fn width_uses_win_ansi_metrics() {
+        assert_eq!(Font::Regular.width("a", 10.0), 5.56);
+        assert_eq!(Font::Bold.width("a", 10.0), 5.56);
+        assert_eq!(Font::Regular.width("W", 10.0), 9.44);
+        // Characters without a code are measured as the ? they print as
+        assert_eq!(Font::Regular.width("\u{6F22}", 10.0), Font::Regular.width("?", 10.0));
+    }
+
+    #[test]
+    fn pdf_string_escapes_delimiters() {
+        assert_eq!(pdf_string("plain"), "(plain)");
+        assert_eq!(pdf_string(r"(a) \ b"), r"(\(a\) \\ b)");
+        assert_eq!(pdf_string(""), "()");
+    }
+
+    #[test]
+    fn pdf_string_encodes_win_ansi() {
+        assert_eq!(pdf_string("caf\u{E9}"), r"(caf\351)");
+        assert_eq!(pdf_string("\u{20AC}5"), r"(\2005)");
+        assert_eq!(pdf_string("\u{201C}q\u{201D}"), r"(\223q\224)");
+        assert_eq!(pdf_string("\u{A0}"), r"(\240)");
+        // No WinAnsi code, or a control character
+        assert_eq!(pdf_string("\u{6F22}\u{5B57}"), "(??)");
+        assert_eq!(pdf_string("a\tb\n"), "(a?b?)");
+        assert_eq!(pdf_string("\u{1F600}"), "(?)");
+    }
+
+    #[test]
+    fn numbers_have_at_most_two_decimals() {
+        assert_eq!(number(0.0), "0");
+        assert_eq!(number(-0.001), "0");
+        assert_eq!(number(12.0), "12");
+        assert_eq!(number(12.5), "12.5");
+        assert_eq!(number(595.28), "595.28");
+        assert_eq!(number(1.005), "1");
+        assert_eq!(number(-3.256), "-3.26");
+    }
+
+    /// Applies PNG filter `filter` to every row, the inverse of `unfilter_png`
+    fn filter_png(rows: &[u8], row_bytes: usize, step: usize, filter: u8) -> Vec<u8> {
+        let mut raw = vec![];
+
+        for (y, row) in rows.chunks(row_bytes).enumerate() {
+            raw.push(filter);
+
+            for i in 0..row_bytes {
+                let a = if i >= step { row[i - step] as i16 } else { 0 };
+                let b = if y > 0 { rows[(y - 1) * row_bytes + i] as i16 } else { 0 };
+                let c = if y > 0 && i >= step {
+                    rows[(y - 1) * row_bytes + i - step] as i16
+                } else {
+                    0
+                };
+
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => (a + b) / 2,
+                    _ => {
+                        let p = a + b - c;
+
+                        if (p - a).abs() <= (p - b).abs() && (p - a).abs() <= (p - c).abs() {
+                            a
+                        } else if (p - b).abs() <= (p - c).abs() {
+                            b
+                        } else {
+                            c
+                        }
+                    }
+                };
+
+                raw.push(row[i].wrapping_sub(predicted as u8));
+            }
+        }
+
+        raw
+    }
+
+    #[test]
+    fn unfilter_png_reverses_every_filter() {
+        // 3 by 3 RGB pixels, with values that make the Paeth predictor pick each neighbour
+        let rows: Vec<u8> = (0..27u32).map(|i| (i * 97 % 256) as u8).collect();
+
+        for filter in 0..=4 {
+            let raw = filter_png(&rows, 9, 3, filter);
+            assert_eq!(
+                unfilter_png(&raw, 3, 3, 24).as_deref(),
+                Some(rows.as_slice()),
+                "filter type {}",
+                filter
+            );
+        }
+    }
+
+    #[test]
+    fn unfilter_png_known_rows() {
+        // One gray byte per pixel: none, sub, up, average and Paeth
+        let raw = [
+            0, 10, 20, 30, //
+            1, 10, 10, 10, //
+            2, 1, 2, 3, //
+            3, 5, 5, 5, //
+            4, 1, 1, 1, //
+        ];
+
+        assert_eq!(
+            unfilter_png(&raw, 3, 5, 8),
+            Some(vec![
+                10, 20, 30, //
+                10, 20, 30, //
+                11, 22, 33, //
+                10, 21, 32, //
+                11, 22, 33, //
+            ])
+        );
+    }
+
+    #[test]
+    fn unfilter_png_packed_pixels() {
+        // 1 bit pixels, a row of 12 is two bytes and sub uses the byte before
+        let rows = [0b1010_1010, 0b1111_0000, 0b0101_0101, 0b0000_1111];
+
+        for filter in 0..=4 {
+            let raw = filter_png(&rows, 2, 1, filter);
+            assert_eq!(unfilter_png(&raw, 12, 2, 1).as_deref(), Some(&rows[..]));
+        }
+    }
+
+    #[test]
+    fn unfilter_png_rejects_bad_data() {
+        assert_eq!(unfilter_png(&[5, 0, 0, 0], 1, 1, 24), None);
+        assert_eq!(unfilter_png(&[0, 0, 0], 1, 1, 24), None);
+        assert_eq!(unfilter_png(&[0, 0, 0, 0], 1, 2, 24), None);
+    }
+
+    #[test]
+    fn cross_reference_table_points_at_objects() {
+        let mut document = Document::default();
+        let mut page = Page::default();
+        page.text(10.0, 10.0, Font::Regular, 12.0, 0.0, "Hello (world)");
+        document.pages.push(page);
+        document.pages.push(Page::default());
+
+        let bytes = document.finish("Test");
+        // Everything from the objects' content on is ASCII, the second line isn't
+        let text = String::from_utf8_lossy(&bytes);
+
+        assert!(bytes.starts_with(b"%PDF-1.4\n"));
+        assert!(text.ends_with("%%EOF\n"));
+
+        let start: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .and_then(|rest| rest.lines().next())
+            .and_then(|offset| offset.parse().ok())
+            .unwrap();
+        let xref = std::str::from_utf8(&bytes[start..]).unwrap();
+        assert!(xref.starts_with("xref\n0 10\n"));
+
+        // Catalog, pages, info, two fonts and a page and its content for each page
+        for (id, entry) in (1..).zip(xref.lines().skip(3).take(9)) {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(
+                bytes[offset..].starts_with(format!("{} 0 obj\n", id).as_bytes()),
+                "object {} isn't at {}",
+                id,
+                offset
+            );
+        }
+
+        assert!(text.contains("<< /Title (Test) >>"));
+        assert!(text.contains("(Hello \\(world\\)) Tj"));
+    }
+}
@@ -1,6 +1,11 @@
 use crate::{
     db::DB,
-    routes::{inventory::count_impl, search::SearchRequest, ApiError, ListRequest},
+    routes::{
+        inventory::count_impl,
+        query::{Column, Columns, ListQuery},
+        search::SearchRequest,
+        ApiError, ListRequest, ListResponse,
+    },
 };
 use bigdecimal::BigDecimal;
 use rocket::{http::Status, serde::json::Json};
@@ -24,51 +29,36 @@ pub(super) struct PublicInventoryItem {
     pub quantity_per_box: i32,
 }
 
+/// Only the columns that are shown publicly, so stock can't be inferred from sorting or filtering
+const COLUMNS: Columns = Columns(&[
+    Column::new("id"),
+    Column::new("name"),
+    Column::new("price"),
+    Column::new("quantity_per_box"),
+]);
+
 #[rocket::post("/inventory/list", data = "<req>")]
 pub(super) async fn list(
     mut db: DB,
     req: Json<ListRequest>,
-) -> Result<Json<Vec<PublicInventoryItem>>, ApiError> {
+) -> Result<Json<ListResponse<PublicInventoryItem>>, ApiError> {
     let req = req.into_inner();
 
-    let mut current_param = 1;
-
-    let sorts_string = super::generate_sorts_string(&req.sorts);
-
-    let (filters_string, filter_binds) =
-        super::generate_filters_string(&req.filters, &mut current_param);
-
-    // TODO: Can they sort by stock even if it's not queried?
-    let query_str = format!(
-        r#"
-        SELECT id, name, price, quantity_per_box FROM inventory
-        {}
-        {}
-        LIMIT ${}
-        OFFSET ${}
-        "#,
-        filters_string,
-        sorts_string,
-        current_param,
-        current_param + 1
-    );
-
-    let query = sqlx::query_as(&query_str);
-
-    let query = filter_binds
-        .into_iter()
-        .fold(query, |query, value| value.bind_to_query_as(query));
-
-    let query = query.bind(req.range.count).bind(req.range.offset);
-
-    let data: Vec<PublicInventoryItem> = query.fetch_all(&mut **db).await.map_err(|e| match e {
-        sqlx::Error::ColumnNotFound(column) => {
-            ApiError(Status::BadRequest, format!("Column not found: {}", column))
-        }
-        _ => e.into(),
-    })?;
-
-    Ok(Json(data))
+    let page = COLUMNS
+        .page::<PublicInventoryItem, _>(
+            ListQuery {
+                select: "id, name, price, quantity_per_box",
+                from: "inventory",
+                key: "id",
+                // Archived items are never public
+                condition: Some("archived_at IS NULL"),
+            },
+            &req,
+            &mut db,
+        )
+        .await?;
+
+    Ok(Json(page))
 }
 
 #[rocket::get("/inventory/<id>")]
@@ -76,7 +66,7 @@ pub(super) async fn get(id: i32, mut db: DB) -> Result<Json<PublicInventoryItem>
     let item = sqlx::query_as(
         r#"
         SELECT id, name, price, quantity_per_box FROM inventory
-        WHERE id = $1
+        WHERE id = $1 AND archived_at IS NULL
         "#,
     )
     .bind(id)
@@ -99,15 +89,13 @@ pub(super) async fn search(
 ) -> Result<Json<Vec<PublicInventoryItem>>, ApiError> {
     let req = req.into_inner();
 
-    let x = req
-        .column
-        .unwrap_or(req.nested_access.unwrap_or("id".to_string()));
+    let x = COLUMNS.search_column(&req)?;
 
     let query_str = format!(
         r#"
         SELECT *, word_similarity($1, {}::text) AS sml
         FROM inventory
-        WHERE $1 <% {}::text
+        WHERE $1 <% {}::text AND archived_at IS NULL
         ORDER BY sml DESC, {}::text
         LIMIT $2
         "#,
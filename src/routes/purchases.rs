@@ -5,15 +5,18 @@ use sqlx::{Acquire, FromRow};
 use crate::{
     db::{FromDB, DB},
     routes::SqlType,
-    types::permissions::UserPermissionEnum,
+    types::permissions::perm,
 };
 
 use super::{
-    auth::{AuthCookie, AuthGuard, User, UserRow},
+    auth::{AuthInfo, AuthGuard, User, UserRow},
+    export::{Export, ExportColumn, ExportFormat, ExportKind},
+    payments,
     public::InventoryItem,
+    query::{Column, Columns, ListQuery},
     search::SearchRequest,
     suppliers::Supplier,
-    ApiError, ApiReturn, StockUpdate, StockUpdateFactory,
+    ApiError, ApiReturn, ListResponse, StockUpdate, StockUpdateFactory,
 };
 
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS, FromRow)]
@@ -23,10 +26,53 @@ pub(super) struct PurchaseMeta {
     pub created_by_user: User,
     pub supplier: Supplier,
     pub date_time: sqlx::types::chrono::DateTime<chrono::Utc>,
+    /// Sum of the payments allocated to this purchase
     pub amount_paid: sqlx::types::BigDecimal,
     pub notes: String,
 }
 
+/// Columns that can be used in list and search requests.
+/// Unqualified names are the purchases columns, users only exposes the username.
+const COLUMNS: Columns = Columns(&[
+    Column::aliased("id", "purchases.id"),
+    Column::new("purchases.id"),
+    Column::aliased("date_time", "purchases.date_time"),
+    Column::aliased("supplier_id", "purchases.supplier_id"),
+    Column::aliased("created_by_user_id", "purchases.created_by_user_id"),
+    Column::aliased("amount_paid", "purchases.amount_paid"),
+    Column::aliased("notes", "purchases.notes"),
+    Column::new("suppliers.name"),
+    Column::new("suppliers.phone"),
+    Column::new("users.username"),
+]);
+
+/// Purchases with their supplier and user, for list and export queries
+const PURCHASES_FROM: &str = r#"
+    purchases
+    LEFT JOIN suppliers ON purchases.supplier_id = suppliers.id
+    LEFT JOIN users ON purchases.created_by_user_id = users.id
+"#;
+
+/// Total of the purchase items, purchases have no total column
+const PURCHASE_TOTAL: &str = r#"
+    COALESCE((
+        SELECT SUM(purchase_items.price * purchase_items.quantity)
+        FROM purchase_items
+        WHERE purchase_items.purchase_id = purchases.id
+    ), 0)
+"#;
+
+/// Columns of purchase exports
+const EXPORT_COLUMNS: &[ExportColumn] = &[
+    ExportColumn::new("ID", "purchases.id", ExportKind::Integer),
+    ExportColumn::new("Date", "purchases.date_time", ExportKind::DateTime),
+    ExportColumn::new("Supplier", "suppliers.name", ExportKind::Text),
+    ExportColumn::new("Created By", "users.username", ExportKind::Text),
+    ExportColumn::new("Total", PURCHASE_TOTAL, ExportKind::Currency),
+    ExportColumn::new("Paid", "purchases.amount_paid", ExportKind::Currency),
+    ExportColumn::new("Notes", "purchases.notes", ExportKind::Text),
+];
+
 #[derive(FromRow, Debug)]
 pub(super) struct PurchaseMetaRow {
     pub id: i32,
@@ -58,7 +104,7 @@ impl From<PurchaseItemRow> for PurchaseItem {
     fn from(row: PurchaseItemRow) -> Self {
         Self {
             id: row.id,
-            inventory_item: row.inventory.0.into(),
+            inventory_item: row.inventory.0,
             quantity: row.quantity,
             price: row.price,
         }
@@ -80,7 +126,7 @@ impl From<PurchaseMetaRow> for PurchaseMeta {
         Self {
             id: row.id,
             created_by_user: row.created_by_user.0.into(),
-            supplier: row.supplier.0.into(),
+            supplier: row.supplier.0,
             date_time: row.date_time,
             amount_paid: row.amount_paid,
             notes: row.notes,
@@ -92,9 +138,7 @@ impl From<PurchaseMetaRow> for PurchaseMeta {
 pub(super) async fn get(
     id: i32,
     mut db: crate::db::DB,
-    _auth: crate::routes::auth::AuthGuard<
-        { crate::types::permissions::UserPermissionEnum::PURCHASE_READ as u32 },
-    >,
+    _auth: crate::routes::auth::AuthGuard<crate::types::permissions::perm::PURCHASE_READ>,
 ) -> Result<rocket::serde::json::Json<PurchaseMeta>, ApiError> {
     let row: PurchaseMeta = sqlx::query_as(
         r#"
@@ -117,20 +161,18 @@ pub(super) async fn get(
     .map_err(|e| ApiError(Status::InternalServerError, e.to_string()))
     .map(|row: PurchaseMetaRow| row.into())?;
 
-    Ok(rocket::serde::json::Json(row.into()))
+    Ok(rocket::serde::json::Json(row))
 }
 
 #[rocket::post("/purchases/search", data = "<req>")]
 pub(super) async fn search(
     req: rocket::serde::json::Json<SearchRequest>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::PURCHASE_READ as u32 }>,
+    _auth: AuthGuard<perm::PURCHASE_READ>,
 ) -> Result<rocket::serde::json::Json<Vec<PurchaseMeta>>, ApiError> {
     let req = req.into_inner();
 
-    let x = req
-        .column
-        .unwrap_or(req.nested_access.unwrap_or("id".to_string()));
+    let x = COLUMNS.search_column(&req)?;
 
     let query_str = format!(
         r#"
@@ -174,7 +216,7 @@ pub(super) async fn search(
 pub(super) async fn get_items(
     id: i32,
     mut db: crate::db::DB,
-    _auth: AuthGuard<{ UserPermissionEnum::PURCHASE_READ as u32 }>,
+    _auth: AuthGuard<perm::PURCHASE_READ>,
 ) -> Result<rocket::serde::json::Json<Vec<PurchaseItem>>, ApiError> {
     let purchase_items: Vec<PurchaseItem> = sqlx::query_as(
         r#"
@@ -200,7 +242,7 @@ pub(super) async fn get_items(
 #[rocket::get("/purchases/count")]
 pub(super) async fn count(
     mut db: crate::db::DB,
-    _auth: AuthGuard<{ UserPermissionEnum::PURCHASE_READ as u32 }>,
+    _auth: AuthGuard<perm::PURCHASE_READ>,
 ) -> Result<rocket::serde::json::Json<i64>, ApiError> {
     let count = sqlx::query_scalar("SELECT COUNT(*) FROM purchases")
         .fetch_one(&mut **db)
@@ -214,60 +256,61 @@ pub(super) async fn count(
 pub(super) async fn list(
     req: rocket::serde::json::Json<crate::routes::public::ListRequest>,
     mut db: crate::db::DB,
-    _auth: AuthGuard<{ UserPermissionEnum::PURCHASE_READ as u32 }>,
-) -> Result<rocket::serde::json::Json<Vec<PurchaseMeta>>, ApiError> {
+    _auth: AuthGuard<perm::PURCHASE_READ>,
+) -> Result<rocket::serde::json::Json<ListResponse<PurchaseMeta>>, ApiError> {
     let req = req.into_inner();
 
-    let mut current_param = 1;
-
-    let sorts_string = super::generate_sorts_string(&req.sorts);
-
-    let (filters_string, filter_binds) =
-        super::generate_filters_string(&req.filters, &mut current_param);
-
-    let query_str = format!(
-        r#"
-        SELECT
-            purchases.id,
-            purchases.date_time,
-            purchases.amount_paid,
-            purchases.notes,
-            row_to_json(suppliers) AS supplier,
-            row_to_json(users) AS created_by_user
-        FROM purchases
-            LEFT JOIN suppliers ON purchases.supplier_id = suppliers.id
-            LEFT JOIN users ON purchases.created_by_user_id = users.id
-        {}
-        {}
-        LIMIT ${}
-        OFFSET ${}
-        "#,
-        filters_string,
-        sorts_string,
-        current_param,
-        current_param + 1
-    );
-
-    let query = sqlx::query_as(&query_str);
-
-    let query = filter_binds
-        .into_iter()
-        .fold(query, |query, value| value.bind_to_query_as(query));
+    let page = COLUMNS
+        .page::<PurchaseMetaRow, _>(
+            ListQuery {
+                select: r#"
+                    purchases.id,
+                    purchases.date_time,
+                    purchases.amount_paid,
+                    purchases.notes,
+                    row_to_json(suppliers) AS supplier,
+                    row_to_json(users) AS created_by_user
+                "#,
+                from: PURCHASES_FROM,
+                key: "purchases.id",
+                condition: None,
+            },
+            &req,
+            &mut db,
+        )
+        .await?;
 
-    let query = query.bind(req.range.count).bind(req.range.offset);
+    Ok(rocket::serde::json::Json(page))
+}
 
-    let purchases: Vec<PurchaseMeta> = query
-        .fetch_all(&mut **db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => {
-                ApiError(Status::NotFound, "No purchases found".to_string())
-            }
-            e => ApiError(Status::InternalServerError, e.to_string()),
-        })
-        .map(|rows: Vec<PurchaseMetaRow>| rows.into_iter().map(Into::into).collect())?;
+// POST /purchases/export?format=xlsx [Permissions: PURCHASE_READ]
+// Request: ListRequest, the range is ignored and every matching row is exported
+// format is csv (default) or xlsx
+// -> 200 OK
+//     purchases.csv or purchases.xlsx
+// -> 400 Bad Request (invalid format, columns or filters)
+#[rocket::post("/purchases/export?<format>", data = "<req>")]
+pub(super) async fn export(
+    format: Option<&str>,
+    req: rocket::serde::json::Json<crate::routes::public::ListRequest>,
+    db: crate::db::DB,
+    _auth: AuthGuard<perm::PURCHASE_READ>,
+) -> Result<Export, ApiError> {
+    let format = ExportFormat::parse(format)?;
+    let req = req.into_inner();
 
-    Ok(rocket::serde::json::Json(purchases))
+    let (rest, binds) = COLUMNS.export_string(
+        &ListQuery {
+            select: "*",
+            from: PURCHASES_FROM,
+            key: "purchases.id",
+            condition: None,
+        },
+        &req,
+        &mut 2,
+    )?;
+
+    Export::new(format, "purchases", EXPORT_COLUMNS, rest, binds, db).await
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
@@ -287,7 +330,6 @@ pub(super) struct PurchaseItemUpdateRequest {
 pub(super) struct PurchasePostRequest {
     pub supplier_id: i32,
     pub notes: String,
-    pub amount_paid: sqlx::types::BigDecimal,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
@@ -295,7 +337,6 @@ pub(super) struct PurchasePostRequest {
 pub(super) struct PurchasePatchRequest {
     pub supplier_id: Option<i32>,
     pub notes: Option<String>,
-    pub amount_paid: Option<sqlx::types::BigDecimal>,
     pub date_time: Option<chrono::DateTime<chrono::Utc>>,
 }
 
@@ -303,21 +344,20 @@ pub(super) struct PurchasePatchRequest {
 pub(super) async fn post(
     req: rocket::serde::json::Json<PurchasePostRequest>,
     mut db: crate::db::DB,
-    auth: AuthGuard<{ UserPermissionEnum::PURCHASE_CREATE as u32 }>,
+    auth: AuthGuard<perm::PURCHASE_CREATE>,
 ) -> Result<ApiReturn<i32>, ApiError> {
     let req = req.into_inner();
 
     let id: (i32,) = sqlx::query_as(
         r#"
-        INSERT INTO purchases (supplier_id, created_by_user_id, notes, amount_paid)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO purchases (supplier_id, created_by_user_id, notes)
+        VALUES ($1, $2, $3)
         RETURNING id
         "#,
     )
     .bind(req.supplier_id)
     .bind(auth.auth_info.user.id)
     .bind(req.notes)
-    .bind(req.amount_paid)
     .fetch_one(&mut **db)
     .await?;
 
@@ -328,7 +368,7 @@ pub(super) async fn post(
 pub(super) async fn total(
     id: i32,
     mut db: crate::db::DB,
-    _auth: AuthGuard<{ UserPermissionEnum::PURCHASE_READ as u32 }>,
+    _auth: AuthGuard<perm::PURCHASE_READ>,
 ) -> Result<rocket::serde::json::Json<PurchaseTotal>, ApiError> {
     Ok(rocket::serde::json::Json(
         get_purchase_total(id, &mut db).await?,
@@ -357,16 +397,34 @@ pub(super) async fn patch(
     id: i32,
     req: rocket::serde::json::Json<PurchasePatchRequest>,
     mut db: crate::db::DB,
-    _auth: AuthGuard<{ UserPermissionEnum::PURCHASE_UPDATE as u32 }>,
+    _auth: AuthGuard<perm::PURCHASE_UPDATE>,
 ) -> Result<ApiReturn<()>, ApiError> {
     let req = req.into_inner();
 
+    if let Some(supplier_id) = req.supplier_id {
+        let changed: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM purchases WHERE id = $1 AND supplier_id <> $2)",
+        )
+        .bind(id)
+        .bind(supplier_id)
+        .fetch_one(&mut **db)
+        .await?;
+
+        // Payments are allocated to purchases from their own supplier, see payments::allocate
+        if changed && payments::purchase_has_allocations(id, &mut db).await? {
+            return Err(ApiError(
+                Status::BadRequest,
+                "Remove the payment allocations of the purchase before changing its supplier"
+                    .to_string(),
+            ));
+        }
+    }
+
     let mut current_param_index = 1;
 
     let columns = vec![
         req.supplier_id.as_ref().map(|_| "supplier_id"),
         req.notes.as_ref().map(|_| "notes"),
-        req.amount_paid.as_ref().map(|_| "amount_paid"),
         req.date_time.as_ref().map(|_| "date_time"),
     ]
     .into_iter()
@@ -382,10 +440,7 @@ pub(super) async fn patch(
     let set_binds = vec![
         req.supplier_id.as_ref().map(|v| SqlType::Int(*v)),
         req.notes.as_ref().map(|v| SqlType::String(v.clone())),
-        req.amount_paid
-            .as_ref()
-            .map(|v| SqlType::BigDecimal(v.clone())),
-        req.date_time.as_ref().map(|v| SqlType::DateTime(v.clone())),
+        req.date_time.as_ref().map(|v| SqlType::DateTime(*v)),
     ]
     .into_iter()
     .flatten();
@@ -421,10 +476,10 @@ pub(super) async fn patch(
 }
 
 async fn calculate_stock_deltas(
-    requests: &Vec<PurchaseItemUpdateRequest>,
-    current_items: &Vec<PurchaseItem>,
+    requests: &[PurchaseItemUpdateRequest],
+    current_items: &[PurchaseItem],
     purchase_id: i32,
-    auth_info: &AuthCookie,
+    auth_info: &AuthInfo,
     db: &mut crate::db::DB,
 ) -> Result<Vec<StockUpdateFactory>, ApiError> {
     let mut stock_update_factories = vec![];
@@ -444,10 +499,12 @@ async fn calculate_stock_deltas(
     // Calculate stock deltas for new items
     for req in requests.iter() {
         // Find and merge
-        stock_update_factories
+        if let Some(update) = stock_update_factories
             .iter_mut()
             .find(|update| update.inventory.id == req.inventory_item_id)
-            .map(|update| update.delta += req.quantity);
+        {
+            update.delta += req.quantity;
+        }
 
         // Add new items
         if !current_items
@@ -478,7 +535,7 @@ pub(super) async fn preview_update_items(
     id: i32,
     req: rocket::serde::json::Json<Vec<PurchaseItemUpdateRequest>>,
     mut db: crate::db::DB,
-    auth: AuthGuard<{ UserPermissionEnum::PURCHASE_READ as u32 }>,
+    auth: AuthGuard<perm::PURCHASE_READ>,
 ) -> Result<ApiReturn<Vec<StockUpdateFactory>>, ApiError> {
     let requests = req.into_inner();
 
@@ -538,12 +595,13 @@ pub(super) async fn preview_update_items(
 /// If an item is not in the request, it will be removed
 /// If an item is in the request, it will be updated
 /// If an item is not in the current items, it will be added
+/// Fails if the new total is less than the amount paid
 #[rocket::post("/purchases/<id>/items/update", data = "<req>")]
 pub(super) async fn update_items(
     id: i32,
     req: rocket::serde::json::Json<Vec<PurchaseItemUpdateRequest>>,
     mut db: crate::db::DB,
-    auth: AuthGuard<{ UserPermissionEnum::PURCHASE_UPDATE as u32 }>,
+    auth: AuthGuard<perm::PURCHASE_UPDATE>,
 ) -> Result<ApiReturn<()>, ApiError> {
     let requests = req.into_inner();
 
@@ -802,6 +860,28 @@ pub(super) async fn update_items(
         stock_updates.push(stock_update);
     }
 
+    // Payments are allocated up to the total, so it can't drop below what was allocated
+    let overpaid: bool = sqlx::query_scalar(&format!(
+        r#"
+        SELECT {} < amount_paid
+        FROM purchases
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        PURCHASE_TOTAL
+    ))
+    .bind(id)
+    .fetch_one(&mut *transaction)
+    .await?;
+
+    if overpaid {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Purchase total would be less than the amount paid, remove or reduce payment allocations first"
+                .to_string(),
+        ));
+    }
+
     transaction.commit().await.map_err(|e| {
         ApiError(
             Status::InternalServerError,
@@ -816,7 +896,7 @@ pub(super) async fn update_items(
 pub(super) async fn delete(
     id: i32,
     mut db: crate::db::DB,
-    auth: AuthGuard<{ UserPermissionEnum::PURCHASE_DELETE as u32 }>,
+    auth: AuthGuard<perm::PURCHASE_DELETE>,
 ) -> Result<ApiReturn<Vec<StockUpdate>>, ApiError> {
     let purchase_items: Vec<PurchaseItem> = sqlx::query_as(
         r#"
@@ -844,7 +924,7 @@ pub(super) async fn delete(
     .collect();
 
     let stock_update_factories =
-        calculate_stock_deltas(&vec![], &purchase_items, id, &auth.auth_info, &mut db)
+        calculate_stock_deltas(&[], &purchase_items, id, &auth.auth_info, &mut db)
             .await
             .map_err(|e| {
                 ApiError(
@@ -0,0 +1,1001 @@
+//! Builds the WHERE, ORDER BY and search parts of list and search queries.
+//!
+//! Column names from requests are never put into SQL directly. Every route declares the
+//! columns clients may use as [`Columns`], and names that aren't in it are rejected.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bigdecimal::BigDecimal;
+use rocket::http::Status;
+use sqlx::{postgres::PgRow, FromRow, PgConnection, Row, TypeInfo, ValueRef};
+
+use super::{
+    search::SearchRequest, ApiError, FilterOperator, FilterTree, FilterValue, ListFilter,
+    ListRange, ListRequest, ListResponse, ListSort, SortOrder, SqlType,
+};
+
+/// A column clients may filter, sort and search on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Column {
+    /// Name used in requests, e.g. `name` or `customers.name` for joined tables
+    pub name: &'static str,
+    /// SQL expression the name stands for, e.g. `orders.id` to avoid ambiguity in joins
+    pub sql: &'static str,
+}
+
+impl Column {
+    /// A column whose request name is also its SQL
+    pub const fn new(name: &'static str) -> Self {
+        Column { name, sql: name }
+    }
+
+    /// A column with a different SQL expression than its request name
+    pub const fn aliased(name: &'static str, sql: &'static str) -> Self {
+        Column { name, sql }
+    }
+}
+
+/// The parts of a list query that differ between routes, see [`Columns::page`]
+pub(super) struct ListQuery {
+    /// Columns of the rows, e.g. `customers.*`
+    pub select: &'static str,
+    /// Table with any joins
+    pub from: &'static str,
+    /// Unique column that sorts last, so every row has a distinct cursor
+    pub key: &'static str,
+    /// Always applied along with the filters, e.g. to hide archived rows
+    pub condition: Option<&'static str>,
+}
+
+/// The columns of one list or search route
+pub(super) struct Columns(pub &'static [Column]);
+
+impl Columns {
+    fn get(&self, name: &str) -> Result<&'static Column, ApiError> {
+        self.0.iter().find(|c| c.name == name).ok_or_else(|| {
+            ApiError(
+                Status::BadRequest,
+                format!(
+                    "Unknown column: {}. Valid columns: {}",
+                    name,
+                    self.0
+                        .iter()
+                        .map(|c| c.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            )
+        })
+    }
+
+    /// SQL and order of the requested sorts, then of `key` so the order is always complete
+    fn sorts<'a>(
+        &self,
+        sorts: &'a [ListSort],
+        key: &'static str,
+    ) -> Result<Vec<(&'static str, &'a SortOrder)>, ApiError> {
+        let mut sorts = sorts
+            .iter()
+            .map(|sort| Ok((self.get(&sort.column)?.sql, &sort.order)))
+            .collect::<Result<Vec<_>, ApiError>>()?;
+        sorts.push((key, &SortOrder::Asc));
+
+        Ok(sorts)
+    }
+
+    fn conditions(
+        &self,
+        req: &ListRequest,
+        binds: &mut Vec<SqlType>,
+        current_param: &mut i32,
+    ) -> Result<Vec<String>, ApiError> {
+        let mut conditions = vec![];
+
+        for filter in &req.filters {
+            conditions.push(self.filter_sql(filter, binds, current_param)?);
+        }
+
+        if let Some(tree) = &req.filter_tree {
+            conditions.push(self.tree_sql(tree, binds, current_param)?);
+        }
+
+        Ok(conditions)
+    }
+
+    /// Fetch one page of a list route, along with the filtered total and a cursor for the next page.
+    ///
+    /// Rows are sorted by the requested sorts and then by `query.key`, so the order is always
+    /// complete. The cursor holds the sort values of the last row, and the next page starts
+    /// after them instead of skipping rows with OFFSET.
+    pub async fn page<R, T>(
+        &self,
+        query: ListQuery,
+        req: &ListRequest,
+        conn: &mut PgConnection,
+    ) -> Result<ListResponse<T>, ApiError>
+    where
+        R: for<'r> FromRow<'r, PgRow> + Send + Unpin,
+        T: From<R>,
+    {
+        let offset = range_offset(&req.range)?;
+        let mut current_param = 1;
+        let mut binds = vec![];
+
+        let mut conditions = self.conditions(req, &mut binds, &mut current_param)?;
+        conditions.extend(query.condition.map(str::to_string));
+
+        let total: (i64,) = binds
+            .iter()
+            .cloned()
+            .fold(
+                sqlx::query_as(&format!(
+                    "SELECT COUNT(*) FROM {} {}",
+                    query.from,
+                    where_string(&conditions)
+                )),
+                |q, value| value.bind_to_query_as(q),
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+
+        let sorts = self.sorts(&req.sorts, query.key)?;
+
+        if let Some(cursor) = &req.range.cursor {
+            let values = decode_cursor(cursor, sorts.len())?;
+            conditions.push(after_cursor(&sorts, &values, &mut binds, &mut current_param));
+        }
+
+        let query_str = format!(
+            r#"
+            SELECT {}, {}
+            FROM {}
+            {}
+            ORDER BY {}
+            LIMIT ${}
+            OFFSET ${}
+            "#,
+            query.select,
+            sorts
+                .iter()
+                .enumerate()
+                .map(|(i, (sql, _))| format!("{} AS cursor_{}", sql, i))
+                .collect::<Vec<_>>()
+                .join(", "),
+            query.from,
+            where_string(&conditions),
+            order_by_string(&sorts),
+            current_param,
+            current_param + 1
+        );
+
+        let count = req.range.count.max(0) as usize;
+
+        // One extra row tells whether there is a next page
+        let mut rows = binds
+            .into_iter()
+            .fold(sqlx::query(&query_str), |q, value| value.bind_to_query(q))
+            .bind(count as i64 + 1)
+            .bind(offset)
+            .fetch_all(conn)
+            .await
+            .map_err(|e| match e {
+                // A cursor with values that don't fit the sorted columns
+                sqlx::Error::Database(e)
+                    if req.range.cursor.is_some()
+                        && e.code().is_some_and(|c| c.starts_with("22") || c == "42883") =>
+                {
+                    ApiError(Status::BadRequest, "Invalid cursor".to_string())
+                }
+                e => e.into(),
+            })?;
+
+        let next_cursor = if rows.len() > count {
+            rows.truncate(count);
+            rows.last()
+                .map(|row| encode_cursor(row, sorts.len()))
+                .transpose()?
+        } else {
+            None
+        };
+
+        let items = rows
+            .iter()
+            .map(|row| R::from_row(row).map(T::from))
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+        Ok(ListResponse {
+            items,
+            total: total.0,
+            next_cursor,
+        })
+    }
+
+    /// FROM, WHERE and ORDER BY of every row a list request matches, for exports.
+    /// The range of the request is ignored. Placeholders start at `current_param`.
+    pub fn export_string(
+        &self,
+        query: &ListQuery,
+        req: &ListRequest,
+        current_param: &mut i32,
+    ) -> Result<(String, Vec<SqlType>), ApiError> {
+        let mut binds = vec![];
+
+        let mut conditions = self.conditions(req, &mut binds, current_param)?;
+        conditions.extend(query.condition.map(str::to_string));
+
+        let sorts = self.sorts(&req.sorts, query.key)?;
+
+        Ok((
+            format!(
+                "FROM {} {} ORDER BY {}",
+                query.from,
+                where_string(&conditions),
+                order_by_string(&sorts)
+            ),
+            binds,
+        ))
+    }
+
+    fn tree_sql(
+        &self,
+        tree: &FilterTree,
+        binds: &mut Vec<SqlType>,
+        current_param: &mut i32,
+    ) -> Result<String, ApiError> {
+        match tree {
+            FilterTree::And(nodes) => self.group_sql(nodes, " AND ", "TRUE", binds, current_param),
+            FilterTree::Or(nodes) => self.group_sql(nodes, " OR ", "FALSE", binds, current_param),
+            FilterTree::Not(node) => Ok(format!(
+                "NOT ({})",
+                self.tree_sql(node, binds, current_param)?
+            )),
+            FilterTree::Filter(filter) => self.filter_sql(filter, binds, current_param),
+        }
+    }
+
+    fn group_sql(
+        &self,
+        nodes: &[FilterTree],
+        separator: &str,
+        empty: &str,
+        binds: &mut Vec<SqlType>,
+        current_param: &mut i32,
+    ) -> Result<String, ApiError> {
+        if nodes.is_empty() {
+            return Ok(empty.to_string());
+        }
+
+        let conditions = nodes
+            .iter()
+            .map(|node| self.tree_sql(node, binds, current_param))
+            .collect::<Result<Vec<_>, ApiError>>()?;
+
+        Ok(format!("({})", conditions.join(separator)))
+    }
+
+    fn filter_sql(
+        &self,
+        filter: &ListFilter,
+        binds: &mut Vec<SqlType>,
+        current_param: &mut i32,
+    ) -> Result<String, ApiError> {
+        let column = self.get(&filter.column)?.sql;
+        let operator = filter.operator.to_sql();
+
+        let mut placeholder = |value: &SqlType| {
+            binds.push(value.clone());
+            *current_param += 1;
+            format!("${}", *current_param - 1)
+        };
+
+        let bad_value = |expected: &str| {
+            Err(ApiError(
+                Status::BadRequest,
+                format!("{} on {} needs {}", operator, filter.column, expected),
+            ))
+        };
+
+        match (&filter.operator, &filter.value) {
+            (FilterOperator::IsNull | FilterOperator::IsNotNull, _) => {
+                Ok(format!("{} {}", column, operator))
+            }
+            (FilterOperator::In | FilterOperator::NotIn, Some(FilterValue::List(values)))
+                if !values.is_empty() =>
+            {
+                let placeholders = values.iter().map(placeholder).collect::<Vec<_>>();
+
+                Ok(format!("{} {} ({})", column, operator, placeholders.join(", ")))
+            }
+            (FilterOperator::In | FilterOperator::NotIn, _) => bad_value("a non-empty list"),
+            (FilterOperator::Between, Some(FilterValue::List(values))) if values.len() == 2 => {
+                Ok(format!(
+                    "{} BETWEEN {} AND {}",
+                    column,
+                    placeholder(&values[0]),
+                    placeholder(&values[1])
+                ))
+            }
+            (FilterOperator::Between, _) => bad_value("a list of two values"),
+            (_, Some(FilterValue::Single(value))) => {
+                Ok(format!("{} {} {}", column, operator, placeholder(value)))
+            }
+            (_, _) => bad_value("a single value"),
+        }
+    }
+
+    /// SQL of the column to search on, `id` if the request doesn't name one
+    pub fn search_column(&self, req: &SearchRequest) -> Result<&'static str, ApiError> {
+        let name = req
+            .column
+            .as_deref()
+            .or(req.nested_access.as_deref())
+            .unwrap_or("id");
+
+        Ok(self.get(name)?.sql)
+    }
+}
+
+/// Rows to skip before the page, none when paging with a cursor
+fn range_offset(range: &ListRange) -> Result<i32, ApiError> {
+    match range.cursor {
+        Some(_) => Ok(0),
+        None if range.offset < 0 => Err(ApiError(
+            Status::BadRequest,
+            "offset can't be negative".to_string(),
+        )),
+        None => Ok(range.offset),
+    }
+}
+
+fn sort_order_sql(order: &SortOrder) -> &'static str {
+    match order {
+        SortOrder::Asc => "ASC",
+        SortOrder::Desc => "DESC",
+    }
+}
+
+fn order_by_string(sorts: &[(&str, &SortOrder)]) -> String {
+    sorts
+        .iter()
+        .map(|(sql, order)| format!("{} {}", sql, sort_order_sql(order)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn where_string(conditions: &[String]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    }
+}
+
+/// Condition for rows that sort after the cursor.
+/// Postgres sorts NULL last when ascending and first when descending.
+fn after_cursor(
+    sorts: &[(&str, &SortOrder)],
+    values: &[SqlType],
+    binds: &mut Vec<SqlType>,
+    current_param: &mut i32,
+) -> String {
+    let mut placeholder = |value: &SqlType| {
+        binds.push(value.clone());
+        *current_param += 1;
+        format!("${}", *current_param - 1)
+    };
+
+    let mut equal_so_far: Vec<String> = vec![];
+    let mut alternatives = vec![];
+
+    for ((sql, order), value) in sorts.iter().zip(values) {
+        let (after, equal) = match (order, value) {
+            (SortOrder::Asc, SqlType::Null) => (None, format!("{} IS NULL", sql)),
+            (SortOrder::Desc, SqlType::Null) => (
+                Some(format!("{} IS NOT NULL", sql)),
+                format!("{} IS NULL", sql),
+            ),
+            (order, value) => {
+                let param = placeholder(value);
+
+                let after = match order {
+                    SortOrder::Asc => format!("({} > {} OR {} IS NULL)", sql, param, sql),
+                    SortOrder::Desc => format!("{} < {}", sql, param),
+                };
+
+                (Some(after), format!("{} = {}", sql, param))
+            }
+        };
+
+        if let Some(after) = after {
+            alternatives.push(
+                equal_so_far
+                    .iter()
+                    .cloned()
+                    .chain([after])
+                    .collect::<Vec<_>>()
+                    .join(" AND "),
+            );
+        }
+
+        equal_so_far.push(equal);
+    }
+
+    if alternatives.is_empty() {
+        return "FALSE".to_string();
+    }
+
+    format!("(({}))", alternatives.join(") OR ("))
+}
+
+fn encode_cursor(row: &PgRow, len: usize) -> Result<String, ApiError> {
+    let values = (0..len)
+        .map(|i| cursor_value(row, &format!("cursor_{}", i)))
+        .collect::<Result<Vec<_>, ApiError>>()?;
+
+    Ok(URL_SAFE_NO_PAD.encode(serde_json::to_vec(&values)?))
+}
+
+fn decode_cursor(cursor: &str, len: usize) -> Result<Vec<SqlType>, ApiError> {
+    let invalid = || ApiError(Status::BadRequest, "Invalid cursor".to_string());
+
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let values: Vec<SqlType> = serde_json::from_slice(&bytes).map_err(|_| invalid())?;
+
+    if values.len() != len {
+        return Err(invalid());
+    }
+
+    Ok(values)
+}
+
+fn cursor_value(row: &PgRow, column: &str) -> Result<SqlType, ApiError> {
+    let raw = row.try_get_raw(column)?;
+
+    if raw.is_null() {
+        return Ok(SqlType::Null);
+    }
+
+    let type_name = raw.type_info().name().to_string();
+
+    Ok(match type_name.as_str() {
+        "INT4" => SqlType::Int(row.try_get(column)?),
+        "NUMERIC" => SqlType::BigDecimal(row.try_get::<BigDecimal, _>(column)?),
+        "FLOAT8" => SqlType::Float(row.try_get(column)?),
+        "BOOL" => SqlType::Boolean(row.try_get(column)?),
+        "TEXT" | "VARCHAR" => SqlType::String(row.try_get(column)?),
+        "TIMESTAMPTZ" => SqlType::DateTime(row.try_get(column)?),
+        _ => {
+            return Err(ApiError(
+                Status::BadRequest,
+                format!("Can't page through {} columns with a cursor", type_name),
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::URL_SAFE;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const COLUMNS: Columns = Columns(&[
+        Column::aliased("id", "orders.id"),
+        Column::new("notes"),
+        Column::new("customers.name"),
+        Column::aliased("total", "orders.total"),
+    ]);
+
+    fn request(filters: Value, filter_tree: Value) -> ListRequest {
+        serde_json::from_value(json!({
+            "range": { "count": 10, "offset": 0 },
+            "sorts": [],
+            "filters": filters,
+            "filter_tree": filter_tree,
+        }))
+        .unwrap()
+    }
+
+    /// WHERE clause of a request, placeholders starting at `current_param`
+    fn filters_string(
+        req: &ListRequest,
+        current_param: &mut i32,
+    ) -> Result<(String, Vec<SqlType>), ApiError> {
+        let mut binds = vec![];
+        let conditions = COLUMNS.conditions(req, &mut binds, current_param)?;
+
+        Ok((where_string(&conditions), binds))
+    }
+
+    fn filters(filters: Value) -> Result<(String, Vec<SqlType>), ApiError> {
+        filters_string(&request(filters, Value::Null), &mut 1)
+    }
+
+    fn sorts(sorts: Value) -> Result<String, ApiError> {
+        let sorts = serde_json::from_value::<Vec<ListSort>>(sorts).unwrap();
+
+        Ok(order_by_string(&COLUMNS.sorts(&sorts, "orders.id")?))
+    }
+
+    fn search(column: Option<&str>, nested_access: Option<&str>) -> Result<&'static str, ApiError> {
+        COLUMNS.search_column(&SearchRequest {
+            search: "x".to_string(),
+            column: column.map(str::to_string),
+            count: 10,
+            nested_access: nested_access.map(str::to_string),
+            include_archived: false,
+        })
+    }
+
+    #[test]
+    fn unknown_columns_are_rejected() {
+        let injected = "id; DROP TABLE users; --";
+
+        for result in [
+            filters(json!([{ "column": injected, "operator": "=", "value": { "Int": 1 } }]))
+                .map(|(sql, _)| sql),
+            sorts(json!([{ "column": injected, "order": "ASC" }])),
+            search(Some(injected), None).map(str::to_string),
+            search(None, Some("users.password")).map(str::to_string),
+            // Request names are matched exactly, not the SQL they stand for
+            filters(json!([{ "column": "orders.id", "operator": "IS NULL" }])).map(|(sql, _)| sql),
+            filters(json!([{ "column": "NOTES", "operator": "IS NULL" }])).map(|(sql, _)| sql),
+        ] {
+            let ApiError(status, message) = result.unwrap_err();
+
+            assert_eq!(status, Status::BadRequest);
+            assert!(
+                message.ends_with("Valid columns: id, notes, customers.name, total"),
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_columns_in_trees_are_rejected() {
+        let req = request(
+            json!([]),
+            json!({ "Or": [
+                { "Filter": { "column": "notes", "operator": "IS NULL" } },
+                { "Not": { "Filter": { "column": "password", "operator": "IS NULL" } } }
+            ] }),
+        );
+
+        let ApiError(status, message) = filters_string(&req, &mut 1).unwrap_err();
+        assert_eq!(status, Status::BadRequest);
+        assert!(message.starts_with("Unknown column: password."));
+    }
+
+    #[test]
+    fn aliased_columns_use_their_sql() {
+        assert_eq!(
+            filters(json!([{ "column": "id", "operator": "=", "value": { "Int": 3 } }])).unwrap(),
+            ("WHERE orders.id = $1".to_string(), vec![SqlType::Int(3)])
+        );
+        assert_eq!(
+            filters(json!([{ "column": "customers.name", "operator": "IS NULL" }]))
+                .unwrap()
+                .0,
+            "WHERE customers.name IS NULL"
+        );
+        assert_eq!(
+            sorts(json!([
+                { "column": "total", "order": "DESC" },
+                { "column": "notes", "order": "ASC" }
+            ]))
+            .unwrap(),
+            "orders.total DESC, notes ASC, orders.id ASC"
+        );
+        assert_eq!(sorts(json!([])).unwrap(), "orders.id ASC");
+        assert_eq!(search(None, None).unwrap(), "orders.id");
+        assert_eq!(search(Some("total"), None).unwrap(), "orders.total");
+        assert_eq!(
+            search(None, Some("customers.name")).unwrap(),
+            "customers.name"
+        );
+    }
+
+    #[test]
+    fn placeholders_follow_current_param() {
+        let req = request(
+            json!([
+                { "column": "id", "operator": ">", "value": { "Int": 5 } },
+                { "column": "notes", "operator": "IS NOT NULL" },
+                { "column": "total", "operator": "<=", "value": { "Int": 100 } }
+            ]),
+            json!({ "Filter": { "column": "customers.name", "operator": "=", "value": { "String": "Acme" } } }),
+        );
+        // Routes bind their own values first
+        let mut current_param = 3;
+
+        let (sql, binds) = filters_string(&req, &mut current_param).unwrap();
+
+        assert_eq!(
+            sql,
+            "WHERE orders.id > $3 AND notes IS NOT NULL AND orders.total <= $4 AND customers.name = $5"
+        );
+        assert_eq!(
+            binds,
+            [
+                SqlType::Int(5),
+                SqlType::Int(100),
+                SqlType::String("Acme".to_string())
+            ]
+        );
+        assert_eq!(current_param, 6);
+
+        // Nothing to bind leaves it where it was
+        let mut current_param = 2;
+        assert_eq!(
+            filters_string(&request(json!([]), Value::Null), &mut current_param).unwrap(),
+            (String::new(), vec![])
+        );
+        assert_eq!(current_param, 2);
+    }
+
+    #[test]
+    fn export_string_numbers_after_the_route() {
+        let req = request(
+            json!([{ "column": "total", "operator": "=", "value": { "Int": 1 } }]),
+            Value::Null,
+        );
+        let query = ListQuery {
+            select: "*",
+            from: "orders",
+            key: "orders.id",
+            condition: Some("orders.archived_at IS NULL"),
+        };
+        let mut current_param = 2;
+
+        let (sql, binds) = COLUMNS
+            .export_string(&query, &req, &mut current_param)
+            .unwrap();
+
+        assert_eq!(
+            sql,
+            "FROM orders WHERE orders.total = $2 AND orders.archived_at IS NULL ORDER BY orders.id ASC"
+        );
+        assert_eq!(binds, [SqlType::Int(1)]);
+        assert_eq!(current_param, 3);
+    }
+
+    fn tree(tree: Value) -> Result<(String, Vec<SqlType>), ApiError> {
+        filters_string(&request(json!([]), tree), &mut 1)
+    }
+
+    fn filter(column: &str, operator: &str, value: Value) -> Value {
+        json!({ "Filter": { "column": column, "operator": operator, "value": value } })
+    }
+
+    fn bad_request(result: Result<(String, Vec<SqlType>), ApiError>) -> String {
+        let ApiError(status, message) = result.unwrap_err();
+        assert_eq!(status, Status::BadRequest);
+        message
+    }
+
+    #[test]
+    fn in_binds_every_value() {
+        assert_eq!(
+            tree(filter(
+                "id",
+                "IN",
+                json!([{ "Int": 1 }, { "Int": 2 }, { "Int": 3 }])
+            ))
+            .unwrap(),
+            (
+                "WHERE orders.id IN ($1, $2, $3)".to_string(),
+                vec![SqlType::Int(1), SqlType::Int(2), SqlType::Int(3)]
+            )
+        );
+        assert_eq!(
+            tree(filter("notes", "NOT IN", json!([{ "String": "a" }]))).unwrap(),
+            (
+                "WHERE notes NOT IN ($1)".to_string(),
+                vec![SqlType::String("a".to_string())]
+            )
+        );
+    }
+
+    #[test]
+    fn in_needs_a_non_empty_list() {
+        // `IN ()` is a syntax error in Postgres
+        assert_eq!(
+            bad_request(tree(filter("id", "IN", json!([])))),
+            "IN on id needs a non-empty list"
+        );
+        assert_eq!(
+            bad_request(tree(filter("id", "NOT IN", json!([])))),
+            "NOT IN on id needs a non-empty list"
+        );
+        assert_eq!(
+            bad_request(tree(filter("id", "IN", json!({ "Int": 1 })))),
+            "IN on id needs a non-empty list"
+        );
+        assert_eq!(
+            bad_request(tree(filter("id", "IN", Value::Null))),
+            "IN on id needs a non-empty list"
+        );
+    }
+
+    #[test]
+    fn between_needs_two_values() {
+        assert_eq!(
+            tree(filter(
+                "total",
+                "BETWEEN",
+                json!([{ "Int": 10 }, { "Int": 20 }])
+            ))
+            .unwrap(),
+            (
+                "WHERE orders.total BETWEEN $1 AND $2".to_string(),
+                vec![SqlType::Int(10), SqlType::Int(20)]
+            )
+        );
+
+        for value in [
+            json!([]),
+            json!([{ "Int": 10 }]),
+            json!([{ "Int": 10 }, { "Int": 20 }, { "Int": 30 }]),
+            json!({ "Int": 10 }),
+        ] {
+            assert_eq!(
+                bad_request(tree(filter("total", "BETWEEN", value))),
+                "BETWEEN on total needs a list of two values"
+            );
+        }
+    }
+
+    #[test]
+    fn like_patterns_are_bound_not_inlined() {
+        let pattern = "%50\\% off'; DROP TABLE orders; --";
+
+        let (sql, binds) = tree(filter("notes", "ILIKE", json!({ "String": pattern }))).unwrap();
+
+        assert_eq!(sql, "WHERE notes ILIKE $1");
+        assert_eq!(binds, [SqlType::String(pattern.to_string())]);
+
+        assert_eq!(
+            tree(filter("notes", "LIKE", json!({ "String": "a_c" }))).unwrap(),
+            (
+                "WHERE notes LIKE $1".to_string(),
+                vec![SqlType::String("a_c".to_string())]
+            )
+        );
+        assert_eq!(
+            bad_request(tree(filter("notes", "ILIKE", json!([{ "String": "a" }])))),
+            "ILIKE on notes needs a single value"
+        );
+    }
+
+    #[test]
+    fn is_null_binds_nothing() {
+        assert_eq!(
+            tree(json!({ "Filter": { "column": "notes", "operator": "IS NULL" } })).unwrap(),
+            ("WHERE notes IS NULL".to_string(), vec![])
+        );
+        // A stray value is ignored rather than bound
+        assert_eq!(
+            tree(filter("notes", "IS NOT NULL", json!({ "String": "x" }))).unwrap(),
+            ("WHERE notes IS NOT NULL".to_string(), vec![])
+        );
+        assert_eq!(
+            bad_request(tree(
+                json!({ "Filter": { "column": "notes", "operator": "=" } })
+            )),
+            "= on notes needs a single value"
+        );
+    }
+
+    #[test]
+    fn nested_groups_keep_their_parentheses_and_bind_order() {
+        let (sql, binds) = tree(json!({ "And": [
+            { "Or": [
+                filter("id", "=", json!({ "Int": 1 })),
+                filter("id", "=", json!({ "Int": 2 })),
+            ] },
+            { "Not": { "And": [
+                filter("notes", "ILIKE", json!({ "String": "%x%" })),
+                { "Filter": { "column": "customers.name", "operator": "IS NULL" } },
+            ] } },
+            filter("total", "BETWEEN", json!([{ "Int": 5 }, { "Int": 9 }])),
+        ] }))
+        .unwrap();
+
+        assert_eq!(
+            sql,
+            "WHERE ((orders.id = $1 OR orders.id = $2) AND NOT ((notes ILIKE $3 AND customers.name IS NULL)) \
+             AND orders.total BETWEEN $4 AND $5)"
+        );
+        assert_eq!(
+            binds,
+            [
+                SqlType::Int(1),
+                SqlType::Int(2),
+                SqlType::String("%x%".to_string()),
+                SqlType::Int(5),
+                SqlType::Int(9)
+            ]
+        );
+    }
+
+    #[test]
+    fn flat_filters_come_before_the_tree() {
+        let req = request(
+            json!([{ "column": "id", "operator": "=", "value": { "Int": 1 } }]),
+            json!({ "Or": [
+                filter("total", "=", json!({ "Int": 2 })),
+                filter("total", "=", json!({ "Int": 3 })),
+            ] }),
+        );
+
+        assert_eq!(
+            filters_string(&req, &mut 1).unwrap(),
+            (
+                "WHERE orders.id = $1 AND (orders.total = $2 OR orders.total = $3)".to_string(),
+                vec![SqlType::Int(1), SqlType::Int(2), SqlType::Int(3)]
+            )
+        );
+    }
+
+    #[test]
+    fn empty_groups_match_everything_or_nothing() {
+        assert_eq!(tree(json!({ "And": [] })).unwrap().0, "WHERE TRUE");
+        assert_eq!(tree(json!({ "Or": [] })).unwrap().0, "WHERE FALSE");
+        assert_eq!(
+            tree(json!({ "Not": { "Or": [] } })).unwrap().0,
+            "WHERE NOT (FALSE)"
+        );
+    }
+
+    fn range(value: Value) -> ListRange {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn negative_offsets_are_rejected() {
+        let ApiError(status, message) =
+            range_offset(&range(json!({ "count": 10, "offset": -1 }))).unwrap_err();
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(message, "offset can't be negative");
+
+        assert_eq!(
+            range_offset(&range(json!({ "count": 10, "offset": 0 }))).unwrap(),
+            0
+        );
+        assert_eq!(
+            range_offset(&range(json!({ "count": 10, "offset": 20 }))).unwrap(),
+            20
+        );
+        // The offset is ignored with a cursor
+        assert_eq!(
+            range_offset(&range(
+                json!({ "count": 10, "offset": -1, "cursor": "W10" })
+            ))
+            .unwrap(),
+            0
+        );
+    }
+
+    fn cursor(
+        sorts: &[(&str, &SortOrder)],
+        values: &[SqlType],
+        current_param: &mut i32,
+    ) -> (String, Vec<SqlType>) {
+        let mut binds = vec![];
+        let sql = after_cursor(sorts, values, &mut binds, current_param);
+        (sql, binds)
+    }
+
+    #[test]
+    fn cursors_compare_on_every_sort() {
+        let mut current_param = 1;
+
+        assert_eq!(
+            cursor(
+                &[("notes", &SortOrder::Asc), ("orders.id", &SortOrder::Asc)],
+                &[SqlType::String("b".to_string()), SqlType::Int(2)],
+                &mut current_param
+            ),
+            (
+                "(((notes > $1 OR notes IS NULL)) OR (notes = $1 AND (orders.id > $2 OR orders.id IS NULL)))"
+                    .to_string(),
+                vec![SqlType::String("b".to_string()), SqlType::Int(2)]
+            )
+        );
+        assert_eq!(current_param, 3);
+    }
+
+    #[test]
+    fn cursors_place_nulls_like_postgres() {
+        // NULL sorts last when ascending, so only later NULLs follow it
+        let mut current_param = 1;
+        assert_eq!(
+            cursor(
+                &[
+                    ("notes", &SortOrder::Asc),
+                    ("orders.total", &SortOrder::Desc),
+                    ("orders.id", &SortOrder::Asc)
+                ],
+                &[SqlType::Null, SqlType::Int(5), SqlType::Int(7)],
+                &mut current_param
+            ),
+            (
+                "((notes IS NULL AND orders.total < $1) OR (notes IS NULL AND orders.total = $1 \
+                 AND (orders.id > $2 OR orders.id IS NULL)))"
+                    .to_string(),
+                vec![SqlType::Int(5), SqlType::Int(7)]
+            )
+        );
+        assert_eq!(current_param, 3);
+
+        // and first when descending, so every non-NULL value follows it
+        let mut current_param = 4;
+        assert_eq!(
+            cursor(
+                &[("notes", &SortOrder::Desc), ("orders.id", &SortOrder::Asc)],
+                &[SqlType::Null, SqlType::Int(3)],
+                &mut current_param
+            ),
+            (
+                "((notes IS NOT NULL) OR (notes IS NULL AND (orders.id > $4 OR orders.id IS NULL)))"
+                    .to_string(),
+                vec![SqlType::Int(3)]
+            )
+        );
+        assert_eq!(current_param, 5);
+
+        // Nothing comes after a trailing NULL
+        let mut current_param = 1;
+        assert_eq!(
+            cursor(
+                &[("notes", &SortOrder::Asc)],
+                &[SqlType::Null],
+                &mut current_param
+            ),
+            ("FALSE".to_string(), vec![])
+        );
+        assert_eq!(current_param, 1);
+    }
+
+    fn encode(values: Value) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&values).unwrap())
+    }
+
+    #[test]
+    fn cursors_decode_to_their_values() {
+        let values = vec![
+            SqlType::String("b".to_string()),
+            SqlType::Null,
+            SqlType::Int(2),
+        ];
+        let cursor = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&values).unwrap());
+
+        assert_eq!(decode_cursor(&cursor, 3).unwrap(), values);
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for (cursor, len) in [
+            // Not base64
+            ("not a cursor!".to_string(), 1),
+            // Padded base64
+            (URL_SAFE.encode(b"[{\"Int\":1}]"), 1),
+            // Not JSON
+            (URL_SAFE_NO_PAD.encode(b"\xff\x00"), 1),
+            // Not a list of values
+            (encode(json!({ "Int": 1 })), 1),
+            (encode(json!([{ "Unknown": 1 }])), 1),
+            // Too short or too long for the sorts
+            (encode(json!([{ "Int": 1 }])), 2),
+            (encode(json!([{ "Int": 1 }, { "Int": 2 }])), 1),
+            (encode(json!([])), 1),
+            (String::new(), 1),
+        ] {
+            let ApiError(status, message) = decode_cursor(&cursor, len).unwrap_err();
+
+            assert_eq!(status, Status::BadRequest, "{}", cursor);
+            assert_eq!(message, "Invalid cursor");
+        }
+    }
+}
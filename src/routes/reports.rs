@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use bigdecimal::BigDecimal;
+use chrono::{Datelike, NaiveDate};
 use rocket::http::Status;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
@@ -11,23 +12,32 @@ use crate::{
         auth::AuthGuard,
         orders::{Order, OrderItem, OrderItemRow, OrderMetaRow},
     },
-    types::permissions::UserPermissionEnum,
+    settings::{payment_terms_days, timezone},
+    types::permissions::perm,
 };
 
 use super::{
-    expenses::{Expense, ExpenseRow},
-    orders::OrderMeta,
-    ApiError,
+    expenses::{Expense, ExpenseRow, EXPENSES_FROM, EXPENSE_EXPORT_COLUMNS},
+    export::{Export, ExportFormat},
+    orders::{OrderMeta, ORDERS_FROM, ORDER_EXPORT_COLUMNS},
+    parse_date,
+    purchases::{Purchase, PurchaseItem, PurchaseItemRow, PurchaseMeta, PurchaseMetaRow},
+    today, ApiError, DateRange, SqlType,
 };
 
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS, Hash, Eq, PartialEq)]
 #[ts(export)]
 pub(super) enum ReportRequestType {
+    /// Order totals after discounts
     Revenue,
+    /// Revenue minus the cost of the goods sold and the expenses of the period
     Profit,
     Expenses,
+    /// Units sold
     Product,
+    /// Unpaid part of the orders
     Receivable,
+    /// Unpaid part of the purchases
     Payable,
 }
 
@@ -44,15 +54,53 @@ impl ReportRequestType {
     }
 }
 
+/// Filters of the same kind match any of their ids, different kinds all have to match.
+/// Documents that can't match a filter are left out, e.g. a customer filter leaves out every
+/// purchase and expense.
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
 #[ts(export)]
+#[allow(clippy::enum_variant_names)]
 pub(super) enum ReportFilter {
+    /// Orders, purchases and expenses created by the user
     UserId(i32),
+    /// Order and purchase items of the inventory item
     ProductId(i32),
+    /// Orders of the customer
     CustomerId(i32),
+    /// Purchases from the supplier
     SupplierId(i32),
 }
 
+/// Ids of each kind of filter, bound as arrays that are null when there is no filter
+#[derive(Default)]
+struct ReportFilterIds {
+    user_ids: Vec<i32>,
+    product_ids: Vec<i32>,
+    customer_ids: Vec<i32>,
+    supplier_ids: Vec<i32>,
+}
+
+impl ReportFilterIds {
+    fn new(filters: &[ReportFilter]) -> Self {
+        let mut ids = Self::default();
+
+        for filter in filters {
+            match filter {
+                ReportFilter::UserId(id) => ids.user_ids.push(*id),
+                ReportFilter::ProductId(id) => ids.product_ids.push(*id),
+                ReportFilter::CustomerId(id) => ids.customer_ids.push(*id),
+                ReportFilter::SupplierId(id) => ids.supplier_ids.push(*id),
+            }
+        }
+
+        ids
+    }
+}
+
+fn bind_ids(ids: &[i32]) -> Option<Vec<i32>> {
+    (!ids.is_empty()).then(|| ids.to_vec())
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
 #[ts(export)]
 pub(super) struct ReportRequest {
@@ -69,54 +117,21 @@ pub(super) struct Report {
     end_date: chrono::DateTime<chrono::Utc>,
     orders: Vec<Order>,
     expenses: Vec<Expense>,
-    purchases: Vec<((), ())>,
+    purchases: Vec<Purchase>,
     data: HashMap<ReportRequestType, BigDecimal>,
 }
 
-#[rocket::post("/reports/create", data = "<report_request>")]
-#[allow(private_interfaces)]
-pub async fn create_report(
-    mut db: DB,
-    report_request: rocket::serde::json::Json<ReportRequest>,
-    _auth: AuthGuard<{ UserPermissionEnum::REPORTS as u32 }>,
-) -> Result<rocket::serde::json::Json<Report>, ApiError> {
-    let ReportRequest {
-        start_date,
-        end_date,
-        filters,
-        report_types,
-    } = report_request.into_inner();
-
-    let mut res = Report {
-        start_date: start_date,
-        end_date: end_date,
-        orders: vec![],
-        expenses: vec![],
-        purchases: vec![],
-        data: HashMap::new(),
-    };
-
-    let start_date = start_date.naive_utc();
-    let end_date = end_date.naive_utc();
-
-    let filters_sql_order_meta = filters
-        .iter()
-        .map(|f| match f {
-            ReportFilter::UserId(id) => format!("AND orders.created_by_user_id = {}", id),
-            ReportFilter::CustomerId(id) => format!("AND orders.customer_id = {}", id),
-            _ => "".to_string(),
-        })
-        .collect::<Vec<String>>()
-        .join(" ");
-
-    let filters_sql_order_items = filters
-        .iter()
-        .map(|f| match f {
-            ReportFilter::ProductId(id) => format!("AND order_items.product_id = {}", id),
-            _ => "".to_string(),
-        })
-        .collect::<Vec<String>>()
-        .join(" ");
+/// Orders between the dates with their items. With product filters only the matching items
+/// are returned, and orders without any are left out. None match a supplier filter.
+async fn report_orders(
+    start_date: chrono::DateTime<chrono::Utc>,
+    end_date: chrono::DateTime<chrono::Utc>,
+    ids: &ReportFilterIds,
+    db: &mut DB,
+) -> Result<Vec<Order>, ApiError> {
+    if !ids.supplier_ids.is_empty() {
+        return Ok(vec![]);
+    }
 
     #[derive(Debug, FromRow)]
     struct OrderMetaRowWithItems {
@@ -125,10 +140,9 @@ pub async fn create_report(
         items: sqlx_core::types::Json<Vec<OrderItemRow>>,
     }
 
-    let orders_query_string = format!(
+    let rows: Vec<OrderMetaRowWithItems> = sqlx::query_as(
         r#"
-    WITH order_meta AS (
-        SELECT 
+        SELECT
             orders.id,
             orders.date_time,
             orders.amount_paid,
@@ -136,116 +150,281 @@ pub async fn create_report(
             orders.retail_customer_name,
             orders.retail_customer_phone,
             orders.retail_customer_address,
-            orders.notes,
+            COALESCE(orders.notes, '') AS notes,
             orders.fulfilled,
+            orders.total,
             row_to_json(customers) AS customer,
-            row_to_json(users) AS created_by_user
+            row_to_json(users) AS created_by_user,
+            -- COALESCE is used to return an empty array if there are no items.
+            -- Amounts are sent as text so they aren't parsed as floats.
+            COALESCE(
+                (
+                    SELECT json_agg(
+                        json_build_object(
+                            'id', order_items.id,
+                            'inventory', row_to_json(inventory),
+                            'price', order_items.price::TEXT,
+                            'quantity', order_items.quantity,
+                            'discount', order_items.discount::TEXT,
+                            'discount_percentage', order_items.discount_percentage,
+                            'cost', order_items.cost::TEXT
+                        )
+                        ORDER BY order_items.id
+                    )
+                    FROM order_items
+                        INNER JOIN inventory ON order_items.inventory_id = inventory.id
+                    WHERE order_items.order_id = orders.id
+                        AND ($5::INT[] IS NULL OR order_items.inventory_id = ANY($5))
+                ), '[]'
+            ) AS items
         FROM orders
             LEFT JOIN customers ON orders.customer_id = customers.id
             INNER JOIN users ON orders.created_by_user_id = users.id
-        WHERE date_time BETWEEN $1 AND $2
-        {}
+        WHERE orders.date_time BETWEEN $1 AND $2
+            AND ($3::INT[] IS NULL OR orders.created_by_user_id = ANY($3))
+            AND ($4::INT[] IS NULL OR orders.customer_id = ANY($4))
+            AND ($5::INT[] IS NULL OR EXISTS (
+                SELECT 1 FROM order_items
+                WHERE order_items.order_id = orders.id AND order_items.inventory_id = ANY($5)
+            ))
+        ORDER BY orders.date_time, orders.id
+        "#,
     )
-    SELECT
-        order_meta.*,
-        -- COALESCE is used to return an empty array if there are no items
-        COALESCE(
-            (
-                SELECT json_agg(
-                    json_build_object(
-                        'id', order_items.id,
-                        'inventory', row_to_json(inventory),
-                        'price', order_items.price,
-                        'quantity', order_items.quantity
-                    )
-                )
-                FROM order_items
-                    INNER JOIN inventory ON order_items.inventory_id = inventory.id
-                WHERE order_items.order_id = order_meta.id
-                {}
-            ), '[]'
-        ) AS items
-    FROM order_meta
-    "#,
-        filters_sql_order_meta, filters_sql_order_items
-    );
+    .bind(start_date)
+    .bind(end_date)
+    .bind(bind_ids(&ids.user_ids))
+    .bind(bind_ids(&ids.customer_ids))
+    .bind(bind_ids(&ids.product_ids))
+    .fetch_all(&mut ***db)
+    .await?;
 
-    // Get every order between the start and end date
-    let orders: Vec<(OrderMeta, Vec<OrderItem>)> = sqlx::query_as(orders_query_string.as_str())
-        .bind(start_date)
-        .bind(end_date)
-        .fetch_all(&mut **db)
-        .await
-        .map_err(|e| ApiError(Status::InternalServerError, e.to_string()))
-        .map(|rows: Vec<OrderMetaRowWithItems>| {
-            rows.into_iter()
-                .map(|row| {
-                    (
-                        OrderMeta::from(row.order_meta),
-                        row.items
-                            .0
-                            .into_iter()
-                            .map(|item| OrderItem::from(item))
-                            .collect(),
+    Ok(rows
+        .into_iter()
+        .map(|row| Order {
+            meta: OrderMeta::from(row.order_meta),
+            items: row.items.0.into_iter().map(OrderItem::from).collect(),
+        })
+        .collect())
+}
+
+/// Purchases between the dates, filtered like `report_orders`, with the total of all their items.
+/// None match a customer filter.
+async fn report_purchases(
+    start_date: chrono::DateTime<chrono::Utc>,
+    end_date: chrono::DateTime<chrono::Utc>,
+    ids: &ReportFilterIds,
+    db: &mut DB,
+) -> Result<Vec<(Purchase, BigDecimal)>, ApiError> {
+    if !ids.customer_ids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    #[derive(Debug, FromRow)]
+    struct PurchaseMetaRowWithItems {
+        #[sqlx(flatten)]
+        purchase_meta: PurchaseMetaRow,
+        items: sqlx_core::types::Json<Vec<PurchaseItemRow>>,
+        total: BigDecimal,
+    }
+
+    let rows: Vec<PurchaseMetaRowWithItems> = sqlx::query_as(
+        r#"
+        SELECT
+            purchases.id,
+            purchases.date_time,
+            purchases.amount_paid,
+            COALESCE(purchases.notes, '') AS notes,
+            row_to_json(suppliers) AS supplier,
+            row_to_json(users) AS created_by_user,
+            COALESCE(
+                (
+                    SELECT json_agg(
+                        json_build_object(
+                            'id', purchase_items.id,
+                            'inventory', row_to_json(inventory),
+                            'price', purchase_items.price::TEXT,
+                            'quantity', purchase_items.quantity
+                        )
+                        ORDER BY purchase_items.id
                     )
-                })
-                .collect()
-        })?;
-
-    let filters_sql_expenses = filters
-        .iter()
-        .map(|f| match f {
-            ReportFilter::UserId(id) => format!("AND expenses.created_by_user_id = {}", id),
-            _ => "".to_string(),
+                    FROM purchase_items
+                        INNER JOIN inventory ON purchase_items.inventory_id = inventory.id
+                    WHERE purchase_items.purchase_id = purchases.id
+                        AND ($5::INT[] IS NULL OR purchase_items.inventory_id = ANY($5))
+                ), '[]'
+            ) AS items,
+            COALESCE(
+                (
+                    SELECT SUM(purchase_items.price * purchase_items.quantity)
+                    FROM purchase_items
+                    WHERE purchase_items.purchase_id = purchases.id
+                ), 0
+            ) AS total
+        FROM purchases
+            INNER JOIN suppliers ON purchases.supplier_id = suppliers.id
+            INNER JOIN users ON purchases.created_by_user_id = users.id
+        WHERE purchases.date_time BETWEEN $1 AND $2
+            AND ($3::INT[] IS NULL OR purchases.created_by_user_id = ANY($3))
+            AND ($4::INT[] IS NULL OR purchases.supplier_id = ANY($4))
+            AND ($5::INT[] IS NULL OR EXISTS (
+                SELECT 1 FROM purchase_items
+                WHERE purchase_items.purchase_id = purchases.id
+                    AND purchase_items.inventory_id = ANY($5)
+            ))
+        ORDER BY purchases.date_time, purchases.id
+        "#,
+    )
+    .bind(start_date)
+    .bind(end_date)
+    .bind(bind_ids(&ids.user_ids))
+    .bind(bind_ids(&ids.supplier_ids))
+    .bind(bind_ids(&ids.product_ids))
+    .fetch_all(&mut ***db)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| {
+            let purchase = Purchase {
+                meta: PurchaseMeta::from(row.purchase_meta),
+                items: row.items.0.into_iter().map(PurchaseItem::from).collect(),
+            };
+
+            (purchase, row.total)
         })
-        .collect::<Vec<String>>()
-        .join(" ");
+        .collect())
+}
 
-    let expenses: Vec<Expense> = todo!();
+/// Expenses between the dates. They only have a user, so none match the other filters.
+async fn report_expenses(
+    start_date: chrono::DateTime<chrono::Utc>,
+    end_date: chrono::DateTime<chrono::Utc>,
+    ids: &ReportFilterIds,
+    db: &mut DB,
+) -> Result<Vec<Expense>, ApiError> {
+    if !ids.product_ids.is_empty() || !ids.customer_ids.is_empty() || !ids.supplier_ids.is_empty() {
+        return Ok(vec![]);
+    }
 
-    // TODO
-    let purchases = ();
+    let rows: Vec<ExpenseRow> = sqlx::query_as(
+        r#"
+        SELECT
+            expenses.id,
+            expenses.date_time,
+            expenses.description,
+            row_to_json(users) AS created_by_user,
+            expenses.amount
+        FROM expenses
+            INNER JOIN users ON expenses.created_by_user_id = users.id
+        WHERE expenses.date_time BETWEEN $1 AND $2
+            AND ($3::INT[] IS NULL OR expenses.created_by_user_id = ANY($3))
+        ORDER BY expenses.date_time, expenses.id
+        "#,
+    )
+    .bind(start_date)
+    .bind(end_date)
+    .bind(bind_ids(&ids.user_ids))
+    .fetch_all(&mut ***db)
+    .await?;
+
+    Ok(rows.into_iter().map(Expense::from).collect())
+}
+
+fn order_total(order: &Order) -> BigDecimal {
+    order.items.iter().map(OrderItem::total).sum()
+}
+
+// POST /reports/create [Permissions: REPORTS]
+// {
+//     "start_date": "2024-01-01T00:00:00Z",
+//     "end_date": "2024-03-31T23:59:59Z",
+//     "report_types": ["Revenue", "Profit", "Payable"],
+//     "filters": [{ "CustomerId": 3 }, { "ProductId": 7 }]
+// }
+// -> 200 OK
+//     Report with the orders, purchases and expenses of the period, and the requested
+//     figures in "data", e.g. { "Revenue": "120.00", ... }
+// Every filter applies to all the figures, and documents without the party or product a
+// filter names are left out: a customer filter leaves out purchases and expenses, a supplier
+// filter orders and expenses, and a product filter expenses. Profit with a customer or product
+// filter is then the margin of the matching items, and Payable with a customer filter is 0.
+#[rocket::post("/reports/create", data = "<report_request>")]
+#[allow(private_interfaces)]
+pub async fn create_report(
+    mut db: DB,
+    report_request: rocket::serde::json::Json<ReportRequest>,
+    _auth: AuthGuard<perm::REPORTS>,
+) -> Result<rocket::serde::json::Json<Report>, ApiError> {
+    let ReportRequest {
+        start_date,
+        end_date,
+        filters,
+        report_types,
+    } = report_request.into_inner();
+
+    let ids = ReportFilterIds::new(&filters);
+
+    let orders = report_orders(start_date, end_date, &ids, &mut db).await?;
+    let purchases = report_purchases(start_date, end_date, &ids, &mut db).await?;
+    let expenses = report_expenses(start_date, end_date, &ids, &mut db).await?;
+
+    let zero = BigDecimal::from(0);
 
-    // Calculate revenue
     let mut total_revenue = BigDecimal::from(0);
     let mut total_receivable = BigDecimal::from(0);
-    for (order_meta, order_items) in orders {
-        let order_total: BigDecimal = order_items
+    let mut total_cogs = BigDecimal::from(0);
+    let mut units_sold = 0i64;
+
+    for order in &orders {
+        // Payments are for the whole order, not just the items a product filter leaves
+        total_receivable += (&order.meta.total - &order.meta.amount_paid).max(zero.clone());
+        total_revenue += order_total(order);
+        total_cogs += order.items.iter().map(|item| &item.cost).sum::<BigDecimal>();
+        units_sold += order
+            .items
             .iter()
-            .map(|item| &item.price * BigDecimal::from(item.quantity))
-            .sum();
+            .map(|item| item.quantity as i64)
+            .sum::<i64>();
+    }
 
-        let receivable = (&order_total - &order_meta.amount_paid).max(BigDecimal::from(0));
-        total_revenue += order_total;
-        total_receivable += receivable;
+    let mut total_payable = BigDecimal::from(0);
 
-        let order = Order {
-            meta: order_meta,
-            items: order_items,
-        };
-
-        res.orders.push(order);
+    for (purchase, total) in &purchases {
+        total_payable += (total - &purchase.meta.amount_paid).max(zero.clone());
     }
 
-    // Calculate profit
+    let purchases = purchases
+        .into_iter()
+        .map(|(purchase, _)| purchase)
+        .collect();
+
+    let total_expenses: BigDecimal = expenses.iter().map(|e| &e.amount).sum();
+
+    let mut data = HashMap::new();
 
-    // Fill return data
     for ty in ReportRequestType::variants() {
         if report_types.contains(&ty) {
             let value = match ty {
                 ReportRequestType::Revenue => total_revenue.clone(),
-                ReportRequestType::Profit => BigDecimal::from(0),
-                ReportRequestType::Expenses => BigDecimal::from(0),
-                ReportRequestType::Product => BigDecimal::from(0),
+                ReportRequestType::Profit => &total_revenue - &total_cogs - &total_expenses,
+                ReportRequestType::Expenses => total_expenses.clone(),
+                ReportRequestType::Product => BigDecimal::from(units_sold),
                 ReportRequestType::Receivable => total_receivable.clone(),
-                ReportRequestType::Payable => BigDecimal::from(0),
+                ReportRequestType::Payable => total_payable.clone(),
             };
 
-            res.data.insert(ty, value);
+            data.insert(ty, value);
         }
     }
 
-    Ok(rocket::serde::json::Json::from(res))
+    Ok(rocket::serde::json::Json(Report {
+        start_date,
+        end_date,
+        orders,
+        expenses,
+        purchases,
+        data,
+    }))
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
@@ -255,6 +434,18 @@ pub(super) enum OrderReportFilter {
     CustomerId(i32),
 }
 
+fn order_report_ids(filters: &[OrderReportFilter]) -> ReportFilterIds {
+    ReportFilterIds::new(
+        &filters
+            .iter()
+            .map(|f| match f {
+                OrderReportFilter::UserId(id) => ReportFilter::UserId(*id),
+                OrderReportFilter::CustomerId(id) => ReportFilter::CustomerId(*id),
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
 #[ts(export)]
 pub(super) struct OrderReportRequest {
@@ -278,7 +469,7 @@ pub(super) struct OrderReport {
 pub(super) async fn create_order_report(
     mut db: DB,
     report_request: rocket::serde::json::Json<OrderReportRequest>,
-    _auth: AuthGuard<{ UserPermissionEnum::REPORTS as u32 }>,
+    _auth: AuthGuard<perm::REPORTS>,
 ) -> Result<rocket::serde::json::Json<OrderReport>, ApiError> {
     let OrderReportRequest {
         start_date,
@@ -286,110 +477,16 @@ pub(super) async fn create_order_report(
         filters,
     } = report_request.into_inner();
 
-    let start_date_naive = start_date.naive_utc();
-    let end_date_naive = end_date.naive_utc();
+    let ids = order_report_ids(&filters);
 
-    let mut filters_sql_order_meta = filters
-        .iter()
-        .map(|f| match f {
-            OrderReportFilter::UserId(id) => format!("AND orders.created_by_user_id = {}", id),
-            OrderReportFilter::CustomerId(id) => format!("AND orders.customer_id = {}", id),
-        })
-        .collect::<Vec<String>>()
-        .join(" ");
-
-    #[derive(Debug, FromRow)]
-    struct OrderMetaRowWithItems {
-        #[sqlx(flatten)]
-        order_meta: OrderMetaRow,
-        items: sqlx_core::types::Json<Vec<OrderItemRow>>,
-    }
-
-    let orders_query_string = format!(
-        r#"
-    WITH order_meta AS (
-        SELECT 
-            orders.id,
-            orders.date_time,
-            orders.amount_paid,
-            orders.retail,
-            orders.retail_customer_name,
-            orders.retail_customer_phone,
-            orders.retail_customer_address,
-            orders.notes,
-            orders.fulfilled,
-            row_to_json(customers) AS customer,
-            row_to_json(users) AS created_by_user
-        FROM orders
-            LEFT JOIN customers ON orders.customer_id = customers.id
-            INNER JOIN users ON orders.created_by_user_id = users.id
-        WHERE date_time BETWEEN $1 AND $2
-        {}
-    )
-    SELECT
-        order_meta.*,
-        -- COALESCE is used to return an empty array if there are no items
-        COALESCE(
-            (
-                SELECT json_agg(
-                    json_build_object(
-                        'id', order_items.id,
-                        'inventory', row_to_json(inventory),
-                        'price', order_items.price,
-                        'quantity', order_items.quantity
-                    )
-                )
-                FROM order_items
-                    INNER JOIN inventory ON order_items.inventory_id = inventory.id
-                WHERE order_items.order_id = order_meta.id
-                {}
-            ), '[]'
-        ) AS items
-    FROM order_meta
-    "#,
-        filters_sql_order_meta, ""
-    );
-
-    let data: Vec<(OrderMeta, Vec<OrderItem>)> = sqlx::query_as(orders_query_string.as_str())
-        .bind(start_date)
-        .bind(end_date)
-        .fetch_all(&mut **db)
-        .await
-        .map_err(|e| ApiError(Status::InternalServerError, e.to_string()))
-        .map(|rows: Vec<OrderMetaRowWithItems>| {
-            rows.into_iter()
-                .map(|row| {
-                    (
-                        OrderMeta::from(row.order_meta),
-                        row.items
-                            .0
-                            .into_iter()
-                            .map(|item| OrderItem::from(item))
-                            .collect(),
-                    )
-                })
-                .collect()
-        })?;
+    let orders = report_orders(start_date, end_date, &ids, &mut db).await?;
 
     let mut total_revenue = BigDecimal::from(0);
     let mut total_receivable = BigDecimal::from(0);
-    let mut orders = vec![];
-    for (order_meta, order_items) in data {
-        let order_total: BigDecimal = order_items
-            .iter()
-            .map(|item| &item.price * BigDecimal::from(item.quantity))
-            .sum();
 
-        let receivable = (&order_total - &order_meta.amount_paid).max(BigDecimal::from(0));
-        total_revenue += order_total;
-        total_receivable += receivable;
-
-        let order = Order {
-            meta: order_meta,
-            items: order_items,
-        };
-
-        orders.push(order);
+    for order in &orders {
+        total_receivable += (&order.meta.total - &order.meta.amount_paid).max(BigDecimal::from(0));
+        total_revenue += order_total(order);
     }
 
     Ok(rocket::serde::json::Json(OrderReport {
@@ -401,6 +498,56 @@ pub(super) async fn create_order_report(
     }))
 }
 
+// POST /reports/create/order/export?format=xlsx [Permissions: REPORTS]
+// Request: OrderReportRequest
+// format is csv (default) or xlsx
+// -> 200 OK
+//     order_report.csv or order_report.xlsx, the orders of the report
+// -> 400 Bad Request (invalid format)
+#[rocket::post("/reports/create/order/export?<format>", data = "<report_request>")]
+#[allow(private_interfaces)]
+pub(super) async fn export_order_report(
+    format: Option<&str>,
+    db: DB,
+    report_request: rocket::serde::json::Json<OrderReportRequest>,
+    _auth: AuthGuard<perm::REPORTS>,
+) -> Result<Export, ApiError> {
+    let format = ExportFormat::parse(format)?;
+    let OrderReportRequest {
+        start_date,
+        end_date,
+        filters,
+    } = report_request.into_inner();
+
+    let ids = order_report_ids(&filters);
+
+    let rest = format!(
+        r#"
+        FROM {}
+        WHERE orders.date_time BETWEEN $2 AND $3
+            AND ($4::INT[] IS NULL OR orders.created_by_user_id = ANY($4))
+            AND ($5::INT[] IS NULL OR orders.customer_id = ANY($5))
+        ORDER BY orders.date_time, orders.id
+        "#,
+        ORDERS_FROM
+    );
+
+    Export::new(
+        format,
+        "order_report",
+        ORDER_EXPORT_COLUMNS,
+        rest,
+        vec![
+            SqlType::DateTime(start_date),
+            SqlType::DateTime(end_date),
+            SqlType::IntArray(bind_ids(&ids.user_ids)),
+            SqlType::IntArray(bind_ids(&ids.customer_ids)),
+        ],
+        db,
+    )
+    .await
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
 #[ts(export)]
 pub(super) enum ExpenseReportFilter {
@@ -426,12 +573,48 @@ pub(super) struct ExpenseReport {
     total_expenses: BigDecimal,
 }
 
+/// Conditions of the expense report filters, bound from `current_param` on.
+/// User filters match any of their ids, like ReportFilter.
+fn expense_report_conditions(
+    filters: &[ExpenseReportFilter],
+    current_param: &mut i32,
+) -> (String, Vec<SqlType>) {
+    let mut conditions = String::new();
+    let mut binds = vec![];
+    let mut user_ids = vec![];
+
+    for filter in filters {
+        match filter {
+            ExpenseReportFilter::UserId(id) => user_ids.push(*id),
+            ExpenseReportFilter::DescriptionSearch(search) => {
+                conditions += &format!(
+                    "\nAND expenses.description @@ to_tsquery(${})",
+                    current_param
+                );
+                binds.push(SqlType::String(search.clone()));
+                *current_param += 1;
+            }
+        }
+    }
+
+    if !user_ids.is_empty() {
+        conditions += &format!(
+            "\nAND expenses.created_by_user_id = ANY(${})",
+            current_param
+        );
+        binds.push(SqlType::IntArray(Some(user_ids)));
+        *current_param += 1;
+    }
+
+    (conditions, binds)
+}
+
 #[rocket::post("/reports/create/expense", data = "<report_request>")]
 #[allow(private_interfaces)]
 pub(super) async fn create_expense_report(
     mut db: DB,
     report_request: rocket::serde::json::Json<ExpenseReportRequest>,
-    _auth: AuthGuard<{ UserPermissionEnum::REPORTS as u32 }>,
+    _auth: AuthGuard<perm::REPORTS>,
 ) -> Result<rocket::serde::json::Json<ExpenseReport>, ApiError> {
     let ExpenseReportRequest {
         start_date,
@@ -439,27 +622,7 @@ pub(super) async fn create_expense_report(
         filters,
     } = report_request.into_inner();
 
-    let start_date_naive = start_date.naive_utc();
-    let end_date_naive = end_date.naive_utc();
-
-    let mut filters_sql_expenses = filters
-        .iter()
-        .map(|f| match f {
-            ExpenseReportFilter::UserId(id) => {
-                format!("\nAND expenses.created_by_user_id = {}", id)
-            }
-            _ => "".to_string(),
-        })
-        .collect::<Vec<String>>()
-        .join(" ");
-
-    // text search
-    if filters
-        .iter()
-        .any(|f| matches!(f, ExpenseReportFilter::DescriptionSearch(_)))
-    {
-        filters_sql_expenses += "\nAND description @@ to_tsquery($3)";
-    }
+    let (conditions, binds) = expense_report_conditions(&filters, &mut 3);
 
     let expenses_query_string = format!(
         r#"
@@ -469,28 +632,21 @@ pub(super) async fn create_expense_report(
         expenses.description,
         row_to_json(users) AS created_by_user,
         expenses.amount
-    FROM expenses
-        INNER JOIN users ON expenses.created_by_user_id = users.id
+    FROM {}
     WHERE date_time BETWEEN $1 AND $2
     {}
     "#,
-        filters_sql_expenses
+        EXPENSES_FROM, conditions
     );
 
-    let mut query = sqlx::query_as::<_, ExpenseRow>(expenses_query_string.as_str())
-        .bind(start_date_naive)
-        .bind(end_date_naive);
-
-    for filter in &filters {
-        match filter {
-            ExpenseReportFilter::DescriptionSearch(search) => {
-                query = query.bind(search);
-            }
-            _ => {}
-        }
-    }
-
-    let expenses: Vec<ExpenseRow> = query
+    let expenses: Vec<ExpenseRow> = binds
+        .into_iter()
+        .fold(
+            sqlx::query_as::<_, ExpenseRow>(expenses_query_string.as_str())
+                .bind(start_date)
+                .bind(end_date),
+            |q, value| value.bind_to_query_as(q),
+        )
         .fetch_all(&mut **db)
         .await
         .map_err(|e| ApiError(Status::InternalServerError, e.to_string()))?;
@@ -506,3 +662,1000 @@ pub(super) async fn create_expense_report(
         total_expenses,
     }))
 }
+
+// POST /reports/create/expense/export?format=xlsx [Permissions: REPORTS]
+// Request: ExpenseReportRequest
+// format is csv (default) or xlsx
+// -> 200 OK
+//     expense_report.csv or expense_report.xlsx, the expenses of the report
+// -> 400 Bad Request (invalid format)
+#[rocket::post("/reports/create/expense/export?<format>", data = "<report_request>")]
+#[allow(private_interfaces)]
+pub(super) async fn export_expense_report(
+    format: Option<&str>,
+    db: DB,
+    report_request: rocket::serde::json::Json<ExpenseReportRequest>,
+    _auth: AuthGuard<perm::REPORTS>,
+) -> Result<Export, ApiError> {
+    let format = ExportFormat::parse(format)?;
+    let ExpenseReportRequest {
+        start_date,
+        end_date,
+        filters,
+    } = report_request.into_inner();
+
+    let (conditions, binds) = expense_report_conditions(&filters, &mut 4);
+
+    let rest = format!(
+        "FROM {} WHERE expenses.date_time BETWEEN $2 AND $3 {} ORDER BY expenses.date_time, expenses.id",
+        EXPENSES_FROM, conditions
+    );
+
+    Export::new(
+        format,
+        "expense_report",
+        EXPENSE_EXPORT_COLUMNS,
+        rest,
+        [SqlType::DateTime(start_date), SqlType::DateTime(end_date)]
+            .into_iter()
+            .chain(binds)
+            .collect(),
+        db,
+    )
+    .await
+}
+
+/// Outstanding amounts split by how many days they are past due
+#[derive(Serialize, Deserialize, Clone, Debug, Default, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct AgingBuckets {
+    /// Not due yet, or due on the report date
+    pub not_due: BigDecimal,
+    /// 1 to 30 days past due
+    pub days_0_30: BigDecimal,
+    pub days_31_60: BigDecimal,
+    pub days_61_90: BigDecimal,
+    pub days_over_90: BigDecimal,
+    pub total: BigDecimal,
+}
+
+impl AgingBuckets {
+    fn add(&mut self, days_overdue: i32, amount: &BigDecimal) {
+        let bucket = match days_overdue {
+            i32::MIN..=0 => &mut self.not_due,
+            1..=30 => &mut self.days_0_30,
+            31..=60 => &mut self.days_31_60,
+            61..=90 => &mut self.days_61_90,
+            _ => &mut self.days_over_90,
+        };
+
+        *bucket += amount;
+        self.total += amount;
+    }
+}
+
+#[derive(FromRow, Debug)]
+struct AgingDocumentRow {
+    id: i32,
+    party_id: Option<i32>,
+    party_name: String,
+    payment_terms_days: i32,
+    date_time: chrono::DateTime<chrono::Utc>,
+    due_date: chrono::NaiveDate,
+    days_overdue: i32,
+    total: BigDecimal,
+    paid: BigDecimal,
+}
+
+/// An order or purchase with an unpaid amount on the report date
+#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct AgingDocument {
+    /// Id of the order or purchase
+    pub id: i32,
+    pub date_time: chrono::DateTime<chrono::Utc>,
+    pub due_date: chrono::NaiveDate,
+    /// Negative when it isn't due yet
+    pub days_overdue: i32,
+    pub total: BigDecimal,
+    /// Allocated from payments made up to the report date
+    pub paid: BigDecimal,
+    pub outstanding: BigDecimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct AgingParty {
+    /// Customer or supplier id, null for retail orders
+    pub party_id: Option<i32>,
+    pub name: String,
+    pub payment_terms_days: i32,
+    pub buckets: AgingBuckets,
+    /// Oldest first
+    pub documents: Vec<AgingDocument>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct AgingReport {
+    pub as_of: chrono::NaiveDate,
+    /// Per customer, retail orders are grouped together
+    pub receivables: Vec<AgingParty>,
+    pub receivable_totals: AgingBuckets,
+    /// Per supplier
+    pub payables: Vec<AgingParty>,
+    pub payable_totals: AgingBuckets,
+}
+
+/// Unpaid orders as of `$1` (exclusive end of the report date), `$2` being the default terms
+const RECEIVABLE_DOCUMENTS: &str = r#"
+    SELECT
+        orders.id,
+        orders.customer_id AS party_id,
+        COALESCE(customers.name, 'Retail') AS party_name,
+        COALESCE(customers.payment_terms_days, $2) AS payment_terms_days,
+        orders.date_time,
+        orders.total,
+        (
+            SELECT COALESCE(SUM(payment_allocations.amount), 0)
+            FROM payment_allocations
+                INNER JOIN payments ON payment_allocations.payment_id = payments.id
+            WHERE payment_allocations.order_id = orders.id AND payments.date_time < $1
+        ) AS paid
+    FROM orders
+        LEFT JOIN customers ON orders.customer_id = customers.id
+    WHERE orders.date_time < $1
+"#;
+
+/// Unpaid purchases, with the same parameters as `RECEIVABLE_DOCUMENTS`
+const PAYABLE_DOCUMENTS: &str = r#"
+    SELECT
+        purchases.id,
+        purchases.supplier_id AS party_id,
+        suppliers.name AS party_name,
+        COALESCE(suppliers.payment_terms_days, $2) AS payment_terms_days,
+        purchases.date_time,
+        (
+            SELECT COALESCE(SUM(price * quantity), 0) FROM purchase_items
+            WHERE purchase_id = purchases.id
+        ) AS total,
+        (
+            SELECT COALESCE(SUM(payment_allocations.amount), 0)
+            FROM payment_allocations
+                INNER JOIN payments ON payment_allocations.payment_id = payments.id
+            WHERE payment_allocations.purchase_id = purchases.id AND payments.date_time < $1
+        ) AS paid
+    FROM purchases
+        INNER JOIN suppliers ON purchases.supplier_id = suppliers.id
+    WHERE purchases.date_time < $1
+"#;
+
+async fn aging_parties(
+    documents_sql: &str,
+    as_of: chrono::NaiveDate,
+    default_terms: i32,
+    db: &mut DB,
+) -> Result<(Vec<AgingParty>, AgingBuckets), ApiError> {
+    let (_, end) = DateRange {
+        from: None,
+        to: Some(as_of),
+    }
+    .bounds(db)
+    .await?;
+    let timezone = timezone(db).await?;
+
+    let rows: Vec<AgingDocumentRow> = sqlx::query_as(&format!(
+        r#"
+        SELECT
+            documents.*,
+            due_dates.due_date,
+            $3::DATE - due_dates.due_date AS days_overdue
+        FROM ({}) documents
+            CROSS JOIN LATERAL (
+                SELECT (
+                    documents.date_time AT TIME ZONE $4
+                        + make_interval(days => documents.payment_terms_days)
+                )::DATE AS due_date
+            ) due_dates
+        WHERE documents.total > documents.paid
+        ORDER BY documents.party_name, documents.party_id, documents.date_time, documents.id
+        "#,
+        documents_sql
+    ))
+    .bind(end)
+    .bind(default_terms)
+    .bind(as_of)
+    .bind(&timezone)
+    .fetch_all(&mut ***db)
+    .await?;
+
+    let mut parties: Vec<AgingParty> = vec![];
+    let mut totals = AgingBuckets::default();
+
+    for row in rows {
+        let outstanding = &row.total - &row.paid;
+
+        totals.add(row.days_overdue, &outstanding);
+
+        let party = match parties.last_mut() {
+            Some(party) if party.party_id == row.party_id => party,
+            _ => {
+                parties.push(AgingParty {
+                    party_id: row.party_id,
+                    name: row.party_name,
+                    payment_terms_days: row.payment_terms_days,
+                    buckets: AgingBuckets::default(),
+                    documents: vec![],
+                });
+                parties.last_mut().unwrap()
+            }
+        };
+
+        party.buckets.add(row.days_overdue, &outstanding);
+        party.documents.push(AgingDocument {
+            id: row.id,
+            date_time: row.date_time,
+            due_date: row.due_date,
+            days_overdue: row.days_overdue,
+            total: row.total,
+            paid: row.paid,
+            outstanding,
+        });
+    }
+
+    Ok((parties, totals))
+}
+
+// GET /reports/aging?as_of=2024-03-31 [Permissions: REPORTS]
+// as_of defaults to today. Days are in the timezone setting. Orders and purchases are due after
+// the payment terms of their customer or supplier, or the payment_terms_days setting.
+// -> 200 OK
+// {
+//     "as_of": "2024-03-31",
+//     "receivables": [
+//         {
+//             "party_id": 3,
+//             "name": "John",
+//             "payment_terms_days": 30,
+//             "buckets": {
+//                 "not_due": "20.00",
+//                 "days_0_30": "0",
+//                 "days_31_60": "45.00",
+//                 "days_61_90": "0",
+//                 "days_over_90": "0",
+//                 "total": "65.00"
+//             },
+//             "documents": [
+//                 {
+//                     "id": 12,
+//                     "date_time": "2024-01-20T10:00:00Z",
+//                     "due_date": "2024-02-19",
+//                     "days_overdue": 41,
+//                     "total": "60.00",
+//                     "paid": "15.00",
+//                     "outstanding": "45.00"
+//                 },
+//                 ...
+//             ]
+//         }
+//     ],
+//     "receivable_totals": { ... },
+//     "payables": [...],
+//     "payable_totals": { ... }
+// }
+// -> 400 Bad Request (invalid date)
+#[rocket::get("/reports/aging?<as_of>")]
+pub(super) async fn aging(
+    as_of: Option<&str>,
+    mut db: DB,
+    _auth: AuthGuard<perm::REPORTS>,
+) -> Result<rocket::serde::json::Json<AgingReport>, ApiError> {
+    let as_of = match as_of {
+        Some(as_of) => parse_date("as_of", as_of)?,
+        None => today(&mut db).await?,
+    };
+
+    let default_terms = payment_terms_days(&mut db).await? as i32;
+
+    let (receivables, receivable_totals) =
+        aging_parties(RECEIVABLE_DOCUMENTS, as_of, default_terms, &mut db).await?;
+    let (payables, payable_totals) =
+        aging_parties(PAYABLE_DOCUMENTS, as_of, default_terms, &mut db).await?;
+
+    Ok(rocket::serde::json::Json(AgingReport {
+        as_of,
+        receivables,
+        receivable_totals,
+        payables,
+        payable_totals,
+    }))
+}
+
+/// Total of an order item after its discount, like get_order_total
+const ORDER_ITEM_TOTAL: &str = r#"
+    CASE WHEN order_items.discount_percentage
+        THEN order_items.price * order_items.quantity * (1 - order_items.discount / 100)
+        ELSE order_items.price * order_items.quantity - order_items.discount
+    END
+"#;
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct ProductMargin {
+    pub inventory_id: i32,
+    pub name: String,
+    pub units_sold: i64,
+    /// Line totals after discounts
+    pub revenue: BigDecimal,
+    /// Cost of the units sold, by the inventory costing method
+    pub cogs: BigDecimal,
+    pub margin: BigDecimal,
+    /// Margin as a percentage of the revenue, null without revenue
+    pub margin_percentage: Option<BigDecimal>,
+}
+
+// GET /reports/product_margins?from=2024-01-01&to=2024-03-31 [Permissions: REPORTS]
+// from and to are days in the timezone setting and both optional.
+// -> 200 OK
+// [
+//     {
+//         "inventory_id": 7,
+//         "name": "Widget",
+//         "units_sold": 30,
+//         "revenue": "297.0000",
+//         "cogs": "180.0000",
+//         "margin": "117.0000",
+//         "margin_percentage": "39.39"
+//     },
+//     ...
+// ]
+// -> 400 Bad Request (invalid date)
+#[rocket::get("/reports/product_margins?<from>&<to>")]
+pub(super) async fn product_margins(
+    from: Option<&str>,
+    to: Option<&str>,
+    mut db: DB,
+    _auth: AuthGuard<perm::REPORTS>,
+) -> Result<rocket::serde::json::Json<Vec<ProductMargin>>, ApiError> {
+    let (start, end) = DateRange::parse(from, to)?.bounds(&mut db).await?;
+
+    let margins: Vec<ProductMargin> = sqlx::query_as(&format!(
+        r#"
+        SELECT
+            inventory_id,
+            name,
+            units_sold,
+            revenue,
+            cogs,
+            revenue - cogs AS margin,
+            ROUND((revenue - cogs) / NULLIF(revenue, 0) * 100, 2) AS margin_percentage
+        FROM (
+            SELECT
+                inventory.id AS inventory_id,
+                inventory.name,
+                SUM(order_items.quantity)::BIGINT AS units_sold,
+                SUM({}) AS revenue,
+                SUM(order_items.cost) AS cogs
+            FROM order_items
+                INNER JOIN orders ON order_items.order_id = orders.id
+                INNER JOIN inventory ON order_items.inventory_id = inventory.id
+            WHERE ($1::TIMESTAMPTZ IS NULL OR orders.date_time >= $1)
+                AND ($2::TIMESTAMPTZ IS NULL OR orders.date_time < $2)
+            GROUP BY inventory.id
+        ) sales
+        ORDER BY margin DESC, inventory_id
+        "#,
+        ORDER_ITEM_TOTAL
+    ))
+    .bind(start)
+    .bind(end)
+    .fetch_all(&mut **db)
+    .await?;
+
+    Ok(rocket::serde::json::Json(margins))
+}
+
+/// Length of the periods of a profit and loss statement
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, ts_rs::TS)]
+#[ts(export)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum PnlPeriod {
+    Month,
+    Quarter,
+    Year,
+}
+
+impl PnlPeriod {
+    fn parse(value: &str) -> Result<Self, ApiError> {
+        match value {
+            "month" => Ok(Self::Month),
+            "quarter" => Ok(Self::Quarter),
+            "year" => Ok(Self::Year),
+            _ => Err(ApiError(
+                Status::BadRequest,
+                format!("Invalid period: {}, expected month, quarter or year", value),
+            )),
+        }
+    }
+
+    fn months(self) -> i32 {
+        match self {
+            Self::Month => 1,
+            Self::Quarter => 3,
+            Self::Year => 12,
+        }
+    }
+
+    /// Number of the period containing the date, counted from year 0
+    fn index(self, date: NaiveDate) -> i32 {
+        (date.year() * 12 + date.month0() as i32) / self.months()
+    }
+
+    /// First day of the period with the index
+    fn start(self, index: i32) -> NaiveDate {
+        let month = index * self.months();
+
+        NaiveDate::from_ymd_opt(month.div_euclid(12), month.rem_euclid(12) as u32 + 1, 1)
+            .expect("the first day of a month is a valid date")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct PnlFigures {
+    /// Order totals after discounts
+    pub revenue: BigDecimal,
+    /// Cost of the goods sold, by the inventory costing method
+    pub cost_of_goods_sold: BigDecimal,
+    pub gross_profit: BigDecimal,
+    pub operating_expenses: BigDecimal,
+    pub net_profit: BigDecimal,
+}
+
+impl PnlFigures {
+    fn new(revenue: BigDecimal, cost_of_goods_sold: BigDecimal, operating_expenses: BigDecimal) -> Self {
+        let gross_profit = &revenue - &cost_of_goods_sold;
+        let net_profit = &gross_profit - &operating_expenses;
+
+        Self {
+            revenue,
+            cost_of_goods_sold,
+            gross_profit,
+            operating_expenses,
+            net_profit,
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.revenue += &other.revenue;
+        self.cost_of_goods_sold += &other.cost_of_goods_sold;
+        self.gross_profit += &other.gross_profit;
+        self.operating_expenses += &other.operating_expenses;
+        self.net_profit += &other.net_profit;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct PnlPeriodFigures {
+    pub start: NaiveDate,
+    /// Last day of the period
+    pub end: NaiveDate,
+    pub figures: PnlFigures,
+    /// Figures of the period just before this one
+    pub previous: PnlFigures,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct PnlReport {
+    /// Start of the first period
+    pub from: NaiveDate,
+    /// End of the last period
+    pub to: NaiveDate,
+    pub period: PnlPeriod,
+    pub periods: Vec<PnlPeriodFigures>,
+    pub totals: PnlFigures,
+    /// Totals of the same number of periods just before `from`
+    pub previous_totals: PnlFigures,
+}
+
+const MAX_PNL_PERIODS: i32 = 120;
+
+// GET /reports/pnl?from=2024-01-01&to=2024-06-30&period=quarter [Permissions: REPORTS]
+// from and to are widened to whole periods, which start at midnight in the timezone setting.
+// period is month (default), quarter or year.
+// -> 200 OK
+// {
+//     "from": "2024-01-01",
+//     "to": "2024-06-30",
+//     "period": "quarter",
+//     "periods": [
+//         {
+//             "start": "2024-01-01",
+//             "end": "2024-03-31",
+//             "figures": {
+//                 "revenue": "1200.00",
+//                 "cost_of_goods_sold": "700.00",
+//                 "gross_profit": "500.00",
+//                 "operating_expenses": "150.00",
+//                 "net_profit": "350.00"
+//             },
+//             "previous": { ...figures of 2023-10-01 to 2023-12-31 }
+//         },
+//         ...
+//     ],
+//     "totals": { ... },
+//     "previous_totals": { ...figures of 2023-07-01 to 2023-12-31 }
+// }
+// -> 400 Bad Request (missing or invalid dates, invalid period, over 120 periods)
+#[rocket::get("/reports/pnl?<from>&<to>&<period>")]
+pub(super) async fn pnl(
+    from: Option<&str>,
+    to: Option<&str>,
+    period: Option<&str>,
+    mut db: DB,
+    _auth: AuthGuard<perm::REPORTS>,
+) -> Result<rocket::serde::json::Json<PnlReport>, ApiError> {
+    let range = DateRange::parse(from, to)?;
+    let (Some(from), Some(to)) = (range.from, range.to) else {
+        return Err(ApiError(
+            Status::BadRequest,
+            "from and to are required".to_string(),
+        ));
+    };
+    let period = period.map(PnlPeriod::parse).transpose()?.unwrap_or(PnlPeriod::Month);
+
+    let first = period.index(from);
+    let count = period.index(to) - first + 1;
+
+    if count > MAX_PNL_PERIODS {
+        return Err(ApiError(
+            Status::BadRequest,
+            format!("At most {} periods can be reported at once", MAX_PNL_PERIODS),
+        ));
+    }
+
+    #[derive(FromRow)]
+    struct PnlRow {
+        revenue: BigDecimal,
+        cost_of_goods_sold: BigDecimal,
+        operating_expenses: BigDecimal,
+    }
+
+    // The periods of the comparison come first, then the periods of the report
+    let rows: Vec<PnlRow> = sqlx::query_as(&format!(
+        r#"
+        SELECT
+            COALESCE((
+                SELECT SUM({})
+                FROM order_items
+                    INNER JOIN orders ON order_items.order_id = orders.id
+                WHERE orders.date_time >= periods.period_start
+                    AND orders.date_time < periods.period_end
+            ), 0) AS revenue,
+            COALESCE((
+                SELECT SUM(order_items.cost)
+                FROM order_items
+                    INNER JOIN orders ON order_items.order_id = orders.id
+                WHERE orders.date_time >= periods.period_start
+                    AND orders.date_time < periods.period_end
+            ), 0) AS cost_of_goods_sold,
+            COALESCE((
+                SELECT SUM(expenses.amount)
+                FROM expenses
+                WHERE expenses.date_time >= periods.period_start
+                    AND expenses.date_time < periods.period_end
+            ), 0) AS operating_expenses
+        FROM generate_series(0, $2 - 1) AS i
+            CROSS JOIN LATERAL (
+                SELECT
+                    ($1::DATE + make_interval(months => i * $3)) AT TIME ZONE $4 AS period_start,
+                    ($1::DATE + make_interval(months => (i + 1) * $3)) AT TIME ZONE $4
+                        AS period_end
+            ) periods
+        ORDER BY i
+        "#,
+        ORDER_ITEM_TOTAL
+    ))
+    .bind(period.start(first - count))
+    .bind(count * 2)
+    .bind(period.months())
+    .bind(timezone(&mut db).await?)
+    .fetch_all(&mut **db)
+    .await?;
+
+    let figures: Vec<PnlFigures> = rows
+        .into_iter()
+        .map(|row| PnlFigures::new(row.revenue, row.cost_of_goods_sold, row.operating_expenses))
+        .collect();
+
+    let mut totals = PnlFigures::default();
+    let mut previous_totals = PnlFigures::default();
+
+    for previous in &figures[..count as usize] {
+        previous_totals.add(previous);
+    }
+
+    let periods = (0..count)
+        .map(|i| {
+            let current = &figures[(count + i) as usize];
+            totals.add(current);
+
+            PnlPeriodFigures {
+                start: period.start(first + i),
+                end: period.start(first + i + 1).pred_opt().unwrap_or(NaiveDate::MIN),
+                figures: current.clone(),
+                previous: figures[(count + i - 1) as usize].clone(),
+            }
+        })
+        .collect();
+
+    Ok(rocket::serde::json::Json(PnlReport {
+        from: period.start(first),
+        to: period.start(first + count).pred_opt().unwrap_or(NaiveDate::MIN),
+        period,
+        periods,
+        totals,
+        previous_totals,
+    }))
+}
+
+/// Length of the buckets of the sales analytics, truncated with date_trunc
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, ts_rs::TS)]
+#[ts(export)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum SalesInterval {
+    Day,
+    /// Weeks start on Monday
+    Week,
+    Month,
+}
+
+impl SalesInterval {
+    fn parse(value: &str) -> Result<Self, ApiError> {
+        match value {
+            "day" => Ok(Self::Day),
+            "week" => Ok(Self::Week),
+            "month" => Ok(Self::Month),
+            _ => Err(ApiError(
+                Status::BadRequest,
+                format!("Invalid interval: {}, expected day, week or month", value),
+            )),
+        }
+    }
+
+    fn sql(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, ts_rs::TS)]
+#[ts(export)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum SalesGroupBy {
+    /// Retail orders have no customer and are grouped together
+    Customer,
+    /// Inventory item of the order items
+    Product,
+    /// User who created the order
+    User,
+}
+
+impl SalesGroupBy {
+    fn parse(value: &str) -> Result<Self, ApiError> {
+        match value {
+            "customer" => Ok(Self::Customer),
+            "product" => Ok(Self::Product),
+            "user" => Ok(Self::User),
+            _ => Err(ApiError(
+                Status::BadRequest,
+                format!("Invalid group_by: {}, expected customer, product or user", value),
+            )),
+        }
+    }
+
+    /// Id and name columns of the group
+    fn sql(self) -> (&'static str, &'static str) {
+        match self {
+            Self::Customer => ("orders.customer_id", "customers.name"),
+            Self::Product => ("order_items.inventory_id", "inventory.name"),
+            Self::User => ("orders.created_by_user_id", "users.username"),
+        }
+    }
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct SalesBucket {
+    /// First day of the bucket in the business timezone
+    pub start: NaiveDate,
+    /// Customer, inventory item or user id, null without grouping and for retail orders
+    pub group_id: Option<i32>,
+    pub group_name: Option<String>,
+    /// Line totals after discounts
+    pub revenue: BigDecimal,
+    /// Orders with items in the bucket and group
+    pub orders: i64,
+    pub units_sold: i64,
+    pub average_order_value: BigDecimal,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct SalesReport {
+    /// Timezone setting the orders were bucketed in
+    pub timezone: String,
+    pub interval: SalesInterval,
+    pub group_by: Option<SalesGroupBy>,
+    pub buckets: Vec<SalesBucket>,
+}
+
+// GET /reports/sales?from=2024-01-01&to=2024-12-31&interval=month&group_by=product [Permissions: REPORTS]
+// from and to are days in the timezone setting and both optional.
+// interval is day (default), week or month, group_by is customer, product or user.
+// -> 200 OK
+// {
+//     "timezone": "Europe/Berlin",
+//     "interval": "month",
+//     "group_by": "product",
+//     "buckets": [
+//         {
+//             "start": "2024-01-01",
+//             "group_id": 7,
+//             "group_name": "Widget",
+//             "revenue": "297.0000",
+//             "orders": 4,
+//             "units_sold": 30,
+//             "average_order_value": "74.2500"
+//         },
+//         ...
+//     ]
+// }
+// -> 400 Bad Request (invalid dates, interval or group_by)
+#[rocket::get("/reports/sales?<from>&<to>&<interval>&<group_by>")]
+pub(super) async fn sales(
+    from: Option<&str>,
+    to: Option<&str>,
+    interval: Option<&str>,
+    group_by: Option<&str>,
+    mut db: DB,
+    _auth: AuthGuard<perm::REPORTS>,
+) -> Result<rocket::serde::json::Json<SalesReport>, ApiError> {
+    let range = DateRange::parse(from, to)?;
+    let interval = interval.map(SalesInterval::parse).transpose()?.unwrap_or(SalesInterval::Day);
+    let group_by = group_by.map(SalesGroupBy::parse).transpose()?;
+
+    let timezone = timezone(&mut db).await?;
+
+    let (group_id, group_name) = group_by
+        .map(SalesGroupBy::sql)
+        .unwrap_or(("NULL::INT", "NULL::TEXT"));
+
+    // local_time is the wall clock time of the order in the business timezone
+    let buckets: Vec<SalesBucket> = sqlx::query_as(&format!(
+        r#"
+        SELECT
+            date_trunc($1, sales.local_time)::DATE AS start,
+            sales.group_id,
+            sales.group_name,
+            SUM(sales.total) AS revenue,
+            COUNT(DISTINCT sales.order_id) AS orders,
+            SUM(sales.quantity)::BIGINT AS units_sold,
+            ROUND(SUM(sales.total) / COUNT(DISTINCT sales.order_id), 4) AS average_order_value
+        FROM (
+            SELECT
+                orders.date_time AT TIME ZONE $2 AS local_time,
+                orders.id AS order_id,
+                {} AS group_id,
+                {} AS group_name,
+                {} AS total,
+                order_items.quantity
+            FROM orders
+                INNER JOIN order_items ON order_items.order_id = orders.id
+                INNER JOIN inventory ON order_items.inventory_id = inventory.id
+                LEFT JOIN customers ON orders.customer_id = customers.id
+                INNER JOIN users ON orders.created_by_user_id = users.id
+        ) sales
+        WHERE ($3::DATE IS NULL OR sales.local_time >= $3)
+            AND ($4::DATE IS NULL OR sales.local_time < $4 + 1)
+        GROUP BY 1, sales.group_id, sales.group_name
+        ORDER BY 1, sales.group_id NULLS FIRST
+        "#,
+        group_id, group_name, ORDER_ITEM_TOTAL
+    ))
+    .bind(interval.sql())
+    .bind(&timezone)
+    .bind(range.from)
+    .bind(range.to)
+    .fetch_all(&mut **db)
+    .await?;
+
+    Ok(rocket::serde::json::Json(SalesReport {
+        timezone,
+        interval,
+        group_by,
+        buckets,
+    }))
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct ProductPerformance {
+    pub inventory_id: i32,
+    pub name: String,
+    pub stock: i32,
+    /// Position by units sold, null without sales in the range
+    pub rank: Option<i64>,
+    pub units_sold: i64,
+    /// Line totals after discounts
+    pub revenue: BigDecimal,
+    /// Difference between the list price of the lines and their totals
+    pub discount: BigDecimal,
+    /// Null for items that were never purchased, as their cost is unknown
+    pub cogs: Option<BigDecimal>,
+    pub margin: Option<BigDecimal>,
+    pub margin_percentage: Option<BigDecimal>,
+    /// Last sale up to the end of the range
+    pub last_sold_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Days the stock lasts at the average daily sales of the range, null without sales
+    pub days_of_cover: Option<BigDecimal>,
+    /// In stock but not sold in the last `dead_stock_days` days of the range
+    pub dead_stock: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct ProductReport {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub dead_stock_days: i32,
+    pub products: Vec<ProductPerformance>,
+}
+
+// GET /reports/products?from=2024-01-01&to=2024-03-31&dead_stock_days=90 [Permissions: REPORTS]
+// to defaults to today, from to 30 days before to, and dead_stock_days to 90.
+// Days are in the timezone setting.
+// Items are sorted by units sold, archived items are only included when they sold.
+// -> 200 OK
+// {
+//     "from": "2024-01-01",
+//     "to": "2024-03-31",
+//     "dead_stock_days": 90,
+//     "products": [
+//         {
+//             "inventory_id": 7,
+//             "name": "Widget",
+//             "stock": 120,
+//             "rank": 1,
+//             "units_sold": 91,
+//             "revenue": "900.0000",
+//             "discount": "10.0000",
+//             "cogs": "546.0000",
+//             "margin": "354.0000",
+//             "margin_percentage": "39.33",
+//             "last_sold_at": "2024-03-30T15:20:00Z",
+//             "days_of_cover": "120.0",
+//             "dead_stock": false
+//         },
+//         ...
+//     ]
+// }
+// -> 400 Bad Request (invalid dates or dead_stock_days)
+#[rocket::get("/reports/products?<from>&<to>&<dead_stock_days>")]
+pub(super) async fn products(
+    from: Option<&str>,
+    to: Option<&str>,
+    dead_stock_days: Option<&str>,
+    mut db: DB,
+    _auth: AuthGuard<perm::REPORTS>,
+) -> Result<rocket::serde::json::Json<ProductReport>, ApiError> {
+    let range = DateRange::parse(from, to)?;
+    let to = match range.to {
+        Some(to) => to,
+        None => today(&mut db).await?,
+    };
+    let from = range.from.unwrap_or(to - chrono::Days::new(29));
+
+    if from > to {
+        return Err(ApiError(
+            Status::BadRequest,
+            "from can't be after to".to_string(),
+        ));
+    }
+
+    let dead_stock_days = match dead_stock_days {
+        Some(days) => match days.parse::<i32>() {
+            Ok(days) if days >= 1 => days,
+            _ => {
+                return Err(ApiError(
+                    Status::BadRequest,
+                    format!("Invalid dead_stock_days: {}, expected a number of days", days),
+                ))
+            }
+        },
+        None => 90,
+    };
+
+    let (start, end) = DateRange {
+        from: Some(from),
+        to: Some(to),
+    }
+    .bounds(&mut db)
+    .await?;
+    let days = (to - from).num_days() + 1;
+
+    let products: Vec<ProductPerformance> = sqlx::query_as(&format!(
+        r#"
+        WITH sales AS (
+            SELECT
+                order_items.inventory_id,
+                SUM(order_items.quantity) AS units_sold,
+                SUM({}) AS revenue,
+                SUM(order_items.price * order_items.quantity) AS list_total,
+                SUM(order_items.cost) AS cogs
+            FROM order_items
+                INNER JOIN orders ON order_items.order_id = orders.id
+            WHERE orders.date_time >= $1 AND orders.date_time < $2
+            GROUP BY order_items.inventory_id
+        ), last_sales AS (
+            SELECT order_items.inventory_id, MAX(orders.date_time) AS last_sold_at
+            FROM order_items
+                INNER JOIN orders ON order_items.order_id = orders.id
+            WHERE orders.date_time < $2
+            GROUP BY order_items.inventory_id
+        ), products AS (
+            SELECT
+                inventory.id AS inventory_id,
+                inventory.name,
+                inventory.stock,
+                COALESCE(sales.units_sold, 0)::BIGINT AS units_sold,
+                COALESCE(sales.revenue, 0) AS revenue,
+                COALESCE(sales.list_total - sales.revenue, 0) AS discount,
+                CASE WHEN EXISTS (
+                    SELECT 1 FROM purchase_items WHERE purchase_items.inventory_id = inventory.id
+                ) THEN COALESCE(sales.cogs, 0) END AS cogs,
+                last_sales.last_sold_at,
+                -- Quantities in the range can sum to zero or less, which has no rate to divide by
+                CASE WHEN sales.units_sold > 0
+                    THEN ROUND(inventory.stock / (sales.units_sold::NUMERIC / $3::INT), 1)
+                END AS days_of_cover,
+                inventory.stock > 0 AND (
+                    last_sales.last_sold_at IS NULL
+                    OR last_sales.last_sold_at < $2 - make_interval(days => $4)
+                ) AS dead_stock
+            FROM inventory
+                LEFT JOIN sales ON sales.inventory_id = inventory.id
+                LEFT JOIN last_sales ON last_sales.inventory_id = inventory.id
+            WHERE inventory.archived_at IS NULL OR sales.inventory_id IS NOT NULL
+        )
+        SELECT
+            *,
+            CASE WHEN units_sold > 0
+                THEN RANK() OVER (ORDER BY units_sold DESC)
+            END AS rank,
+            revenue - cogs AS margin,
+            ROUND((revenue - cogs) / NULLIF(revenue, 0) * 100, 2) AS margin_percentage
+        FROM products
+        ORDER BY units_sold DESC, revenue DESC, name
+        "#,
+        ORDER_ITEM_TOTAL
+    ))
+    .bind(start)
+    .bind(end)
+    .bind(days as i32)
+    .bind(dead_stock_days)
+    .fetch_all(&mut **db)
+    .await?;
+
+    Ok(rocket::serde::json::Json(ProductReport {
+        from,
+        to,
+        dead_stock_days,
+        products,
+    }))
+}
@@ -0,0 +1,396 @@
+use rocket::{http::Status, serde::json::Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, Executor, PgConnection, Postgres};
+
+use crate::{
+    db::DB,
+    types::permissions::{perm, PermissionKey, ADMIN_KEY},
+};
+
+use super::{
+    auth::{self, AuthGuard},
+    ApiError, ApiReturn, SqlType,
+};
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct Role {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+    pub permissions: Vec<PermissionKey>,
+    pub created_at: sqlx::types::chrono::DateTime<chrono::Utc>,
+    /// Number of users with this role, including archived ones
+    pub user_count: i64,
+}
+
+/// Reject keys that aren't in `PERMISSIONS`, so typos don't silently grant nothing
+pub(super) fn validate_permissions(permissions: &[PermissionKey]) -> Result<(), ApiError> {
+    let unknown: Vec<&str> = permissions
+        .iter()
+        .filter(|p| !p.is_registered())
+        .map(|p| p.0.as_str())
+        .collect();
+
+    if !unknown.is_empty() {
+        return Err(ApiError(
+            Status::BadRequest,
+            format!("Unknown permissions: {}", unknown.join(", ")),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Recalculate the `users.permissions` cache from the roles of every user.
+/// Call in the same transaction as any change to roles or role assignments.
+pub(super) async fn refresh_user_permissions(conn: &mut PgConnection) -> Result<(), ApiError> {
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET permissions = ARRAY(
+            SELECT DISTINCT role_permissions.permission
+            FROM user_roles
+            JOIN role_permissions ON role_permissions.role_id = user_roles.role_id
+            WHERE user_roles.user_id = users.id
+            ORDER BY 1
+        )
+        "#,
+    )
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Replace the roles of a user. Doesn't refresh the permissions cache.
+pub(super) async fn set_user_roles(
+    user_id: i32,
+    role_ids: &[i32],
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    sqlx::query("DELETE FROM user_roles WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_roles (user_id, role_id)
+        SELECT $1, UNNEST($2::INT[])
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(user_id)
+    .bind(role_ids)
+    .execute(conn)
+    .await
+    .map_err(map_role_error)?;
+
+    Ok(())
+}
+
+/// Id of a role holding ADMIN, created if none exists.
+/// Used for the default admin user, see db.rs.
+pub async fn administrator_role<'a, E>(conn: E) -> Result<i32, ApiError>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let id = sqlx::query_scalar(
+        r#"
+        WITH existing AS (
+            SELECT role_id AS id FROM role_permissions
+            WHERE permission = $1
+            ORDER BY role_id
+            LIMIT 1
+        ), created AS (
+            INSERT INTO roles (name, description)
+            SELECT 'Administrator', 'Full access'
+            WHERE NOT EXISTS (SELECT 1 FROM existing)
+            ON CONFLICT (name) DO UPDATE SET name = EXCLUDED.name
+            RETURNING id
+        ), granted AS (
+            INSERT INTO role_permissions (role_id, permission)
+            SELECT id, $1 FROM created
+            ON CONFLICT DO NOTHING
+        )
+        SELECT id FROM existing
+        UNION ALL
+        SELECT id FROM created
+        "#,
+    )
+    .bind(ADMIN_KEY)
+    .fetch_one(conn)
+    .await?;
+
+    Ok(id)
+}
+
+fn map_role_error(e: sqlx::Error) -> ApiError {
+    match e {
+        sqlx::Error::Database(e) if e.is_unique_violation() => {
+            ApiError(Status::BadRequest, "Role name already exists".to_string())
+        }
+        sqlx::Error::Database(e) if e.is_foreign_key_violation() => {
+            ApiError(Status::BadRequest, "Role not found".to_string())
+        }
+        e => e.into(),
+    }
+}
+
+// GET /auth/roles [Permissions: ADMIN]
+// -> 200 OK
+//     [
+//         {
+//             "id": 1,
+//             "name": "Administrator",
+//             "description": "Full access",
+//             "permissions": ["ADMIN"],
+//             "created_at": "...",
+//             "user_count": 1
+//         }
+//     ]
+#[rocket::get("/auth/roles")]
+pub(super) async fn list(
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Json<Vec<Role>>, ApiError> {
+    let roles = sqlx::query_as(
+        r#"
+        SELECT
+            roles.*,
+            ARRAY(
+                SELECT permission FROM role_permissions
+                WHERE role_id = roles.id
+                ORDER BY permission
+            ) AS permissions,
+            (SELECT COUNT(*) FROM user_roles WHERE role_id = roles.id) AS user_count
+        FROM roles
+        ORDER BY name
+        "#,
+    )
+    .fetch_all(&mut **db)
+    .await?;
+
+    Ok(Json(roles))
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct RolePostRequest {
+    name: String,
+    #[serde(default)]
+    description: String,
+    permissions: Vec<PermissionKey>,
+}
+
+// POST /auth/roles [Permissions: ADMIN]
+// {
+//     "name": "Cashier",
+//     "description": "Front counter",
+//     "permissions": ["ORDER_CREATE", "ORDER_READ", "CUSTOMERS_READ"]
+// }
+// -> 201 Created
+//     3 (id of the new role)
+// -> 400 Bad Request
+#[rocket::post("/auth/roles", data = "<req>")]
+pub(super) async fn post(
+    req: Json<RolePostRequest>,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<ApiReturn<i32>, ApiError> {
+    let req = req.into_inner();
+
+    if req.name.trim().is_empty() {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Role name cannot be empty".to_string(),
+        ));
+    }
+
+    validate_permissions(&req.permissions)?;
+
+    let mut transaction = db.begin().await?;
+
+    let id: i32 =
+        sqlx::query_scalar("INSERT INTO roles (name, description) VALUES ($1, $2) RETURNING id")
+            .bind(&req.name)
+            .bind(&req.description)
+            .fetch_one(&mut *transaction)
+            .await
+            .map_err(map_role_error)?;
+
+    set_role_permissions(id, &req.permissions, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(ApiReturn(Status::Created, id))
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct RolePatchRequest {
+    name: Option<String>,
+    description: Option<String>,
+    /// Replaces all permissions of the role
+    permissions: Option<Vec<PermissionKey>>,
+}
+
+// PATCH /auth/roles/<id> [Permissions: ADMIN]
+// {
+//     "name": "Cashier",
+//     "permissions": ["ORDER_CREATE", "ORDER_READ"]
+// }
+// -> 200 OK
+// -> 400 Bad Request
+// -> 404 Not Found
+// Takes effect immediately for every user with the role.
+#[rocket::patch("/auth/roles/<id>", data = "<req>")]
+pub(super) async fn patch(
+    req: Json<RolePatchRequest>,
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Status, ApiError> {
+    let req = req.into_inner();
+
+    if let Some(name) = &req.name {
+        if name.trim().is_empty() {
+            return Err(ApiError(
+                Status::BadRequest,
+                "Role name cannot be empty".to_string(),
+            ));
+        }
+    }
+
+    if let Some(permissions) = &req.permissions {
+        validate_permissions(permissions)?;
+    }
+
+    let mut current_param = 1;
+
+    let columns = vec![
+        req.name.as_ref().map(|_| "name"),
+        req.description.as_ref().map(|_| "description"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<&str>>();
+
+    let sets_string = super::generate_sets_string(&columns, &mut current_param);
+
+    let set_binds = vec![
+        req.name.as_ref().map(|v| SqlType::String(v.clone())),
+        req.description.as_ref().map(|v| SqlType::String(v.clone())),
+    ]
+    .into_iter()
+    .flatten();
+
+    let mut transaction = db.begin().await?;
+
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM roles WHERE id = $1)")
+        .bind(id)
+        .fetch_one(&mut *transaction)
+        .await?;
+
+    if !exists {
+        return Err(ApiError(Status::NotFound, "Role not found".to_string()));
+    }
+
+    if !sets_string.is_empty() {
+        let query_str = format!(
+            r#"
+            UPDATE roles
+            SET {}
+            WHERE id = ${}
+            "#,
+            sets_string, current_param
+        );
+
+        let query = sqlx::query(&query_str);
+
+        let query = set_binds.fold(query, |query, value| value.bind_to_query(query));
+
+        query
+            .bind(id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(map_role_error)?;
+    }
+
+    if let Some(permissions) = &req.permissions {
+        set_role_permissions(id, permissions, &mut transaction).await?;
+    }
+
+    transaction.commit().await?;
+
+    Ok(Status::Ok)
+}
+
+// DELETE /auth/roles/<id> [Permissions: ADMIN]
+// -> 200 OK
+// -> 400 Bad Request (would leave no admin)
+// -> 404 Not Found
+// Users with the role lose its permissions.
+#[rocket::delete("/auth/roles/<id>")]
+pub(super) async fn delete(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Status, ApiError> {
+    let mut transaction = db.begin().await?;
+
+    // Same as patch_user, stops two admins from removing each other's admin role
+    sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await?;
+
+    let res = sqlx::query("DELETE FROM roles WHERE id = $1")
+        .bind(id)
+        .execute(&mut *transaction)
+        .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ApiError(Status::NotFound, "Role not found".to_string()));
+    }
+
+    refresh_user_permissions(&mut transaction).await?;
+
+    auth::ensure_admin_exists(&mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Status::Ok)
+}
+
+/// Replace the permissions of a role and refresh the cache of its users
+async fn set_role_permissions(
+    role_id: i32,
+    permissions: &[PermissionKey],
+    conn: &mut PgConnection,
+) -> Result<(), ApiError> {
+    sqlx::query("LOCK TABLE users IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM role_permissions WHERE role_id = $1")
+        .bind(role_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO role_permissions (role_id, permission)
+        SELECT $1, UNNEST($2::TEXT[])
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(role_id)
+    .bind(permissions)
+    .execute(&mut *conn)
+    .await?;
+
+    refresh_user_permissions(conn).await?;
+
+    auth::ensure_admin_exists(conn).await
+}
@@ -8,8 +8,10 @@ pub(super) struct SearchRequest {
     /// Access nested columns using dot notation
     /// customers.name
     /// This will mean that column is disregarded
-    /// NOTE: Nested access only works if the tables are
-    /// joined in the query. This will happen if there is a foreign key
-    /// relationship between the tables.
+    /// NOTE: Only columns the route allows can be searched, see routes/query.rs.
+    /// Others are rejected with 400 Bad Request and the list of valid names.
     pub nested_access: Option<String>,
+    /// Also return archived rows. Only used by tables that can be archived
+    #[serde(default)]
+    pub include_archived: bool,
 }
\ No newline at end of file
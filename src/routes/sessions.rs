@@ -0,0 +1,235 @@
+use rocket::{
+    http::{Cookie, CookieJar, SameSite, Status},
+    serde::json::Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, Postgres};
+
+use crate::{db::DB, types::permissions::perm};
+
+use super::{auth::AuthGuard, ApiError};
+
+/// Name of the private cookie holding the session token
+pub(super) const SESSION_COOKIE: &str = "auth_info";
+
+#[derive(Serialize, Deserialize, sqlx::FromRow, Debug, Clone, PartialEq, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct Session {
+    pub id: i32,
+    pub created_at: sqlx::types::chrono::DateTime<chrono::Utc>,
+    pub last_seen_at: sqlx::types::chrono::DateTime<chrono::Utc>,
+    /// Sessions without an expiry last until they are logged out
+    pub expires_at: Option<sqlx::types::chrono::DateTime<chrono::Utc>>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    /// True for the session that made the request
+    #[sqlx(default)]
+    pub current: bool,
+}
+
+/// IP address and user agent of the client, stored with new sessions
+pub(super) struct ClientInfo {
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        rocket::request::Outcome::Success(ClientInfo {
+            ip_address: request.client_ip().map(|ip| ip.to_string()),
+            user_agent: request.headers().get_one("User-Agent").map(String::from),
+        })
+    }
+}
+
+/// Sessions are looked up by the SHA-256 of their token,
+/// so a leaked database doesn't leak usable tokens.
+pub(super) fn hash_token(token: &str) -> String {
+    use ring::digest::{digest, SHA256};
+
+    let hash = digest(&SHA256, token.as_bytes());
+
+    hash.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(super) fn generate_token() -> String {
+    use rand::RngCore;
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Create a session and return its token, which is only ever sent to the client
+pub(super) async fn create_session<'a, E>(
+    user_id: i32,
+    expires_at: Option<rocket::time::OffsetDateTime>,
+    client: &ClientInfo,
+    conn: E,
+) -> Result<String, ApiError>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let token = generate_token();
+
+    // Expired sessions are never used again, clean them up while we're here
+    sqlx::query(
+        r#"
+        WITH expired AS (
+            DELETE FROM sessions
+            WHERE user_id = $2 AND expires_at <= CURRENT_TIMESTAMP
+        )
+        INSERT INTO sessions (token_hash, user_id, expires_at, ip_address, user_agent)
+        VALUES ($1, $2, to_timestamp($3), $4, $5)
+        "#,
+    )
+    .bind(hash_token(&token))
+    .bind(user_id)
+    .bind(expires_at.map(|t| t.unix_timestamp() as f64))
+    .bind(&client.ip_address)
+    .bind(&client.user_agent)
+    .execute(conn)
+    .await?;
+
+    Ok(token)
+}
+
+pub(super) fn session_cookie(
+    token: String,
+    expires_at: Option<rocket::time::OffsetDateTime>,
+) -> Cookie<'static> {
+    let mut cookie = Cookie::new(SESSION_COOKIE, token);
+
+    // No expiry makes it a session cookie
+    cookie.set_expires(expires_at);
+
+    cookie.set_same_site(SameSite::Lax);
+    cookie.set_secure(true);
+
+    cookie
+}
+
+/// Log out every session of a user, except `keep_session_id` if provided
+pub(super) async fn revoke_user_sessions<'a, E>(
+    user_id: i32,
+    keep_session_id: Option<i32>,
+    conn: E,
+) -> Result<u64, ApiError>
+where
+    E: Executor<'a, Database = Postgres>,
+{
+    let res = sqlx::query("DELETE FROM sessions WHERE user_id = $1 AND id IS DISTINCT FROM $2")
+        .bind(user_id)
+        .bind(keep_session_id)
+        .execute(conn)
+        .await?;
+
+    Ok(res.rows_affected())
+}
+
+// GET /auth/sessions
+// -> 200 OK
+//     [
+//         {
+//             "id": 1,
+//             "created_at": "...",
+//             "last_seen_at": "...",
+//             "expires_at": null,
+//             "ip_address": "127.0.0.1",
+//             "user_agent": "...",
+//             "current": true
+//         }
+//     ]
+#[rocket::get("/auth/sessions")]
+pub(super) async fn list(mut db: DB, auth: AuthGuard<perm::NONE>) -> Result<Json<Vec<Session>>, ApiError> {
+    let sessions: Vec<Session> = sqlx::query_as(
+        r#"
+        SELECT id, created_at, last_seen_at, expires_at, ip_address, user_agent, id = $2 AS current
+        FROM sessions
+        WHERE user_id = $1 AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+        ORDER BY last_seen_at DESC
+        "#,
+    )
+    .bind(auth.auth_info.user.id)
+    .bind(auth.auth_info.session_id())
+    .fetch_all(&mut **db)
+    .await?;
+
+    Ok(Json(sessions))
+}
+
+// DELETE /auth/sessions/<id>
+// -> 200 OK
+// -> 404 Not Found
+// -> 403 Forbidden (sent with an API token)
+// Only sessions of the logged in user can be revoked.
+#[rocket::delete("/auth/sessions/<id>")]
+pub(super) async fn revoke(
+    id: i32,
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+    cookies: &CookieJar<'_>,
+) -> Result<Status, ApiError> {
+    auth.auth_info.require_session()?;
+
+    let res = sqlx::query("DELETE FROM sessions WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(auth.auth_info.user.id)
+        .execute(&mut **db)
+        .await?;
+
+    if res.rows_affected() == 0 {
+        return Err(ApiError(Status::NotFound, "Session not found".to_string()));
+    }
+
+    if Some(id) == auth.auth_info.session_id() {
+        cookies.remove_private(Cookie::from(SESSION_COOKIE));
+    }
+
+    Ok(Status::Ok)
+}
+
+// POST /auth/logout_everywhere
+// -> 200 OK
+// -> 403 Forbidden (sent with an API token)
+// Logs out every session of the logged in user, including the current one.
+#[rocket::post("/auth/logout_everywhere")]
+pub(super) async fn logout_everywhere(
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+    cookies: &CookieJar<'_>,
+) -> Result<Status, ApiError> {
+    auth.auth_info.require_session()?;
+
+    let count = revoke_user_sessions(auth.auth_info.user.id, None, &mut **db).await?;
+
+    log::info!(
+        "Logged out {} sessions of user {}",
+        count,
+        auth.auth_info.user.id
+    );
+
+    cookies.remove_private(Cookie::from(SESSION_COOKIE));
+
+    Ok(Status::Ok)
+}
+
+// POST /auth/users/<id>/logout [Permissions: ADMIN]
+// -> 200 OK
+//     2 (number of sessions logged out)
+#[rocket::post("/auth/users/<id>/logout")]
+pub(super) async fn force_logout(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Json<u64>, ApiError> {
+    let count = revoke_user_sessions(id, None, &mut **db).await?;
+
+    Ok(Json(count))
+}
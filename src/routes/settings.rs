@@ -3,9 +3,10 @@ use rocket::{http::Status, serde::json::Json};
 use crate::{
     db::DB,
     settings::{
-        ensure_settings_exist, get_setting, get_settings, reset_settings, set_setting, Setting, SettingRow
+        ensure_settings_exist, get_setting, get_settings, reset_settings, set_setting, Setting, SettingRow,
+        SettingValue, INVENTORY_COSTING_METHODS,
     },
-    types::permissions::UserPermissionEnum,
+    types::permissions::perm,
 };
 
 use super::{auth::AuthGuard, ApiError};
@@ -91,15 +92,54 @@ pub(super) async fn get_multiple(
 pub(super) async fn set(
     setting: Json<Setting>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::SETTINGS as u32 }>,
+    _auth: AuthGuard<perm::SETTINGS>,
 ) -> Result<(), ApiError> {
+    if setting.key == "inventory_costing_method" {
+        let valid = matches!(
+            &setting.value,
+            SettingValue::Text(method) if INVENTORY_COSTING_METHODS.contains(&method.as_str())
+        );
+
+        if !valid {
+            return Err(ApiError(
+                Status::BadRequest,
+                format!(
+                    "inventory_costing_method must be one of: {}",
+                    INVENTORY_COSTING_METHODS.join(", ")
+                ),
+            ));
+        }
+    }
+
+    if setting.key == "timezone" {
+        let SettingValue::Text(timezone) = &setting.value else {
+            return Err(ApiError(
+                Status::BadRequest,
+                "timezone must be text".to_string(),
+            ));
+        };
+
+        let known: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_timezone_names WHERE name = $1)")
+                .bind(timezone)
+                .fetch_one(&mut **db)
+                .await?;
+
+        if !known {
+            return Err(ApiError(
+                Status::BadRequest,
+                format!("Unknown timezone: {}", timezone),
+            ));
+        }
+    }
+
     set_setting(&mut db, setting.0.into()).await?;
 
     Ok(())
 }
 
 #[rocket::get("/settings/reset")]
-pub(super) async fn reset(mut db: DB, _auth: AuthGuard<{ UserPermissionEnum::SETTINGS as u32 }>) -> Result<(), ApiError> {
+pub(super) async fn reset(mut db: DB, _auth: AuthGuard<perm::SETTINGS>) -> Result<(), ApiError> {
     reset_settings(&mut db).await?;
 
     Ok(())
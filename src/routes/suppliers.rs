@@ -5,19 +5,51 @@ use sqlx::prelude::FromRow;
 use crate::{
     db::{FromDB, DB},
     routes::{auth::AuthGuard, search::SearchRequest, ListRequest},
-    types::permissions::UserPermissionEnum,
+    types::permissions::perm,
 };
 
-use super::{ApiError, ApiReturn, SqlType};
+use super::{
+    export::{Export, ExportColumn, ExportFormat, ExportKind},
+    query::{Column, Columns, ListQuery},
+    ApiError, ApiReturn, ListResponse, SqlType,
+};
 
 #[derive(Serialize, Deserialize, Clone, Debug, ts_rs::TS, FromRow)]
 #[ts(export)]
 pub(super) struct Supplier {
-    id: i32,
-    name: String,
-    phone: String,
-    address: String,
-    notes: String,
+    pub id: i32,
+    pub name: String,
+    pub phone: String,
+    pub address: String,
+    pub notes: String,
+    /// Days to pay an purchase, null uses the payment_terms_days setting
+    #[serde(default)]
+    pub payment_terms_days: Option<i32>,
+    /// Archived suppliers are hidden from lists but still shown on existing records
+    #[serde(default)]
+    pub archived_at: Option<sqlx::types::chrono::DateTime<chrono::Utc>>,
+}
+
+/// Columns that can be used in list and search requests
+const COLUMNS: Columns = Columns(&[
+    Column::new("id"),
+    Column::new("name"),
+    Column::new("phone"),
+    Column::new("address"),
+    Column::new("notes"),
+    Column::new("payment_terms_days"),
+    Column::new("archived_at"),
+]);
+
+fn check_payment_terms(days: Option<i32>) -> Result<(), ApiError> {
+    if days.is_some_and(|days| days < 0) {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Payment terms can't be negative".to_string(),
+        ));
+    }
+
+    Ok(())
 }
 
 impl FromDB for Supplier {
@@ -43,9 +75,9 @@ impl FromDB for Supplier {
 #[rocket::get("/suppliers/count")]
 pub(super) async fn count(
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::SUPPLIERS_READ as u32 }>,
+    _auth: AuthGuard<perm::SUPPLIERS_READ>,
 ) -> Result<Json<i64>, ApiError> {
-    let count = sqlx::query_scalar("SELECT COUNT(*) FROM suppliers")
+    let count = sqlx::query_scalar("SELECT COUNT(*) FROM suppliers WHERE archived_at IS NULL")
         .fetch_one(&mut **db)
         .await?;
 
@@ -56,57 +88,72 @@ pub(super) async fn count(
 pub(super) async fn list(
     req: Json<ListRequest>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::SUPPLIERS_READ as u32 }>,
-) -> Result<Json<Vec<Supplier>>, ApiError> {
+    _auth: AuthGuard<perm::SUPPLIERS_READ>,
+) -> Result<Json<ListResponse<Supplier>>, ApiError> {
     let req = req.into_inner();
 
-    let mut current_param = 1;
-
-    let sorts_string = super::generate_sorts_string(&req.sorts);
-
-    let (filters_string, filter_binds) =
-        super::generate_filters_string(&req.filters, &mut current_param);
-
-    let query_str = format!(
-        r#"
-        SELECT * FROM suppliers
-        {}
-        {}
-        LIMIT ${}
-        OFFSET ${}
-        "#,
-        filters_string,
-        sorts_string,
-        current_param,
-        current_param + 1
-    );
-
-    let query = sqlx::query_as(&query_str);
+    let page = COLUMNS
+        .page::<Supplier, _>(
+            ListQuery {
+                select: "*",
+                from: "suppliers",
+                key: "id",
+                condition: (!req.include_archived).then_some("archived_at IS NULL"),
+            },
+            &req,
+            &mut db,
+        )
+        .await?;
 
-    let query = filter_binds
-        .into_iter()
-        .fold(query, |query, value| value.bind_to_query_as(query));
+    Ok(Json(page))
+}
 
-    let data = query
-        .bind(req.range.count)
-        .bind(req.range.offset)
-        .fetch_all(&mut **db)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::ColumnNotFound(column) => {
-                ApiError(Status::BadRequest, format!("Column not found: {}", column))
-            }
-            _ => e.into(),
-        })?;
+/// Columns of supplier exports
+const EXPORT_COLUMNS: &[ExportColumn] = &[
+    ExportColumn::new("ID", "id", ExportKind::Integer),
+    ExportColumn::new("Name", "name", ExportKind::Text),
+    ExportColumn::new("Phone", "phone", ExportKind::Text),
+    ExportColumn::new("Address", "address", ExportKind::Text),
+    ExportColumn::new("Notes", "notes", ExportKind::Text),
+    ExportColumn::new("Payment Terms (Days)", "payment_terms_days", ExportKind::Integer),
+    ExportColumn::new("Archived At", "archived_at", ExportKind::DateTime),
+];
+
+// POST /suppliers/export?format=xlsx [Permissions: SUPPLIERS_READ]
+// Request: ListRequest, the range is ignored and every matching row is exported
+// format is csv (default) or xlsx
+// -> 200 OK
+//     suppliers.csv or suppliers.xlsx
+// -> 400 Bad Request (invalid format, columns or filters)
+#[rocket::post("/suppliers/export?<format>", data = "<req>")]
+pub(super) async fn export(
+    format: Option<&str>,
+    req: Json<ListRequest>,
+    db: DB,
+    _auth: AuthGuard<perm::SUPPLIERS_READ>,
+) -> Result<Export, ApiError> {
+    let format = ExportFormat::parse(format)?;
+    let req = req.into_inner();
 
-    Ok(Json(data))
+    let (rest, binds) = COLUMNS.export_string(
+        &ListQuery {
+            select: "*",
+            from: "suppliers",
+            key: "id",
+            condition: (!req.include_archived).then_some("archived_at IS NULL"),
+        },
+        &req,
+        &mut 2,
+    )?;
+
+    Export::new(format, "suppliers", EXPORT_COLUMNS, rest, binds, db).await
 }
 
 #[rocket::get("/suppliers/<id>")]
 pub(super) async fn get(
     id: i32,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::SUPPLIERS_READ as u32 }>,
+    _auth: AuthGuard<perm::SUPPLIERS_READ>,
 ) -> Result<Json<Supplier>, ApiError> {
     Ok(Json(Supplier::from_db(id, &mut db).await?))
 }
@@ -118,20 +165,26 @@ pub(super) struct SupplierPostRequest {
     pub phone: String,
     pub address: String,
     pub notes: String,
+    /// Null uses the payment_terms_days setting
+    #[serde(default)]
+    #[ts(optional)]
+    pub payment_terms_days: Option<i32>,
 }
 
 #[rocket::post("/suppliers", data = "<item>")]
 pub(super) async fn post(
     item: Json<SupplierPostRequest>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::SUPPLIERS_CREATE as u32 }>,
+    _auth: AuthGuard<perm::SUPPLIERS_CREATE>,
 ) -> Result<ApiReturn<i32>, ApiError> {
     let item = item.into_inner();
 
+    check_payment_terms(item.payment_terms_days)?;
+
     let id: (i32,) = sqlx::query_as(
         r#"
-        INSERT INTO suppliers (name, phone, address, notes)
-        VALUES ($1, $2, $3, $4)
+        INSERT INTO suppliers (name, phone, address, notes, payment_terms_days)
+        VALUES ($1, $2, $3, $4, $5)
         RETURNING id
         "#,
     )
@@ -139,6 +192,7 @@ pub(super) async fn post(
     .bind(&item.phone)
     .bind(&item.address)
     .bind(&item.notes)
+    .bind(item.payment_terms_days)
     .fetch_one(&mut **db)
     .await?;
 
@@ -152,6 +206,12 @@ pub(super) struct SupplierPatchRequest {
     pub phone: Option<String>,
     pub address: Option<String>,
     pub notes: Option<String>,
+    #[serde(default)]
+    #[ts(optional)]
+    pub payment_terms_days: Option<i32>,
+    /// Go back to the payment_terms_days setting
+    #[serde(default)]
+    pub set_payment_terms_days_null: bool,
 }
 
 #[rocket::patch("/suppliers/<id>", data = "<req>")]
@@ -159,10 +219,14 @@ pub(super) async fn patch(
     id: i32,
     req: Json<SupplierPatchRequest>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::SUPPLIERS_UPDATE as u32 }>,
+    _auth: AuthGuard<perm::SUPPLIERS_UPDATE>,
 ) -> Result<Status, ApiError> {
     let req = req.into_inner();
 
+    check_payment_terms(req.payment_terms_days)?;
+
+    let set_terms_null = req.set_payment_terms_days_null;
+
     let mut current_param = 1;
 
     let columns = vec![
@@ -170,6 +234,7 @@ pub(super) async fn patch(
         req.phone.as_ref().map(|_| "phone"),
         req.address.as_ref().map(|_| "address"),
         req.notes.as_ref().map(|_| "notes"),
+        (req.payment_terms_days.is_some() || set_terms_null).then_some("payment_terms_days"),
     ]
     .into_iter()
     .flatten()
@@ -186,6 +251,11 @@ pub(super) async fn patch(
         req.phone.as_ref().map(|v| SqlType::String(v.clone())),
         req.address.as_ref().map(|v| SqlType::String(v.clone())),
         req.notes.as_ref().map(|v| SqlType::String(v.clone())),
+        if set_terms_null {
+            Some(SqlType::Null)
+        } else {
+            req.payment_terms_days.map(SqlType::Int)
+        },
     ]
     .into_iter()
     .flatten();
@@ -221,19 +291,17 @@ pub(super) async fn patch(
 pub(super) async fn search(
     req: Json<SearchRequest>,
     mut db: DB,
-    _auth: AuthGuard<{ UserPermissionEnum::SUPPLIERS_READ as u32 }>,
+    _auth: AuthGuard<perm::SUPPLIERS_READ>,
 ) -> Result<Json<Vec<Supplier>>, ApiError> {
     let req = req.into_inner();
 
-    let x = req
-        .column
-        .unwrap_or(req.nested_access.unwrap_or("id".to_string()));
+    let x = COLUMNS.search_column(&req)?;
 
     let query_str = format!(
         r#"
         SELECT *, word_similarity($1, {}::text) AS sml
         FROM suppliers
-        WHERE $1 <% {}::text
+        WHERE $1 <% {}::text AND ($3 OR archived_at IS NULL)
         ORDER BY sml DESC, {}::text
         LIMIT $2
         "#,
@@ -245,6 +313,7 @@ pub(super) async fn search(
     let data = query
         .bind(req.search)
         .bind(req.count)
+        .bind(req.include_archived)
         .fetch_all(&mut **db)
         .await
         .map_err(|e| match e {
@@ -256,3 +325,27 @@ pub(super) async fn search(
 
     Ok(Json(data))
 }
+
+// POST /suppliers/<id>/archive [Permissions: SUPPLIERS_DELETE]
+// -> 200 OK
+// -> 404 Not Found
+#[rocket::post("/suppliers/<id>/archive")]
+pub(super) async fn archive(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::SUPPLIERS_DELETE>,
+) -> Result<Status, ApiError> {
+    super::set_archived("suppliers", id, true, &mut db).await
+}
+
+// POST /suppliers/<id>/restore [Permissions: SUPPLIERS_DELETE]
+// -> 200 OK
+// -> 404 Not Found
+#[rocket::post("/suppliers/<id>/restore")]
+pub(super) async fn restore(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::SUPPLIERS_DELETE>,
+) -> Result<Status, ApiError> {
+    super::set_archived("suppliers", id, false, &mut db).await
+}
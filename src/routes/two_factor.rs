@@ -0,0 +1,651 @@
+use rocket::{
+    http::{CookieJar, Status},
+    serde::json::Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, PgConnection};
+
+use crate::{
+    db::DB,
+    settings::{get_setting, SettingValue},
+    totp,
+    types::permissions::{perm, PermissionKey},
+};
+
+use super::{
+    auth::{verify_password, AuthGuard, PasswordCheck, User, UserRow},
+    lockout::{self, AuthEventKind},
+    roles,
+    sessions::{self, ClientInfo},
+    ApiError,
+};
+
+/// How long the user has to enter their code after the password step
+const CHALLENGE_LIFETIME_SECS: i64 = 5 * 60;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Characters per half of a recovery code, formatted as XXXXX-XXXXX
+const RECOVERY_CODE_HALF_LENGTH: usize = 5;
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("System clock is before 1970")
+        .as_secs()
+}
+
+/// Whether the user holds a permission that requires 2FA but hasn't enabled it
+pub(super) async fn is_missing_two_factor(
+    user: &User,
+    conn: &mut PgConnection,
+) -> Result<bool, ApiError> {
+    if user.two_factor_enabled {
+        return Ok(false);
+    }
+
+    let required: Vec<String> =
+        sqlx::query_scalar("SELECT permission FROM two_factor_required_permissions")
+            .fetch_all(conn)
+            .await?;
+
+    Ok(required.iter().any(|key| user.has_permission(key)))
+}
+
+fn generate_recovery_code() -> String {
+    use rand::Rng;
+
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+    let mut rng = rand::thread_rng();
+
+    let mut half = || -> String {
+        (0..RECOVERY_CODE_HALF_LENGTH)
+            .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+            .collect()
+    };
+
+    format!("{}-{}", half(), half())
+}
+
+/// Recovery codes are compared without dashes, spaces or case
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    sessions::hash_token(&normalized)
+}
+
+/// Replace all recovery codes of a user and return the new ones
+async fn regenerate_recovery_codes(
+    user_id: i32,
+    conn: &mut PgConnection,
+) -> Result<Vec<String>, ApiError> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO recovery_codes (user_id, code_hash)
+        SELECT $1, UNNEST($2::TEXT[])
+        "#,
+    )
+    .bind(user_id)
+    .bind(codes.iter().map(|c| hash_recovery_code(c)).collect::<Vec<_>>())
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(codes)
+}
+
+/// Check a TOTP code or an unused recovery code, and consume it
+async fn verify_code(
+    user_row: &UserRow,
+    code: &str,
+    conn: &mut PgConnection,
+) -> Result<bool, ApiError> {
+    let Some(secret) = user_row
+        .totp_secret
+        .as_deref()
+        .and_then(totp::base32_decode)
+    else {
+        return Ok(false);
+    };
+
+    let code = code.trim();
+
+    if code.len() == totp::DIGITS as usize {
+        let step = totp::verify(
+            &secret,
+            code,
+            unix_now(),
+            user_row.totp_last_step.map(|s| s as u64),
+        );
+
+        let Some(step) = step else {
+            return Ok(false);
+        };
+
+        // Checked again here so two requests with the same code can't both use it
+        let res = sqlx::query(
+            r#"
+            UPDATE users
+            SET totp_last_step = $1
+            WHERE id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1)
+            "#,
+        )
+        .bind(step as i64)
+        .bind(user_row.id)
+        .execute(conn)
+        .await?;
+
+        return Ok(res.rows_affected() > 0);
+    }
+
+    let res = sqlx::query(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = CURRENT_TIMESTAMP
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user_row.id)
+    .bind(hash_recovery_code(code))
+    .execute(conn)
+    .await?;
+
+    Ok(res.rows_affected() > 0)
+}
+
+/// Called by login once the password is verified.
+/// Returns the challenge token the client has to send back with a code.
+pub(super) async fn create_challenge(
+    user_id: i32,
+    session_expires_at: Option<rocket::time::OffsetDateTime>,
+    conn: &mut PgConnection,
+) -> Result<String, ApiError> {
+    let token = sessions::generate_token();
+
+    sqlx::query(
+        r#"
+        WITH expired AS (
+            DELETE FROM two_factor_challenges
+            WHERE expires_at <= CURRENT_TIMESTAMP
+        )
+        INSERT INTO two_factor_challenges (token_hash, user_id, expires_at, session_expires_at)
+        VALUES ($1, $2, CURRENT_TIMESTAMP + make_interval(secs => $3), to_timestamp($4))
+        "#,
+    )
+    .bind(sessions::hash_token(&token))
+    .bind(user_id)
+    .bind(CHALLENGE_LIFETIME_SECS as f64)
+    .bind(session_expires_at.map(|t| t.unix_timestamp() as f64))
+    .execute(conn)
+    .await?;
+
+    Ok(token)
+}
+
+#[derive(Deserialize)]
+pub(super) struct TwoFactorLoginData {
+    challenge: String,
+    /// A TOTP code or a recovery code
+    code: String,
+}
+
+// POST /auth/login/two_factor
+// {
+//     "challenge": "...", (from /auth/login)
+//     "code": "123456"
+// }
+// -> 200 OK
+// -> 401 Unauthorized
+// -> 429 Too Many Requests
+#[rocket::post("/auth/login/two_factor", data = "<login_data>")]
+pub(super) async fn login(
+    mut db: DB,
+    client: ClientInfo,
+    login_data: Json<TwoFactorLoginData>,
+    cookies: &CookieJar<'_>,
+) -> Result<Status, ApiError> {
+    let TwoFactorLoginData { challenge, code } = login_data.into_inner();
+
+    let challenge: Option<(i32, i32, Option<sqlx::types::chrono::DateTime<chrono::Utc>>)> =
+        sqlx::query_as(
+            r#"
+            SELECT id, user_id, session_expires_at
+            FROM two_factor_challenges
+            WHERE token_hash = $1 AND expires_at > CURRENT_TIMESTAMP
+            "#,
+        )
+        .bind(sessions::hash_token(&challenge))
+        .fetch_optional(&mut **db)
+        .await?;
+
+    let Some((challenge_id, user_id, session_expires_at)) = challenge else {
+        return Err(ApiError(
+            Status::Unauthorized,
+            "Login expired, log in again".to_string(),
+        ));
+    };
+
+    let user_row: UserRow = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&mut **db)
+        .await?;
+
+    if user_row.archived_at.is_some() || !user_row.totp_enabled {
+        return Err(ApiError(
+            Status::Unauthorized,
+            "Login expired, log in again".to_string(),
+        ));
+    }
+
+    if let Some(retry_after) = lockout::locked_for(&user_row.username, &client, &mut db).await? {
+        lockout::log_auth_event(
+            AuthEventKind::LoginLocked,
+            &user_row.username,
+            Some(user_row.id),
+            &client,
+            &mut db,
+        )
+        .await?;
+
+        return Err(ApiError(
+            Status::TooManyRequests,
+            format!(
+                "Too many failed login attempts, try again in {} seconds",
+                retry_after
+            ),
+        ));
+    }
+
+    if !verify_code(&user_row, &code, &mut db).await? {
+        lockout::record_failure(&user_row.username, Some(user_row.id), &client, &mut db).await?;
+
+        return Err(ApiError(Status::Unauthorized, "Invalid code".to_string()));
+    }
+
+    sqlx::query("DELETE FROM two_factor_challenges WHERE id = $1")
+        .bind(challenge_id)
+        .execute(&mut **db)
+        .await?;
+
+    lockout::record_success(&user_row.username, user_row.id, &client, &mut db).await?;
+
+    let session_expires_at = session_expires_at
+        .map(|t| rocket::time::OffsetDateTime::from_unix_timestamp(t.timestamp()))
+        .transpose()
+        .map_err(|e| ApiError(Status::InternalServerError, e.to_string()))?;
+
+    let token =
+        sessions::create_session(user_row.id, session_expires_at, &client, &mut **db).await?;
+
+    log::info!("Sending token cookie");
+
+    cookies.add_private(sessions::session_cookie(token, session_expires_at));
+
+    Ok(Status::Ok)
+}
+
+#[derive(Serialize, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct TwoFactorStatus {
+    enabled: bool,
+    /// The user holds a permission that requires 2FA
+    required: bool,
+    unused_recovery_codes: i64,
+}
+
+// GET /auth/two_factor/status
+#[rocket::get("/auth/two_factor/status")]
+pub(super) async fn status(
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+) -> Result<Json<TwoFactorStatus>, ApiError> {
+    let user = &auth.auth_info.user;
+
+    // Ignores whether it's already enabled
+    let required = is_missing_two_factor(
+        &User {
+            two_factor_enabled: false,
+            ..user.clone()
+        },
+        &mut db,
+    )
+    .await?;
+
+    let unused_recovery_codes: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+    )
+    .bind(user.id)
+    .fetch_one(&mut **db)
+    .await?;
+
+    Ok(Json(TwoFactorStatus {
+        enabled: user.two_factor_enabled,
+        required,
+        unused_recovery_codes,
+    }))
+}
+
+#[derive(Serialize, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct TwoFactorSetup {
+    /// Base32 secret for entering manually
+    secret: String,
+    /// otpauth:// URI to show as a QR code
+    provisioning_uri: String,
+}
+
+// POST /auth/two_factor/setup
+// -> 200 OK
+//     {
+//         "secret": "JBSWY3DPEHPK3PXP...",
+//         "provisioning_uri": "otpauth://totp/..."
+//     }
+// -> 400 Bad Request (already enabled)
+// -> 403 Forbidden (sent with an API token)
+// Starts enrolment, 2FA isn't enabled until the first code is confirmed.
+#[rocket::post("/auth/two_factor/setup")]
+pub(super) async fn setup(
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+) -> Result<Json<TwoFactorSetup>, ApiError> {
+    auth.auth_info.require_session()?;
+
+    let user = &auth.auth_info.user;
+
+    if user.two_factor_enabled {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    let secret = totp::generate_secret();
+
+    sqlx::query("UPDATE users SET totp_secret = $1, totp_last_step = NULL WHERE id = $2")
+        .bind(totp::base32_encode(&secret))
+        .bind(user.id)
+        .execute(&mut **db)
+        .await?;
+
+    let issuer = match get_setting(&mut db, "business_name").await?.map(|s| s.value) {
+        Some(SettingValue::Text(name)) if !name.is_empty() => name,
+        _ => "ERP".to_string(),
+    };
+
+    Ok(Json(TwoFactorSetup {
+        secret: totp::base32_encode(&secret),
+        provisioning_uri: totp::provisioning_uri(&issuer, &user.username, &secret),
+    }))
+}
+
+#[derive(Deserialize)]
+pub(super) struct TwoFactorCodeData {
+    code: String,
+}
+
+#[derive(Serialize, ts_rs::TS)]
+#[ts(export)]
+pub(super) struct RecoveryCodes {
+    /// Only shown once, each can be used instead of a TOTP code one time
+    codes: Vec<String>,
+}
+
+// POST /auth/two_factor/enable
+// {
+//     "code": "123456"
+// }
+// -> 200 OK
+//     {
+//         "codes": ["ABCDE-FGHJK", ...]
+//     }
+// -> 400 Bad Request
+// -> 403 Forbidden (sent with an API token)
+#[rocket::post("/auth/two_factor/enable", data = "<data>")]
+pub(super) async fn enable(
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+    data: Json<TwoFactorCodeData>,
+) -> Result<Json<RecoveryCodes>, ApiError> {
+    auth.auth_info.require_session()?;
+
+    let user_row: UserRow = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        .bind(auth.auth_info.user.id)
+        .fetch_one(&mut **db)
+        .await?;
+
+    if user_row.totp_enabled {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    if user_row.totp_secret.is_none() {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Two-factor authentication has not been set up".to_string(),
+        ));
+    }
+
+    let mut transaction = db.begin().await?;
+
+    // Only accept TOTP codes, recovery codes don't exist yet
+    if data.code.trim().len() != totp::DIGITS as usize
+        || !verify_code(&user_row, &data.code, &mut transaction).await?
+    {
+        return Err(ApiError(Status::BadRequest, "Invalid code".to_string()));
+    }
+
+    sqlx::query("UPDATE users SET totp_enabled = TRUE WHERE id = $1")
+        .bind(user_row.id)
+        .execute(&mut *transaction)
+        .await?;
+
+    let codes = regenerate_recovery_codes(user_row.id, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(RecoveryCodes { codes }))
+}
+
+// POST /auth/two_factor/recovery_codes
+// {
+//     "code": "123456"
+// }
+// -> 200 OK
+//     {
+//         "codes": ["ABCDE-FGHJK", ...]
+//     }
+// -> 400 Bad Request
+// -> 403 Forbidden (sent with an API token)
+// Invalidates the previous recovery codes.
+#[rocket::post("/auth/two_factor/recovery_codes", data = "<data>")]
+pub(super) async fn recovery_codes(
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+    data: Json<TwoFactorCodeData>,
+) -> Result<Json<RecoveryCodes>, ApiError> {
+    auth.auth_info.require_session()?;
+
+    let user_row: UserRow = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        .bind(auth.auth_info.user.id)
+        .fetch_one(&mut **db)
+        .await?;
+
+    if !user_row.totp_enabled {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Two-factor authentication is not enabled".to_string(),
+        ));
+    }
+
+    let mut transaction = db.begin().await?;
+
+    if !verify_code(&user_row, &data.code, &mut transaction).await? {
+        return Err(ApiError(Status::BadRequest, "Invalid code".to_string()));
+    }
+
+    let codes = regenerate_recovery_codes(user_row.id, &mut transaction).await?;
+
+    transaction.commit().await?;
+
+    Ok(Json(RecoveryCodes { codes }))
+}
+
+#[derive(Deserialize)]
+pub(super) struct DisableTwoFactorData {
+    password: String,
+}
+
+// POST /auth/two_factor/disable
+// {
+//     "password": "pass"
+// }
+// -> 200 OK
+// -> 400 Bad Request (required by one of the user's permissions)
+// -> 401 Unauthorized
+// -> 403 Forbidden (sent with an API token)
+#[rocket::post("/auth/two_factor/disable", data = "<data>")]
+pub(super) async fn disable(
+    mut db: DB,
+    auth: AuthGuard<perm::NONE>,
+    data: Json<DisableTwoFactorData>,
+) -> Result<Status, ApiError> {
+    auth.auth_info.require_session()?;
+
+    let user_row: UserRow = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        .bind(auth.auth_info.user.id)
+        .fetch_one(&mut **db)
+        .await?;
+
+    if verify_password(&data.password, &user_row.password, &user_row.salt)
+        == PasswordCheck::Invalid
+    {
+        return Err(ApiError(
+            Status::Unauthorized,
+            "Password is incorrect".to_string(),
+        ));
+    }
+
+    let user = User {
+        two_factor_enabled: false,
+        ..auth.auth_info.user
+    };
+
+    if is_missing_two_factor(&user, &mut db).await? {
+        return Err(ApiError(
+            Status::BadRequest,
+            "Two-factor authentication is required for your permissions".to_string(),
+        ));
+    }
+
+    disable_for_user(user.id, &mut db).await?;
+
+    Ok(Status::Ok)
+}
+
+async fn disable_for_user(user_id: i32, conn: &mut PgConnection) -> Result<u64, ApiError> {
+    let mut transaction = conn.begin().await?;
+
+    let res = sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_enabled = FALSE, totp_secret = NULL, totp_last_step = NULL
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *transaction)
+    .await?;
+
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(res.rows_affected())
+}
+
+// POST /auth/users/<id>/two_factor/reset [Permissions: ADMIN]
+// -> 200 OK
+// -> 404 Not Found
+// For users that lost their device and recovery codes.
+// If 2FA is required for them, they have to set it up again before doing anything else.
+#[rocket::post("/auth/users/<id>/two_factor/reset")]
+pub(super) async fn reset(
+    id: i32,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Status, ApiError> {
+    if disable_for_user(id, &mut db).await? == 0 {
+        return Err(ApiError(Status::NotFound, "User not found".to_string()));
+    }
+
+    Ok(Status::Ok)
+}
+
+// GET /auth/two_factor/required_permissions [Permissions: ADMIN]
+// -> 200 OK
+//     ["ADMIN", "MANAGE_DB", "SETTINGS"]
+#[rocket::get("/auth/two_factor/required_permissions")]
+pub(super) async fn get_required_permissions(
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Json<Vec<PermissionKey>>, ApiError> {
+    let required = sqlx::query_scalar(
+        "SELECT permission FROM two_factor_required_permissions ORDER BY permission",
+    )
+    .fetch_all(&mut **db)
+    .await?;
+
+    Ok(Json(required))
+}
+
+// POST /auth/two_factor/required_permissions [Permissions: ADMIN]
+// ["ADMIN", "MANAGE_DB", "SETTINGS"]
+// -> 200 OK
+// Users holding any of these can only use routes without permissions until they enable 2FA.
+#[rocket::post("/auth/two_factor/required_permissions", data = "<permissions>")]
+pub(super) async fn set_required_permissions(
+    permissions: Json<Vec<PermissionKey>>,
+    mut db: DB,
+    _auth: AuthGuard<perm::ADMIN>,
+) -> Result<Status, ApiError> {
+    let permissions = permissions.into_inner();
+
+    roles::validate_permissions(&permissions)?;
+
+    let mut transaction = db.begin().await?;
+
+    sqlx::query("DELETE FROM two_factor_required_permissions")
+        .execute(&mut *transaction)
+        .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO two_factor_required_permissions (permission)
+        SELECT DISTINCT UNNEST($1::TEXT[])
+        "#,
+    )
+    .bind(&permissions)
+    .execute(&mut *transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(Status::Ok)
+}
+
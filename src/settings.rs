@@ -1,7 +1,6 @@
 use bigdecimal::BigDecimal;
 use serde::{Deserialize, Serialize};
 use sqlx::Acquire;
-use sqlx_core::transaction;
 
 use crate::db::DB;
 
@@ -74,6 +73,31 @@ fn default_settings() -> Vec<Setting> {
             description: None,
             value: SettingValue::Text(",".to_string()),
         },
+        Setting {
+            key: "payment_terms_days".to_string(),
+            long_name: "Payment Terms (Days)".to_string(),
+            description: Some(
+                "Days after an order or purchase before its unpaid amount is overdue".to_string(),
+            ),
+            value: SettingValue::UnsignedInt(30),
+        },
+        Setting {
+            key: "timezone".to_string(),
+            long_name: "Timezone".to_string(),
+            description: Some(
+                "Timezone of the business used to group reports by day, e.g. Europe/Berlin"
+                    .to_string(),
+            ),
+            value: SettingValue::Text("UTC".to_string()),
+        },
+        Setting {
+            key: "inventory_costing_method".to_string(),
+            long_name: "Inventory Costing Method".to_string(),
+            description: Some(
+                "How the cost of sold items is computed: WEIGHTED_AVERAGE or FIFO".to_string(),
+            ),
+            value: SettingValue::Text("WEIGHTED_AVERAGE".to_string()),
+        },
         Setting {
             key: "logo_high_resolution".to_string(),
             long_name: "Logo High Resolution".to_string(),
@@ -245,7 +269,7 @@ pub async fn get_setting(db: &mut DB, key: &str) -> Result<Option<Setting>, sqlx
         WHERE key = $1
         "#,
     )
-    .bind(&key)
+    .bind(key)
     .fetch_optional(&mut ***db)
     .await?;
 
@@ -379,9 +403,177 @@ pub async fn delete_setting(db: &mut DB, key: &str) -> Result<(), sqlx::Error> {
         WHERE key = $1
         "#,
     )
-    .bind(&key)
+    .bind(key)
     .execute(&mut ***db)
     .await?;
 
     Ok(())
 }
+
+fn text_setting(settings: &[Setting], key: &str) -> String {
+    match settings.iter().find(|s| s.key == key).map(|s| &s.value) {
+        Some(SettingValue::Text(text)) | Some(SettingValue::ImageBase64URI(text)) => text.clone(),
+        _ => String::new(),
+    }
+}
+
+fn text_vec_setting(settings: &[Setting], key: &str) -> Vec<String> {
+    match settings.iter().find(|s| s.key == key).map(|s| &s.value) {
+        Some(SettingValue::TextVec(texts)) => texts.clone(),
+        _ => vec![],
+    }
+}
+
+/// The business_* settings and logo, shown on printed documents
+#[derive(Serialize, Deserialize, Debug, Clone, ts_rs::TS)]
+#[ts(export)]
+pub struct BusinessDetails {
+    pub name: String,
+    pub address: String,
+    pub email: String,
+    pub website: String,
+    pub phone_numbers: Vec<String>,
+    pub bank_accounts: Vec<String>,
+    /// logo_high_resolution as a data URI
+    pub logo: String,
+}
+
+impl BusinessDetails {
+    pub async fn load(db: &mut DB) -> Result<Self, sqlx::Error> {
+        let settings = get_settings(
+            db,
+            [
+                "business_name",
+                "business_address",
+                "business_email",
+                "business_website",
+                "business_phone_numbers",
+                "business_bank_accounts",
+                "logo_high_resolution",
+            ]
+            .map(String::from)
+            .to_vec(),
+        )
+        .await?;
+
+        Ok(Self {
+            name: text_setting(&settings, "business_name"),
+            address: text_setting(&settings, "business_address"),
+            email: text_setting(&settings, "business_email"),
+            website: text_setting(&settings, "business_website"),
+            phone_numbers: text_vec_setting(&settings, "business_phone_numbers"),
+            bank_accounts: text_vec_setting(&settings, "business_bank_accounts"),
+            logo: text_setting(&settings, "logo_high_resolution"),
+        })
+    }
+}
+
+/// The currency_* settings, for amounts the server formats itself
+#[derive(Debug, Clone)]
+pub struct CurrencyFormat {
+    pub prefix: String,
+    pub suffix: String,
+    pub decimal_places: u32,
+    pub decimal_separator: String,
+    pub thousand_separator: String,
+}
+
+impl CurrencyFormat {
+    pub async fn load(db: &mut DB) -> Result<Self, sqlx::Error> {
+        let settings = get_settings(
+            db,
+            [
+                "currency_prefix",
+                "currency_suffix",
+                "currency_decimal_places",
+                "currency_decimal_separator",
+                "currency_thousand_separator",
+            ]
+            .map(String::from)
+            .to_vec(),
+        )
+        .await?;
+
+        let decimal_places = match settings
+            .iter()
+            .find(|s| s.key == "currency_decimal_places")
+            .map(|s| &s.value)
+        {
+            Some(SettingValue::UnsignedInt(places)) => *places,
+            _ => 2,
+        };
+
+        Ok(Self {
+            prefix: text_setting(&settings, "currency_prefix"),
+            suffix: text_setting(&settings, "currency_suffix"),
+            decimal_places,
+            decimal_separator: text_setting(&settings, "currency_decimal_separator"),
+            thousand_separator: text_setting(&settings, "currency_thousand_separator"),
+        })
+    }
+
+    /// Rounds to decimal_places, e.g. `-1234.5` becomes `-$1,234.50`
+    pub fn format(&self, amount: &BigDecimal) -> String {
+        let places = self.decimal_places as i64;
+        let rounded = amount.round(places).with_scale(places).to_string();
+
+        let (negative, digits) = match rounded.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, rounded.as_str()),
+        };
+
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+        let mut grouped = String::new();
+
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                grouped.push_str(&self.thousand_separator);
+            }
+            grouped.push(digit);
+        }
+
+        if !fraction.is_empty() {
+            grouped.push_str(&self.decimal_separator);
+            grouped.push_str(fraction);
+        }
+
+        // Rounding can leave "-0.00"
+        let negative = negative && digits.chars().any(|c| c.is_ascii_digit() && c != '0');
+
+        format!(
+            "{}{}{}{}",
+            if negative { "-" } else { "" },
+            self.prefix,
+            grouped,
+            self.suffix
+        )
+    }
+}
+
+/// Values of the inventory_costing_method setting, see recompute_inventory_cost in SQL
+pub const INVENTORY_COSTING_METHODS: &[&str] = &["WEIGHTED_AVERAGE", "FIFO"];
+
+/// Default number of days to pay an order or purchase
+pub async fn payment_terms_days(db: &mut DB) -> Result<u32, sqlx::Error> {
+    match get_setting(db, "payment_terms_days").await?.map(|s| s.value) {
+        Some(SettingValue::UnsignedInt(days)) => Ok(days),
+        _ => Ok(30),
+    }
+}
+
+/// Timezone name of the business, `UTC` if it isn't set
+pub async fn timezone(db: &mut DB) -> Result<String, sqlx::Error> {
+    match get_setting(db, "timezone").await?.map(|s| s.value) {
+        Some(SettingValue::Text(timezone)) if !timezone.is_empty() => Ok(timezone),
+        _ => Ok("UTC".to_string()),
+    }
+}
+
+/// Whether invoices end with the checked, received and authorised by signature lines
+pub async fn invoice_signature_fields(db: &mut DB) -> Result<bool, sqlx::Error> {
+    match get_setting(db, "invoice_signature_fields").await?.map(|s| s.value) {
+        Some(SettingValue::Boolean(enabled)) => Ok(enabled),
+        _ => Ok(true),
+    }
+}
@@ -0,0 +1,292 @@
+//! RFC 6238 time-based one-time passwords, compatible with common authenticator apps
+//! (HMAC-SHA1, 6 digits, 30 second steps).
+//!
+//! Every function takes the current time as a parameter instead of reading the clock,
+//! so codes can be checked against a fixed time.
+
+use ring::hmac;
+
+use crate::routes::auth::constant_time_eq;
+
+pub const DIGITS: u32 = 6;
+pub const STEP_SECONDS: u64 = 30;
+/// Number of steps before and after the current one that are still accepted,
+/// to allow for clock drift between the server and the phone
+pub const ALLOWED_DRIFT_STEPS: u64 = 1;
+/// RFC 4226 recommends at least 128 bits, 160 bits matches the HMAC-SHA1 block
+pub const SECRET_BYTES: usize = 20;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_secret() -> Vec<u8> {
+    use rand::RngCore;
+
+    let mut secret = vec![0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret);
+
+    secret
+}
+
+/// RFC 4648 base32 without padding, which is what authenticator apps expect
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity((data.len() * 8).div_ceil(5));
+
+    for chunk in data.chunks(5) {
+        let mut buffer = [0u8; 5];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+
+        let bits = buffer.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+
+        let chars = (chunk.len() * 8).div_ceil(5);
+
+        for i in 0..chars {
+            let index = (bits >> (35 - i * 5)) & 0b11111;
+            encoded.push(BASE32_ALPHABET[index as usize] as char);
+        }
+    }
+
+    encoded
+}
+
+/// Accepts lowercase, spaces and padding, since secrets are sometimes typed in by hand
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+
+    let mut buffer: u64 = 0;
+    let mut bits = 0;
+
+    for c in encoded.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())?;
+
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    Some(decoded)
+}
+
+/// RFC 4226 HOTP value for a counter
+pub fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let hash = hmac::sign(&key, &counter.to_be_bytes());
+    let hash = hash.as_ref();
+
+    // Dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    binary % 10_u32.pow(DIGITS)
+}
+
+pub fn step_at(unix_time: u64) -> u64 {
+    unix_time / STEP_SECONDS
+}
+
+/// The code an authenticator app shows at `unix_time`
+pub fn code_at(secret: &[u8], unix_time: u64) -> String {
+    format!(
+        "{:0width$}",
+        hotp(secret, step_at(unix_time)),
+        width = DIGITS as usize
+    )
+}
+
+/// Check a code at `unix_time` and return the step it belongs to.
+/// Steps up to and including `last_used_step` are rejected, so a code can't be used twice.
+pub fn verify(
+    secret: &[u8],
+    code: &str,
+    unix_time: u64,
+    last_used_step: Option<u64>,
+) -> Option<u64> {
+    let code = code.trim();
+
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let current_step = step_at(unix_time);
+
+    (current_step.saturating_sub(ALLOWED_DRIFT_STEPS)..=current_step + ALLOWED_DRIFT_STEPS)
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| {
+            let expected = format!(
+                "{:0width$}",
+                hotp(secret, *step),
+                width = DIGITS as usize
+            );
+
+            constant_time_eq(expected.as_bytes(), code.as_bytes())
+        })
+}
+
+/// `otpauth://` URI that authenticator apps read from a QR code.
+/// See https://github.com/google/google-authenticator/wiki/Key-Uri-Format
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        base32_encode(secret),
+        percent_encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Secret of the RFC 4226 and RFC 6238 test vectors
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn hotp_matches_rfc_4226() {
+        let expected = [
+            755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489,
+        ];
+
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as u64), code, "counter {}", counter);
+        }
+    }
+
+    #[test]
+    fn code_at_matches_rfc_6238() {
+        // The RFC lists 8 digit codes, 6 digit codes are their last 6 digits
+        let expected = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+
+        for (time, code) in expected {
+            assert_eq!(code_at(RFC_SECRET, time), code, "time {}", time);
+        }
+    }
+
+    #[test]
+    fn verify_allows_one_step_of_drift() {
+        let now = 1111111111;
+        let step = step_at(now);
+
+        for offset in [-1i64, 0, 1] {
+            let code = code_at(RFC_SECRET, now.wrapping_add_signed(offset * STEP_SECONDS as i64));
+            assert_eq!(
+                verify(RFC_SECRET, &code, now, None),
+                Some(step.wrapping_add_signed(offset)),
+                "offset {}",
+                offset
+            );
+        }
+
+        for offset in [-2i64, 2] {
+            let code = code_at(RFC_SECRET, now.wrapping_add_signed(offset * STEP_SECONDS as i64));
+            assert_eq!(verify(RFC_SECRET, &code, now, None), None, "offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn verify_rejects_used_steps() {
+        let now = 1234567890;
+        let step = step_at(now);
+        let code = code_at(RFC_SECRET, now);
+
+        assert_eq!(verify(RFC_SECRET, &code, now, Some(step)), None);
+        assert_eq!(verify(RFC_SECRET, &code, now, Some(step + 1)), None);
+        assert_eq!(verify(RFC_SECRET, &code, now, Some(step - 1)), Some(step));
+    }
+
+    #[test]
+    fn verify_rejects_malformed_codes() {
+        let now = 59;
+
+        assert_eq!(verify(RFC_SECRET, " 287082 ", now, None), Some(1));
+        assert_eq!(verify(RFC_SECRET, "28708", now, None), None);
+        assert_eq!(verify(RFC_SECRET, "2870822", now, None), None);
+        assert_eq!(verify(RFC_SECRET, "28708a", now, None), None);
+    }
+
+    #[test]
+    fn base32_matches_rfc_4648() {
+        let expected = [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ];
+
+        for (data, encoded) in expected {
+            assert_eq!(base32_encode(data.as_bytes()), encoded);
+            assert_eq!(base32_decode(encoded).as_deref(), Some(data.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        let secret = generate_secret();
+
+        assert_eq!(secret.len(), SECRET_BYTES);
+        assert_eq!(base32_decode(&base32_encode(&secret)), Some(secret));
+    }
+
+    #[test]
+    fn base32_decode_accepts_typed_secrets() {
+        assert_eq!(base32_decode("mzxw 6ytb oi======").as_deref(), Some(&b"foobar"[..]));
+    }
+
+    #[test]
+    fn base32_decode_rejects_invalid_characters() {
+        // 0, 1, 8 and 9 are not in the alphabet
+        for invalid in ["MZXW0", "MZXW1", "MZ8W6", "MZXW9", "MZ-W6", "MZXWé"] {
+            assert_eq!(base32_decode(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn provisioning_uri_escapes_issuer_and_account() {
+        assert_eq!(
+            provisioning_uri("ACME Co", "alice@example.com", RFC_SECRET),
+            "otpauth://totp/ACME%20Co:alice%40example.com\
+             ?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=ACME%20Co\
+             &algorithm=SHA1&digits=6&period=30"
+        );
+        assert_eq!(
+            provisioning_uri("A&B:C?", "ü/x", b"f"),
+            "otpauth://totp/A%26B%3AC%3F:%C3%BC%2Fx\
+             ?secret=MY&issuer=A%26B%3AC%3F&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}